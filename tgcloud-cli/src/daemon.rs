@@ -0,0 +1,262 @@
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+/// Name of the unit, plist label and scheduled task.
+const SERVICE_NAME: &str = "tgcloud";
+const LAUNCHD_LABEL: &str = "com.github.angel2rider.tgcloud";
+
+/// Extra time the service manager waits on top of the shutdown grace before
+/// killing the daemon, so running transfers can finish or roll back.
+const STOP_MARGIN_SECS: u64 = 30;
+
+/// How the daemon gets registered with the platform's service manager.
+pub enum ServiceInstall {
+    /// A unit or plist written to `path`, followed by the commands that
+    /// enable it.
+    File {
+        path: PathBuf,
+        contents: String,
+        next_steps: Vec<String>,
+    },
+    /// A command that registers the daemon directly.
+    Command { program: String, args: Vec<String> },
+}
+
+/// Builds the service definition that runs the current binary with `--gui`
+/// as the current user, for the platform tgcloud was built for.
+pub fn service_install(system: bool, shutdown_grace_secs: u64) -> anyhow::Result<ServiceInstall> {
+    let exe = std::env::current_exe().context("Cannot locate the tgcloud binary")?;
+    let stop_timeout = shutdown_grace_secs + STOP_MARGIN_SECS;
+    if cfg!(target_os = "windows") {
+        windows_task(&exe, system)
+    } else if cfg!(target_os = "macos") {
+        launchd_plist(&exe, system, stop_timeout)
+    } else {
+        systemd_unit(&exe, system, stop_timeout)
+    }
+}
+
+/// Writes the unit file, or runs the registering command.
+pub fn install(service: &ServiceInstall, force: bool) -> anyhow::Result<()> {
+    match service {
+        ServiceInstall::File { path, contents, .. } => {
+            if path.exists() && !force {
+                anyhow::bail!(
+                    "{} already exists; use --force to replace it",
+                    path.display()
+                );
+            }
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Cannot create {}", dir.display()))?;
+            }
+            std::fs::write(path, contents).with_context(|| {
+                format!(
+                    "Cannot write {} (use --print and write it with sufficient rights)",
+                    path.display()
+                )
+            })
+        }
+        ServiceInstall::Command { program, args } => {
+            let mut args = args.clone();
+            if force {
+                args.push("/F".into());
+            }
+            let status = std::process::Command::new(program)
+                .args(&args)
+                .status()
+                .with_context(|| format!("Cannot run {}", program))?;
+            if !status.success() {
+                anyhow::bail!("{} failed with {}", program, status);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn current_user() -> anyhow::Result<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+        .filter(|u| !u.is_empty())
+        .context("Cannot tell which user the daemon should run as; set USER")
+}
+
+fn home_dir() -> anyhow::Result<PathBuf> {
+    dirs::home_dir().context("Cannot resolve the home directory")
+}
+
+// ---------------------------------------------------------------------------
+// systemd
+// ---------------------------------------------------------------------------
+
+fn systemd_unit(exe: &Path, system: bool, stop_timeout: u64) -> anyhow::Result<ServiceInstall> {
+    let user = current_user()?;
+    let (path, wanted_by, user_line, systemctl) = if system {
+        (
+            PathBuf::from(format!("/etc/systemd/system/{}.service", SERVICE_NAME)),
+            "multi-user.target",
+            format!("User={}\n", user),
+            "sudo systemctl",
+        )
+    } else {
+        (
+            dirs::config_dir()
+                .context("Cannot resolve the config directory")?
+                .join(format!("systemd/user/{}.service", SERVICE_NAME)),
+            "default.target",
+            String::new(),
+            "systemctl --user",
+        )
+    };
+
+    let contents = format!(
+        "[Unit]\n\
+         Description=TG-Cloud server\n\
+         Wants=network-online.target\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=simple\n\
+         {user_line}\
+         ExecStart={exec} --gui\n\
+         Restart=on-failure\n\
+         RestartSec=5\n\
+         # Running transfers get SHUTDOWN_GRACE_SECS to finish after SIGTERM.\n\
+         TimeoutStopSec={stop_timeout}\n\
+         \n\
+         [Install]\n\
+         WantedBy={wanted_by}\n",
+        exec = systemd_quote(exe),
+    );
+
+    let mut next_steps = vec![
+        format!("{} daemon-reload", systemctl),
+        format!("{} enable --now {}.service", systemctl, SERVICE_NAME),
+    ];
+    if !system {
+        // User units otherwise only run while the user is logged in.
+        next_steps.push(format!("loginctl enable-linger {}", user));
+    }
+    Ok(ServiceInstall::File {
+        path,
+        contents,
+        next_steps,
+    })
+}
+
+/// Quotes a path for `ExecStart`, where `%` starts a specifier.
+fn systemd_quote(path: &Path) -> String {
+    let escaped = path
+        .display()
+        .to_string()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%");
+    format!("\"{}\"", escaped)
+}
+
+// ---------------------------------------------------------------------------
+// launchd
+// ---------------------------------------------------------------------------
+
+fn launchd_plist(exe: &Path, system: bool, stop_timeout: u64) -> anyhow::Result<ServiceInstall> {
+    let home = home_dir()?;
+    let (path, user_key, domain) = if system {
+        (
+            PathBuf::from(format!("/Library/LaunchDaemons/{}.plist", LAUNCHD_LABEL)),
+            format!(
+                "    <key>UserName</key>\n    <string>{}</string>\n",
+                xml_escape(&current_user()?)
+            ),
+            "sudo launchctl bootstrap system".to_string(),
+        )
+    } else {
+        (
+            home.join(format!("Library/LaunchAgents/{}.plist", LAUNCHD_LABEL)),
+            String::new(),
+            "launchctl bootstrap gui/$(id -u)".to_string(),
+        )
+    };
+    let log = home.join("Library/Logs/tgcloud.log");
+
+    let contents = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+{user_key}    <key>ProgramArguments</key>
+    <array>
+        <string>{exe}</string>
+        <string>--gui</string>
+    </array>
+    <key>EnvironmentVariables</key>
+    <dict>
+        <key>HOME</key>
+        <string>{home}</string>
+    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>ExitTimeOut</key>
+    <integer>{stop_timeout}</integer>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#,
+        label = LAUNCHD_LABEL,
+        exe = xml_escape(&exe.display().to_string()),
+        home = xml_escape(&home.display().to_string()),
+        log = xml_escape(&log.display().to_string()),
+    );
+
+    let next_steps = vec![format!("{} {}", domain, path.display())];
+    Ok(ServiceInstall::File {
+        path,
+        contents,
+        next_steps,
+    })
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// ---------------------------------------------------------------------------
+// Windows
+// ---------------------------------------------------------------------------
+
+/// A scheduled task started at logon. A real Windows service would have to
+/// speak the service control protocol, which `tgcloud` does not.
+fn windows_task(exe: &Path, system: bool) -> anyhow::Result<ServiceInstall> {
+    if system {
+        anyhow::bail!(
+            "--system is not supported on Windows: the task has to run as the user that owns the configuration"
+        );
+    }
+    Ok(ServiceInstall::Command {
+        program: "schtasks".into(),
+        args: vec![
+            "/Create".into(),
+            "/TN".into(),
+            SERVICE_NAME.into(),
+            "/SC".into(),
+            "ONLOGON".into(),
+            "/RL".into(),
+            "LIMITED".into(),
+            "/TR".into(),
+            format!("\"{}\" --gui", exe.display()),
+        ],
+    })
+}
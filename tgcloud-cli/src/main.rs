@@ -1,15 +1,22 @@
+mod daemon;
+mod top;
 mod ui;
-mod web;
 
 use anyhow::Context;
-use clap::{Parser, Subcommand};
-use indicatif::ProgressBar;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use futures::stream::{FuturesUnordered, StreamExt};
+use indicatif::{MultiProgress, ProgressBar};
 use owo_colors::OwoColorize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
-use tgcloud_core::{Config, DownloadStatus, TgCloudService, UploadStatus};
-use tokio::sync::mpsc;
+use tgcloud_core::{
+    is_glob, remote_root, walk_local, ApiKeyScope, Config, DownloadOptions, DownloadStatus,
+    FileFilter, FileMetadata, FilterRule, HistoryAction, HistoryQuery, PathFilter, SearchMode,
+    SyncDirection, TgCloudError, TgCloudService, UploadOptions, UploadStatus,
+};
+use tgcloud_server::ServerConfig;
+use tokio::sync::{mpsc, Semaphore};
 use ui::*;
 
 #[derive(Parser)]
@@ -20,36 +27,424 @@ struct Cli {
     #[arg(long)]
     gui: bool,
 
+    /// Show what delete, rename, mv, sync or trash empty would change without touching anything; other commands refuse it
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Do not ask for confirmation before destructive commands
+    #[arg(long, short, global = true)]
+    yes: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Upload a file
-    Upload { path: String },
-    /// Download a file
-    Download { remote_path: String },
+    /// Upload a file, or a directory with --recursive
+    Upload {
+        path: String,
+        /// Replace an existing remote file with the same name
+        #[arg(long)]
+        force: bool,
+        /// Upload every file below a directory
+        #[arg(long, short)]
+        recursive: bool,
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
+    /// Download files, globs, or folders with --recursive
+    Download {
+        #[arg(required = true)]
+        remote_paths: Vec<String>,
+        /// Where to write: a file name for a single file, otherwise a directory
+        /// that receives the remote paths (default: the current directory)
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<std::path::PathBuf>,
+        /// Overwrite an existing local file
+        #[arg(long)]
+        force: bool,
+        /// Number of files to download at once
+        #[arg(long, short, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+        jobs: u16,
+        /// Download every file below a remote folder
+        #[arg(long, short)]
+        recursive: bool,
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
     /// List files
     List {
         #[arg(default_value = "root")]
         folder: String,
+        /// List everything underneath the folder
+        #[arg(long, short, conflicts_with = "depth")]
+        recursive: bool,
+        /// Descend at most N levels (1 = immediate children only, the default)
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        depth: Option<u32>,
+        /// Show at most N files
+        #[arg(long, value_name = "N", default_value_t = 1000, value_parser = clap::value_parser!(i64).range(1..))]
+        limit: i64,
+        /// Continue a previous listing from the cursor it printed
+        #[arg(long, value_name = "TOKEN")]
+        cursor: Option<String>,
+        /// Also list archived files
+        #[arg(long)]
+        archived: bool,
+    },
+    /// Show files as a tree with per-directory sizes
+    Tree {
+        #[arg(default_value = "root")]
+        prefix: String,
+        /// Also show archived files
+        #[arg(long)]
+        archived: bool,
     },
     /// Rename a file
     Rename { old_path: String, new_path: String },
-    /// Delete a file
+    /// Move a folder: rename every file under OLD_PREFIX to NEW_PREFIX
+    Mv {
+        old_prefix: String,
+        new_prefix: String,
+    },
+    /// Move a file to the trash
     Delete { path: String },
+    /// Revert the most recent rename or delete
+    Undo,
+    /// Inspect or empty the trash
+    #[command(subcommand)]
+    Trash(TrashCommands),
+    /// Move a file, or every file under a folder, to ARCHIVE_CHAT_ID
+    Archive { path: String },
+    /// Move archived files back to the storage chat
+    Unarchive { path: String },
+    /// Send a stored file to another chat
+    Send {
+        path: String,
+        /// Target chat id or @username
+        #[arg(long)]
+        to: String,
+    },
+    /// Compare a local directory with a remote folder
+    Diff {
+        local_dir: std::path::PathBuf,
+        #[arg(default_value = "root")]
+        remote_prefix: String,
+    },
+    /// Upload new and changed files so a remote folder matches a local directory
+    Sync {
+        local_dir: std::path::PathBuf,
+        #[arg(default_value = "root")]
+        remote_prefix: String,
+        /// Make the local directory match the remote folder instead
+        #[arg(long)]
+        pull: bool,
+        /// Also delete files that only exist on the side being updated
+        #[arg(long)]
+        delete: bool,
+        /// Print the itemized plan and ask before executing it
+        #[arg(long)]
+        plan: bool,
+        #[command(flatten)]
+        filters: FilterArgs,
+    },
+    /// Find files by name (case-insensitive substring match by default)
+    Search {
+        query: String,
+        /// Match the start of any path component
+        #[arg(long, conflicts_with = "fuzzy")]
+        prefix: bool,
+        /// Match the query's characters in order, best matches first
+        #[arg(long)]
+        fuzzy: bool,
+        /// Show at most N files
+        #[arg(long, value_name = "N", default_value_t = 50, value_parser = clap::value_parser!(i64).range(1..))]
+        limit: i64,
+        /// Also search archived files
+        #[arg(long)]
+        archived: bool,
+    },
+    /// Show file, chunk and byte totals by bot and by upload month
+    Stats {
+        #[arg(default_value = "root")]
+        folder: String,
+    },
+    /// Show the disk usage of each subfolder
+    Du {
+        #[arg(default_value = "root")]
+        folder: String,
+    },
+    /// Print a `sha256sum`-compatible manifest of the files under a folder
+    Checksums {
+        #[arg(default_value = "root")]
+        prefix: String,
+        /// Also list archived files
+        #[arg(long)]
+        archived: bool,
+    },
+    /// Show the audit log of uploads, downloads, renames and deletes
+    History {
+        /// Only show entries for this remote path
+        #[arg(long)]
+        file: Option<String>,
+        /// Only show entries newer than this, e.g. `7d` or `12h`
+        #[arg(long, value_parser = humantime::parse_duration)]
+        since: Option<Duration>,
+        /// Maximum number of entries to show
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+    },
+    /// Encrypt file names and history stored before METADATA_KEY was set
+    EncryptMetadata,
+    /// Rebuild lost file metadata from the manifest pinned in the storage chat
+    Recover {
+        /// Read the manifest written with METADATA_MIRROR (the only source so far)
+        #[arg(long, required = true)]
+        from_chat: bool,
+    },
+    /// Manage storage bots
+    #[command(subcommand)]
+    Bot(BotCommands),
+    /// Check the configuration, MongoDB, the Bot API server and every bot
+    Doctor,
+    /// Inspect the configuration file
+    #[command(subcommand)]
+    Config(ConfigCommands),
+    /// Run the server (--gui) as a background service
+    #[command(subcommand)]
+    Daemon(DaemonCommands),
+    /// Manage keys for the server's REST API
+    #[command(subcommand)]
+    Apikey(ApikeyCommands),
+    /// Live view of the server's transfers, bots and chunk queue
+    Top {
+        /// Server address (default: from SERVER_BIND and SERVER_TLS_*)
+        #[arg(long)]
+        url: Option<String>,
+        /// Token or API key (default: SERVER_AUTH_TOKEN)
+        #[arg(long)]
+        token: Option<String>,
+        /// Seconds between refreshes
+        #[arg(long, default_value_t = 1)]
+        interval: u64,
+    },
+}
+
+/// `--include`/`--exclude` rules shared by the transfer commands.
+#[derive(Args)]
+struct FilterArgs {
+    /// Transfer paths matching this glob (repeatable; the first matching rule wins)
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+    /// Skip paths matching this glob (repeatable; the first matching rule wins)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+}
+
+impl FilterArgs {
+    /// Builds the filter with the rules in the order they were given on the
+    /// command line, which clap does not preserve across two flags.
+    fn to_filter(&self, matches: &clap::ArgMatches) -> anyhow::Result<PathFilter> {
+        let sub = matches.subcommand().map(|(_, m)| m);
+        let indices = |id: &str| -> Vec<usize> {
+            sub.and_then(|m| m.indices_of(id))
+                .map(Iterator::collect)
+                .unwrap_or_default()
+        };
+
+        let mut rules: Vec<(usize, FilterRule)> = indices("include")
+            .into_iter()
+            .zip(&self.include)
+            .map(|(i, p)| (i, FilterRule::Include(p.clone())))
+            .chain(
+                indices("exclude")
+                    .into_iter()
+                    .zip(&self.exclude)
+                    .map(|(i, p)| (i, FilterRule::Exclude(p.clone()))),
+            )
+            .collect();
+        rules.sort_by_key(|(i, _)| *i);
+        let rules: Vec<FilterRule> = rules.into_iter().map(|(_, r)| r).collect();
+        Ok(PathFilter::new(&rules)?)
+    }
+}
+
+#[derive(Subcommand)]
+enum TrashCommands {
+    /// List deleted files that can still be restored
+    List,
+    /// Permanently delete everything in the trash
+    Empty,
+}
+
+#[derive(Subcommand)]
+enum BotCommands {
+    /// List bots and whether they are active
+    List,
+    /// Show which bots are backing off after Telegram rate limits (HTTP 429)
+    Status,
+    /// Re-enable a deactivated bot
+    Enable { bot_id: String },
+    /// Replace a bot's token after it leaked or was regenerated with @BotFather
+    RotateToken {
+        bot_id: String,
+        /// Token issued by @BotFather for the same bot
+        new_token: String,
+    },
+    /// Measure latency and throughput of every active bot
+    Bench {
+        /// Size of the test payload in MiB
+        #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u64).range(1..=2000))]
+        size_mib: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Check a .env file for missing, unknown and suspicious settings
+    Validate {
+        /// File to check instead of the default configuration
+        #[arg(long, value_name = "PATH")]
+        file: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ApikeyCommands {
+    /// Create a key; it is shown only once
+    Create {
+        /// What the key is for, e.g. the script using it
+        name: String,
+        /// read-only (list, search, download), upload (also upload) or admin (everything)
+        #[arg(long, default_value = "read-only")]
+        scope: ApiKeyScope,
+    },
+    /// List keys
+    List,
+    /// Revoke a key so it stops working immediately
+    Revoke { key_id: String },
+}
+
+#[derive(Subcommand)]
+enum DaemonCommands {
+    /// Register `tgcloud --gui` with systemd, launchd or the Windows Task Scheduler
+    Install {
+        /// Install a system-wide unit instead of one for the current user
+        #[arg(long)]
+        system: bool,
+        /// Print the unit instead of writing it
+        #[arg(long)]
+        print: bool,
+        /// Replace an existing unit
+        #[arg(long)]
+        force: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    tgcloud_core::init_logger("warn");
 
-    print_banner();
+    // Other commands would make their changes regardless.
+    if args.dry_run
+        && !matches!(
+            args.command,
+            Some(Commands::Delete { .. })
+                | Some(Commands::Rename { .. })
+                | Some(Commands::Mv { .. })
+                | Some(Commands::Sync { .. })
+                | Some(Commands::Trash(TrashCommands::Empty))
+        )
+    {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--dry-run is only supported by delete, rename, mv, sync and trash empty",
+            )
+            .exit();
+    }
+
+    // Commands whose stdout is meant to be redirected skip the banner.
+    if !matches!(
+        args.command,
+        Some(Commands::Checksums { .. })
+            | Some(Commands::Top { .. })
+            | Some(Commands::Daemon(DaemonCommands::Install {
+                print: true,
+                ..
+            }))
+    ) {
+        print_banner();
+    }
+
+    // Runs before the configuration is loaded, since it diagnoses it.
+    if matches!(args.command, Some(Commands::Doctor)) {
+        let spinner = create_spinner("Running checks...");
+        let results = tgcloud_core::run_doctor().await;
+        spinner.finish_and_clear();
+        let healthy = print_doctor(&results);
+        std::process::exit(if healthy { 0 } else { 1 });
+    }
+    if let Some(Commands::Config(ConfigCommands::Validate { file })) = &args.command {
+        let (path, issues) = tgcloud_core::validate_config_file(file.as_deref())
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        std::process::exit(print_config_issues(&path, &issues));
+    }
 
     // Load configuration
     let config = Config::from_env().map_err(|e| anyhow::anyhow!(e.to_string()))?;
 
+    // Needs the configuration, but no connection.
+    if let Some(Commands::Daemon(DaemonCommands::Install {
+        system,
+        print,
+        force,
+    })) = &args.command
+    {
+        let service = daemon::service_install(*system, config.shutdown_grace_secs)?;
+        if *print {
+            match &service {
+                daemon::ServiceInstall::File { contents, .. } => print!("{}", contents),
+                daemon::ServiceInstall::Command { program, args } => {
+                    println!("{} {}", program, args.join(" "))
+                }
+            }
+            return Ok(());
+        }
+        daemon::install(&service, *force)?;
+        match &service {
+            daemon::ServiceInstall::File {
+                path, next_steps, ..
+            } => {
+                print_success(&format!("Wrote {}", path.display()));
+                println!("\n  {}", "Enable it with:".bold());
+                for step in next_steps {
+                    println!("    {}", step);
+                }
+            }
+            daemon::ServiceInstall::Command { .. } => {
+                print_success("Registered tgcloud to start at logon")
+            }
+        }
+        return Ok(());
+    }
+
+    // Talks to the running server, not to MongoDB or Telegram.
+    if let Some(Commands::Top {
+        url,
+        token,
+        interval,
+    }) = args.command
+    {
+        let server_config = ServerConfig::from_env().map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        let target = top::Target::new(&server_config, url, token);
+        return top::run(target, Duration::from_secs(interval.max(1))).await;
+    }
+
     let spinner = create_spinner("Connecting to services...");
     let service = TgCloudService::new(config)
         .await
@@ -62,7 +457,8 @@ async fn main() -> anyhow::Result<()> {
     let service = Arc::new(service);
 
     if args.gui {
-        web::start_server(service).await?;
+        let server_config = ServerConfig::from_env().map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        tgcloud_server::start_server(service, server_config).await?;
         return Ok(());
     }
 
@@ -73,173 +469,180 @@ async fn main() -> anyhow::Result<()> {
         // ===================================================================
         // Upload
         // ===================================================================
-        Commands::Upload { path } => {
-            println!("🚀 Starting upload for: {}", path.cyan());
-            let (tx, mut rx) = mpsc::channel(256);
-
-            let service_handle = service.clone();
-            let upload_handle =
-                tokio::spawn(async move { service_handle.upload_file(&path, tx).await });
-
-            let mut progress_bar: Option<ProgressBar> = None;
-            let mut spinner: Option<ProgressBar> = None;
-
-            while let Some(event) = rx.recv().await {
-                match event.status {
-                    UploadStatus::Started {
-                        total_size,
-                        total_chunks: _,
-                        progress,
-                    } => {
-                        if total_size > 256 * 1024 * 1024 {
-                            let pb = create_overall_bar_direct(total_size);
-                            progress_bar = Some(pb.clone());
-
-                            // Spawn a task to update the progress bar from the atomic counter.
-                            tokio::spawn(async move {
-                                while !pb.is_finished() {
-                                    let current = progress.load(Ordering::Relaxed);
-                                    pb.set_position(current);
-                                    tokio::time::sleep(Duration::from_millis(100)).await;
-                                }
-                            });
-                        } else {
-                            spinner = Some(create_spinner("Uploading..."));
-                        }
-                    }
-                    UploadStatus::Hashing => {
-                        if let Some(s) = spinner.take() {
-                            s.finish_and_clear();
-                        }
-                        spinner = Some(create_spinner("Calculating SHA-256 hash..."));
-                    }
-                    UploadStatus::HashComplete { sha256 } => {
-                        if let Some(s) = spinner.take() {
-                            s.finish_and_clear();
-                        }
-                        println!(
-                            "  {} SHA-256: {}",
-                            "🔒".cyan(),
-                            sha256[..16].to_string().yellow()
-                        );
-                    }
-                    UploadStatus::Completed { file_id } => {
-                        if let Some(pb) = progress_bar.take() {
-                            pb.finish_and_clear();
-                        }
-                        if let Some(s) = spinner.take() {
-                            s.finish_and_clear();
-                        }
-                        print_success(&format!("Upload completed!\n    File ID: {}\n", file_id));
-                    }
-                    UploadStatus::Failed { error } => {
-                        if let Some(pb) = progress_bar.take() {
-                            pb.finish_and_clear();
-                        }
-                        if let Some(s) = spinner.take() {
-                            s.finish_and_clear();
-                        }
-                        print_error(&format!("Upload failed: {}", error));
-                    }
-                }
+        Commands::Upload {
+            path,
+            force,
+            recursive,
+            filters,
+        } => {
+            if !std::path::Path::new(&path).is_dir() {
+                run_upload(&service, path, None, force).await?;
+                return Ok(());
+            }
+            if !recursive {
+                print_error(&format!("'{}' is a directory; use --recursive", path));
+                return Ok(());
             }
 
-            if let Err(e) = upload_handle.await? {
-                print_error(&e.to_string());
+            let filter = filters.to_filter(&matches)?;
+            let root = path.trim_end_matches('/');
+            for (name, (local, _)) in walk_local(std::path::Path::new(root))? {
+                if !filter.matches(&name) {
+                    continue;
+                }
+                let remote_name = format!("{}/{}", root, name);
+                if !run_upload(
+                    &service,
+                    local.display().to_string(),
+                    Some(remote_name),
+                    force,
+                )
+                .await?
+                {
+                    break;
+                }
             }
         }
 
         // ===================================================================
         // Download
         // ===================================================================
-        Commands::Download { remote_path } => {
-            println!("📥 Local fetch for: {}", remote_path.cyan());
-
-            let (tx, mut rx) = mpsc::channel(256);
-            let service_handle = service.clone();
-
-            let download_handle =
-                tokio::spawn(async move { service_handle.download_file(&remote_path, tx).await });
-
-            let mut progress_bar: Option<ProgressBar> = None;
-            let mut spinner: Option<ProgressBar> = None;
-
-            while let Some(event) = rx.recv().await {
-                match event.status {
-                    DownloadStatus::Started {
-                        total_size,
-                        total_chunks,
-                        progress,
-                    } => {
-                        println!(
-                            "  {} File: {} in {} chunk(s)",
-                            "📁".cyan(),
-                            human_bytes::human_bytes(total_size as f64).yellow(),
-                            total_chunks.to_string().green()
-                        );
+        Commands::Download {
+            remote_paths,
+            output,
+            force,
+            jobs,
+            recursive,
+            filters,
+        } => {
+            let filter = filters.to_filter(&matches)?;
+            if let [path] = remote_paths.as_slice() {
+                if !is_glob(path) && !recursive {
+                    let destination = download_destination(output.as_deref(), path, true);
+                    run_download(&service, path.clone(), destination, force).await?;
+                    return Ok(());
+                }
+            }
 
-                        if total_size > 256 * 1024 * 1024 {
-                            let pb = create_overall_bar_direct(total_size);
-                            progress_bar = Some(pb.clone());
-
-                            tokio::spawn(async move {
-                                while !pb.is_finished() {
-                                    let current = progress.load(Ordering::Relaxed);
-                                    pb.set_position(current);
-                                    tokio::time::sleep(Duration::from_millis(100)).await;
-                                }
-                            });
-                        } else {
-                            spinner = Some(create_spinner("Fetching to server cache..."));
-                        }
-                    }
-                    DownloadStatus::Merging => {
-                        if let Some(s) = spinner.take() {
-                            s.finish_and_clear();
-                        }
-                        spinner = Some(create_spinner("Merging chunks in cache..."));
+            // Files keep their path below the folder, or their full name.
+            let mut files: Vec<(FileMetadata, std::path::PathBuf)> = Vec::new();
+            for remote_path in &remote_paths {
+                // Filters see names relative to the folder, or full names otherwise.
+                let (lookup, root) = if recursive {
+                    (
+                        service.list_files_with_depth(remote_path, None, true).await,
+                        remote_root(remote_path),
+                    )
+                } else if is_glob(remote_path) {
+                    (service.find_files(remote_path, true).await, String::new())
+                } else {
+                    (
+                        service.get_file(remote_path).await.map(|f| vec![f]),
+                        String::new(),
+                    )
+                };
+                let matched = match lookup {
+                    Ok(f) => f,
+                    Err(e) => {
+                        print_error(&e.to_string());
+                        continue;
                     }
-                    DownloadStatus::Verifying => {
-                        if let Some(s) = spinner.take() {
-                            s.finish_and_clear();
-                        }
-                        spinner = Some(create_spinner("Verifying integrity..."));
-                    }
-                    DownloadStatus::Completed { path } => {
-                        if let Some(pb) = progress_bar.take() {
-                            pb.finish_and_clear();
-                        }
-                        if let Some(s) = spinner.take() {
-                            s.finish_and_clear();
-                        }
-                        print_success(&format!(
-                            "Download completed! File is at:\n  {}",
-                            path.yellow()
-                        ));
-                    }
-                    DownloadStatus::Failed { error } => {
-                        if let Some(pb) = progress_bar.take() {
-                            pb.finish_and_clear();
-                        }
-                        if let Some(s) = spinner.take() {
-                            s.finish_and_clear();
-                        }
-                        print_error(&format!("Download failed: {}", error));
+                };
+                for f in matched {
+                    let name = f
+                        .original_name
+                        .strip_prefix(&root)
+                        .unwrap_or(&f.original_name);
+                    if filter.matches(name) && !files.iter().any(|(g, _)| g.file_id == f.file_id) {
+                        let destination = download_destination(output.as_deref(), name, false);
+                        files.push((f, destination));
                     }
                 }
             }
 
-            if let Err(e) = download_handle.await? {
-                print_error(&e.to_string());
+            match files.len() {
+                0 => println!("No files match {}", remote_paths.join(" ")),
+                1 => {
+                    let (file, destination) = files.remove(0);
+                    run_download(&service, file.original_name, destination, force).await?;
+                }
+                _ => run_downloads(&service, files, force, jobs as usize).await?,
             }
         }
 
         // ===================================================================
         // List
         // ===================================================================
-        Commands::List { folder } => {
+        Commands::List {
+            folder,
+            recursive,
+            depth,
+            limit,
+            cursor,
+            archived,
+        } => {
+            let depth = if recursive {
+                None
+            } else {
+                Some(depth.unwrap_or(1))
+            };
+            let filter = if is_glob(&folder) {
+                FileFilter::Glob(folder.clone())
+            } else {
+                FileFilter::Folder {
+                    folder: folder.clone(),
+                    depth,
+                }
+            };
             let spinner = create_spinner(&format!("Listing files in '{}'...", folder));
-            let files = match service.list_files(&folder).await {
+            let listing = async {
+                let page = service
+                    .list_files_page(&filter, cursor.as_deref(), limit, archived)
+                    .await?;
+                // Folders are only shown with the first page.
+                let folders = if depth == Some(1) && cursor.is_none() && !is_glob(&folder) {
+                    service.list_subfolders(&folder).await?
+                } else {
+                    Vec::new()
+                };
+                Ok::<_, TgCloudError>((page, folders))
+            };
+            let (page, folders) = match listing.await {
+                Ok(l) => {
+                    spinner.finish_and_clear();
+                    l
+                }
+                Err(e) => {
+                    spinner.finish_and_clear();
+                    print_error(&e.to_string());
+                    return Ok(());
+                }
+            };
+
+            if page.files.is_empty() && folders.is_empty() {
+                println!("No files found in '{}'", folder);
+            } else {
+                if !folders.is_empty() {
+                    print_folder_list(folders);
+                }
+                if !page.files.is_empty() {
+                    print_file_list(page.files);
+                }
+            }
+            if let Some(next) = page.next_cursor {
+                print_warning(&format!(
+                    "Showing {} files; continue with --cursor {}",
+                    limit, next
+                ));
+            }
+        }
+
+        // ===================================================================
+        // Tree
+        // ===================================================================
+        Commands::Tree { prefix, archived } => {
+            let spinner = create_spinner(&format!("Listing files in '{}'...", prefix));
+            let files = match service.list_files(&prefix, archived).await {
                 Ok(f) => {
                     spinner.finish_and_clear();
                     f
@@ -252,15 +655,36 @@ async fn main() -> anyhow::Result<()> {
             };
 
             if files.is_empty() {
-                println!("No files found in '{}'", folder);
+                println!("No files found in '{}'", prefix);
             } else {
-                print_file_list(files);
+                let root = if prefix == "root" {
+                    ""
+                } else {
+                    prefix.as_str()
+                };
+                print_file_tree(files, root);
             }
         }
 
         // ===================================================================
         // Rename
         // ===================================================================
+        Commands::Rename { old_path, new_path } if args.dry_run => {
+            match service.get_file(&old_path).await {
+                Ok(file) => match service.get_file(&new_path).await {
+                    Ok(_) => print_error(&format!("Rename failed: '{}' already exists", new_path)),
+                    Err(TgCloudError::FileNotFound(_)) => {
+                        println!(
+                            "Would rename document {}: '{}' -> '{}' (no Telegram messages change)",
+                            file.file_id, old_path, new_path
+                        );
+                        print_dry_run_footer();
+                    }
+                    Err(e) => print_error(&format!("Rename failed: {}", e)),
+                },
+                Err(e) => print_error(&format!("Rename failed: {}", e)),
+            }
+        }
         Commands::Rename { old_path, new_path } => {
             let spinner = create_spinner(&format!("Renaming '{}' to '{}'...", old_path, new_path));
             match service.rename_file(&old_path, &new_path).await {
@@ -275,23 +699,1000 @@ async fn main() -> anyhow::Result<()> {
             }
         }
 
+        // ===================================================================
+        // Move folder
+        // ===================================================================
+        Commands::Mv {
+            old_prefix,
+            new_prefix,
+        } if args.dry_run => {
+            let old_dir = format!("{}/", old_prefix.trim_end_matches('/'));
+            let new_dir = format!("{}/", new_prefix.trim_end_matches('/'));
+            match service.list_files_with_depth(&old_dir, None, true).await {
+                Ok(files) if files.is_empty() => println!("No files under '{}'", old_dir),
+                Ok(files) => {
+                    for file in &files {
+                        let Some(rest) = file.original_name.strip_prefix(&old_dir) else {
+                            continue;
+                        };
+                        println!(
+                            "Would rename document {}: '{}' -> '{}{}'",
+                            file.file_id, file.original_name, new_dir, rest
+                        );
+                    }
+                    print_dry_run_footer();
+                }
+                Err(e) => print_error(&format!("Move failed: {}", e)),
+            }
+        }
+        Commands::Mv {
+            old_prefix,
+            new_prefix,
+        } => {
+            let spinner =
+                create_spinner(&format!("Moving '{}' to '{}'...", old_prefix, new_prefix));
+            let result = service.rename_prefix(&old_prefix, &new_prefix).await;
+            spinner.finish_and_clear();
+            match result {
+                Ok(n) => print_success(&format!(
+                    "Moved {} file(s) from '{}' to '{}'",
+                    n, old_prefix, new_prefix
+                )),
+                Err(e) => print_error(&format!("Move failed: {}", e)),
+            }
+        }
+
         // ===================================================================
         // Delete
         // ===================================================================
         Commands::Delete { path } => {
-            let spinner = create_spinner(&format!("Deleting '{}'...", path));
-            match service.delete_file(&path).await {
-                Ok(_) => {
-                    spinner.finish_and_clear();
-                    print_success(&format!("Deleted '{}' (Telegram & Metadata)", path));
-                }
+            let lookup = if is_glob(&path) {
+                service.find_files(&path, true).await
+            } else {
+                service.get_file(&path).await.map(|f| vec![f])
+            };
+            let files = match lookup {
+                Ok(files) => files,
                 Err(e) => {
-                    spinner.finish_and_clear();
                     print_error(&format!("Delete failed: {}", e));
+                    return Ok(());
+                }
+            };
+            if files.is_empty() {
+                println!("No files match '{}'", path);
+                return Ok(());
+            }
+            if args.dry_run {
+                print_delete_plan(&files);
+                return Ok(());
+            }
+            if !args.yes && !confirm(&format!("Delete {}?", describe_files(&files))) {
+                print_warning("Aborted; nothing was deleted. Pass --yes to skip this prompt.");
+                return Ok(());
+            }
+
+            let spinner = create_spinner(&format!("Deleting {}...", describe_files(&files)));
+            let outcomes = service.delete_files(files).await;
+            spinner.finish_and_clear();
+            let mut deleted = 0;
+            for (target, result) in outcomes {
+                match result {
+                    Ok(()) => {
+                        deleted += 1;
+                        print_success(&format!("Moved '{}' to the trash", target));
+                    }
+                    Err(e) => print_error(&format!("Delete of '{}' failed: {}", target, e)),
+                }
+            }
+            if deleted > 0 {
+                println!(
+                    "   Run `tgcloud undo` to restore {}",
+                    if deleted == 1 { "it" } else { "them" }
+                );
+            }
+        }
+
+        // ===================================================================
+        // Archive
+        // ===================================================================
+        Commands::Archive { path } => {
+            let spinner = create_spinner(&format!("Archiving '{}'...", path));
+            let result = service.archive(&path).await;
+            spinner.finish_and_clear();
+            match result {
+                Ok(0) => println!("'{}' is already archived", path),
+                Ok(n) => print_success(&format!("Archived {} file(s) under '{}'", n, path)),
+                Err(e) => print_error(&format!("Archive failed: {}", e)),
+            }
+        }
+        Commands::Unarchive { path } => {
+            let spinner = create_spinner(&format!("Unarchiving '{}'...", path));
+            let result = service.unarchive(&path).await;
+            spinner.finish_and_clear();
+            match result {
+                Ok(0) => println!("'{}' is not archived", path),
+                Ok(n) => print_success(&format!("Restored {} file(s) under '{}'", n, path)),
+                Err(e) => print_error(&format!("Unarchive failed: {}", e)),
+            }
+        }
+
+        // ===================================================================
+        // Undo / Trash
+        // ===================================================================
+        Commands::Undo => match service.undo().await {
+            Ok(entries) => {
+                for entry in entries {
+                    match entry.target {
+                        Some(target) if entry.action == HistoryAction::Rename => print_success(
+                            &format!("Undid rename: '{}' is back at '{}'", target, entry.path),
+                        ),
+                        _ => print_success(&format!("Undid {} of '{}'", entry.action, entry.path)),
+                    }
+                }
+            }
+            Err(e) => print_error(&format!("Undo failed: {}", e)),
+        },
+        Commands::Trash(TrashCommands::List) => match service.list_trash().await {
+            Ok(files) if files.is_empty() => println!("The trash is empty"),
+            Ok(files) => print_trash_list(files),
+            Err(e) => print_error(&format!("Failed to list trash: {}", e)),
+        },
+        Commands::Trash(TrashCommands::Empty) => {
+            let files: Vec<_> = match service.list_trash().await {
+                Ok(trashed) => trashed.into_iter().map(|t| t.file).collect(),
+                Err(e) => {
+                    print_error(&format!("Failed to list trash: {}", e));
+                    return Ok(());
                 }
+            };
+            if files.is_empty() {
+                println!("The trash is empty");
+                return Ok(());
+            }
+            if args.dry_run {
+                print_delete_plan(&files);
+                return Ok(());
+            }
+            if !args.yes
+                && !confirm(&format!(
+                    "Permanently delete {}? This cannot be undone.",
+                    describe_files(&files)
+                ))
+            {
+                print_warning(
+                    "Aborted; the trash was not emptied. Pass --yes to skip this prompt.",
+                );
+                return Ok(());
+            }
+
+            let spinner = create_spinner("Emptying trash...");
+            let result = service.purge_trash(true).await;
+            spinner.finish_and_clear();
+            match result {
+                Ok(n) => print_success(&format!("Permanently deleted {} file(s)", n)),
+                Err(e) => print_error(&format!("Emptying trash failed: {}", e)),
             }
         }
+
+        // ===================================================================
+        // Send
+        // ===================================================================
+        Commands::Send { path, to } => {
+            println!("📤 Sending {} to {}", path.cyan(), to.cyan());
+
+            let service_handle = service.clone();
+            let target = to.clone();
+            let send_path = path.clone();
+
+            let mut send_handle =
+                tokio::spawn(async move { service_handle.send_file(&path, &target).await });
+
+            let spinner = create_spinner("Forwarding...");
+            let mut cancelled = false;
+            let result = join_or_cancel(&mut send_handle, &service, &mut cancelled).await?;
+            spinner.finish_and_clear();
+            match result {
+                Ok(message_ids) if message_ids.len() == 1 => {
+                    print_success(&format!("Sent to {} (message ID: {})", to, message_ids[0]))
+                }
+                Ok(message_ids) => {
+                    let ids: Vec<String> = message_ids.iter().map(|id| id.to_string()).collect();
+                    print_success(&format!(
+                        "Sent to {} in {} parts (message IDs: {})",
+                        to,
+                        message_ids.len(),
+                        ids.join(", ")
+                    ));
+                    let name = std::path::Path::new(&send_path)
+                        .file_name()
+                        .map(|n| n.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| send_path.clone());
+                    let parts: Vec<String> = (0..message_ids.len())
+                        .map(|i| format!("{}.chunk{}", name, i))
+                        .collect();
+                    println!("   Join them in order: cat {} > {}", parts.join(" "), name);
+                }
+                Err(e) if matches!(e.root(), TgCloudError::Cancelled) => print_warning(&format!(
+                    "Send cancelled. Run `tgcloud send {} --to {}` to try again.",
+                    send_path, to
+                )),
+                Err(e) => print_error(&format!("Send failed: {}", e)),
+            }
+        }
+
+        // ===================================================================
+        // Diff
+        // ===================================================================
+        Commands::Diff {
+            local_dir,
+            remote_prefix,
+        } => {
+            let spinner = create_spinner(&format!(
+                "Comparing '{}' with '{}'...",
+                local_dir.display(),
+                remote_prefix
+            ));
+            let result = service.diff_dir(&local_dir, &remote_prefix).await;
+            spinner.finish_and_clear();
+            match result {
+                Ok(diff) => print_diff(&diff),
+                Err(e) => print_error(&format!("Diff failed: {}", e)),
+            }
+        }
+
+        // ===================================================================
+        // Sync
+        // ===================================================================
+        Commands::Sync {
+            local_dir,
+            remote_prefix,
+            pull,
+            delete,
+            plan,
+            filters,
+        } => {
+            let filter = filters.to_filter(&matches)?;
+            let direction = if pull {
+                SyncDirection::Pull
+            } else {
+                SyncDirection::Push
+            };
+            let spinner = create_spinner("Planning sync...");
+            let result = service
+                .plan_sync(&local_dir, &remote_prefix, direction, delete, &filter)
+                .await;
+            spinner.finish_and_clear();
+            let sync_plan = match result {
+                Ok(p) => p,
+                Err(e) => {
+                    print_error(&format!("Sync failed: {}", e));
+                    return Ok(());
+                }
+            };
+            if sync_plan.is_empty() {
+                print_success("Already in sync");
+                return Ok(());
+            }
+
+            if plan || args.dry_run {
+                print_sync_plan(&sync_plan);
+                if args.dry_run {
+                    print_dry_run_footer();
+                    return Ok(());
+                }
+                if !args.yes && !confirm("Execute this plan?") {
+                    print_warning("Aborted; nothing was changed.");
+                    return Ok(());
+                }
+            } else if sync_plan.deletions().0 > 0 && !args.yes {
+                print_sync_plan(&sync_plan);
+                if !confirm("This sync deletes files. Continue?") {
+                    print_warning("Aborted; nothing was changed. Pass --yes to skip this prompt.");
+                    return Ok(());
+                }
+            }
+
+            let total = sync_plan.actions.len();
+            let mut failed = 0;
+            for (i, action) in sync_plan.actions.iter().enumerate() {
+                let label = describe_sync_action(action);
+                let spinner = create_spinner(&format!("[{}/{}] {}", i + 1, total, label));
+                let result = service.apply_sync_action(action).await;
+                spinner.finish_and_clear();
+                match result {
+                    Ok(()) => print_success(&label),
+                    Err(e) => {
+                        failed += 1;
+                        print_error(&format!("{} failed: {}", label, e));
+                    }
+                }
+            }
+            if failed > 0 {
+                print_warning(&format!("{} of {} sync steps failed", failed, total));
+            }
+        }
+
+        // ===================================================================
+        // Search
+        // ===================================================================
+        Commands::Search {
+            query,
+            prefix,
+            fuzzy,
+            limit,
+            archived,
+        } => {
+            let mode = if prefix {
+                SearchMode::Prefix
+            } else if fuzzy {
+                SearchMode::Fuzzy
+            } else {
+                SearchMode::Substring
+            };
+            let spinner = create_spinner(&format!("Searching for '{}'...", query));
+            let results = service.search(&query, mode, limit, archived).await;
+            spinner.finish_and_clear();
+            match results {
+                Ok(files) if files.is_empty() => println!("No files match '{}'", query),
+                Ok(files) => print_file_list(files),
+                Err(e) => print_error(&format!("Search failed: {}", e)),
+            }
+        }
+
+        // ===================================================================
+        // Stats / du
+        // ===================================================================
+        Commands::Stats { folder } => {
+            let spinner = create_spinner(&format!("Computing statistics for '{}'...", folder));
+            let filter = FileFilter::Folder {
+                folder: folder.clone(),
+                depth: None,
+            };
+            let stats = service.storage_stats(&filter).await;
+            spinner.finish_and_clear();
+            match stats {
+                Ok(stats) if stats.file_count == 0 => println!("No files found in '{}'", folder),
+                Ok(stats) => print_stats(&stats),
+                Err(e) => print_error(&format!("Failed to compute statistics: {}", e)),
+            }
+        }
+        Commands::Du { folder } => {
+            let spinner = create_spinner(&format!("Computing disk usage of '{}'...", folder));
+            let usage = async {
+                let folders = service.list_subfolders(&folder).await?;
+                let loose = service
+                    .storage_stats(&FileFilter::Folder {
+                        folder: folder.clone(),
+                        depth: Some(1),
+                    })
+                    .await?;
+                Ok::<_, TgCloudError>((folders, loose))
+            };
+            let usage = usage.await;
+            spinner.finish_and_clear();
+            match usage {
+                Ok((folders, loose)) if folders.is_empty() && loose.file_count == 0 => {
+                    println!("No files found in '{}'", folder)
+                }
+                Ok((folders, loose)) => print_du(folders, loose.file_count, loose.total_bytes),
+                Err(e) => print_error(&format!("Failed to compute disk usage: {}", e)),
+            }
+        }
+
+        // ===================================================================
+        // Checksums
+        // ===================================================================
+        Commands::Checksums { prefix, archived } => {
+            let mut files = service
+                .list_files_with_depth(&prefix, None, archived)
+                .await?;
+            files.sort_by(|a, b| a.original_name.cmp(&b.original_name));
+
+            let root = remote_root(&prefix);
+            for file in files {
+                let name = file
+                    .original_name
+                    .strip_prefix(&root)
+                    .unwrap_or(&file.original_name);
+                println!("{}", sha256sum_line(&file.sha256, name));
+            }
+        }
+
+        // ===================================================================
+        // History
+        // ===================================================================
+        Commands::History { file, since, limit } => {
+            let since = match since.map(chrono::Duration::from_std).transpose() {
+                Ok(since) => since.map(|d| chrono::Utc::now() - d),
+                Err(_) => anyhow::bail!("--since is too far in the past"),
+            };
+            let query = HistoryQuery {
+                path: file,
+                since,
+                limit: Some(limit),
+            };
+            match service.history(&query).await {
+                Ok(entries) if entries.is_empty() => println!("No history entries found"),
+                Ok(entries) => print_history(entries),
+                Err(e) => print_error(&format!("Failed to read history: {}", e)),
+            }
+        }
+
+        // ===================================================================
+        // Bots
+        // ===================================================================
+        Commands::EncryptMetadata => {
+            let spinner = create_spinner("Encrypting metadata...");
+            let result = service.encrypt_metadata().await;
+            spinner.finish_and_clear();
+            match result {
+                Ok(0) => println!("Everything is already encrypted"),
+                Ok(count) => print_success(&format!("Encrypted {} documents", count)),
+                Err(e) => print_error(&format!("Failed to encrypt metadata: {}", e)),
+            }
+        }
+        Commands::Recover { .. } => {
+            let spinner = create_spinner("Reading the metadata manifest from the storage chat...");
+            let result = service.recover_from_chat().await;
+            spinner.finish_and_clear();
+            match result {
+                Ok(report) => {
+                    print_success(&format!(
+                        "Restored {} files from the manifest written {}",
+                        report.restored,
+                        report
+                            .written_at
+                            .with_timezone(&chrono::Local)
+                            .format("%Y-%m-%d %H:%M")
+                    ));
+                    if report.skipped > 0 {
+                        println!("  {} files were already present", report.skipped);
+                    }
+                }
+                Err(e) => print_error(&format!("Recovery failed: {}", e)),
+            }
+        }
+        Commands::Bot(BotCommands::List) => match service.list_bots().await {
+            Ok(bots) => print_bot_list(bots),
+            Err(e) => print_error(&format!("Failed to list bots: {}", e)),
+        },
+        Commands::Bot(BotCommands::Status) => match service.rate_limit_status().await {
+            Ok(status) => print_rate_limits(status),
+            Err(e) => print_error(&format!("Failed to read rate limit status: {}", e)),
+        },
+        Commands::Bot(BotCommands::Bench { size_mib }) => {
+            let spinner = create_spinner(&format!(
+                "Benchmarking {} bot(s) with a {} MiB payload...",
+                service.bots().active_bots().len(),
+                size_mib
+            ));
+            let result = service.bench_bots(size_mib * 1024 * 1024).await;
+            spinner.finish_and_clear();
+            match result {
+                Ok(results) => print_benchmarks(results),
+                Err(e) => print_error(&format!("Benchmark failed: {}", e)),
+            }
+        }
+        Commands::Bot(BotCommands::Enable { bot_id }) => match service.enable_bot(&bot_id).await {
+            Ok(_) => print_success(&format!("Bot {} re-enabled", bot_id)),
+            Err(e) => print_error(&format!("Enable failed: {}", e)),
+        },
+        Commands::Bot(BotCommands::RotateToken { bot_id, new_token }) => {
+            let spinner = create_spinner("Checking the new token...");
+            let result = service.rotate_bot_token(&bot_id, &new_token).await;
+            spinner.finish_and_clear();
+            if let Err(e) = result {
+                print_error(&format!("Token rotation failed: {}", e));
+            } else {
+                match tgcloud_core::set_bot_token_in_file(None, &bot_id, new_token.trim()) {
+                    Ok(path) => {
+                        print_success(&format!(
+                            "Rotated the token of bot {} in {}",
+                            bot_id,
+                            path.display()
+                        ));
+                        print_warning(
+                            "Restart a running server or daemon so it picks up the new token",
+                        );
+                    }
+                    Err(e) => print_error(&format!("Failed to save the new token: {}", e)),
+                }
+            }
+        }
+        // ===================================================================
+        // API keys
+        // ===================================================================
+        Commands::Apikey(ApikeyCommands::Create { name, scope }) => {
+            match service.create_api_key(&name, scope).await {
+                Ok((key, secret)) => {
+                    print_success(&format!(
+                        "Created {} key '{}' ({})",
+                        key.scope, key.name, key.key_id
+                    ));
+                    println!("\n  {}\n", secret.bold());
+                    print_warning("Store it now: it cannot be shown again");
+                }
+                Err(e) => print_error(&format!("Failed to create key: {}", e)),
+            }
+        }
+        Commands::Apikey(ApikeyCommands::List) => match service.list_api_keys().await {
+            Ok(keys) if keys.is_empty() => println!("No API keys"),
+            Ok(keys) => print_api_keys(keys),
+            Err(e) => print_error(&format!("Failed to list keys: {}", e)),
+        },
+        Commands::Apikey(ApikeyCommands::Revoke { key_id }) => {
+            match service.revoke_api_key(&key_id).await {
+                Ok(true) => print_success(&format!("Revoked key {}", key_id)),
+                Ok(false) => print_error(&format!("No API key {}", key_id)),
+                Err(e) => print_error(&format!("Failed to revoke key: {}", e)),
+            }
+        }
+        Commands::Doctor | Commands::Config(_) | Commands::Daemon(_) | Commands::Top { .. } => {
+            unreachable!("handled before connecting")
+        }
     }
 
     Ok(())
 }
+
+/// Formats one line of `sha256sum` output. Like coreutils, names containing a
+/// backslash or newline are escaped and the line is prefixed with `\`.
+fn sha256sum_line(sha256: &str, name: &str) -> String {
+    if name.contains(['\\', '\n']) {
+        let escaped = name.replace('\\', "\\\\").replace('\n', "\\n");
+        format!("\\{}  {}", sha256, escaped)
+    } else {
+        format!("{}  {}", sha256, name)
+    }
+}
+
+/// Uploads one local file with progress output. Returns `false` if the user
+/// cancelled it.
+async fn run_upload(
+    service: &Arc<TgCloudService>,
+    path: String,
+    remote_name: Option<String>,
+    force: bool,
+) -> anyhow::Result<bool> {
+    println!("🚀 Starting upload for: {}", path.cyan());
+    let (tx, mut rx) = mpsc::channel(256);
+
+    let service_handle = Arc::clone(service);
+    let upload_path = path.clone();
+    let upload_handle = tokio::spawn(async move {
+        service_handle
+            .upload_file(
+                &path,
+                UploadOptions {
+                    force,
+                    remote_name,
+                    ..Default::default()
+                },
+                tx,
+            )
+            .await
+    });
+
+    let mut progress_bar: Option<ProgressBar> = None;
+    let mut spinner: Option<ProgressBar> = None;
+    let mut cancelled = false;
+
+    while let Some(event) = recv_or_cancel(&mut rx, service, &mut cancelled).await {
+        match event.status {
+            UploadStatus::Started {
+                total_size,
+                total_chunks: _,
+                progress,
+            } => {
+                if total_size > 256 * 1024 * 1024 {
+                    let pb = create_overall_bar_direct(total_size);
+                    progress_bar = Some(pb.clone());
+
+                    // Spawn a task to update the progress bar from the atomic counter.
+                    tokio::spawn(async move {
+                        while !pb.is_finished() {
+                            let current = progress.load(Ordering::Relaxed);
+                            pb.set_position(current);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                        }
+                    });
+                } else {
+                    spinner = Some(create_spinner("Uploading..."));
+                }
+            }
+            UploadStatus::Hashing => {
+                if let Some(s) = spinner.take() {
+                    s.finish_and_clear();
+                }
+                spinner = Some(create_spinner("Calculating SHA-256 hash..."));
+            }
+            UploadStatus::HashComplete { sha256 } => {
+                if let Some(s) = spinner.take() {
+                    s.finish_and_clear();
+                }
+                // Rate and ETA should only cover sending, not hashing.
+                if let Some(pb) = &progress_bar {
+                    pb.reset_elapsed();
+                    pb.reset_eta();
+                }
+                println!(
+                    "  {} SHA-256: {}",
+                    "🔒".cyan(),
+                    sha256[..16].to_string().yellow()
+                );
+            }
+            UploadStatus::Resuming { reused_chunks } => println!(
+                "  {} Reusing {} chunk(s) from an interrupted upload",
+                "♻".cyan(),
+                reused_chunks
+            ),
+            UploadStatus::Completed { file_id } => {
+                if let Some(pb) = progress_bar.take() {
+                    pb.finish_and_clear();
+                }
+                if let Some(s) = spinner.take() {
+                    s.finish_and_clear();
+                }
+                print_success(&format!("Upload completed!\n    File ID: {}\n", file_id));
+            }
+            UploadStatus::Failed { error } => {
+                if let Some(pb) = progress_bar.take() {
+                    pb.finish_and_clear();
+                }
+                if let Some(s) = spinner.take() {
+                    s.finish_and_clear();
+                }
+                if !cancelled {
+                    print_error(&format!("Upload failed: {}", error));
+                }
+            }
+        }
+    }
+
+    if let Err(e) = upload_handle.await? {
+        match e.root() {
+            TgCloudError::Cancelled => {
+                if let Some(pb) = progress_bar.take() {
+                    pb.finish_and_clear();
+                }
+                if let Some(s) = spinner.take() {
+                    s.finish_and_clear();
+                }
+                print_warning(&format!(
+                "Upload cancelled; chunks already sent were removed. Run `tgcloud upload {}` to start again.",
+                upload_path
+            ))
+            }
+            TgCloudError::AlreadyExists(target) => print_error(&format!(
+                "{} already exists; use --force to overwrite it",
+                target
+            )),
+            _ => print_error(&e.to_string()),
+        }
+    }
+
+    Ok(!cancelled)
+}
+
+/// Local path a remote file named `name` is downloaded to. A single file goes
+/// to `output` itself unless that is a directory; otherwise `name` (only its
+/// base name for a single file) is placed inside `output`, by default the
+/// current directory. `..` and root components of remote names are dropped,
+/// so nothing is written outside `output`.
+fn download_destination(
+    output: Option<&std::path::Path>,
+    name: &str,
+    single: bool,
+) -> std::path::PathBuf {
+    let dir = match output {
+        Some(out) if single && !out.is_dir() && !name_is_dir(out) => return out.to_path_buf(),
+        Some(out) => out.to_path_buf(),
+        None => std::path::PathBuf::from("."),
+    };
+    let relative: std::path::PathBuf = std::path::Path::new(name)
+        .components()
+        .filter_map(|c| match c {
+            std::path::Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect();
+    match relative.file_name() {
+        Some(base) if single => dir.join(base),
+        _ => dir.join(relative),
+    }
+}
+
+/// Whether `path` was written as a directory, e.g. `backups/`.
+fn name_is_dir(path: &std::path::Path) -> bool {
+    path.as_os_str()
+        .to_string_lossy()
+        .ends_with(std::path::is_separator)
+}
+
+/// Fetches one remote file to `destination` with progress output. Returns
+/// `false` if the user cancelled it.
+async fn run_download(
+    service: &Arc<TgCloudService>,
+    remote_path: String,
+    destination: std::path::PathBuf,
+    force: bool,
+) -> anyhow::Result<bool> {
+    println!("📥 Local fetch for: {}", remote_path.cyan());
+
+    let (tx, mut rx) = mpsc::channel(256);
+    let service_handle = Arc::clone(service);
+
+    let download_path = remote_path.clone();
+    let download_handle = tokio::spawn(async move {
+        service_handle
+            .download_file(
+                &remote_path,
+                DownloadOptions {
+                    force,
+                    destination: Some(destination),
+                    ..Default::default()
+                },
+                tx,
+            )
+            .await
+    });
+
+    let mut progress_bar: Option<ProgressBar> = None;
+    let mut spinner: Option<ProgressBar> = None;
+    let mut cancelled = false;
+
+    while let Some(event) = recv_or_cancel(&mut rx, service, &mut cancelled).await {
+        match event.status {
+            DownloadStatus::Started {
+                total_size,
+                total_chunks,
+                progress,
+            } => {
+                println!(
+                    "  {} File: {} in {} chunk(s)",
+                    "📁".cyan(),
+                    human_bytes::human_bytes(total_size as f64).yellow(),
+                    total_chunks.to_string().green()
+                );
+
+                if total_size > 256 * 1024 * 1024 {
+                    let pb = create_overall_bar_direct(total_size);
+                    progress_bar = Some(pb.clone());
+
+                    tokio::spawn(async move {
+                        while !pb.is_finished() {
+                            let current = progress.load(Ordering::Relaxed);
+                            pb.set_position(current);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                        }
+                    });
+                } else {
+                    spinner = Some(create_spinner("Fetching to server cache..."));
+                }
+            }
+            DownloadStatus::Merging => {
+                if let Some(s) = spinner.take() {
+                    s.finish_and_clear();
+                }
+                spinner = Some(create_spinner("Merging chunks in cache..."));
+            }
+            DownloadStatus::Verifying => {
+                if let Some(s) = spinner.take() {
+                    s.finish_and_clear();
+                }
+                spinner = Some(create_spinner("Verifying integrity..."));
+            }
+            DownloadStatus::Completed { path } => {
+                if let Some(pb) = progress_bar.take() {
+                    pb.finish_and_clear();
+                }
+                if let Some(s) = spinner.take() {
+                    s.finish_and_clear();
+                }
+                print_success(&format!(
+                    "Download completed! File is at:\n  {}",
+                    path.yellow()
+                ));
+            }
+            DownloadStatus::Failed { error } => {
+                if let Some(pb) = progress_bar.take() {
+                    pb.finish_and_clear();
+                }
+                if let Some(s) = spinner.take() {
+                    s.finish_and_clear();
+                }
+                if !cancelled {
+                    print_error(&format!("Download failed: {}", error));
+                }
+            }
+        }
+    }
+
+    if let Err(e) = download_handle.await? {
+        match e.root() {
+            TgCloudError::Cancelled => {
+                if let Some(pb) = progress_bar.take() {
+                    pb.finish_and_clear();
+                }
+                if let Some(s) = spinner.take() {
+                    s.finish_and_clear();
+                }
+                print_warning(&format!(
+                "Download cancelled; partial files were removed. Run `tgcloud download {}` to start again.",
+                download_path
+            ))
+            }
+            TgCloudError::AlreadyExists(target) => print_error(&format!(
+                "{} already exists; use --force to overwrite it",
+                target
+            )),
+            _ => print_error(&e.to_string()),
+        }
+    }
+
+    Ok(!cancelled)
+}
+
+/// Downloads several files, each to its destination, at most `jobs` at a
+/// time, with an overall progress bar and one bar per file. Ctrl-C cancels
+/// all of them.
+async fn run_downloads(
+    service: &Arc<TgCloudService>,
+    files: Vec<(FileMetadata, std::path::PathBuf)>,
+    force: bool,
+    jobs: usize,
+) -> anyhow::Result<()> {
+    let multi = MultiProgress::new();
+    let total: u64 = files.iter().map(|(f, _)| f.size).sum();
+    let overall = multi.add(create_overall_bar_direct(total));
+    let permits = Arc::new(Semaphore::new(jobs));
+
+    let mut bars = Vec::with_capacity(files.len());
+    let mut tasks = FuturesUnordered::new();
+    for (file, destination) in files {
+        let bar = multi.add(create_file_bar(&file.original_name, file.size));
+        bars.push(bar.clone());
+        let service = Arc::clone(service);
+        let permits = Arc::clone(&permits);
+
+        tasks.push(tokio::spawn(async move {
+            let name = file.original_name;
+            let Ok(_permit) = permits.acquire_owned().await else {
+                return (name, Err(TgCloudError::Cancelled));
+            };
+            let (tx, mut rx) = mpsc::channel(16);
+            let download_service = Arc::clone(&service);
+            let download_name = name.clone();
+            let handle = tokio::spawn(async move {
+                let options = DownloadOptions {
+                    force,
+                    destination: Some(destination),
+                    ..Default::default()
+                };
+                download_service
+                    .download_file(&download_name, options, tx)
+                    .await
+            });
+
+            let mut progress = None;
+            let mut tick = tokio::time::interval(Duration::from_millis(100));
+            loop {
+                tokio::select! {
+                    event = rx.recv() => match event.map(|e| e.status) {
+                        Some(DownloadStatus::Started { progress: p, .. }) => progress = Some(p),
+                        Some(DownloadStatus::Merging) => bar.set_message(format!("{} (merging)", name)),
+                        Some(DownloadStatus::Verifying) => bar.set_message(format!("{} (verifying)", name)),
+                        Some(_) => {}
+                        None => break,
+                    },
+                    _ = tick.tick() => {
+                        if let Some(p) = &progress {
+                            bar.set_position(p.load(Ordering::Relaxed));
+                        }
+                    }
+                }
+            }
+
+            let result = handle
+                .await
+                .unwrap_or_else(|e| Err(TgCloudError::Unknown(format!("Task panicked: {}", e))));
+            match &result {
+                Ok(()) => {
+                    bar.set_position(bar.length().unwrap_or(0));
+                    bar.finish_with_message(format!("{} ✓", name));
+                }
+                Err(_) => bar.abandon_with_message(format!("{} ✗", name)),
+            }
+            (name, result)
+        }));
+    }
+
+    let mut tick = tokio::time::interval(Duration::from_millis(100));
+    let mut cancelled = false;
+    let mut failures = Vec::new();
+    let mut completed = 0;
+    while !tasks.is_empty() {
+        tokio::select! {
+            Some(joined) = tasks.next() => match joined {
+                Ok((_, Ok(()))) => completed += 1,
+                Ok((name, Err(e))) => failures.push((name, e)),
+                Err(e) => failures.push((String::new(), TgCloudError::Unknown(e.to_string()))),
+            },
+            _ = tick.tick() => overall.set_position(bars.iter().map(|b| b.position()).sum()),
+            _ = tokio::signal::ctrl_c() => {
+                permits.close();
+                interrupt(service, &mut cancelled);
+            }
+        }
+    }
+    overall.finish_and_clear();
+
+    for (name, e) in &failures {
+        match e.root() {
+            TgCloudError::Cancelled => {}
+            TgCloudError::AlreadyExists(target) => print_error(&format!(
+                "{} already exists; use --force to overwrite it",
+                target
+            )),
+            _ => print_error(&format!("{}: {}", name, e)),
+        }
+    }
+    if cancelled {
+        print_warning(&format!(
+            "Downloads cancelled after {} of {} files; partial files were removed.",
+            completed,
+            bars.len()
+        ));
+    } else if failures.is_empty() {
+        print_success(&format!("Downloaded {} files", completed));
+    } else {
+        print_warning(&format!(
+            "{} of {} downloads failed",
+            failures.len(),
+            bars.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Handles Ctrl-C during a transfer: the first one cancels the running
+/// transfers through the service so they can clean up, a second one exits
+/// right away.
+fn interrupt(service: &TgCloudService, cancelled: &mut bool) {
+    if *cancelled {
+        print_warning("Interrupted again, exiting without cleaning up");
+        std::process::exit(130);
+    }
+    *cancelled = true;
+    print_warning("Cancelling, cleaning up... (Ctrl-C again to exit now)");
+    service.cancel_transfers();
+}
+
+/// Awaits a spawned task, cancelling the service's transfers on Ctrl-C.
+async fn join_or_cancel<T>(
+    handle: &mut tokio::task::JoinHandle<T>,
+    service: &TgCloudService,
+    cancelled: &mut bool,
+) -> std::result::Result<T, tokio::task::JoinError> {
+    loop {
+        tokio::select! {
+            result = &mut *handle => return result,
+            _ = tokio::signal::ctrl_c() => interrupt(service, cancelled),
+        }
+    }
+}
+
+/// Receives the next transfer event, handling Ctrl-C with [`interrupt`];
+/// after the first one events keep flowing until the transfer has stopped.
+async fn recv_or_cancel<T>(
+    rx: &mut mpsc::Receiver<T>,
+    service: &TgCloudService,
+    cancelled: &mut bool,
+) -> Option<T> {
+    loop {
+        tokio::select! {
+            event = rx.recv() => return event,
+            _ = tokio::signal::ctrl_c() => interrupt(service, cancelled),
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! `tgcloud top`: a live view of the transfers running in the server started
+//! with `--gui` (or `tgcloud daemon install`), built from its REST API.
+
+use chrono::Utc;
+use comfy_table::{presets::UTF8_FULL, Attribute, Cell, Color, Table};
+use console::{style, Term};
+use human_bytes::human_bytes;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tgcloud_core::{ChunkState, RateLimitStatus, TransferOverview, TransferStatus};
+use tgcloud_server::ServerConfig;
+
+/// Per-bot speeds are averaged over chunks finished this long ago at most.
+const RECENT_WINDOW_SECS: i64 = 60;
+
+/// Where the server listens and how to authenticate, from `SERVER_BIND`,
+/// `SERVER_TLS_*` and `SERVER_AUTH_TOKEN` unless overridden.
+pub struct Target {
+    pub url: String,
+    pub token: Option<String>,
+}
+
+impl Target {
+    pub fn new(config: &ServerConfig, url: Option<String>, token: Option<String>) -> Self {
+        let url = url.unwrap_or_else(|| {
+            let scheme = if config.is_tls() { "https" } else { "http" };
+            // A server listening on every interface is reachable over loopback.
+            let ip = match config.bind.ip() {
+                std::net::IpAddr::V4(ip) if ip.is_unspecified() => {
+                    std::net::Ipv4Addr::LOCALHOST.into()
+                }
+                std::net::IpAddr::V6(ip) if ip.is_unspecified() => {
+                    std::net::Ipv6Addr::LOCALHOST.into()
+                }
+                ip => ip,
+            };
+            format!(
+                "{}://{}",
+                scheme,
+                std::net::SocketAddr::new(ip, config.bind.port())
+            )
+        });
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            token: token.or_else(|| config.auth_token.clone()),
+        }
+    }
+}
+
+struct Snapshot {
+    transfers: TransferOverview,
+    bots: Vec<RateLimitStatus>,
+}
+
+/// Redraws the view every `interval` until Ctrl-C. A server that cannot be
+/// reached is reported on screen and polled again.
+pub async fn run(target: Target, interval: Duration) -> anyhow::Result<()> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let term = Term::stdout();
+    term.hide_cursor()?;
+    let result = tokio::select! {
+        result = refresh_loop(&client, &target, &term, interval) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+    term.show_cursor()?;
+    result
+}
+
+async fn refresh_loop(
+    client: &reqwest::Client,
+    target: &Target,
+    term: &Term,
+    interval: Duration,
+) -> anyhow::Result<()> {
+    loop {
+        let screen = match fetch(client, target).await {
+            Ok(snapshot) => render(&snapshot, target),
+            Err(e) => format!(
+                "{}\n\n  {} Cannot reach the server at {}: {}\n  Retrying every {}s...\n",
+                header(target),
+                style("⚠").yellow(),
+                target.url,
+                e,
+                interval.as_secs().max(1)
+            ),
+        };
+        term.clear_screen()?;
+        term.write_str(&screen)?;
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn fetch(client: &reqwest::Client, target: &Target) -> anyhow::Result<Snapshot> {
+    let get = |path: &str| {
+        let request = client.get(format!("{}{}", target.url, path));
+        match &target.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    };
+    let transfers = get("/api/v1/transfers")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let bots = get("/api/v1/ratelimits")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(Snapshot { transfers, bots })
+}
+
+fn header(target: &Target) -> String {
+    format!(
+        "{} {}  {}",
+        style(" tgcloud top ").bold().white().on_blue(),
+        style(&target.url).dim(),
+        style(chrono::Local::now().format("%H:%M:%S")).dim()
+    )
+}
+
+fn render(snapshot: &Snapshot, target: &Target) -> String {
+    let active = &snapshot.transfers.active;
+    let count = |state: ChunkState| -> usize {
+        active
+            .iter()
+            .flat_map(|t| &t.chunks)
+            .filter(|c| c.state == state)
+            .count()
+    };
+    let speed: u64 = active.iter().map(|t| t.bytes_per_sec).sum();
+
+    let mut screen = header(target);
+    screen.push_str(&format!(
+        "\n\n  Transfers: {}   Queued chunks: {}   In flight: {}   Total: {}/s\n\n",
+        style(active.len()).bold(),
+        style(count(ChunkState::Pending)).bold(),
+        style(count(ChunkState::Running)).bold(),
+        style(human_bytes(speed as f64)).bold()
+    ));
+    if active.is_empty() {
+        screen.push_str("  No transfers running.\n\n");
+    } else {
+        screen.push_str(&transfer_table(active).to_string());
+        screen.push_str("\n\n");
+    }
+    screen.push_str(&bot_table(snapshot).to_string());
+    screen.push_str(&format!("\n\n  {}\n", style("Ctrl-C to quit").dim()));
+    screen
+}
+
+fn transfer_table(transfers: &[TransferStatus]) -> Table {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_header(vec![
+        Cell::new("Transfer")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("Kind")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("Path")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+        Cell::new("Progress")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Yellow),
+        Cell::new("Speed")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Blue),
+        Cell::new("Chunks")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Magenta),
+    ]);
+    for t in transfers {
+        let percent = (t.transferred * 100).checked_div(t.total_size).unwrap_or(0);
+        let done = t
+            .chunks
+            .iter()
+            .filter(|c| matches!(c.state, ChunkState::Done | ChunkState::Reused))
+            .count();
+        let running = t
+            .chunks
+            .iter()
+            .filter(|c| c.state == ChunkState::Running)
+            .count();
+        table.add_row(vec![
+            Cell::new(t.info.id.get(..8).unwrap_or(&t.info.id)),
+            Cell::new(format!("{:?}", t.info.kind).to_lowercase()),
+            Cell::new(&t.info.path),
+            Cell::new(format!(
+                "{:>3}% {}/{}",
+                percent,
+                human_bytes(t.transferred as f64),
+                human_bytes(t.total_size as f64)
+            )),
+            Cell::new(format!("{}/s", human_bytes(t.bytes_per_sec as f64))),
+            Cell::new(format!("{}/{} ({} running)", done, t.chunks.len(), running)),
+        ]);
+    }
+    table
+}
+
+#[derive(Default)]
+struct BotActivity {
+    running: usize,
+    recent_bytes: u64,
+}
+
+fn bot_table(snapshot: &Snapshot) -> Table {
+    let since = Utc::now() - chrono::Duration::seconds(RECENT_WINDOW_SECS);
+    let mut activity: BTreeMap<&str, BotActivity> = BTreeMap::new();
+    let transfers = &snapshot.transfers;
+    for chunk in transfers
+        .active
+        .iter()
+        .chain(&transfers.recent)
+        .flat_map(|t| &t.chunks)
+    {
+        let Some(bot_id) = chunk.bot_id.as_deref() else {
+            continue;
+        };
+        let entry = activity.entry(bot_id).or_default();
+        match chunk.state {
+            ChunkState::Running => entry.running += 1,
+            ChunkState::Done if chunk.finished_at.is_some_and(|t| t >= since) => {
+                entry.recent_bytes += chunk.size
+            }
+            _ => {}
+        }
+    }
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_header(vec![
+        Cell::new("Bot ID")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("State")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+        Cell::new("Chunks in flight")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Yellow),
+        Cell::new(format!("Last {}s", RECENT_WINDOW_SECS))
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Blue),
+        Cell::new("Throughput")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Blue),
+        Cell::new("Concurrency")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Magenta),
+    ]);
+    for bot in &snapshot.bots {
+        let state = if !bot.active {
+            Cell::new("inactive").fg(Color::Red)
+        } else if bot.cooldown_remaining_secs > 0 {
+            Cell::new(format!("throttled, {}s left", bot.cooldown_remaining_secs)).fg(Color::Yellow)
+        } else {
+            Cell::new("ready").fg(Color::Green)
+        };
+        let seen = activity.get(bot.bot_id.as_str());
+        let recent = seen.map_or(0, |a| a.recent_bytes) / RECENT_WINDOW_SECS as u64;
+        let throughput = if bot.throughput_bytes_per_sec == 0 {
+            "-".to_string()
+        } else {
+            format!("{}/s", human_bytes(bot.throughput_bytes_per_sec as f64))
+        };
+        table.add_row(vec![
+            Cell::new(&bot.bot_id),
+            state,
+            Cell::new(seen.map_or(0, |a| a.running)),
+            Cell::new(format!("{}/s", human_bytes(recent as f64))),
+            Cell::new(throughput),
+            Cell::new(format!("{}/{}", bot.concurrency, bot.max_concurrency)),
+        ]);
+    }
+    table
+}
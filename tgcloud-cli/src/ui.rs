@@ -2,6 +2,7 @@ use comfy_table::{presets::UTF8_FULL, Attribute, Cell, Color, Table};
 use console::{style, Emoji};
 use human_bytes::human_bytes;
 use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::BTreeMap;
 use std::time::Duration;
 
 // ---------------------------------------------------------------------------
@@ -23,6 +24,32 @@ pub fn print_error(message: &str) {
     eprintln!("{} {}", Emoji("❌", "Error"), style(message).red());
 }
 
+pub fn print_warning(message: &str) {
+    eprintln!("{} {}", Emoji("⚠️ ", "Warning"), style(message).yellow());
+}
+
+/// Asks the user to confirm a destructive action. Returns `false` when they
+/// decline or no terminal is attached to answer.
+pub fn confirm(prompt: &str) -> bool {
+    dialoguer::Confirm::new()
+        .with_prompt(prompt)
+        .default(false)
+        .interact()
+        .unwrap_or(false)
+}
+
+/// One-line summary of what a destructive command is about to remove.
+pub fn describe_files(files: &[tgcloud_core::FileMetadata]) -> String {
+    let chunks: usize = files.iter().map(|f| f.chunks.len()).sum();
+    let bytes: u64 = files.iter().map(|f| f.size).sum();
+    format!(
+        "{} file(s), {} chunk(s), {}",
+        files.len(),
+        chunks,
+        human_bytes(bytes as f64)
+    )
+}
+
 // ---------------------------------------------------------------------------
 // Spinners
 // ---------------------------------------------------------------------------
@@ -58,6 +85,19 @@ pub fn create_overall_bar_direct(total_size: u64) -> ProgressBar {
     pb
 }
 
+/// Per-file bar shown below the overall bar of a multi-file transfer.
+pub fn create_file_bar(name: &str, size: u64) -> ProgressBar {
+    let pb = ProgressBar::new(size);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("   [{bar:25.green/white}] {bytes:>10}/{total_bytes:<10} {msg}")
+            .expect("invalid bar template")
+            .progress_chars("█▓░"),
+    );
+    pb.set_message(name.to_string());
+    pb
+}
+
 // ---------------------------------------------------------------------------
 // File listing table
 // ---------------------------------------------------------------------------
@@ -86,8 +126,13 @@ pub fn print_file_list(files: Vec<tgcloud_core::FileMetadata>) {
     ]);
 
     for file in files {
+        let name = if file.is_archived() {
+            Cell::new(format!("{} (archived)", file.original_name)).fg(Color::DarkGrey)
+        } else {
+            Cell::new(&file.original_name)
+        };
         table.add_row(vec![
-            Cell::new(&file.original_name),
+            name,
             Cell::new(human_bytes(file.size as f64)),
             Cell::new(format!("{}", file.total_chunks)),
             Cell::new(file.created_at.to_rfc3339()),
@@ -97,3 +142,823 @@ pub fn print_file_list(files: Vec<tgcloud_core::FileMetadata>) {
 
     println!("{table}");
 }
+
+pub fn print_folder_list(folders: Vec<tgcloud_core::FolderSummary>) {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+    table.set_header(vec![
+        Cell::new("Folder")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("Files")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Yellow),
+        Cell::new("Size")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+    ]);
+
+    for folder in folders {
+        table.add_row(vec![
+            Cell::new(format!("{}/", folder.name)),
+            Cell::new(folder.file_count),
+            Cell::new(human_bytes(folder.total_size as f64)),
+        ]);
+    }
+
+    println!("{table}");
+}
+
+/// Prints `du`-style usage: each subfolder (largest first), the files
+/// directly inside the folder and a total.
+pub fn print_du(mut folders: Vec<tgcloud_core::FolderSummary>, loose_files: u64, loose_bytes: u64) {
+    folders.sort_by_key(|f| std::cmp::Reverse(f.total_size));
+    let total_files = loose_files + folders.iter().map(|f| f.file_count).sum::<u64>();
+    let total_bytes = loose_bytes + folders.iter().map(|f| f.total_size).sum::<u64>();
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+    table.set_header(vec![
+        Cell::new("Folder")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("Files")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Yellow),
+        Cell::new("Size")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+        Cell::new("Share")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Magenta),
+    ]);
+
+    let share = |bytes: u64| {
+        if total_bytes == 0 {
+            "-".to_string()
+        } else {
+            format!("{:.1}%", bytes as f64 * 100.0 / total_bytes as f64)
+        }
+    };
+    for folder in folders {
+        table.add_row(vec![
+            Cell::new(format!("{}/", folder.name)),
+            Cell::new(folder.file_count),
+            Cell::new(human_bytes(folder.total_size as f64)),
+            Cell::new(share(folder.total_size)),
+        ]);
+    }
+    if loose_files > 0 {
+        table.add_row(vec![
+            Cell::new("(files here)").fg(Color::DarkGrey),
+            Cell::new(loose_files),
+            Cell::new(human_bytes(loose_bytes as f64)),
+            Cell::new(share(loose_bytes)),
+        ]);
+    }
+    table.add_row(vec![
+        Cell::new("Total").add_attribute(Attribute::Bold),
+        Cell::new(total_files).add_attribute(Attribute::Bold),
+        Cell::new(human_bytes(total_bytes as f64)).add_attribute(Attribute::Bold),
+        Cell::new(""),
+    ]);
+
+    println!("{table}");
+}
+
+// ---------------------------------------------------------------------------
+// Storage statistics
+// ---------------------------------------------------------------------------
+
+pub fn print_stats(stats: &tgcloud_core::StorageStats) {
+    println!(
+        "  {} files, {} chunks, {}",
+        style(stats.file_count).bold(),
+        style(stats.chunk_count).bold(),
+        style(human_bytes(stats.total_bytes as f64)).bold().green()
+    );
+    println!();
+    print_usage_table("Bot ID", "Chunks", &stats.by_bot);
+    print_usage_table("Month", "Files", &stats.by_month);
+    print_quota_table(&stats.quotas);
+}
+
+fn print_quota_table(quotas: &[tgcloud_core::BotQuota]) {
+    if quotas.is_empty() {
+        return;
+    }
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+    table.set_header(vec![
+        Cell::new("Bot ID")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("Stored (all files)")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+        Cell::new("Quota")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Yellow),
+        Cell::new("Used")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Magenta),
+    ]);
+
+    for q in quotas {
+        let (quota, used) = match q.quota_bytes {
+            Some(quota) => {
+                let percent = q.stored_bytes as f64 * 100.0 / quota as f64;
+                let color = if percent >= 100.0 {
+                    Color::Red
+                } else if percent >= 90.0 {
+                    Color::Yellow
+                } else {
+                    Color::Green
+                };
+                (
+                    Cell::new(human_bytes(quota as f64)),
+                    Cell::new(format!("{:.1}%", percent)).fg(color),
+                )
+            }
+            None => (Cell::new("unlimited"), Cell::new("-")),
+        };
+        table.add_row(vec![
+            Cell::new(&q.bot_id),
+            Cell::new(human_bytes(q.stored_bytes as f64)),
+            quota,
+            used,
+        ]);
+    }
+
+    println!("{table}");
+}
+
+fn print_usage_table(key: &str, count: &str, rows: &[tgcloud_core::UsageRow]) {
+    if rows.is_empty() {
+        return;
+    }
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+    table.set_header(vec![
+        Cell::new(key)
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new(count)
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Yellow),
+        Cell::new("Size")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+    ]);
+
+    for row in rows {
+        table.add_row(vec![
+            Cell::new(&row.key),
+            Cell::new(row.count),
+            Cell::new(human_bytes(row.bytes as f64)),
+        ]);
+    }
+
+    println!("{table}");
+}
+
+// ---------------------------------------------------------------------------
+// File tree
+// ---------------------------------------------------------------------------
+
+#[derive(Default)]
+struct TreeNode {
+    size: u64,
+    is_file: bool,
+    children: BTreeMap<String, TreeNode>,
+}
+
+impl TreeNode {
+    fn insert(&mut self, components: &[&str], size: u64) {
+        self.size += size;
+        match components.split_first() {
+            Some((first, rest)) => {
+                let child = self.children.entry(first.to_string()).or_default();
+                if rest.is_empty() {
+                    child.is_file = true;
+                    child.size += size;
+                } else {
+                    child.insert(rest, size);
+                }
+            }
+            None => self.is_file = true,
+        }
+    }
+}
+
+/// Prints files as an indented tree below `prefix`, with aggregate sizes for
+/// every directory.
+pub fn print_file_tree(files: Vec<tgcloud_core::FileMetadata>, prefix: &str) {
+    let mut root = TreeNode::default();
+    for file in &files {
+        let relative = file
+            .original_name
+            .strip_prefix(prefix)
+            .unwrap_or(&file.original_name);
+        let components: Vec<&str> = relative.split('/').filter(|c| !c.is_empty()).collect();
+        root.insert(&components, file.size);
+    }
+
+    let label = if prefix.is_empty() { "/" } else { prefix };
+    println!(
+        "{} {} ({})",
+        Emoji("📁", ""),
+        style(label).bold().cyan(),
+        style(human_bytes(root.size as f64)).green()
+    );
+    print_tree_children(&root, "");
+}
+
+fn print_tree_children(node: &TreeNode, indent: &str) {
+    let count = node.children.len();
+    for (i, (name, child)) in node.children.iter().enumerate() {
+        let last = i + 1 == count;
+        let branch = if last { "└── " } else { "├── " };
+        let size = style(human_bytes(child.size as f64)).green();
+        if child.children.is_empty() {
+            println!("{}{}{} ({})", indent, branch, name, size);
+        } else {
+            println!(
+                "{}{}{} {}/ ({})",
+                indent,
+                branch,
+                Emoji("📁", ""),
+                style(name).bold().cyan(),
+                size
+            );
+            let next = format!("{}{}", indent, if last { "    " } else { "│   " });
+            print_tree_children(child, &next);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Bot listing table
+// ---------------------------------------------------------------------------
+
+pub fn print_bot_list(bots: Vec<tgcloud_core::Bot>) {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+    table.set_header(vec![
+        Cell::new("Bot ID")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("Status")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+        Cell::new("Reason")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Yellow),
+        Cell::new("Bot API server")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Blue),
+    ]);
+
+    for bot in bots {
+        let status = if bot.active {
+            Cell::new("active").fg(Color::Green)
+        } else {
+            Cell::new("inactive").fg(Color::Red)
+        };
+        table.add_row(vec![
+            Cell::new(&bot.bot_id),
+            status,
+            Cell::new(bot.deactivated_reason.as_deref().unwrap_or("")),
+            Cell::new(bot.telegram_api_url.as_deref().unwrap_or("")),
+        ]);
+    }
+
+    println!("{table}");
+}
+
+pub fn print_api_keys(keys: Vec<tgcloud_core::ApiKey>) {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+    table.set_header(vec![
+        Cell::new("Key ID")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("Name")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+        Cell::new("Scope")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Yellow),
+        Cell::new("Created")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Magenta),
+        Cell::new("Last used")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Blue),
+    ]);
+
+    for key in keys {
+        let scope = match key.scope {
+            tgcloud_core::ApiKeyScope::Admin => Cell::new(key.scope).fg(Color::Red),
+            _ => Cell::new(key.scope),
+        };
+        table.add_row(vec![
+            Cell::new(&key.key_id),
+            Cell::new(&key.name),
+            scope,
+            Cell::new(key.created_at.format("%Y-%m-%d %H:%M")),
+            Cell::new(
+                key.last_used_at
+                    .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_else(|| "never".to_string()),
+            ),
+        ]);
+    }
+
+    println!("{table}");
+}
+
+pub fn print_rate_limits(status: Vec<tgcloud_core::RateLimitStatus>) {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+    table.set_header(vec![
+        Cell::new("Bot ID")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("State")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+        Cell::new("429s")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Yellow),
+        Cell::new("Last 429")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Magenta),
+        Cell::new("Throughput")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Blue),
+        Cell::new("Concurrency")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Magenta),
+    ]);
+
+    for bot in &status {
+        let state = if !bot.active {
+            Cell::new("inactive").fg(Color::Red)
+        } else if bot.cooldown_remaining_secs > 0 {
+            Cell::new(format!("throttled, {}s left", bot.cooldown_remaining_secs)).fg(Color::Yellow)
+        } else {
+            Cell::new("ready").fg(Color::Green)
+        };
+        let last = bot
+            .last_rate_limited_at
+            .map(|t| {
+                t.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_else(|| "never".to_string());
+        let throughput = if bot.throughput_bytes_per_sec == 0 {
+            "-".to_string()
+        } else {
+            format!("{}/s", human_bytes(bot.throughput_bytes_per_sec as f64))
+        };
+        table.add_row(vec![
+            Cell::new(&bot.bot_id),
+            state,
+            Cell::new(bot.rate_limit_count),
+            Cell::new(last),
+            Cell::new(throughput),
+            Cell::new(format!("{}/{}", bot.concurrency, bot.max_concurrency)),
+        ]);
+    }
+
+    println!("{table}");
+
+    let throttled = status
+        .iter()
+        .filter(|b| b.active && b.cooldown_remaining_secs > 0)
+        .count();
+    if throttled > 0 {
+        print_warning(&format!(
+            "{} of {} bot(s) are throttled by Telegram; transfers slow down until the cool-down ends",
+            throttled,
+            status.len()
+        ));
+    }
+}
+
+/// Prints the `tgcloud doctor` report. Returns whether no check failed.
+pub fn print_doctor(results: &[tgcloud_core::CheckResult]) -> bool {
+    use tgcloud_core::CheckStatus;
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+    table.set_header(vec![
+        Cell::new("Check")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("Result")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+        Cell::new("Details")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Yellow),
+    ]);
+
+    for check in results {
+        let status = match check.status {
+            CheckStatus::Pass => Cell::new("pass").fg(Color::Green),
+            CheckStatus::Warn => Cell::new("warn").fg(Color::Yellow),
+            CheckStatus::Fail => Cell::new("FAIL").fg(Color::Red),
+        };
+        table.add_row(vec![
+            Cell::new(&check.name),
+            status,
+            Cell::new(&check.detail),
+        ]);
+    }
+
+    println!("{table}");
+
+    let fixes: Vec<_> = results.iter().filter(|c| c.fix.is_some()).collect();
+    if !fixes.is_empty() {
+        println!("\n  {}", style("How to fix:").bold());
+        for check in fixes {
+            println!(
+                "  {} {}: {}",
+                style("→").cyan(),
+                check.name,
+                check.fix.as_deref().unwrap_or_default()
+            );
+        }
+    }
+
+    let failed = results
+        .iter()
+        .filter(|c| c.status == CheckStatus::Fail)
+        .count();
+    println!();
+    if failed == 0 {
+        print_success("All checks passed");
+    } else {
+        print_error(&format!("{} check(s) failed", failed));
+    }
+    failed == 0
+}
+
+/// Prints the findings of `tgcloud config validate` and returns the exit
+/// code: 1 if there are errors, 2 if there are only warnings, 0 otherwise.
+pub fn print_config_issues(path: &std::path::Path, issues: &[tgcloud_core::ConfigIssue]) -> i32 {
+    use tgcloud_core::IssueSeverity;
+
+    println!("  {} {}\n", style("Checked").bold(), path.display());
+    for issue in issues {
+        let message = match &issue.key {
+            Some(key) => format!("{}: {}", key, issue.message),
+            None => issue.message.clone(),
+        };
+        match issue.severity {
+            IssueSeverity::Error => print_error(&message),
+            IssueSeverity::Warning => print_warning(&message),
+        }
+    }
+
+    let errors = issues
+        .iter()
+        .filter(|i| i.severity == IssueSeverity::Error)
+        .count();
+    let warnings = issues.len() - errors;
+    if issues.is_empty() {
+        print_success("Configuration is valid");
+        0
+    } else {
+        println!("\n  {} error(s), {} warning(s)", errors, warnings);
+        if errors > 0 {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+pub fn print_benchmarks(mut results: Vec<tgcloud_core::BotBenchmark>) {
+    results.sort_by(|a, b| b.upload_bytes_per_sec.total_cmp(&a.upload_bytes_per_sec));
+
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+    table.set_header(vec![
+        Cell::new("Bot ID")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("Latency")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Magenta),
+        Cell::new("Upload")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+        Cell::new("Download")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+        Cell::new("Status")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Yellow),
+    ]);
+
+    for b in results {
+        let rate = |r: f64| format!("{}/s", human_bytes(r));
+        match &b.error {
+            None => table.add_row(vec![
+                Cell::new(&b.bot_id),
+                Cell::new(format!("{} ms", b.latency_ms)),
+                Cell::new(rate(b.upload_bytes_per_sec)),
+                Cell::new(rate(b.download_bytes_per_sec)),
+                Cell::new("ok").fg(Color::Green),
+            ]),
+            Some(error) => table.add_row(vec![
+                Cell::new(&b.bot_id),
+                Cell::new("-"),
+                Cell::new("-"),
+                Cell::new("-"),
+                Cell::new(error).fg(Color::Red),
+            ]),
+        };
+    }
+
+    println!("{table}");
+}
+
+// ---------------------------------------------------------------------------
+// History table
+// ---------------------------------------------------------------------------
+
+pub fn print_history(entries: Vec<tgcloud_core::HistoryEntry>) {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+    table.set_header(vec![
+        Cell::new("Time")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Magenta),
+        Cell::new("Action")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("Path")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("Result")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+        Cell::new("Detail")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Yellow),
+    ]);
+
+    for entry in entries {
+        let result = if entry.success {
+            Cell::new("ok").fg(Color::Green)
+        } else {
+            Cell::new("failed").fg(Color::Red)
+        };
+        let detail = match (&entry.target, &entry.detail) {
+            (_, Some(error)) => error.clone(),
+            (Some(target), None) => format!("→ {}", target),
+            (None, None) => String::new(),
+        };
+        table.add_row(vec![
+            Cell::new(
+                entry
+                    .at
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S"),
+            ),
+            Cell::new(entry.action),
+            Cell::new(&entry.path),
+            result,
+            Cell::new(detail),
+        ]);
+    }
+
+    println!("{table}");
+}
+
+// ---------------------------------------------------------------------------
+// Trash table
+// ---------------------------------------------------------------------------
+
+pub fn print_trash_list(files: Vec<tgcloud_core::TrashedFile>) {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+    table.set_header(vec![
+        Cell::new("Name")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("Size")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+        Cell::new("Deleted")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Magenta),
+    ]);
+
+    for trashed in files {
+        table.add_row(vec![
+            Cell::new(&trashed.file.original_name),
+            Cell::new(human_bytes(trashed.file.size as f64)),
+            Cell::new(
+                trashed
+                    .deleted_at
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S"),
+            ),
+        ]);
+    }
+
+    println!("{table}");
+}
+
+// ---------------------------------------------------------------------------
+// Directory diff
+// ---------------------------------------------------------------------------
+
+pub fn print_diff(diff: &tgcloud_core::DirDiff) {
+    use tgcloud_core::DiffKind;
+
+    for entry in &diff.entries {
+        match entry.kind {
+            DiffKind::OnlyLocal => println!("{} {}", style("+").green().bold(), entry.name),
+            DiffKind::OnlyRemote => println!("{} {}", style("-").red().bold(), entry.name),
+            DiffKind::Modified => println!("{} {}", style("~").yellow().bold(), entry.name),
+            DiffKind::Unchanged => {}
+        }
+    }
+
+    if diff.is_clean() {
+        print_success("Local and remote are identical");
+        return;
+    }
+    println!();
+    println!(
+        "{} only local, {} only remote, {} modified, {} unchanged",
+        style(diff.count(DiffKind::OnlyLocal)).green(),
+        style(diff.count(DiffKind::OnlyRemote)).red(),
+        style(diff.count(DiffKind::Modified)).yellow(),
+        diff.count(DiffKind::Unchanged)
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Sync plan
+// ---------------------------------------------------------------------------
+
+/// Short description of one sync step, e.g. `upload photos/a.jpg`.
+pub fn describe_sync_action(action: &tgcloud_core::SyncAction) -> String {
+    use tgcloud_core::SyncAction;
+
+    match action {
+        SyncAction::Upload {
+            remote, replace, ..
+        } => {
+            let verb = if *replace { "Replace" } else { "Upload" };
+            format!("{} {}", verb, remote)
+        }
+        SyncAction::Download { local, replace, .. } => {
+            let verb = if *replace { "Overwrite" } else { "Download" };
+            format!("{} {}", verb, local.display())
+        }
+        SyncAction::DeleteRemote { remote, .. } => format!("Delete remote {}", remote),
+        SyncAction::DeleteLocal { local, .. } => format!("Delete local {}", local.display()),
+    }
+}
+
+/// Itemized list of a sync plan with byte totals, in the spirit of
+/// `rsync --itemize-changes`.
+pub fn print_sync_plan(plan: &tgcloud_core::SyncPlan) {
+    use tgcloud_core::SyncAction;
+
+    for action in &plan.actions {
+        let (marker, name, size) = match action {
+            SyncAction::Upload {
+                remote,
+                size,
+                replace,
+                ..
+            } => (if *replace { ">f~" } else { ">f+" }, remote.clone(), *size),
+            SyncAction::Download {
+                local,
+                size,
+                replace,
+                ..
+            } => (
+                if *replace { "<f~" } else { "<f+" },
+                local.display().to_string(),
+                *size,
+            ),
+            SyncAction::DeleteRemote { remote, size } => ("*del", remote.clone(), *size),
+            SyncAction::DeleteLocal { local, size } => ("*del", local.display().to_string(), *size),
+        };
+        println!(
+            "{:<4} {} {}",
+            style(marker).cyan(),
+            name,
+            style(format!("({})", human_bytes(size as f64))).dim()
+        );
+    }
+
+    let uploads = plan
+        .actions
+        .iter()
+        .filter(|a| matches!(a, SyncAction::Upload { .. }))
+        .count();
+    let downloads = plan
+        .actions
+        .iter()
+        .filter(|a| matches!(a, SyncAction::Download { .. }))
+        .count();
+    let (deletes, deleted_bytes) = plan.deletions();
+    println!();
+    println!(
+        "{} upload(s) ({}), {} download(s) ({}), {} deletion(s) ({})",
+        uploads,
+        human_bytes(plan.upload_bytes() as f64),
+        downloads,
+        human_bytes(plan.download_bytes() as f64),
+        deletes,
+        human_bytes(deleted_bytes as f64)
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Dry runs
+// ---------------------------------------------------------------------------
+
+/// Prints the metadata documents and Telegram messages a delete would remove.
+pub fn print_delete_plan(files: &[tgcloud_core::FileMetadata]) {
+    let mut table = Table::new();
+    table.load_preset(UTF8_FULL);
+    table.set_content_arrangement(comfy_table::ContentArrangement::Dynamic);
+
+    table.set_header(vec![
+        Cell::new("File")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Cyan),
+        Cell::new("Document")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Yellow),
+        Cell::new("Chunk")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Green),
+        Cell::new("Message ID")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Magenta),
+        Cell::new("Bot")
+            .add_attribute(Attribute::Bold)
+            .fg(Color::Blue),
+    ]);
+
+    for file in files {
+        for chunk in &file.chunks {
+            table.add_row(vec![
+                Cell::new(&file.original_name),
+                Cell::new(&file.file_id),
+                Cell::new(chunk.index),
+                Cell::new(chunk.message_id),
+                Cell::new(
+                    chunk
+                        .bot_id
+                        .as_deref()
+                        .or(file.bot_id.as_deref())
+                        .unwrap_or("-"),
+                ),
+            ]);
+        }
+    }
+
+    println!("{table}");
+    print_dry_run_footer();
+}
+
+pub fn print_dry_run_footer() {
+    println!("{}", style("Dry run: nothing was changed.").dim());
+}
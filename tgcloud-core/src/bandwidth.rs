@@ -0,0 +1,242 @@
+use chrono::{Local, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncRead;
+use tokio::time::Sleep;
+
+/// A bandwidth cap that applies between two times of day (local time).
+/// Windows may wrap around midnight, e.g. `23:00-07:00`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BandwidthWindow {
+    /// `None` means the window covers the whole day.
+    pub hours: Option<(NaiveTime, NaiveTime)>,
+    /// Bytes per second, or `None` for unlimited.
+    pub bytes_per_sec: Option<u64>,
+}
+
+impl BandwidthWindow {
+    fn contains(&self, t: NaiveTime) -> bool {
+        match self.hours {
+            None => true,
+            Some((start, end)) if start <= end => start <= t && t < end,
+            Some((start, end)) => t >= start || t < end,
+        }
+    }
+}
+
+/// Time-of-day bandwidth caps, parsed from a spec such as
+/// `08:00-23:00=5MB/s, 23:00-08:00=unlimited`. The first window containing
+/// the current time applies; outside every window transfers are unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct BandwidthSchedule {
+    pub windows: Vec<BandwidthWindow>,
+}
+
+impl BandwidthSchedule {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let windows = spec
+            .split([',', ';'])
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(parse_window)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { windows })
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.windows.iter().all(|w| w.bytes_per_sec.is_none())
+    }
+
+    /// The cap in bytes per second at time `t`, or `None` if unlimited.
+    pub fn limit_at(&self, t: NaiveTime) -> Option<u64> {
+        self.windows
+            .iter()
+            .find(|w| w.contains(t))
+            .and_then(|w| w.bytes_per_sec)
+    }
+}
+
+impl TryFrom<String> for BandwidthSchedule {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        Self::parse(&spec)
+    }
+}
+
+impl From<BandwidthSchedule> for String {
+    fn from(schedule: BandwidthSchedule) -> Self {
+        schedule.to_string()
+    }
+}
+
+impl fmt::Display for BandwidthSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, w) in self.windows.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            if let Some((start, end)) = w.hours {
+                write!(f, "{}-{}=", start.format("%H:%M"), end.format("%H:%M"))?;
+            }
+            match w.bytes_per_sec {
+                Some(rate) => write!(f, "{}B/s", rate)?,
+                None => f.write_str("unlimited")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses `HH:MM-HH:MM=RATE` or a bare `RATE` that applies all day.
+fn parse_window(spec: &str) -> Result<BandwidthWindow, String> {
+    let (hours, rate) = match spec.split_once('=') {
+        Some((hours, rate)) => {
+            let (start, end) = hours
+                .split_once('-')
+                .ok_or_else(|| format!("expected HH:MM-HH:MM in '{}'", spec))?;
+            let parse_time = |s: &str| {
+                NaiveTime::parse_from_str(s.trim(), "%H:%M")
+                    .map_err(|_| format!("invalid time '{}' in '{}'", s.trim(), spec))
+            };
+            (Some((parse_time(start)?, parse_time(end)?)), rate)
+        }
+        None => (None, spec),
+    };
+    Ok(BandwidthWindow {
+        hours,
+        bytes_per_sec: parse_rate(rate.trim())?,
+    })
+}
+
+/// Parses a rate like `5MB/s`, `512KiB/s` or `unlimited`.
+fn parse_rate(rate: &str) -> Result<Option<u64>, String> {
+    if rate.eq_ignore_ascii_case("unlimited") || rate.eq_ignore_ascii_case("off") {
+        return Ok(None);
+    }
+    let amount = rate.strip_suffix("/s").unwrap_or(rate).trim();
+    parse_size(amount).map(Some)
+}
+
+/// Parses a positive size like `4MiB`, `512KB` or `65536`.
+pub(crate) fn parse_size(amount: &str) -> Result<u64, String> {
+    let amount = amount.trim();
+    let split = amount
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(amount.len());
+    let (number, unit) = amount.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", amount))?;
+    let multiplier: f64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "k" | "kb" => 1e3,
+        "m" | "mb" => 1e6,
+        "g" | "gb" => 1e9,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        other => return Err(format!("unknown unit '{}' in '{}'", other, amount)),
+    };
+    let bytes = (number * multiplier) as u64;
+    if bytes == 0 {
+        return Err(format!("'{}' must be positive", amount));
+    }
+    Ok(bytes)
+}
+
+/// Token bucket shared by every upload of a service, following a
+/// [`BandwidthSchedule`]. Allows bursts of up to one second of traffic.
+pub struct RateLimiter {
+    schedule: BandwidthSchedule,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(schedule: BandwidthSchedule) -> Self {
+        Self {
+            schedule,
+            bucket: Mutex::new(Bucket {
+                tokens: 0.0,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    /// Accounts for `bytes` just sent and returns how long the sender should
+    /// pause to stay within the current cap.
+    pub fn reserve(&self, bytes: usize) -> Duration {
+        let mut bucket = self.bucket.lock().expect("rate limiter poisoned");
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        bucket.last = now;
+
+        let Some(rate) = self.schedule.limit_at(Local::now().time()) else {
+            bucket.tokens = 0.0;
+            return Duration::ZERO;
+        };
+        let rate = rate as f64;
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate) - bytes as f64;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+}
+
+/// Reader that pauses between reads as requested by a [`RateLimiter`].
+pub(crate) struct ThrottledReader<R> {
+    inner: R,
+    limiter: Option<Arc<RateLimiter>>,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<R> ThrottledReader<R> {
+    pub(crate) fn new(inner: R, limiter: Option<Arc<RateLimiter>>) -> Self {
+        Self {
+            inner,
+            limiter,
+            delay: None,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ThrottledReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if let Some(delay) = self.delay.as_mut() {
+            if delay.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.delay = None;
+        }
+
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            let n = buf.filled().len() - before;
+            if let Some(limiter) = &self.limiter {
+                let wait = limiter.reserve(n);
+                if !wait.is_zero() {
+                    self.delay = Some(Box::pin(tokio::time::sleep(wait)));
+                }
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,163 @@
+use crate::config::LocalBotApiConfig;
+use crate::errors::{Result, TgCloudError};
+
+use reqwest::Client;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::process::{Child, Command};
+use tokio_util::sync::CancellationToken;
+
+/// How long a freshly started server gets to start answering requests.
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Wait before restarting a server that exited; doubled while it keeps
+/// exiting soon after starting.
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// Keeps a local `telegram-bot-api` server running, restarting it whenever it
+/// exits. The server is stopped when the supervisor is dropped.
+pub struct BotApiSupervisor {
+    stop: CancellationToken,
+}
+
+impl BotApiSupervisor {
+    /// Starts the server and waits until it answers. If something already
+    /// answers on the port, such as a server started by another tgcloud
+    /// process, that one is used and `None` is returned.
+    pub async fn start(config: LocalBotApiConfig, client: Client) -> Result<Option<Self>> {
+        let url = format!("http://127.0.0.1:{}", config.port);
+        if answers(&client, &url).await {
+            log::info!("Using the Bot API server already running at {}", url);
+            return Ok(None);
+        }
+
+        let child = start_server(&config, &client, &url).await?;
+        log::info!("Started local Bot API server at {}", url);
+
+        let stop = CancellationToken::new();
+        tokio::spawn(supervise(config, client, url, child, stop.clone()));
+        Ok(Some(Self { stop }))
+    }
+}
+
+impl Drop for BotApiSupervisor {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+/// File the server writes its log to, inside its working directory.
+pub fn bot_api_log_path(config: &LocalBotApiConfig) -> PathBuf {
+    config.work_dir.join("telegram-bot-api.log")
+}
+
+async fn supervise(
+    config: LocalBotApiConfig,
+    client: Client,
+    url: String,
+    child: Child,
+    stop: CancellationToken,
+) {
+    let mut child = Some((child, Instant::now()));
+    let mut delay = MIN_RESTART_DELAY;
+    loop {
+        if let Some((mut running, started)) = child.take() {
+            let status = tokio::select! {
+                _ = stop.cancelled() => {
+                    let _ = running.kill().await;
+                    return;
+                }
+                status = running.wait() => status,
+            };
+            // Only back off further if the server did not stay up for long.
+            delay = if started.elapsed() < MAX_RESTART_DELAY {
+                (delay * 2).min(MAX_RESTART_DELAY)
+            } else {
+                MIN_RESTART_DELAY
+            };
+            log::error!(
+                "Local Bot API server exited ({}), restarting in {}s; see {}",
+                status.map_or_else(|e| e.to_string(), |s| s.to_string()),
+                delay.as_secs(),
+                bot_api_log_path(&config).display()
+            );
+        }
+
+        tokio::select! {
+            _ = stop.cancelled() => return,
+            _ = tokio::time::sleep(delay) => {}
+        }
+
+        match start_server(&config, &client, &url).await {
+            Ok(restarted) => {
+                log::info!("Restarted local Bot API server at {}", url);
+                child = Some((restarted, Instant::now()));
+            }
+            Err(e) => {
+                delay = (delay * 2).min(MAX_RESTART_DELAY);
+                log::error!("{}, retrying in {}s", e, delay.as_secs());
+            }
+        }
+    }
+}
+
+/// Spawns the server and waits until it answers HTTP requests.
+async fn start_server(config: &LocalBotApiConfig, client: &Client, url: &str) -> Result<Child> {
+    let mut child = spawn(config)?;
+    let deadline = Instant::now() + READY_TIMEOUT;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Err(TgCloudError::BotApiServer(format!(
+                "exited with {} before accepting connections; see {}",
+                status,
+                bot_api_log_path(config).display()
+            )));
+        }
+        if answers(client, url).await {
+            return Ok(child);
+        }
+        if Instant::now() >= deadline {
+            // Dropping the child kills it.
+            return Err(TgCloudError::BotApiServer(format!(
+                "not answering at {} after {}s; see {}",
+                url,
+                READY_TIMEOUT.as_secs(),
+                bot_api_log_path(config).display()
+            )));
+        }
+        tokio::time::sleep(READY_POLL_INTERVAL).await;
+    }
+}
+
+fn spawn(config: &LocalBotApiConfig) -> Result<Child> {
+    std::fs::create_dir_all(&config.work_dir)?;
+    Command::new(&config.binary)
+        .arg("--local")
+        .arg("--http-ip-address=127.0.0.1")
+        .arg(format!("--http-port={}", config.port))
+        .arg(format!("--dir={}", config.work_dir.display()))
+        .arg(format!("--log={}", bot_api_log_path(config).display()))
+        // Passed through the environment to keep the hash out of `ps`.
+        .env("TELEGRAM_API_ID", &config.api_id)
+        .env("TELEGRAM_API_HASH", &config.api_hash)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| {
+            TgCloudError::BotApiServer(format!("cannot run {}: {}", config.binary.display(), e))
+        })
+}
+
+/// Whether anything answers HTTP requests at `url`; any status will do.
+async fn answers(client: &Client, url: &str) -> bool {
+    client
+        .get(url)
+        .timeout(Duration::from_secs(2))
+        .send()
+        .await
+        .is_ok()
+}
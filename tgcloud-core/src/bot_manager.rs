@@ -0,0 +1,543 @@
+use crate::config::BotConfig;
+use crate::errors::{Result, TgCloudError};
+use crate::models::{Bot, BotBenchmark, BotQuota, RateLimitStatus};
+use crate::storage::MongoStore;
+
+use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Number of consecutive 401/403 responses after which a bot is deactivated.
+const AUTH_FAILURE_THRESHOLD: u32 = 3;
+/// Pause after a 429 before a bot gets new chunks; doubles for every further
+/// 429 until the bot succeeds again.
+const RATE_LIMIT_COOLDOWN: Duration = Duration::from_secs(5);
+const MAX_RATE_LIMIT_COOLDOWN: Duration = Duration::from_secs(120);
+/// A bot's concurrency is halved at most once per this period, so the 429s of
+/// chunks that were already in flight count as one signal.
+const CONCURRENCY_DECREASE_INTERVAL: Duration = RATE_LIMIT_COOLDOWN;
+/// Weight of a new sample in the throughput moving average.
+const THROUGHPUT_SMOOTHING: f64 = 0.3;
+
+/// Credentials of a bot selected for a transfer.
+#[derive(Debug, Clone)]
+pub struct BotCredentials {
+    pub bot_id: String,
+    pub token: String,
+    pub weight: u32,
+    /// Number of chunk operations this bot may run at once at most. See
+    /// [`BotManager::concurrency_limit`] for how many it should run now.
+    pub concurrency: usize,
+}
+
+struct BotEntry {
+    bot_id: String,
+    token: String,
+    weight: u32,
+    concurrency: usize,
+    /// Chunk operations the bot should run at once, between 1 and
+    /// `concurrency`: halved on 429s and raised by one after every round of
+    /// `limit` successes.
+    limit: AtomicUsize,
+    successes: AtomicUsize,
+    decreased_at: Mutex<Option<Instant>>,
+    active: bool,
+    auth_failures: AtomicU32,
+    /// Running score for smooth weighted round-robin selection.
+    current_weight: AtomicI64,
+    /// Moving average of measured upload throughput in bytes/s (0 = unknown).
+    throughput: AtomicU64,
+    rate_limit_streak: AtomicU32,
+    cooldown_until: Mutex<Option<Instant>>,
+    /// Bytes the bot may store at most.
+    quota: Option<u64>,
+    /// Bytes stored through the bot, including chunks being uploaded.
+    stored: AtomicU64,
+}
+
+impl BotEntry {
+    fn cooldown_remaining(&self) -> Option<Duration> {
+        let until = (*self.cooldown_until.lock().expect("cooldown poisoned"))?;
+        until.checked_duration_since(Instant::now())
+    }
+
+    fn credentials(&self) -> BotCredentials {
+        BotCredentials {
+            bot_id: self.bot_id.clone(),
+            token: self.token.clone(),
+            weight: self.weight,
+            concurrency: self.concurrency,
+        }
+    }
+}
+
+/// Tracks the configured bots, hands them out for transfers and retires bots
+/// that Telegram keeps rejecting.
+pub struct BotManager {
+    store: MongoStore,
+    bots: RwLock<Vec<BotEntry>>,
+    /// Serializes weighted selection so the running scores stay consistent.
+    select_lock: Mutex<()>,
+}
+
+impl BotManager {
+    /// Registers every configured bot in the store, with the Bot API server it
+    /// uses (its own or `default_api_url`), and loads its active flag.
+    /// Each bot runs `config.max_concurrency` operations at once, or
+    /// `per_bot_concurrency * weight` when that is not set. If any bot has a
+    /// quota, the bytes each bot stores are loaded too.
+    pub async fn new(
+        store: MongoStore,
+        configs: &[BotConfig],
+        per_bot_concurrency: usize,
+        default_api_url: &str,
+    ) -> Result<Self> {
+        let stored = if configs.iter().any(|c| c.quota.is_some()) {
+            store.stored_bytes_by_bot().await?
+        } else {
+            Default::default()
+        };
+        let mut bots = Vec::with_capacity(configs.len());
+        for config in configs {
+            let api_url = config
+                .telegram_api_url
+                .as_deref()
+                .unwrap_or(default_api_url);
+            let record = store.register_bot(&config.bot_id, api_url).await?;
+            if !record.active {
+                log::warn!(
+                    "Bot {} is deactivated ({}); it will not be used for uploads",
+                    config.bot_id,
+                    record.deactivated_reason.as_deref().unwrap_or("no reason")
+                );
+            }
+            let concurrency = config
+                .max_concurrency
+                .unwrap_or(per_bot_concurrency * config.weight.max(1) as usize)
+                .max(1);
+            bots.push(BotEntry {
+                bot_id: config.bot_id.clone(),
+                token: config.token.clone(),
+                weight: config.weight.max(1),
+                concurrency,
+                limit: AtomicUsize::new(concurrency),
+                successes: AtomicUsize::new(0),
+                decreased_at: Mutex::new(None),
+                active: record.active,
+                auth_failures: AtomicU32::new(0),
+                current_weight: AtomicI64::new(0),
+                throughput: AtomicU64::new(0),
+                rate_limit_streak: AtomicU32::new(0),
+                cooldown_until: Mutex::new(None),
+                quota: config.quota,
+                stored: AtomicU64::new(stored.get(&config.bot_id).copied().unwrap_or(0)),
+            });
+        }
+
+        Ok(Self {
+            store,
+            bots: RwLock::new(bots),
+            select_lock: Mutex::new(()),
+        })
+    }
+
+    /// Returns the credentials of every active bot.
+    pub fn active_bots(&self) -> Vec<BotCredentials> {
+        self.bots
+            .read()
+            .expect("bot list poisoned")
+            .iter()
+            .filter(|b| b.active)
+            .map(BotEntry::credentials)
+            .collect()
+    }
+
+    /// Whether `bot_id` is configured and currently active.
+    pub fn is_active(&self, bot_id: &str) -> bool {
+        self.bots
+            .read()
+            .expect("bot list poisoned")
+            .iter()
+            .any(|b| b.bot_id == bot_id && b.active)
+    }
+
+    /// Picks the next active bot using smooth weighted round-robin, so a bot
+    /// with weight 2 is chosen twice as often as one with weight 1 without
+    /// being chosen twice in a row. Weights are scaled by measured throughput
+    /// relative to the other bots, and bots cooling down after a 429 are only
+    /// chosen when every bot is cooling down.
+    pub fn get_upload_bot(&self) -> Result<BotCredentials> {
+        let _guard = self.select_lock.lock().expect("bot selection poisoned");
+        let bots = self.bots.read().expect("bot list poisoned");
+
+        let active: Vec<&BotEntry> = bots.iter().filter(|b| b.active).collect();
+        let ready: Vec<&BotEntry> = active
+            .iter()
+            .copied()
+            .filter(|b| b.cooldown_remaining().is_none())
+            .collect();
+        let candidates = if ready.is_empty() { active } else { ready };
+
+        let known: Vec<u64> = candidates
+            .iter()
+            .map(|b| b.throughput.load(Ordering::Relaxed))
+            .filter(|&t| t > 0)
+            .collect();
+        let mean = if known.is_empty() {
+            0.0
+        } else {
+            known.iter().sum::<u64>() as f64 / known.len() as f64
+        };
+
+        let mut total: i64 = 0;
+        let mut best: Option<&BotEntry> = None;
+        for b in candidates {
+            let weight = effective_weight(b, mean);
+            total += weight;
+            let current = b.current_weight.fetch_add(weight, Ordering::Relaxed) + weight;
+            if best.is_none_or(|x| current > x.current_weight.load(Ordering::Relaxed)) {
+                best = Some(b);
+            }
+        }
+
+        let best = best
+            .ok_or_else(|| TgCloudError::BotManagerError("No active bots available".to_string()))?;
+        best.current_weight.fetch_sub(total, Ordering::Relaxed);
+        Ok(best.credentials())
+    }
+
+    /// Looks up a bot by id, including deactivated ones (their chunks are
+    /// still addressed through them).
+    pub fn get_bot(&self, bot_id: &str) -> Option<BotCredentials> {
+        self.bots
+            .read()
+            .expect("bot list poisoned")
+            .iter()
+            .find(|b| b.bot_id == bot_id)
+            .map(BotEntry::credentials)
+    }
+
+    /// Resolves the bot that owns a chunk, falling back to any active bot for
+    /// legacy chunks without a recorded `bot_id`.
+    pub fn bot_for_chunk(&self, bot_id: Option<&str>) -> Result<BotCredentials> {
+        match bot_id {
+            Some(id) => self.get_bot(id).ok_or_else(|| {
+                TgCloudError::BotManagerError(format!("Bot {} is not configured", id))
+            }),
+            None => self.get_upload_bot(),
+        }
+    }
+
+    /// Resets the failure streaks of a bot after a successful request and,
+    /// once as many requests succeeded as it may run at once, lets it run one
+    /// more.
+    pub fn report_success(&self, bot_id: &str) {
+        if let Some(b) = self
+            .bots
+            .read()
+            .expect("bot list poisoned")
+            .iter()
+            .find(|b| b.bot_id == bot_id)
+        {
+            b.auth_failures.store(0, Ordering::Relaxed);
+            b.rate_limit_streak.store(0, Ordering::Relaxed);
+
+            let limit = b.limit.load(Ordering::Relaxed);
+            if limit < b.concurrency && b.successes.fetch_add(1, Ordering::Relaxed) + 1 >= limit {
+                b.successes.store(0, Ordering::Relaxed);
+                if b.limit
+                    .compare_exchange(limit, limit + 1, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    log::debug!(
+                        "Bot {} is healthy; raising its concurrency to {}",
+                        bot_id,
+                        limit + 1
+                    );
+                }
+            }
+        }
+    }
+
+    /// Counts `bytes` against a bot's quota before it uploads them. Returns
+    /// `false`, reserving nothing, if they would take the bot over its quota.
+    pub fn try_reserve(&self, bot_id: &str, bytes: u64) -> bool {
+        let bots = self.bots.read().expect("bot list poisoned");
+        let Some(b) = bots.iter().find(|b| b.bot_id == bot_id) else {
+            return false;
+        };
+        match b.quota {
+            None => {
+                b.stored.fetch_add(bytes, Ordering::Relaxed);
+                true
+            }
+            Some(quota) => b
+                .stored
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |stored| {
+                    stored.checked_add(bytes).filter(|&total| total <= quota)
+                })
+                .is_ok(),
+        }
+    }
+
+    /// Gives back bytes reserved for a chunk that was not uploaded, or that
+    /// belonged to a chunk that was deleted.
+    pub fn release(&self, bot_id: &str, bytes: u64) {
+        if let Some(b) = self
+            .bots
+            .read()
+            .expect("bot list poisoned")
+            .iter()
+            .find(|b| b.bot_id == bot_id)
+        {
+            let _ = b
+                .stored
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |stored| {
+                    Some(stored.saturating_sub(bytes))
+                });
+        }
+    }
+
+    /// Whether any bot has a storage quota.
+    pub fn has_quotas(&self) -> bool {
+        self.bots
+            .read()
+            .expect("bot list poisoned")
+            .iter()
+            .any(|b| b.quota.is_some())
+    }
+
+    /// Bytes stored through every configured bot against its quota.
+    pub fn quota_usage(&self) -> Vec<BotQuota> {
+        self.bots
+            .read()
+            .expect("bot list poisoned")
+            .iter()
+            .map(|b| BotQuota {
+                bot_id: b.bot_id.clone(),
+                stored_bytes: b.stored.load(Ordering::Relaxed),
+                quota_bytes: b.quota,
+            })
+            .collect()
+    }
+
+    /// How many chunk operations a bot should run at once right now.
+    pub fn concurrency_limit(&self, bot_id: &str) -> usize {
+        self.bots
+            .read()
+            .expect("bot list poisoned")
+            .iter()
+            .find(|b| b.bot_id == bot_id)
+            .map_or(1, |b| b.limit.load(Ordering::Relaxed))
+    }
+
+    /// Records that a bot moved `bytes` in `elapsed`.
+    pub fn report_throughput(&self, bot_id: &str, bytes: u64, elapsed: Duration) {
+        let sample = (bytes as f64 / elapsed.as_secs_f64().max(1e-3)) as u64;
+        if let Some(b) = self
+            .bots
+            .read()
+            .expect("bot list poisoned")
+            .iter()
+            .find(|b| b.bot_id == bot_id)
+        {
+            let old = b.throughput.load(Ordering::Relaxed);
+            let new = if old == 0 {
+                sample
+            } else {
+                (old as f64 * (1.0 - THROUGHPUT_SMOOTHING) + sample as f64 * THROUGHPUT_SMOOTHING)
+                    as u64
+            };
+            b.throughput.store(new, Ordering::Relaxed);
+        }
+    }
+
+    /// Records a 429 from Telegram and keeps the bot out of chunk assignment
+    /// for a cool-down that grows while the 429s continue.
+    pub fn report_rate_limited(&self, bot_id: &str) {
+        if let Some(b) = self
+            .bots
+            .read()
+            .expect("bot list poisoned")
+            .iter()
+            .find(|b| b.bot_id == bot_id)
+        {
+            let streak = b.rate_limit_streak.fetch_add(1, Ordering::Relaxed);
+            let cooldown = RATE_LIMIT_COOLDOWN
+                .saturating_mul(1 << streak.min(8))
+                .min(MAX_RATE_LIMIT_COOLDOWN);
+            *b.cooldown_until.lock().expect("cooldown poisoned") = Some(Instant::now() + cooldown);
+            log::warn!(
+                "Bot {} is rate limited; pausing new chunks for {}s",
+                bot_id,
+                cooldown.as_secs()
+            );
+
+            let mut decreased_at = b.decreased_at.lock().expect("concurrency poisoned");
+            if decreased_at.is_none_or(|at| at.elapsed() >= CONCURRENCY_DECREASE_INTERVAL) {
+                *decreased_at = Some(Instant::now());
+                let limit = b.limit.load(Ordering::Relaxed);
+                b.limit.store((limit / 2).max(1), Ordering::Relaxed);
+                b.successes.store(0, Ordering::Relaxed);
+                if limit > 1 {
+                    log::info!(
+                        "Lowering the concurrency of bot {} from {} to {}",
+                        bot_id,
+                        limit,
+                        (limit / 2).max(1)
+                    );
+                }
+            }
+            drop(decreased_at);
+
+            // Persisted so `tgcloud bot status` in another process sees it too.
+            let store = self.store.clone();
+            let bot_id = bot_id.to_string();
+            let until = chrono::Utc::now()
+                + chrono::Duration::from_std(cooldown).unwrap_or(chrono::Duration::zero());
+            tokio::spawn(async move {
+                if let Err(e) = store.record_rate_limit(&bot_id, until).await {
+                    log::warn!("Failed to record rate limit of bot {}: {}", bot_id, e);
+                }
+            });
+        }
+    }
+
+    /// How long a bot still waits after its last 429, if at all.
+    pub fn cooldown_remaining(&self, bot_id: &str) -> Option<Duration> {
+        self.bots
+            .read()
+            .expect("bot list poisoned")
+            .iter()
+            .find(|b| b.bot_id == bot_id)
+            .and_then(BotEntry::cooldown_remaining)
+    }
+
+    /// Combines this process's back-off state with the 429s recorded in the
+    /// store by other processes.
+    pub fn rate_limit_status(&self, records: &[Bot]) -> Vec<RateLimitStatus> {
+        let now = chrono::Utc::now();
+        self.bots
+            .read()
+            .expect("bot list poisoned")
+            .iter()
+            .map(|b| {
+                let record = records.iter().find(|r| r.bot_id == b.bot_id);
+                let stored = record
+                    .and_then(|r| r.rate_limited_until)
+                    .and_then(|until| (until - now).to_std().ok());
+                let cooldown = b.cooldown_remaining().max(stored).unwrap_or_default();
+                RateLimitStatus {
+                    bot_id: b.bot_id.clone(),
+                    active: b.active,
+                    cooldown_remaining_secs: cooldown.as_secs_f64().ceil() as u64,
+                    streak: b.rate_limit_streak.load(Ordering::Relaxed),
+                    rate_limit_count: record.map_or(0, |r| r.rate_limit_count),
+                    last_rate_limited_at: record.and_then(|r| r.last_rate_limited_at),
+                    throughput_bytes_per_sec: b.throughput.load(Ordering::Relaxed),
+                    concurrency: b.limit.load(Ordering::Relaxed),
+                    max_concurrency: b.concurrency,
+                }
+            })
+            .collect()
+    }
+
+    /// Seeds the throughput estimates from stored `tgcloud bot bench` results.
+    pub fn apply_benchmarks(&self, benchmarks: &[BotBenchmark]) {
+        let bots = self.bots.read().expect("bot list poisoned");
+        for bench in benchmarks.iter().filter(|b| b.error.is_none()) {
+            if let Some(b) = bots.iter().find(|b| b.bot_id == bench.bot_id) {
+                b.throughput
+                    .store(bench.upload_bytes_per_sec as u64, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Records a 401/403 from Telegram. Once a bot hits the threshold it is
+    /// deactivated in memory and in the store.
+    pub async fn report_auth_failure(&self, bot_id: &str, status: u16) {
+        let deactivate = {
+            let bots = self.bots.read().expect("bot list poisoned");
+            match bots.iter().find(|b| b.bot_id == bot_id) {
+                Some(b) if b.active => {
+                    b.auth_failures.fetch_add(1, Ordering::Relaxed) + 1 >= AUTH_FAILURE_THRESHOLD
+                }
+                _ => false,
+            }
+        };
+        if !deactivate {
+            return;
+        }
+
+        {
+            let mut bots = self.bots.write().expect("bot list poisoned");
+            match bots.iter_mut().find(|b| b.bot_id == bot_id) {
+                Some(b) if b.active => b.active = false,
+                _ => return,
+            }
+        }
+
+        let reason = format!(
+            "Telegram returned HTTP {} {} times in a row",
+            status, AUTH_FAILURE_THRESHOLD
+        );
+        log::error!(
+            "!!! Deactivating bot {}: {}. Check its token and chat membership, then re-enable it with `tgcloud bot enable {}`",
+            bot_id,
+            reason,
+            bot_id
+        );
+        if let Err(e) = self
+            .store
+            .set_bot_active(bot_id, false, Some(&reason))
+            .await
+        {
+            log::error!("Failed to persist deactivation of bot {}: {}", bot_id, e);
+        }
+    }
+
+    /// Re-enables a deactivated bot.
+    pub async fn enable_bot(&self, bot_id: &str) -> Result<()> {
+        self.store.set_bot_active(bot_id, true, None).await?;
+        let mut bots = self.bots.write().expect("bot list poisoned");
+        if let Some(b) = bots.iter_mut().find(|b| b.bot_id == bot_id) {
+            b.active = true;
+            b.auth_failures.store(0, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Replaces the token of a bot, e.g. after it was regenerated with
+    /// @BotFather. A bot deactivated because its old token was rejected is
+    /// enabled again.
+    pub async fn set_token(&self, bot_id: &str, token: &str) -> Result<()> {
+        let was_active = {
+            let mut bots = self.bots.write().expect("bot list poisoned");
+            let b = bots
+                .iter_mut()
+                .find(|b| b.bot_id == bot_id)
+                .ok_or_else(|| {
+                    TgCloudError::BotManagerError(format!("Bot {} is not configured", bot_id))
+                })?;
+            b.token = token.to_string();
+            b.auth_failures.store(0, Ordering::Relaxed);
+            b.active
+        };
+        if !was_active {
+            self.enable_bot(bot_id).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Selection weight of a bot: its configured weight scaled by its throughput
+/// relative to `mean` (between 1/4 and 4 times), or unscaled while unmeasured.
+fn effective_weight(bot: &BotEntry, mean: f64) -> i64 {
+    let throughput = bot.throughput.load(Ordering::Relaxed);
+    let factor = if throughput == 0 || mean <= 0.0 {
+        1.0
+    } else {
+        (throughput as f64 / mean).clamp(0.25, 4.0)
+    };
+    ((bot.weight as f64) * factor * 100.0).round().max(1.0) as i64
+}
@@ -0,0 +1,99 @@
+use crate::models::{Bot, FileMetadata};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A small map whose entries expire after a fixed time-to-live.
+///
+/// Lookups that miss should take a [`generation`](Self::generation) before
+/// querying the backing store and pass it to [`insert`](Self::insert): if the
+/// cache was cleared in between, the possibly stale result is not stored.
+pub struct TtlCache<K, V> {
+    ttl: Duration,
+    generation: AtomicU64,
+    entries: Mutex<HashMap<K, (Instant, V)>>,
+}
+
+impl<K: Eq + Hash, V: Clone> TtlCache<K, V> {
+    /// A zero `ttl` disables the cache.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            generation: AtomicU64::new(0),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+        let mut entries = self.entries.lock().expect("cache poisoned");
+        match entries.get(key) {
+            Some((at, value)) if at.elapsed() < self.ttl => Some(value.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, generation: u64, key: K, value: V) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let mut entries = self.entries.lock().expect("cache poisoned");
+        if generation != self.generation() {
+            return;
+        }
+        entries.retain(|_, (at, _)| at.elapsed() < self.ttl);
+        entries.insert(key, (Instant::now(), value));
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().expect("cache poisoned");
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        entries.clear();
+    }
+}
+
+/// Short-lived copies of frequently read metadata, kept by [`MongoStore`]
+/// and cleared whenever it writes the underlying collection.
+///
+/// [`MongoStore`]: crate::storage::MongoStore
+pub struct MetadataCache {
+    pub by_path: TtlCache<String, Option<FileMetadata>>,
+    pub by_id: TtlCache<String, Option<FileMetadata>>,
+    /// `list_files` results keyed by name prefix.
+    pub listings: TtlCache<String, Vec<FileMetadata>>,
+    /// `list_files_with_depth` results keyed by folder and depth.
+    pub folder_listings: TtlCache<(String, Option<u32>), Vec<FileMetadata>>,
+    pub bots: TtlCache<(), Vec<Bot>>,
+}
+
+impl MetadataCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            by_path: TtlCache::new(ttl),
+            by_id: TtlCache::new(ttl),
+            listings: TtlCache::new(ttl),
+            folder_listings: TtlCache::new(ttl),
+            bots: TtlCache::new(ttl),
+        }
+    }
+
+    /// Drops everything derived from the `files` collection.
+    pub fn invalidate_files(&self) {
+        self.by_path.clear();
+        self.by_id.clear();
+        self.listings.clear();
+        self.folder_listings.clear();
+    }
+}
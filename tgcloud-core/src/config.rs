@@ -1,34 +1,295 @@
+use crate::bandwidth::BandwidthSchedule;
 use crate::errors::ConfigError;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::env;
+use std::path::{Path, PathBuf};
 
 /// Default maximum number of concurrent chunk operations across all bots.
 pub const DEFAULT_MAX_GLOBAL_CONCURRENCY: usize = 12;
 /// Default maximum number of concurrent chunk operations per individual bot.
 pub const DEFAULT_MAX_PER_BOT_CONCURRENCY: usize = 3;
+/// Default time running transfers get to finish after a shutdown signal.
+pub const DEFAULT_SHUTDOWN_GRACE_SECS: u64 = 300;
+/// How long deleted files stay in the trash and can be undone.
+pub const DEFAULT_TRASH_RETENTION_SECS: u64 = 86_400;
+/// How long chunks of a failed upload are kept for a retry to reuse.
+pub const DEFAULT_LEDGER_RETENTION_SECS: u64 = 604_800;
+/// Default time allowed to establish a connection to the Bot API server.
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+/// Default time a transfer may go without moving a byte before it is retried.
+pub const DEFAULT_STALL_TIMEOUT_SECS: u64 = 60;
+/// Default time file listings and lookups are served from memory.
+pub const DEFAULT_METADATA_CACHE_TTL_SECS: u64 = 5;
+/// Default number of messages all bots together send to one chat per minute.
+pub const DEFAULT_CHAT_MESSAGES_PER_MINUTE: u32 = 20;
+/// Default number of idle connections kept open to the Bot API server.
+pub const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 32;
+/// Default time an idle pooled connection is kept open.
+pub const DEFAULT_POOL_IDLE_TIMEOUT_SECS: u64 = 90;
+/// Default TCP keep-alive interval for Bot API connections.
+pub const DEFAULT_TCP_KEEPALIVE_SECS: u64 = 60;
+/// Default port of a Bot API server started by tgcloud.
+pub const DEFAULT_BOT_API_PORT: u16 = 8081;
+/// Default size of the buffers used to hash, merge and verify files.
+pub const DEFAULT_IO_BUFFER_SIZE: usize = 1024 * 1024;
+/// Default time between refreshes of the metadata manifest in the storage chat.
+pub const DEFAULT_METADATA_MIRROR_INTERVAL_SECS: u64 = 3600;
+/// Default number of seconds a transfer hook command may run.
+pub const DEFAULT_HOOK_TIMEOUT_SECS: u64 = 300;
+/// Range accepted for `IO_BUFFER_SIZE`.
+const MIN_IO_BUFFER_SIZE: u64 = 4 * 1024;
+const MAX_IO_BUFFER_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Credentials for a single bot used to store chunks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BotConfig {
+    pub bot_id: String,
+    pub token: String,
+    /// Relative share of chunks this bot receives (e.g. 2 for a bot on a
+    /// faster local API instance).
+    #[serde(default = "default_bot_weight")]
+    pub weight: u32,
+    /// Overrides the number of concurrent chunk operations for this bot,
+    /// which otherwise is `max_per_bot_concurrency * weight`.
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+    /// Bytes of chunks this bot may store at most, as a number or a size such
+    /// as `"50GiB"`; unlimited if unset.
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub quota: Option<u64>,
+    /// Bot API server this bot talks to instead of `TELEGRAM_API_URL`, e.g.
+    /// a second local server so uploads are not capped by a single one.
+    #[serde(default)]
+    pub telegram_api_url: Option<String>,
+}
+
+fn default_bot_weight() -> u32 {
+    1
+}
+
+/// Reads an optional byte count given as a number or a size string.
+fn deserialize_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Option::<Size>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Size::Bytes(bytes)) => Ok(Some(bytes)),
+        Some(Size::Text(text)) => crate::bandwidth::parse_size(&text)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+fn default_ledger_retention_secs() -> u64 {
+    DEFAULT_LEDGER_RETENTION_SECS
+}
+
+fn default_metadata_cache_ttl_secs() -> u64 {
+    DEFAULT_METADATA_CACHE_TTL_SECS
+}
+
+fn default_metadata_mirror_interval_secs() -> u64 {
+    DEFAULT_METADATA_MIRROR_INTERVAL_SECS
+}
+
+fn default_chat_messages_per_minute() -> u32 {
+    DEFAULT_CHAT_MESSAGES_PER_MINUTE
+}
+
+fn default_io_buffer_size() -> usize {
+    DEFAULT_IO_BUFFER_SIZE
+}
+
+/// Connection pool and timeout settings for the HTTP client shared by all Bot
+/// API requests. A timeout of 0 disables it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpConfig {
+    /// Seconds allowed to establish a connection.
+    pub connect_timeout_secs: u64,
+    /// Seconds a whole request may take, including the body; off by default
+    /// since large chunks legitimately take long on slow links.
+    pub request_timeout_secs: u64,
+    /// Seconds an upload or download may go without transferring any bytes
+    /// before it is aborted and retried.
+    pub stall_timeout_secs: u64,
+    /// Idle connections kept open to the server for reuse by later chunks.
+    pub pool_max_idle_per_host: usize,
+    /// Seconds an idle pooled connection is kept open.
+    pub pool_idle_timeout_secs: u64,
+    /// TCP keep-alive interval in seconds.
+    pub tcp_keepalive_secs: u64,
+    /// Speaks HTTP/2 without negotiation, multiplexing all requests over one
+    /// connection; the server must support it.
+    pub http2: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: DEFAULT_CONNECT_TIMEOUT_SECS,
+            request_timeout_secs: 0,
+            stall_timeout_secs: DEFAULT_STALL_TIMEOUT_SECS,
+            pool_max_idle_per_host: DEFAULT_POOL_MAX_IDLE_PER_HOST,
+            pool_idle_timeout_secs: DEFAULT_POOL_IDLE_TIMEOUT_SECS,
+            tcp_keepalive_secs: DEFAULT_TCP_KEEPALIVE_SECS,
+            http2: false,
+        }
+    }
+}
+
+/// Shell commands run before and after transfers; see [`crate::ShellHook`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HookConfig {
+    pub before_upload: Option<String>,
+    pub after_upload: Option<String>,
+    pub before_download: Option<String>,
+    pub after_download: Option<String>,
+    /// Seconds a command may run before it is killed; 0 disables the limit.
+    pub timeout_secs: u64,
+}
+
+impl Default for HookConfig {
+    fn default() -> Self {
+        Self {
+            before_upload: None,
+            after_upload: None,
+            before_download: None,
+            after_download: None,
+            timeout_secs: DEFAULT_HOOK_TIMEOUT_SECS,
+        }
+    }
+}
+
+impl HookConfig {
+    /// Whether no command is configured.
+    pub fn is_empty(&self) -> bool {
+        self.before_upload.is_none()
+            && self.after_upload.is_none()
+            && self.before_download.is_none()
+            && self.after_download.is_none()
+    }
+}
+
+/// TLS settings for the connection to the Bot API server, for self-hosted
+/// servers behind a private CA or requiring client certificates.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM file with additional CA certificates to trust.
+    #[serde(default)]
+    pub ca_bundle: Option<PathBuf>,
+    /// Client certificate, either PEM (together with `client_key`) or PKCS#12.
+    #[serde(default)]
+    pub client_cert: Option<PathBuf>,
+    /// PKCS#8 PEM private key for a PEM `client_cert`.
+    #[serde(default)]
+    pub client_key: Option<PathBuf>,
+    /// Password of a PKCS#12 `client_cert`.
+    #[serde(default, skip_serializing)]
+    pub client_cert_password: Option<String>,
+    /// Skips certificate verification, e.g. for a self-signed local server.
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+}
+
+/// A local `telegram-bot-api` server that tgcloud starts and restarts itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalBotApiConfig {
+    /// Application id from my.telegram.org.
+    pub api_id: String,
+    #[serde(default, skip_serializing)]
+    pub api_hash: String,
+    /// Path to the `telegram-bot-api` executable.
+    pub binary: PathBuf,
+    /// Port the server listens on, on 127.0.0.1.
+    pub port: u16,
+    /// Directory the server keeps its state and downloaded files in.
+    pub work_dir: PathBuf,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub mongo_uri: String,
     pub telegram_api_url: String,
     pub telegram_chat_id: String,
-    pub bot_id: String,
-    pub bot_token: String,
+    /// Chat that `tgcloud archive` moves chunks to; archiving is unavailable
+    /// without it.
+    #[serde(default)]
+    pub archive_chat_id: Option<String>,
+    /// All bots available for chunk transfers, in configuration order.
+    pub bots: Vec<BotConfig>,
     /// Maximum number of concurrent chunk operations.
     pub max_concurrency: usize,
+    /// Maximum number of concurrent chunk operations handled by a single bot.
+    pub max_per_bot_concurrency: usize,
+    /// Seconds to wait for running transfers when shutting down.
+    pub shutdown_grace_secs: u64,
+    /// Seconds a deleted file stays restorable in the trash.
+    pub trash_retention_secs: u64,
+    /// Seconds the chunks of a failed upload are kept for resuming it.
+    #[serde(default = "default_ledger_retention_secs")]
+    pub ledger_retention_secs: u64,
+    /// Time-of-day upload bandwidth caps; unlimited by default.
+    #[serde(default)]
+    pub bandwidth_schedule: BandwidthSchedule,
+    /// Seconds metadata reads are cached in memory; 0 disables the cache.
+    #[serde(default = "default_metadata_cache_ttl_secs")]
+    pub metadata_cache_ttl_secs: u64,
+    /// Messages (chunk uploads, forwards and deletes) sent to one chat per
+    /// minute across all bots; 0 disables pacing.
+    #[serde(default = "default_chat_messages_per_minute")]
+    pub chat_messages_per_minute: u32,
+    /// Bytes read or written at a time when hashing, merging and verifying
+    /// files.
+    #[serde(default = "default_io_buffer_size")]
+    pub io_buffer_size: usize,
+    /// Timeouts for the Bot API connection.
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// TLS options for the Bot API connection.
+    #[serde(default)]
+    pub tls: TlsConfig,
+    /// Bot API server to supervise; `telegram_api_url` points at it if set.
+    #[serde(default)]
+    pub local_bot_api: Option<LocalBotApiConfig>,
+    /// Commands run before and after uploads and downloads.
+    #[serde(default)]
+    pub hooks: HookConfig,
+    /// Key file names and history entries are encrypted with in MongoDB.
+    #[serde(default, skip_serializing)]
+    pub metadata_key: Option<[u8; 32]>,
+    /// Keep an encrypted copy of the file metadata pinned in the storage
+    /// chat, for `tgcloud recover --from-chat`. Requires `metadata_key`.
+    #[serde(default)]
+    pub metadata_mirror: bool,
+    /// Seconds between refreshes of the mirror besides those after uploads;
+    /// 0 refreshes it only after uploads.
+    #[serde(default = "default_metadata_mirror_interval_secs")]
+    pub metadata_mirror_interval_secs: u64,
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self, ConfigError> {
-        let config_dir = dirs::config_dir()
-            .ok_or_else(|| ConfigError::General("Could not resolve config directory".into()))?
-            .join("tgcloud");
+    /// Bot API servers of the bots that set their own `telegram_api_url`, by
+    /// the Telegram bot id at the start of their token, for
+    /// [`crate::TelegramClient::with_bot_api_urls`].
+    pub fn bot_api_urls(&self) -> HashMap<String, String> {
+        self.bots
+            .iter()
+            .filter_map(|b| {
+                let url = b.telegram_api_url.clone()?;
+                let telegram_id = b.token.split(':').next().unwrap_or_default();
+                Some((telegram_id.to_string(), url))
+            })
+            .collect()
+    }
 
-        if !config_dir.exists() {
-            std::fs::create_dir_all(&config_dir).map_err(|e| {
-                ConfigError::General(format!("Failed to create config directory: {}", e))
-            })?;
-        }
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let config_dir = config_dir()?;
 
         let config_path = config_dir.join(".env");
         if !config_path.exists() {
@@ -41,41 +302,739 @@ impl Config {
 
         dotenv::from_path(&config_path).ok();
 
+        Self::from_vars(&|name| env::var(name).ok())
+    }
+
+    /// Builds the configuration from `var`, which looks a variable up by name.
+    fn from_vars(var: &dyn Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
         let mongo_uri =
-            env::var("MONGO_URI").map_err(|_| ConfigError::MissingEnvVar("MONGO_URI".into()))?;
+            var("MONGO_URI").ok_or_else(|| ConfigError::MissingEnvVar("MONGO_URI".into()))?;
         if mongo_uri.trim().is_empty() {
             return Err(ConfigError::MissingEnvVar("MONGO_URI".into()));
         }
 
-        let telegram_api_url =
-            env::var("TELEGRAM_API_URL").unwrap_or_else(|_| "http://localhost:8081".to_string());
+        let local_bot_api = local_bot_api_from_vars(var)?;
+        let telegram_api_url = match &local_bot_api {
+            Some(local) => format!("http://127.0.0.1:{}", local.port),
+            None => var("TELEGRAM_API_URL").unwrap_or_else(|| "http://localhost:8081".to_string()),
+        };
 
-        let telegram_chat_id = env::var("TELEGRAM_CHAT_ID")
-            .map_err(|_| ConfigError::MissingEnvVar("TELEGRAM_CHAT_ID".into()))?;
+        let telegram_chat_id = var("TELEGRAM_CHAT_ID")
+            .ok_or_else(|| ConfigError::MissingEnvVar("TELEGRAM_CHAT_ID".into()))?;
         if telegram_chat_id.trim().is_empty() {
             return Err(ConfigError::MissingEnvVar("TELEGRAM_CHAT_ID".into()));
         }
 
-        let bot_id = env::var("BOT_ID").map_err(|_| ConfigError::MissingEnvVar("BOT_ID".into()))?;
-        let bot_id = bot_id.trim();
-        if bot_id.is_empty() {
-            return Err(ConfigError::MissingEnvVar("BOT_ID".into()));
+        let archive_chat_id = var("ARCHIVE_CHAT_ID").filter(|v| !v.trim().is_empty());
+
+        let bots = match var("BOTS_JSON") {
+            Some(json) if !json.trim().is_empty() => parse_bots_json(&json)?,
+            _ => vec![single_bot_from_env(var)?],
+        };
+
+        let shutdown_grace_secs = match var("SHUTDOWN_GRACE_SECS") {
+            Some(v) => v.trim().parse().map_err(|_| {
+                ConfigError::General("SHUTDOWN_GRACE_SECS must be a number of seconds".into())
+            })?,
+            None => DEFAULT_SHUTDOWN_GRACE_SECS,
+        };
+
+        let trash_retention_secs = match var("TRASH_RETENTION_SECS") {
+            Some(v) => v.trim().parse().map_err(|_| {
+                ConfigError::General("TRASH_RETENTION_SECS must be a number of seconds".into())
+            })?,
+            None => DEFAULT_TRASH_RETENTION_SECS,
+        };
+
+        let ledger_retention_secs = match var("LEDGER_RETENTION_SECS") {
+            Some(v) => v.trim().parse().map_err(|_| {
+                ConfigError::General("LEDGER_RETENTION_SECS must be a number of seconds".into())
+            })?,
+            None => DEFAULT_LEDGER_RETENTION_SECS,
+        };
+
+        let bandwidth_schedule = match var("BANDWIDTH_SCHEDULE") {
+            Some(spec) => BandwidthSchedule::parse(&spec)
+                .map_err(|e| ConfigError::General(format!("Invalid BANDWIDTH_SCHEDULE: {}", e)))?,
+            None => BandwidthSchedule::default(),
+        };
+
+        let metadata_cache_ttl_secs = env_secs(
+            var,
+            "METADATA_CACHE_TTL_SECS",
+            DEFAULT_METADATA_CACHE_TTL_SECS,
+        )?;
+
+        let chat_messages_per_minute = match var("CHAT_MESSAGES_PER_MINUTE") {
+            Some(v) => v.trim().parse().map_err(|_| {
+                ConfigError::General("CHAT_MESSAGES_PER_MINUTE must be a number".into())
+            })?,
+            None => DEFAULT_CHAT_MESSAGES_PER_MINUTE,
+        };
+
+        let io_buffer_size = match var("IO_BUFFER_SIZE") {
+            Some(v) => {
+                let size = crate::bandwidth::parse_size(&v)
+                    .map_err(|e| ConfigError::General(format!("Invalid IO_BUFFER_SIZE: {}", e)))?;
+                if !(MIN_IO_BUFFER_SIZE..=MAX_IO_BUFFER_SIZE).contains(&size) {
+                    return Err(ConfigError::General(
+                        "IO_BUFFER_SIZE must be between 4KiB and 256MiB".into(),
+                    ));
+                }
+                size as usize
+            }
+            None => DEFAULT_IO_BUFFER_SIZE,
+        };
+
+        let defaults = HttpConfig::default();
+        let http = HttpConfig {
+            connect_timeout_secs: env_secs(
+                var,
+                "HTTP_CONNECT_TIMEOUT_SECS",
+                defaults.connect_timeout_secs,
+            )?,
+            request_timeout_secs: env_secs(
+                var,
+                "HTTP_REQUEST_TIMEOUT_SECS",
+                defaults.request_timeout_secs,
+            )?,
+            stall_timeout_secs: env_secs(
+                var,
+                "HTTP_STALL_TIMEOUT_SECS",
+                defaults.stall_timeout_secs,
+            )?,
+            pool_max_idle_per_host: match var("HTTP_POOL_MAX_IDLE_PER_HOST") {
+                Some(v) => v.trim().parse().map_err(|_| {
+                    ConfigError::General("HTTP_POOL_MAX_IDLE_PER_HOST must be a number".into())
+                })?,
+                None => defaults.pool_max_idle_per_host,
+            },
+            pool_idle_timeout_secs: env_secs(
+                var,
+                "HTTP_POOL_IDLE_TIMEOUT_SECS",
+                defaults.pool_idle_timeout_secs,
+            )?,
+            tcp_keepalive_secs: env_secs(
+                var,
+                "HTTP_TCP_KEEPALIVE_SECS",
+                defaults.tcp_keepalive_secs,
+            )?,
+            http2: match var("HTTP2") {
+                Some(v) => parse_bool(&v)
+                    .ok_or_else(|| ConfigError::General("HTTP2 must be true or false".into()))?,
+                None => defaults.http2,
+            },
+        };
+
+        let tls = TlsConfig {
+            ca_bundle: env_path(var, "TLS_CA_BUNDLE"),
+            client_cert: env_path(var, "TLS_CLIENT_CERT"),
+            client_key: env_path(var, "TLS_CLIENT_KEY"),
+            client_cert_password: var("TLS_CLIENT_CERT_PASSWORD"),
+            accept_invalid_certs: match var("TLS_ACCEPT_INVALID_CERTS") {
+                Some(v) => parse_bool(&v).ok_or_else(|| {
+                    ConfigError::General("TLS_ACCEPT_INVALID_CERTS must be true or false".into())
+                })?,
+                None => false,
+            },
+        };
+        if tls.client_key.is_some() && tls.client_cert.is_none() {
+            return Err(ConfigError::General(
+                "TLS_CLIENT_KEY requires TLS_CLIENT_CERT".into(),
+            ));
         }
 
-        let bot_token =
-            env::var("BOT_TOKEN").map_err(|_| ConfigError::MissingEnvVar("BOT_TOKEN".into()))?;
-        let bot_token = bot_token.trim();
-        if bot_token.is_empty() {
-            return Err(ConfigError::MissingEnvVar("BOT_TOKEN".into()));
+        let command = |name: &str| var(name).filter(|v| !v.trim().is_empty());
+        let hooks = HookConfig {
+            before_upload: command("HOOK_BEFORE_UPLOAD"),
+            after_upload: command("HOOK_AFTER_UPLOAD"),
+            before_download: command("HOOK_BEFORE_DOWNLOAD"),
+            after_download: command("HOOK_AFTER_DOWNLOAD"),
+            timeout_secs: env_secs(var, "HOOK_TIMEOUT_SECS", DEFAULT_HOOK_TIMEOUT_SECS)?,
+        };
+
+        let metadata_key = match var("METADATA_KEY") {
+            Some(v) if !v.trim().is_empty() => Some(
+                crate::crypto::parse_metadata_key(&v)
+                    .map_err(|e| ConfigError::General(format!("METADATA_KEY {}", e)))?,
+            ),
+            _ => None,
+        };
+
+        let metadata_mirror = match var("METADATA_MIRROR") {
+            Some(v) => parse_bool(&v).ok_or_else(|| {
+                ConfigError::General("METADATA_MIRROR must be true or false".into())
+            })?,
+            None => false,
+        };
+        if metadata_mirror && metadata_key.is_none() {
+            return Err(ConfigError::General(
+                "METADATA_MIRROR requires METADATA_KEY, which the mirror is encrypted with".into(),
+            ));
         }
+        let metadata_mirror_interval_secs = env_secs(
+            var,
+            "METADATA_MIRROR_INTERVAL_SECS",
+            DEFAULT_METADATA_MIRROR_INTERVAL_SECS,
+        )?;
 
         Ok(Self {
             mongo_uri,
             telegram_api_url,
             telegram_chat_id,
-            bot_id: bot_id.to_string(),
-            bot_token: bot_token.to_string(),
+            archive_chat_id,
+            bots,
             max_concurrency: DEFAULT_MAX_GLOBAL_CONCURRENCY,
+            max_per_bot_concurrency: DEFAULT_MAX_PER_BOT_CONCURRENCY,
+            shutdown_grace_secs,
+            trash_retention_secs,
+            ledger_retention_secs,
+            bandwidth_schedule,
+            metadata_cache_ttl_secs,
+            chat_messages_per_minute,
+            io_buffer_size,
+            http,
+            tls,
+            local_bot_api,
+            hooks,
+            metadata_key,
+            metadata_mirror,
+            metadata_mirror_interval_secs,
+        })
+    }
+}
+
+/// Reads a number of seconds from `name`, falling back to `default` if unset.
+fn env_secs(
+    var: &dyn Fn(&str) -> Option<String>,
+    name: &str,
+    default: u64,
+) -> Result<u64, ConfigError> {
+    match var(name) {
+        Some(v) => v
+            .trim()
+            .parse()
+            .map_err(|_| ConfigError::General(format!("{} must be a number of seconds", name))),
+        None => Ok(default),
+    }
+}
+
+/// Reads an optional path variable, treating an empty value as unset.
+fn env_path(var: &dyn Fn(&str) -> Option<String>, name: &str) -> Option<PathBuf> {
+    var(name)
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" | "" => Some(false),
+        _ => None,
+    }
+}
+
+/// Returns the tgcloud configuration directory, creating it if needed.
+pub fn config_dir() -> Result<PathBuf, ConfigError> {
+    let config_dir = dirs::config_dir()
+        .ok_or_else(|| ConfigError::General("Could not resolve config directory".into()))?
+        .join("tgcloud");
+
+    if !config_dir.exists() {
+        std::fs::create_dir_all(&config_dir).map_err(|e| {
+            ConfigError::General(format!("Failed to create config directory: {}", e))
+        })?;
+    }
+    Ok(config_dir)
+}
+
+/// Parses `BOTS_JSON`, a JSON array of `{"bot_id": "...", "token": "..."}`
+/// objects with optional `weight` and `max_concurrency` fields.
+fn parse_bots_json(json: &str) -> Result<Vec<BotConfig>, ConfigError> {
+    let bots: Vec<BotConfig> =
+        serde_json::from_str(json).map_err(|e| ConfigError::InvalidBotsJson(e.to_string()))?;
+
+    let bots: Vec<BotConfig> = bots
+        .into_iter()
+        .map(|b| BotConfig {
+            bot_id: b.bot_id.trim().to_string(),
+            token: b.token.trim().to_string(),
+            telegram_api_url: b
+                .telegram_api_url
+                .map(|url| url.trim().trim_end_matches('/').to_string())
+                .filter(|url| !url.is_empty()),
+            ..b
         })
+        .collect();
+
+    if bots.is_empty() {
+        return Err(ConfigError::NoValidBotConfig);
+    }
+    if let Some(bad) = bots
+        .iter()
+        .find(|b| b.bot_id.is_empty() || b.token.is_empty())
+    {
+        return Err(ConfigError::InvalidBotsJson(format!(
+            "bot '{}' has an empty bot_id or token",
+            bad.bot_id
+        )));
+    }
+    if let Some(bad) = bots
+        .iter()
+        .find(|b| b.weight == 0 || b.max_concurrency == Some(0))
+    {
+        return Err(ConfigError::InvalidBotsJson(format!(
+            "bot '{}' must have a weight and max_concurrency of at least 1",
+            bad.bot_id
+        )));
+    }
+    if let Some(bad) = bots.iter().find(|b| {
+        b.telegram_api_url
+            .as_deref()
+            .is_some_and(|url| url::Url::parse(url).is_err())
+    }) {
+        return Err(ConfigError::InvalidBotsJson(format!(
+            "bot '{}' has a telegram_api_url that is not a valid URL",
+            bad.bot_id
+        )));
+    }
+    if let Some(bad) = bots.iter().find(|b| b.quota == Some(0)) {
+        return Err(ConfigError::InvalidBotsJson(format!(
+            "bot '{}' has a quota of 0; leave it out for no limit",
+            bad.bot_id
+        )));
+    }
+    Ok(bots)
+}
+
+/// Reads the supervised Bot API server settings, enabled by setting
+/// `TELEGRAM_API_ID` and `TELEGRAM_API_HASH`.
+fn local_bot_api_from_vars(
+    var: &dyn Fn(&str) -> Option<String>,
+) -> Result<Option<LocalBotApiConfig>, ConfigError> {
+    let nonempty = |name| var(name).filter(|v| !v.trim().is_empty());
+    let (api_id, api_hash) = match (nonempty("TELEGRAM_API_ID"), nonempty("TELEGRAM_API_HASH")) {
+        (None, None) => return Ok(None),
+        (Some(id), Some(hash)) => (id.trim().to_string(), hash.trim().to_string()),
+        _ => {
+            return Err(ConfigError::General(
+                "TELEGRAM_API_ID and TELEGRAM_API_HASH must be set together".into(),
+            ))
+        }
+    };
+    if api_id.parse::<u64>().is_err() {
+        return Err(ConfigError::General(
+            "TELEGRAM_API_ID must be a number".into(),
+        ));
+    }
+    let port = match nonempty("BOT_API_PORT") {
+        Some(v) => v
+            .trim()
+            .parse()
+            .map_err(|_| ConfigError::General("BOT_API_PORT must be a port number".into()))?,
+        None => DEFAULT_BOT_API_PORT,
+    };
+    let work_dir = match env_path(var, "BOT_API_DIR") {
+        Some(dir) => dir,
+        None => config_dir()?.join("bot-api"),
+    };
+    Ok(Some(LocalBotApiConfig {
+        api_id,
+        api_hash,
+        binary: env_path(var, "BOT_API_BINARY").unwrap_or_else(|| "telegram-bot-api".into()),
+        port,
+        work_dir,
+    }))
+}
+
+/// Builds the single-bot configuration from `BOT_ID` / `BOT_TOKEN`.
+fn single_bot_from_env(var: &dyn Fn(&str) -> Option<String>) -> Result<BotConfig, ConfigError> {
+    let bot_id = var("BOT_ID").ok_or_else(|| ConfigError::MissingEnvVar("BOT_ID".into()))?;
+    let bot_id = bot_id.trim();
+    if bot_id.is_empty() {
+        return Err(ConfigError::MissingEnvVar("BOT_ID".into()));
+    }
+
+    let bot_token =
+        var("BOT_TOKEN").ok_or_else(|| ConfigError::MissingEnvVar("BOT_TOKEN".into()))?;
+    let bot_token = bot_token.trim();
+    if bot_token.is_empty() {
+        return Err(ConfigError::MissingEnvVar("BOT_TOKEN".into()));
+    }
+
+    Ok(BotConfig {
+        bot_id: bot_id.to_string(),
+        token: bot_token.to_string(),
+        weight: default_bot_weight(),
+        max_concurrency: None,
+        quota: None,
+        telegram_api_url: None,
+    })
+}
+
+/// Whether `url` points at Telegram's public Bot API server, which caps
+/// uploads at 50 MB.
+pub fn is_public_api_url(url: &str) -> bool {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h == "api.telegram.org"))
+        .unwrap_or(false)
+}
+
+/// Every variable tgcloud reads from its `.env` file, including those of
+/// `tgcloud-server`.
+const KNOWN_KEYS: &[&str] = &[
+    "MONGO_URI",
+    "TELEGRAM_API_URL",
+    "TELEGRAM_API_ID",
+    "TELEGRAM_API_HASH",
+    "BOT_API_BINARY",
+    "BOT_API_PORT",
+    "BOT_API_DIR",
+    "TELEGRAM_CHAT_ID",
+    "ARCHIVE_CHAT_ID",
+    "BOTS_JSON",
+    "BOT_ID",
+    "BOT_TOKEN",
+    "SHUTDOWN_GRACE_SECS",
+    "TRASH_RETENTION_SECS",
+    "LEDGER_RETENTION_SECS",
+    "BANDWIDTH_SCHEDULE",
+    "METADATA_CACHE_TTL_SECS",
+    "CHAT_MESSAGES_PER_MINUTE",
+    "IO_BUFFER_SIZE",
+    "METADATA_KEY",
+    "METADATA_MIRROR",
+    "METADATA_MIRROR_INTERVAL_SECS",
+    "HOOK_BEFORE_UPLOAD",
+    "HOOK_AFTER_UPLOAD",
+    "HOOK_BEFORE_DOWNLOAD",
+    "HOOK_AFTER_DOWNLOAD",
+    "HOOK_TIMEOUT_SECS",
+    "HTTP_CONNECT_TIMEOUT_SECS",
+    "HTTP_REQUEST_TIMEOUT_SECS",
+    "HTTP_STALL_TIMEOUT_SECS",
+    "HTTP_POOL_MAX_IDLE_PER_HOST",
+    "HTTP_POOL_IDLE_TIMEOUT_SECS",
+    "HTTP_TCP_KEEPALIVE_SECS",
+    "HTTP2",
+    "TLS_CA_BUNDLE",
+    "TLS_CLIENT_CERT",
+    "TLS_CLIENT_KEY",
+    "TLS_CLIENT_CERT_PASSWORD",
+    "TLS_ACCEPT_INVALID_CERTS",
+    "SERVER_BIND",
+    "SERVER_TLS_CERT",
+    "SERVER_TLS_KEY",
+    "SERVER_WORKER_THREADS",
+    "SERVER_MAX_TRANSFERS",
+    "SERVER_AUTH_TOKEN",
+    "SERVER_UPLOAD_DIR",
+    "RUST_LOG",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IssueSeverity {
+    Error,
+    Warning,
+}
+
+/// A problem found by [`validate_config_file`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigIssue {
+    pub severity: IssueSeverity,
+    /// The variable the problem is about, if it concerns a single one.
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn error(key: Option<&str>, message: impl Into<String>) -> Self {
+        Self {
+            severity: IssueSeverity::Error,
+            key: key.map(str::to_string),
+            message: message.into(),
+        }
+    }
+
+    fn warning(key: Option<&str>, message: impl Into<String>) -> Self {
+        Self {
+            severity: IssueSeverity::Warning,
+            key: key.map(str::to_string),
+            message: message.into(),
+        }
+    }
+}
+
+/// Checks a `.env` file, the default one if `path` is `None`, on its own:
+/// the process environment is ignored and nothing is connected to. Returns
+/// the path that was checked and every problem found.
+pub fn validate_config_file(
+    path: Option<&Path>,
+) -> Result<(PathBuf, Vec<ConfigIssue>), ConfigError> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => config_dir()?.join(".env"),
+    };
+    // Deprecated in favour of loading into the environment, which is exactly
+    // what validation must not do.
+    #[allow(deprecated)]
+    let entries = dotenv::from_path_iter(&path)
+        .map_err(|e| ConfigError::General(format!("Cannot read {}: {}", path.display(), e)))?;
+
+    let mut issues = Vec::new();
+    let mut vars: HashMap<String, String> = HashMap::new();
+    for entry in entries {
+        match entry {
+            Ok((key, value)) => {
+                if !KNOWN_KEYS.contains(&key.as_str()) {
+                    issues.push(ConfigIssue::warning(
+                        Some(&key),
+                        "unknown key, tgcloud ignores it",
+                    ));
+                }
+                match vars.entry(key) {
+                    Entry::Occupied(e) => issues.push(ConfigIssue::warning(
+                        Some(e.key()),
+                        "set more than once, only the first value is used",
+                    )),
+                    Entry::Vacant(e) => {
+                        e.insert(value);
+                    }
+                }
+            }
+            Err(e) => issues.push(ConfigIssue::error(None, format!("unparsable line: {}", e))),
+        }
+    }
+
+    let is_set = |key: &str| vars.get(key).is_some_and(|v| !v.trim().is_empty());
+    let mut required = vec!["MONGO_URI", "TELEGRAM_CHAT_ID"];
+    if is_set("BOTS_JSON") {
+        for key in ["BOT_ID", "BOT_TOKEN"] {
+            if vars.contains_key(key) {
+                issues.push(ConfigIssue::warning(
+                    Some(key),
+                    "ignored because BOTS_JSON is set",
+                ));
+            }
+        }
+    } else {
+        required.extend(["BOT_ID", "BOT_TOKEN"]);
+    }
+    for key in required {
+        if !is_set(key) {
+            issues.push(ConfigIssue::error(Some(key), "required but not set"));
+        }
+    }
+
+    let config = match Config::from_vars(&|name| vars.get(name).cloned()) {
+        Ok(config) => config,
+        // Already reported above, along with any other missing value.
+        Err(ConfigError::MissingEnvVar(_)) => return Ok((path, issues)),
+        Err(e) => {
+            issues.push(ConfigIssue::error(None, e.to_string()));
+            return Ok((path, issues));
+        }
+    };
+
+    if config.local_bot_api.is_some() && vars.contains_key("TELEGRAM_API_URL") {
+        issues.push(ConfigIssue::warning(
+            Some("TELEGRAM_API_URL"),
+            "ignored because TELEGRAM_API_ID starts a local Bot API server",
+        ));
+    }
+    if url::Url::parse(&config.telegram_api_url).is_err() {
+        issues.push(ConfigIssue::error(
+            Some("TELEGRAM_API_URL"),
+            "not a valid URL",
+        ));
+    } else if is_public_api_url(&config.telegram_api_url) {
+        issues.push(ConfigIssue::warning(
+            Some("TELEGRAM_API_URL"),
+            "the public Bot API server limits uploads to 50 MB, so 2 GB chunks will fail",
+        ));
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    for bot in &config.bots {
+        if !seen.insert(bot.bot_id.as_str()) {
+            issues.push(ConfigIssue::warning(
+                Some("BOTS_JSON"),
+                format!("bot '{}' is listed more than once", bot.bot_id),
+            ));
+        }
+        if bot
+            .telegram_api_url
+            .as_deref()
+            .is_some_and(is_public_api_url)
+        {
+            issues.push(ConfigIssue::warning(
+                Some("BOTS_JSON"),
+                format!(
+                    "bot '{}' uses the public Bot API server, which limits uploads to 50 MB, so it fails on larger chunks",
+                    bot.bot_id
+                ),
+            ));
+        }
+        if let Some(max) = bot.max_concurrency {
+            if max > config.max_concurrency {
+                issues.push(ConfigIssue::warning(
+                    Some("BOTS_JSON"),
+                    format!(
+                        "bot '{}' has max_concurrency {}, above the global limit of {}; the extra slots are never used",
+                        bot.bot_id, max, config.max_concurrency
+                    ),
+                ));
+            }
+        }
+    }
+
+    if config.tls.accept_invalid_certs {
+        issues.push(ConfigIssue::warning(
+            Some("TLS_ACCEPT_INVALID_CERTS"),
+            "certificate verification is disabled",
+        ));
+    }
+    for (key, file) in [
+        ("TLS_CA_BUNDLE", &config.tls.ca_bundle),
+        ("TLS_CLIENT_CERT", &config.tls.client_cert),
+        ("TLS_CLIENT_KEY", &config.tls.client_key),
+    ] {
+        if let Some(file) = file {
+            if !file.exists() {
+                issues.push(ConfigIssue::error(
+                    Some(key),
+                    format!("{} does not exist", file.display()),
+                ));
+            }
+        }
+    }
+    if config.http.stall_timeout_secs > 0
+        && config.http.request_timeout_secs > 0
+        && config.http.stall_timeout_secs >= config.http.request_timeout_secs
+    {
+        issues.push(ConfigIssue::warning(
+            Some("HTTP_STALL_TIMEOUT_SECS"),
+            "not below HTTP_REQUEST_TIMEOUT_SECS, so stalls are never detected before the request times out",
+        ));
+    }
+
+    Ok((path, issues))
+}
+
+/// Replaces the token of `bot_id` in a `.env` file, the default one if `path`
+/// is `None`: in its `BOTS_JSON` entry, or in `BOT_TOKEN` for a single bot.
+/// Every other line is kept as it is. Returns the path that was written.
+pub fn set_bot_token_in_file(
+    path: Option<&Path>,
+    bot_id: &str,
+    token: &str,
+) -> Result<PathBuf, ConfigError> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => config_dir()?.join(".env"),
+    };
+    let read_error = |e: &dyn std::fmt::Display| {
+        ConfigError::General(format!("Cannot read {}: {}", path.display(), e))
+    };
+    #[allow(deprecated)]
+    let entries = dotenv::from_path_iter(&path).map_err(|e| read_error(&e))?;
+    let mut vars: HashMap<String, String> = HashMap::new();
+    for entry in entries {
+        let (key, value) = entry.map_err(|e| read_error(&e))?;
+        vars.entry(key).or_insert(value);
+    }
+
+    let (key, line) = match vars.get("BOTS_JSON").filter(|v| !v.trim().is_empty()) {
+        Some(json) => {
+            let mut bots: Vec<serde_json::Value> = serde_json::from_str(json)
+                .map_err(|e| ConfigError::InvalidBotsJson(e.to_string()))?;
+            let bot = bots
+                .iter_mut()
+                .find(|b| b["bot_id"].as_str().map(str::trim) == Some(bot_id))
+                .ok_or_else(|| {
+                    ConfigError::General(format!(
+                        "bot '{}' is not in BOTS_JSON in {}",
+                        bot_id,
+                        path.display()
+                    ))
+                })?;
+            bot["token"] = token.into();
+            let json = serde_json::to_string(&bots)
+                .map_err(|e| ConfigError::InvalidBotsJson(e.to_string()))?;
+            // Single quotes keep the JSON's double quotes and `$` literal.
+            if json.contains('\'') {
+                return Err(ConfigError::InvalidBotsJson(
+                    "cannot be rewritten because it contains a single quote".into(),
+                ));
+            }
+            ("BOTS_JSON", format!("BOTS_JSON='{}'", json))
+        }
+        None if vars.get("BOT_ID").map(|id| id.trim()) == Some(bot_id) => {
+            ("BOT_TOKEN", format!("BOT_TOKEN={}", token))
+        }
+        None => {
+            return Err(ConfigError::General(format!(
+                "bot '{}' is not configured in {}",
+                bot_id,
+                path.display()
+            )))
+        }
+    };
+
+    let contents = std::fs::read_to_string(&path).map_err(|e| read_error(&e))?;
+    let mut replaced = false;
+    let mut lines: Vec<&str> = Vec::new();
+    for existing in contents.lines() {
+        let assignment = existing.trim_start();
+        let assignment = assignment.strip_prefix("export ").unwrap_or(assignment);
+        let is_key = assignment
+            .strip_prefix(key)
+            .is_some_and(|rest| rest.trim_start().starts_with('='));
+        if is_key && !replaced {
+            lines.push(&line);
+            replaced = true;
+        } else {
+            lines.push(existing);
+        }
+    }
+    if !replaced {
+        return Err(ConfigError::General(format!(
+            "{} is not set in {}",
+            key,
+            path.display()
+        )));
+    }
+
+    // Written next to the original and renamed over it, so an interrupted
+    // write cannot lose the configuration. The file holds secrets: it is
+    // created readable by the owner only and gets the original's permissions
+    // once written.
+    let write_error =
+        |e: std::io::Error| ConfigError::General(format!("Cannot write {}: {}", path.display(), e));
+    let temp = path.with_file_name(".env.rotate.tmp");
+    let permissions = std::fs::metadata(&path).map_err(write_error)?.permissions();
+    // A leftover from an interrupted rotation may have looser permissions.
+    match std::fs::remove_file(&temp) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(write_error(e)),
+        _ => {}
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    {
+        use std::io::Write;
+        let mut file = options.open(&temp).map_err(write_error)?;
+        file.write_all((lines.join("\n") + "\n").as_bytes())
+            .map_err(write_error)?;
     }
+    std::fs::set_permissions(&temp, permissions).map_err(write_error)?;
+    std::fs::rename(&temp, &path).map_err(write_error)?;
+    Ok(path)
 }
@@ -0,0 +1,106 @@
+use crate::errors::{Result, TgCloudError};
+
+use aes_gcm_siv::aead::{Aead, KeyInit};
+use aes_gcm_siv::{Aes256GcmSiv, Nonce};
+use base64::Engine;
+
+/// Marks an encrypted value, so values written before encryption was enabled
+/// can still be told apart and read.
+const SEALED_PREFIX: &str = "enc1:";
+
+/// Encrypts metadata strings (file names, history entries) before they are
+/// written to MongoDB.
+///
+/// Encryption is deterministic: AES-256-GCM-SIV with a fixed nonce, which is
+/// safe with repeated nonces and only reveals whether two values are equal.
+/// That keeps exact lookups by name working in the store; listings, globs and
+/// searches decrypt the names and match them in memory instead.
+pub struct MetadataCipher {
+    cipher: Aes256GcmSiv,
+}
+
+impl MetadataCipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self {
+            cipher: Aes256GcmSiv::new(key.into()),
+        }
+    }
+
+    /// Encrypts `plain` into a printable `enc1:` value.
+    pub fn seal(&self, plain: &str) -> String {
+        let sealed = self
+            .cipher
+            .encrypt(Nonce::from_slice(&[0; 12]), plain.as_bytes())
+            .expect("AES-GCM-SIV encryption cannot fail for in-memory strings");
+        format!(
+            "{}{}",
+            SEALED_PREFIX,
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(sealed)
+        )
+    }
+
+    /// Decrypts a value written by [`MetadataCipher::seal`]. Values without
+    /// the `enc1:` prefix are returned unchanged.
+    pub fn open(&self, value: &str) -> Result<String> {
+        let Some(encoded) = value.strip_prefix(SEALED_PREFIX) else {
+            return Ok(value.to_string());
+        };
+        base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(encoded)
+            .ok()
+            .and_then(|sealed| {
+                self.cipher
+                    .decrypt(Nonce::from_slice(&[0; 12]), sealed.as_slice())
+                    .ok()
+            })
+            .and_then(|plain| String::from_utf8(plain).ok())
+            .ok_or_else(|| {
+                TgCloudError::IntegrityFailed(
+                    "Cannot decrypt metadata: METADATA_KEY differs from the one it was written with"
+                        .to_string(),
+                )
+            })
+    }
+
+    /// Encrypts `plain` with a random nonce, which is stored in front of the
+    /// ciphertext. Unlike [`MetadataCipher::seal`], equal inputs give
+    /// different outputs.
+    pub fn seal_bytes(&self, plain: &[u8]) -> Vec<u8> {
+        use rand::RngCore;
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let sealed = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plain)
+            .expect("AES-GCM-SIV encryption cannot fail for in-memory buffers");
+        [nonce.as_slice(), &sealed].concat()
+    }
+
+    /// Decrypts a buffer written by [`MetadataCipher::seal_bytes`].
+    pub fn open_bytes(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        let (nonce, ciphertext) = sealed.split_at_checked(12).ok_or_else(|| {
+            TgCloudError::IntegrityFailed("Encrypted data is truncated".to_string())
+        })?;
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                TgCloudError::IntegrityFailed(
+                    "Cannot decrypt: METADATA_KEY differs from the one it was written with"
+                        .to_string(),
+                )
+            })
+    }
+}
+
+/// Whether `value` was written by [`MetadataCipher::seal`].
+pub fn is_sealed(value: &str) -> bool {
+    value.starts_with(SEALED_PREFIX)
+}
+
+/// Parses a 256-bit key given as 64 hex digits (e.g. `openssl rand -hex 32`).
+pub fn parse_metadata_key(hex_key: &str) -> std::result::Result<[u8; 32], String> {
+    let bytes = hex::decode(hex_key.trim()).map_err(|_| "must be hex digits".to_string())?;
+    bytes
+        .try_into()
+        .map_err(|_| "must be 64 hex digits (32 bytes)".to_string())
+}
@@ -0,0 +1,149 @@
+use crate::errors::{Result, TgCloudError};
+use crate::filter::PathFilter;
+use crate::models::FileMetadata;
+use crate::service::sha256_file;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffKind {
+    /// Present locally, missing remotely.
+    OnlyLocal,
+    /// Present remotely, missing locally.
+    OnlyRemote,
+    /// Present on both sides with a different size or SHA-256.
+    Modified,
+    Unchanged,
+}
+
+/// One file compared between a local directory and a remote folder.
+#[derive(Debug, Clone)]
+pub struct DiffEntry {
+    /// Path relative to the compared directory, with `/` separators.
+    pub name: String,
+    pub kind: DiffKind,
+    pub local_path: Option<PathBuf>,
+    pub local_size: Option<u64>,
+    pub remote: Option<FileMetadata>,
+}
+
+/// Result of comparing a local directory with a remote folder, sorted by name.
+#[derive(Debug, Clone, Default)]
+pub struct DirDiff {
+    pub entries: Vec<DiffEntry>,
+}
+
+impl DirDiff {
+    pub fn of_kind(&self, kind: DiffKind) -> impl Iterator<Item = &DiffEntry> {
+        self.entries.iter().filter(move |e| e.kind == kind)
+    }
+
+    pub fn count(&self, kind: DiffKind) -> usize {
+        self.of_kind(kind).count()
+    }
+
+    /// Whether both sides hold the same files.
+    pub fn is_clean(&self) -> bool {
+        self.entries.iter().all(|e| e.kind == DiffKind::Unchanged)
+    }
+}
+
+/// Remote name prefix that files of the folder `prefix` start with.
+pub fn remote_root(prefix: &str) -> String {
+    let trimmed = prefix.trim_end_matches('/');
+    if prefix == "root" || trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", trimmed)
+    }
+}
+
+/// Regular files below `dir`, keyed by their `/`-separated relative path.
+pub fn walk_local(dir: &Path) -> Result<BTreeMap<String, (PathBuf, u64)>> {
+    let mut files = BTreeMap::new();
+    for entry in walkdir::WalkDir::new(dir).follow_links(true) {
+        let entry = entry.map_err(|e| {
+            TgCloudError::Unknown(format!("Failed to read {}: {}", dir.display(), e))
+        })?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(dir)
+            .expect("walkdir yields paths below its root");
+        let name = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let size = entry
+            .metadata()
+            .map_err(|e| TgCloudError::Unknown(e.to_string()))?
+            .len();
+        files.insert(name, (entry.path().to_path_buf(), size));
+    }
+    Ok(files)
+}
+
+/// Compares the files below `local_dir` with `remote`, whose names are
+/// expected to start with `remote_root`. Sizes are compared first; files of
+/// equal size are hashed, `buffer_size` bytes at a time, to detect
+/// modifications. Paths rejected by `filter` are left out entirely.
+pub async fn diff_dir(
+    local_dir: &Path,
+    remote: &[FileMetadata],
+    remote_root: &str,
+    filter: &PathFilter,
+    buffer_size: usize,
+) -> Result<DirDiff> {
+    let mut local = walk_local(local_dir)?;
+    local.retain(|name, _| filter.matches(name));
+    let mut entries = Vec::new();
+
+    for file in remote {
+        let Some(name) = file.original_name.strip_prefix(remote_root) else {
+            continue;
+        };
+        if !filter.matches(name) {
+            continue;
+        }
+        let entry = match local.remove(name) {
+            None => DiffEntry {
+                name: name.to_string(),
+                kind: DiffKind::OnlyRemote,
+                local_path: None,
+                local_size: None,
+                remote: Some(file.clone()),
+            },
+            Some((path, size)) => {
+                let unchanged =
+                    size == file.size && sha256_file(&path, buffer_size).await? == file.sha256;
+                DiffEntry {
+                    name: name.to_string(),
+                    kind: if unchanged {
+                        DiffKind::Unchanged
+                    } else {
+                        DiffKind::Modified
+                    },
+                    local_path: Some(path),
+                    local_size: Some(size),
+                    remote: Some(file.clone()),
+                }
+            }
+        };
+        entries.push(entry);
+    }
+
+    entries.extend(local.into_iter().map(|(name, (path, size))| DiffEntry {
+        name,
+        kind: DiffKind::OnlyLocal,
+        local_path: Some(path),
+        local_size: Some(size),
+        remote: None,
+    }));
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(DirDiff { entries })
+}
@@ -0,0 +1,376 @@
+use crate::bot_api_server::{bot_api_log_path, BotApiSupervisor};
+use crate::config::{config_dir, is_public_api_url, Config};
+use crate::errors::{ConfigError, TgCloudError};
+use crate::storage::MongoStore;
+use crate::telegram_client::{build_http_client, TelegramClient};
+
+use serde::Serialize;
+use std::time::Duration;
+
+/// How long a single network check may take before it counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// Outcome of one `tgcloud doctor` check.
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// What to do about a warning or failure.
+    pub fix: Option<String>,
+}
+
+impl CheckResult {
+    fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn warn(name: impl Into<String>, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn fail(name: impl Into<String>, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+/// Checks the configuration, MongoDB, the Bot API server, every bot and the
+/// temp directory. Checks that depend on a failed one are skipped, so this
+/// never needs a working setup to run.
+pub async fn run_doctor() -> Vec<CheckResult> {
+    let mut results = Vec::new();
+
+    let env_path = match config_dir() {
+        Ok(dir) => dir.join(".env"),
+        Err(e) => {
+            results.push(CheckResult::fail(
+                "Config file",
+                e.to_string(),
+                "Set HOME (or the platform's config directory) for the user running tgcloud",
+            ));
+            return results;
+        }
+    };
+    if !env_path.exists() {
+        results.push(CheckResult::fail(
+            "Config file",
+            format!("{} does not exist", env_path.display()),
+            "Create it with MONGO_URI, TELEGRAM_CHAT_ID and BOT_ID/BOT_TOKEN (see README)",
+        ));
+        return results;
+    }
+    results.push(CheckResult::pass(
+        "Config file",
+        env_path.display().to_string(),
+    ));
+
+    let config = match Config::from_env() {
+        Ok(config) => {
+            results.push(CheckResult::pass(
+                "Config values",
+                format!(
+                    "{} bot(s), chat {}",
+                    config.bots.len(),
+                    config.telegram_chat_id
+                ),
+            ));
+            config
+        }
+        Err(e) => {
+            let fix = match &e {
+                ConfigError::MissingEnvVar(var) => {
+                    format!("Add {}=... to {}", var, env_path.display())
+                }
+                ConfigError::InvalidBotsJson(_) => {
+                    r#"BOTS_JSON must be a JSON list like [{"bot_id":"123","token":"123:AAA"}]"#
+                        .to_string()
+                }
+                _ => format!("Correct the value in {}", env_path.display()),
+            };
+            results.push(CheckResult::fail("Config values", e.to_string(), fix));
+            return results;
+        }
+    };
+
+    check_mongo(&config, &mut results).await;
+    check_telegram(&config, &mut results).await;
+    results.push(check_temp_dir());
+    results
+}
+
+async fn check_mongo(config: &Config, results: &mut Vec<CheckResult>) {
+    let store = match MongoStore::new(&config.mongo_uri).await {
+        Ok(store) => store,
+        Err(e) => {
+            results.push(CheckResult::fail(
+                "MongoDB",
+                e.to_string(),
+                "Check the format of MONGO_URI",
+            ));
+            return;
+        }
+    };
+    match tokio::time::timeout(CHECK_TIMEOUT, store.ping()).await {
+        Ok(Ok(())) => results.push(CheckResult::pass("MongoDB", "reachable")),
+        Ok(Err(e)) => {
+            results.push(CheckResult::fail(
+                "MongoDB",
+                e.to_string(),
+                "Check MONGO_URI, the credentials and that the server is running",
+            ));
+            return;
+        }
+        Err(_) => {
+            results.push(CheckResult::fail(
+                "MongoDB",
+                format!("no answer within {}s", CHECK_TIMEOUT.as_secs()),
+                "Check that MONGO_URI points at a running server reachable from this host",
+            ));
+            return;
+        }
+    }
+    match store.missing_indexes().await {
+        Ok(missing) if missing.is_empty() => {
+            results.push(CheckResult::pass("MongoDB indexes", "present"))
+        }
+        Ok(missing) => results.push(CheckResult::warn(
+            "MongoDB indexes",
+            format!("missing: {}", missing.join(", ")),
+            "Run any tgcloud command to create them; if it warns that index creation failed, grant the user the createIndex privilege",
+        )),
+        Err(e) => results.push(CheckResult::warn(
+            "MongoDB indexes",
+            e.to_string(),
+            "Grant the user the listIndexes privilege",
+        )),
+    }
+}
+
+async fn check_telegram(config: &Config, results: &mut Vec<CheckResult>) {
+    let http = match build_http_client(&config.http, &config.tls) {
+        Ok(http) => http,
+        Err(e) => {
+            results.push(CheckResult::fail(
+                "Bot API server",
+                e.to_string(),
+                "Check the TLS_* and HTTP_* settings",
+            ));
+            return;
+        }
+    };
+
+    // Kept alive until the bots have been checked against it.
+    let _supervisor = match &config.local_bot_api {
+        Some(local) => match BotApiSupervisor::start(local.clone(), http.clone()).await {
+            Ok(supervisor) => supervisor,
+            Err(e) => {
+                results.push(CheckResult::fail(
+                    "Bot API server",
+                    e.to_string(),
+                    format!(
+                        "Install telegram-bot-api or set BOT_API_BINARY, check TELEGRAM_API_ID/TELEGRAM_API_HASH and see {}",
+                        bot_api_log_path(local).display()
+                    ),
+                ));
+                return;
+            }
+        },
+        None => None,
+    };
+    let client = TelegramClient::with_client(http, config.telegram_api_url.clone())
+        .with_bot_api_urls(config.bot_api_urls());
+
+    match tokio::time::timeout(CHECK_TIMEOUT, client.probe(&config.telegram_api_url)).await {
+        Ok(Ok(status)) => results.push(CheckResult::pass(
+            "Bot API server",
+            format!("{} answered HTTP {}", config.telegram_api_url, status),
+        )),
+        Ok(Err(e)) => {
+            results.push(CheckResult::fail(
+                "Bot API server",
+                e.to_string(),
+                "Start the local Bot API server or correct TELEGRAM_API_URL",
+            ));
+            return;
+        }
+        Err(_) => {
+            results.push(CheckResult::fail(
+                "Bot API server",
+                format!("no answer within {}s", CHECK_TIMEOUT.as_secs()),
+                "Start the local Bot API server or correct TELEGRAM_API_URL",
+            ));
+            return;
+        }
+    }
+    check_bot_api_urls(config, &client, results).await;
+
+    if is_public_api_url(&config.telegram_api_url) {
+        results.push(CheckResult::fail(
+            "Bot API mode",
+            "public api.telegram.org (50 MB upload limit)",
+            "Run a local Bot API server (telegram-bot-api --local) and point TELEGRAM_API_URL at it; chunks are up to 2 GB",
+        ));
+    } else {
+        results.push(CheckResult::pass(
+            "Bot API mode",
+            "self-hosted server (2 GB uploads with --local)",
+        ));
+    }
+
+    for bot in &config.bots {
+        let name = format!("Bot {}", bot.bot_id);
+        match tokio::time::timeout(CHECK_TIMEOUT, client.get_me(&bot.token)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(TgCloudError::BotUnauthorized { status, .. })) => {
+                results.push(CheckResult::fail(
+                    name,
+                    format!("token rejected (HTTP {})", status),
+                    "Create a new token with @BotFather and update the configuration",
+                ));
+                continue;
+            }
+            Ok(Err(e)) => {
+                results.push(CheckResult::fail(
+                    name,
+                    format!("getMe failed: {}", e),
+                    if bot.telegram_api_url.is_some() {
+                        "Check the bot token and that its telegram_api_url is reachable"
+                    } else {
+                        "Check the bot token"
+                    },
+                ));
+                continue;
+            }
+            Err(_) => {
+                results.push(CheckResult::fail(
+                    name,
+                    "getMe timed out",
+                    "Check the Bot API server",
+                ));
+                continue;
+            }
+        }
+        let membership = tokio::time::timeout(
+            CHECK_TIMEOUT,
+            client.chat_member_status(&bot.token, &config.telegram_chat_id),
+        )
+        .await;
+        results.push(match membership {
+            Ok(Ok(status)) if status == "administrator" || status == "creator" => {
+                CheckResult::pass(name, format!("token valid, {} of the chat", status))
+            }
+            Ok(Ok(status)) if status == "member" => CheckResult::warn(
+                name,
+                "token valid, member of the chat",
+                "Make the bot an administrator if the chat is a channel, otherwise it cannot post",
+            ),
+            Ok(Ok(status)) => CheckResult::fail(
+                name,
+                format!("token valid, but the bot is {} in the chat", status),
+                format!("Add the bot to chat {}", config.telegram_chat_id),
+            ),
+            Ok(Err(e)) => CheckResult::fail(
+                name,
+                format!("token valid, chat membership unknown: {}", e),
+                format!(
+                    "Check TELEGRAM_CHAT_ID and add the bot to chat {}",
+                    config.telegram_chat_id
+                ),
+            ),
+            Err(_) => {
+                CheckResult::fail(name, "getChatMember timed out", "Check the Bot API server")
+            }
+        });
+    }
+}
+
+/// Probes every distinct per-bot `telegram_api_url` other than
+/// `TELEGRAM_API_URL`, and warns about those pointing at the public Bot API.
+async fn check_bot_api_urls(
+    config: &Config,
+    client: &TelegramClient,
+    results: &mut Vec<CheckResult>,
+) {
+    let mut servers: Vec<(&str, Vec<&str>)> = Vec::new();
+    for bot in &config.bots {
+        let Some(url) = bot.telegram_api_url.as_deref() else {
+            continue;
+        };
+        if url == config.telegram_api_url {
+            continue;
+        }
+        match servers.iter_mut().find(|(u, _)| *u == url) {
+            Some((_, bots)) => bots.push(&bot.bot_id),
+            None => servers.push((url, vec![&bot.bot_id])),
+        }
+    }
+
+    for (url, bots) in servers {
+        let name = format!("Bot API server {}", url);
+        let fix = format!(
+            "Start that Bot API server or correct the telegram_api_url of bot(s) {}",
+            bots.join(", ")
+        );
+        match tokio::time::timeout(CHECK_TIMEOUT, client.probe(url)).await {
+            Ok(Ok(status)) => results.push(CheckResult::pass(
+                name.as_str(),
+                format!("answered HTTP {}", status),
+            )),
+            Ok(Err(e)) => results.push(CheckResult::fail(name.as_str(), e.to_string(), fix)),
+            Err(_) => results.push(CheckResult::fail(
+                name.as_str(),
+                format!("no answer within {}s", CHECK_TIMEOUT.as_secs()),
+                fix,
+            )),
+        }
+        if is_public_api_url(url) {
+            results.push(CheckResult::warn(
+                format!("Bot API mode {}", url),
+                format!(
+                    "public api.telegram.org (50 MB upload limit) for bot(s) {}",
+                    bots.join(", ")
+                ),
+                "These bots only take files under 50 MB; point their telegram_api_url at a local Bot API server for 2 GB chunks",
+            ));
+        }
+    }
+}
+
+fn check_temp_dir() -> CheckResult {
+    let dir = std::env::temp_dir();
+    let probe = dir.join(format!("tgcloud-doctor-{}", std::process::id()));
+    match std::fs::write(&probe, b"tgcloud") {
+        Ok(()) => {
+            let _ = std::fs::remove_file(&probe);
+            CheckResult::pass("Temp directory", format!("{} is writable", dir.display()))
+        }
+        Err(e) => CheckResult::fail(
+            "Temp directory",
+            format!("{}: {}", dir.display(), e),
+            "Make it writable or point TMPDIR at a writable directory",
+        ),
+    }
+}
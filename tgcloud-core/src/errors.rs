@@ -1,3 +1,5 @@
+use serde::{Serialize, Serializer};
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -32,9 +34,18 @@ pub enum TgCloudError {
     #[error("Bot manager error: {0}")]
     BotManagerError(String),
 
+    #[error("Local Bot API server: {0}")]
+    BotApiServer(String),
+
+    #[error("Bot rejected by Telegram (HTTP {status}): {message}")]
+    BotUnauthorized { status: u16, message: String },
+
     #[error("File not found: {0}")]
     FileNotFound(String),
 
+    #[error("Already exists: {0}")]
+    AlreadyExists(String),
+
     #[error("Configuration error: {0}")]
     ConfigError(#[from] ConfigError),
 
@@ -50,14 +61,229 @@ pub enum TgCloudError {
     #[error("Integrity error: {0}")]
     IntegrityFailed(String),
 
-    #[error("Rate limited: {0}")]
-    RateLimited(String),
+    /// A request that failed in a way expected to go away when repeated.
+    #[error("{message}")]
+    Transient {
+        kind: TransientKind,
+        http_status: Option<u16>,
+        /// How long Telegram asked us to wait before trying again.
+        retry_after: Option<Duration>,
+        message: String,
+    },
 
     #[error("Retry exhausted after {attempts} attempts: {last_error}")]
     RetryExhausted { attempts: u32, last_error: String },
 
+    #[error("Transfer cancelled")]
+    Cancelled,
+
+    #[error("Service is shutting down and not accepting new transfers")]
+    ShuttingDown,
+
+    #[error("Nothing to undo")]
+    NothingToUndo,
+
     #[error("Unknown error: {0}")]
     Unknown(String),
+
+    /// An error raised while transferring one chunk through one bot.
+    #[error("Chunk {index} via bot {bot_id}: {source}")]
+    InChunk {
+        index: u32,
+        bot_id: String,
+        source: Box<TgCloudError>,
+    },
+
+    /// An error raised while running a transfer, tagged with its id.
+    #[error("{source} [transfer {transfer_id}]")]
+    InTransfer {
+        transfer_id: String,
+        file: String,
+        source: Box<TgCloudError>,
+    },
+}
+
+/// Why a request failed transiently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransientKind {
+    /// Telegram answered 429 Too Many Requests.
+    RateLimited,
+    /// The Bot API server answered with a 5xx status.
+    ServerError,
+    /// The request or response body stopped moving for the stall timeout.
+    Stalled,
+    /// Telegram stored fewer bytes than were sent.
+    Truncated,
+}
+
+/// Serialized form of a `TgCloudError`: a stable `code` for programs, the
+/// message for humans and whatever structured context is known.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_index: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bot_id: Option<String>,
+    /// Status of the Telegram response that caused the error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
+}
+
+impl ErrorReport {
+    /// A report for an error that did not come from a `TgCloudError`.
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            transfer_id: None,
+            file: None,
+            chunk_index: None,
+            bot_id: None,
+            http_status: None,
+            retry_after_secs: None,
+        }
+    }
+}
+
+impl TgCloudError {
+    /// Tags the error with the transfer it happened in (once).
+    pub fn in_transfer(self, transfer_id: &str, file: &str) -> Self {
+        match self {
+            TgCloudError::InTransfer { .. } => self,
+            other => TgCloudError::InTransfer {
+                transfer_id: transfer_id.to_string(),
+                file: file.to_string(),
+                source: Box::new(other),
+            },
+        }
+    }
+
+    /// Tags the error with the chunk and bot it happened on. Cancellation is
+    /// left untagged since it is not a failure of that chunk.
+    pub fn in_chunk(self, index: u32, bot_id: &str) -> Self {
+        match self {
+            TgCloudError::Cancelled | TgCloudError::InChunk { .. } => self,
+            other => TgCloudError::InChunk {
+                index,
+                bot_id: bot_id.to_string(),
+                source: Box::new(other),
+            },
+        }
+    }
+
+    /// The error without its transfer and chunk tags.
+    pub fn root(&self) -> &TgCloudError {
+        match self {
+            TgCloudError::InTransfer { source, .. } | TgCloudError::InChunk { source, .. } => {
+                source.root()
+            }
+            other => other,
+        }
+    }
+
+    /// Stable identifier of the kind of error, for API clients and scripts.
+    pub fn code(&self) -> &'static str {
+        match self.root() {
+            TgCloudError::MongoError(_) => "metadata_store",
+            TgCloudError::TelegramError(_) => "telegram_request",
+            TgCloudError::IoError(_) => "io",
+            TgCloudError::BotManagerError(_) => "bot_manager",
+            TgCloudError::BotApiServer(_) => "bot_api_server",
+            TgCloudError::BotUnauthorized { .. } => "bot_unauthorized",
+            TgCloudError::FileNotFound(_) => "file_not_found",
+            TgCloudError::AlreadyExists(_) => "already_exists",
+            TgCloudError::ConfigError(_) => "config",
+            TgCloudError::UploadFailed(_) => "upload_failed",
+            TgCloudError::DownloadFailed(_) => "download_failed",
+            TgCloudError::DeleteFailed(_) => "delete_failed",
+            TgCloudError::IntegrityFailed(_) => "integrity_failed",
+            TgCloudError::Transient { kind, .. } => match kind {
+                TransientKind::RateLimited => "rate_limited",
+                TransientKind::ServerError => "telegram_unavailable",
+                TransientKind::Stalled => "stalled",
+                TransientKind::Truncated => "truncated",
+            },
+            TgCloudError::RetryExhausted { .. } => "retry_exhausted",
+            TgCloudError::Cancelled => "cancelled",
+            TgCloudError::ShuttingDown => "shutting_down",
+            TgCloudError::NothingToUndo => "nothing_to_undo",
+            TgCloudError::Unknown(_) => "unknown",
+            TgCloudError::InChunk { .. } | TgCloudError::InTransfer { .. } => {
+                unreachable!("root() strips context")
+            }
+        }
+    }
+
+    /// Status of the Telegram response behind the error, if there was one.
+    pub fn http_status(&self) -> Option<u16> {
+        match self.root() {
+            TgCloudError::BotUnauthorized { status, .. } => Some(*status),
+            TgCloudError::Transient { http_status, .. } => *http_status,
+            TgCloudError::TelegramError(e) => e.status().map(|s| s.as_u16()),
+            _ => None,
+        }
+    }
+
+    /// Collects the code, message and context of the error.
+    pub fn report(&self) -> ErrorReport {
+        let mut report = ErrorReport::new(self.code(), self.to_string());
+        report.http_status = self.http_status();
+        if let TgCloudError::Transient { retry_after, .. } = self.root() {
+            report.retry_after_secs = retry_after.map(|d| d.as_secs());
+        }
+        let mut current = self;
+        loop {
+            match current {
+                TgCloudError::InTransfer {
+                    transfer_id,
+                    file,
+                    source,
+                } => {
+                    report.transfer_id = Some(transfer_id.clone());
+                    report.file = Some(file.clone());
+                    current = source;
+                }
+                TgCloudError::InChunk {
+                    index,
+                    bot_id,
+                    source,
+                } => {
+                    report.chunk_index = Some(*index);
+                    report.bot_id = Some(bot_id.clone());
+                    current = source;
+                }
+                TgCloudError::FileNotFound(path) | TgCloudError::AlreadyExists(path) => {
+                    report.file.get_or_insert_with(|| path.clone());
+                    break;
+                }
+                _ => break,
+            }
+        }
+        report
+    }
+
+    /// Id of the transfer the error happened in, if known.
+    pub fn transfer_id(&self) -> Option<&str> {
+        match self {
+            TgCloudError::InTransfer { transfer_id, .. } => Some(transfer_id),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for TgCloudError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.report().serialize(serializer)
+    }
 }
 
 pub type Result<T> = std::result::Result<T, TgCloudError>;
@@ -0,0 +1,67 @@
+use crate::errors::{Result, TgCloudError};
+use crate::storage::glob_to_regex;
+
+use regex::Regex;
+
+/// An `--include` or `--exclude` glob.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterRule {
+    Include(String),
+    Exclude(String),
+}
+
+/// Ordered include/exclude rules with rsync-like precedence: the first rule
+/// matching a path decides, and paths matching no rule are included.
+///
+/// A pattern without `/` is matched against every component of the path, so
+/// `--exclude node_modules` skips everything inside such a directory. A pattern
+/// containing `/` is matched against the path relative to the transfer root,
+/// and against each of its parent directories.
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    rules: Vec<(bool, bool, Regex)>,
+}
+
+impl PathFilter {
+    pub fn new(rules: &[FilterRule]) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let (include, pattern) = match rule {
+                    FilterRule::Include(p) => (true, p),
+                    FilterRule::Exclude(p) => (false, p),
+                };
+                let pattern = pattern.trim_start_matches('/').trim_end_matches('/');
+                let anchored = pattern.contains('/');
+                let regex = Regex::new(&glob_to_regex(pattern)).map_err(|e| {
+                    TgCloudError::Unknown(format!("Invalid pattern '{}': {}", pattern, e))
+                })?;
+                Ok((include, anchored, regex))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether the relative, `/`-separated `path` passes the filter.
+    pub fn matches(&self, path: &str) -> bool {
+        let path = path.trim_start_matches('/');
+        for (include, anchored, regex) in &self.rules {
+            let hit = if *anchored {
+                path.match_indices('/')
+                    .map(|(i, _)| &path[..i])
+                    .chain(std::iter::once(path))
+                    .any(|prefix| regex.is_match(prefix))
+            } else {
+                path.split('/').any(|component| regex.is_match(component))
+            };
+            if hit {
+                return *include;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,161 @@
+//! Commands and callbacks run before and after uploads and downloads, e.g. to
+//! send a notification, index new files or prune local copies.
+
+use crate::config::HookConfig;
+use crate::errors::{Result, TgCloudError};
+use crate::models::FileMetadata;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// When a hook runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    BeforeUpload,
+    AfterUpload,
+    BeforeDownload,
+    AfterDownload,
+}
+
+impl std::fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            HookEvent::BeforeUpload => "before_upload",
+            HookEvent::AfterUpload => "after_upload",
+            HookEvent::BeforeDownload => "before_download",
+            HookEvent::AfterDownload => "after_download",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What a hook is told about the transfer.
+#[derive(Debug, Clone, Serialize)]
+pub struct HookContext {
+    pub event: HookEvent,
+    pub transfer_id: String,
+    /// Remote name of the file.
+    pub path: String,
+    /// File being uploaded, or where a finished download was written.
+    pub local_path: Option<PathBuf>,
+    /// Stored metadata of `path`, if any: before an upload, that of the file
+    /// it replaces. Unset after a failed transfer.
+    pub file: Option<FileMetadata>,
+    /// Outcome of the transfer, for the `after_*` events.
+    pub success: Option<bool>,
+    pub error: Option<String>,
+}
+
+/// Called around every upload and download, for library users; see
+/// [`crate::TgCloudService::with_hook`]. An error from a `before_*` event
+/// aborts the transfer; errors from `after_*` events are only logged.
+#[async_trait]
+pub trait TransferHook: Send + Sync {
+    async fn on_transfer(&self, context: &HookContext) -> Result<()>;
+}
+
+/// Runs the shell commands from `HOOK_BEFORE_UPLOAD`, `HOOK_AFTER_UPLOAD`,
+/// `HOOK_BEFORE_DOWNLOAD` and `HOOK_AFTER_DOWNLOAD`.
+///
+/// The context is passed as JSON on stdin and its main fields as `TGCLOUD_*`
+/// environment variables. A command that exits with a non-zero status or runs
+/// longer than `HOOK_TIMEOUT_SECS` fails the hook.
+pub struct ShellHook {
+    config: HookConfig,
+}
+
+impl ShellHook {
+    pub fn new(config: HookConfig) -> Self {
+        Self { config }
+    }
+
+    fn command_for(&self, event: HookEvent) -> Option<&str> {
+        match event {
+            HookEvent::BeforeUpload => self.config.before_upload.as_deref(),
+            HookEvent::AfterUpload => self.config.after_upload.as_deref(),
+            HookEvent::BeforeDownload => self.config.before_download.as_deref(),
+            HookEvent::AfterDownload => self.config.after_download.as_deref(),
+        }
+    }
+}
+
+#[async_trait]
+impl TransferHook for ShellHook {
+    async fn on_transfer(&self, context: &HookContext) -> Result<()> {
+        let Some(command) = self.command_for(context.event) else {
+            return Ok(());
+        };
+        let hook_error = |message: String| {
+            TgCloudError::Unknown(format!("{} hook `{}`: {}", context.event, command, message))
+        };
+
+        let mut shell = if cfg!(windows) {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        } else {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        };
+        shell
+            .arg(command)
+            .envs(hook_env(context))
+            .stdin(Stdio::piped())
+            .kill_on_drop(true);
+        let mut child = shell.spawn().map_err(|e| hook_error(e.to_string()))?;
+
+        let json = serde_json::to_vec(context).map_err(|e| hook_error(e.to_string()))?;
+        let run = async {
+            if let Some(mut stdin) = child.stdin.take() {
+                // A command that does not read its input closes the pipe early.
+                let _ = stdin.write_all(&json).await;
+            }
+            child.wait().await
+        };
+        let status = if self.config.timeout_secs == 0 {
+            run.await
+        } else {
+            let timeout = Duration::from_secs(self.config.timeout_secs);
+            // Dropping the child on timeout kills it.
+            tokio::time::timeout(timeout, run)
+                .await
+                .map_err(|_| hook_error(format!("timed out after {}s", self.config.timeout_secs)))?
+        }
+        .map_err(|e| hook_error(e.to_string()))?;
+
+        if !status.success() {
+            return Err(hook_error(format!("exited with {}", status)));
+        }
+        Ok(())
+    }
+}
+
+fn hook_env(context: &HookContext) -> Vec<(&'static str, String)> {
+    let mut env = vec![
+        ("TGCLOUD_EVENT", context.event.to_string()),
+        ("TGCLOUD_TRANSFER_ID", context.transfer_id.clone()),
+        ("TGCLOUD_PATH", context.path.clone()),
+    ];
+    if let Some(local) = &context.local_path {
+        env.push(("TGCLOUD_LOCAL_PATH", local.display().to_string()));
+    }
+    if let Some(file) = &context.file {
+        env.push(("TGCLOUD_FILE_ID", file.file_id.clone()));
+        env.push(("TGCLOUD_SIZE", file.size.to_string()));
+        env.push(("TGCLOUD_SHA256", file.sha256.clone()));
+    }
+    if let Some(success) = context.success {
+        env.push(("TGCLOUD_SUCCESS", success.to_string()));
+    }
+    if let Some(error) = &context.error {
+        env.push(("TGCLOUD_ERROR", error.clone()));
+    }
+    env
+}
@@ -1,13 +1,44 @@
+pub mod bandwidth;
+pub mod bot_api_server;
+pub mod bot_manager;
+pub mod cache;
 pub mod config;
+pub mod crypto;
+pub mod diff;
+pub mod doctor;
 pub mod errors;
+pub mod filter;
+pub mod hooks;
+pub mod logging;
+pub mod mirror;
 pub mod models;
+pub mod pacer;
+mod scheduler;
 pub mod service;
 pub mod storage;
+pub mod sync;
 pub mod telegram_client;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod transfer;
 
+pub use bandwidth::*;
+pub use bot_api_server::*;
+pub use bot_manager::*;
+pub use cache::*;
 pub use config::*;
+pub use crypto::*;
+pub use diff::*;
+pub use doctor::*;
 pub use errors::*;
+pub use filter::*;
+pub use hooks::*;
+pub use logging::*;
+pub use mirror::*;
 pub use models::*;
+pub use pacer::*;
 pub use service::*;
 pub use storage::*;
+pub use sync::*;
 pub use telegram_client::*;
+pub use transfer::*;
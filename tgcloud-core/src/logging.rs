@@ -0,0 +1,33 @@
+use crate::transfer::current_transfer_id;
+
+use std::io::Write;
+
+/// Sets up `env_logger` (filtered by `RUST_LOG`, `default_filter` otherwise).
+/// Lines written while a transfer runs carry its id, so a failure in the logs
+/// can be matched to the transfer reported by the CLI or the web API.
+pub fn init_logger(default_filter: &str) {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_filter))
+        .format(|buf, record| {
+            let timestamp = buf.timestamp();
+            match current_transfer_id() {
+                Some(id) => writeln!(
+                    buf,
+                    "[{} {:<5} {}] [transfer {}] {}",
+                    timestamp,
+                    record.level(),
+                    record.target(),
+                    id,
+                    record.args()
+                ),
+                None => writeln!(
+                    buf,
+                    "[{} {:<5} {}] {}",
+                    timestamp,
+                    record.level(),
+                    record.target(),
+                    record.args()
+                ),
+            }
+        })
+        .init();
+}
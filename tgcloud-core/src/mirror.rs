@@ -0,0 +1,244 @@
+//! An encrypted copy of the file metadata kept pinned in the storage chat, so
+//! the MongoDB store can be rebuilt from Telegram if it is lost.
+
+use crate::bot_manager::{BotCredentials, BotManager};
+use crate::crypto::MetadataCipher;
+use crate::errors::{Result, TgCloudError};
+use crate::models::FileMetadata;
+use crate::storage::MongoStore;
+use crate::telegram_client::TelegramClient;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// File name prefix of manifest documents; only pinned documents named like
+/// this are replaced by a newer manifest.
+const MANIFEST_PREFIX: &str = "tgcloud-manifest-";
+const MANIFEST_VERSION: u32 = 1;
+
+/// Everything needed to rebuild the `files` collection.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MetadataManifest {
+    pub version: u32,
+    pub written_at: DateTime<Utc>,
+    pub files: Vec<FileMetadata>,
+}
+
+/// Outcome of [`MetadataMirror::recover`].
+#[derive(Debug, Clone, Serialize)]
+pub struct RecoveryReport {
+    /// When the manifest that was read had been written.
+    pub written_at: DateTime<Utc>,
+    /// Files added to the store.
+    pub restored: usize,
+    /// Files already in the store, left untouched.
+    pub skipped: usize,
+}
+
+/// Writes the manifest to the storage chat, pins it and deletes the one it
+/// replaces; reads it back for recovery.
+///
+/// The manifest is a JSON [`MetadataManifest`] encrypted with `METADATA_KEY`,
+/// so the chat's members learn nothing from it. The bot writing it must be
+/// allowed to pin messages.
+pub struct MetadataMirror {
+    store: MongoStore,
+    telegram: TelegramClient,
+    bots: Arc<BotManager>,
+    chat_id: String,
+    cipher: MetadataCipher,
+    /// Number of refreshes asked for so far.
+    requested: AtomicU64,
+    /// Serializes writes; holds the number of requests the last manifest
+    /// written covers, so concurrent requests share one write.
+    written: tokio::sync::Mutex<u64>,
+}
+
+impl MetadataMirror {
+    pub fn new(
+        store: MongoStore,
+        telegram: TelegramClient,
+        bots: Arc<BotManager>,
+        chat_id: String,
+        key: &[u8; 32],
+    ) -> Self {
+        Self {
+            store,
+            telegram,
+            bots,
+            chat_id,
+            cipher: MetadataCipher::new(key),
+            requested: AtomicU64::new(0),
+            written: tokio::sync::Mutex::new(0),
+        }
+    }
+
+    /// Refreshes the manifest every `interval` for as long as the mirror is
+    /// in use.
+    pub fn spawn_refresh(self: &Arc<Self>, interval: Duration) {
+        let mirror = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let Some(mirror) = mirror.upgrade() else {
+                    return;
+                };
+                if let Err(e) = mirror.refresh().await {
+                    log::warn!("Failed to refresh the metadata manifest: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Writes a manifest of the current metadata, unless one was started
+    /// after this call while it waited for a running write.
+    pub async fn refresh(&self) -> Result<()> {
+        let wanted = self.requested.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut written = self.written.lock().await;
+        if *written >= wanted {
+            return Ok(());
+        }
+        let covers = self.requested.load(Ordering::SeqCst);
+        self.write().await?;
+        *written = covers;
+        Ok(())
+    }
+
+    async fn write(&self) -> Result<()> {
+        let manifest = MetadataManifest {
+            version: MANIFEST_VERSION,
+            written_at: Utc::now(),
+            files: self.store.all_files().await?,
+        };
+        let json = serde_json::to_vec(&manifest)
+            .map_err(|e| TgCloudError::Unknown(format!("Cannot encode manifest: {}", e)))?;
+        let sealed = self.cipher.seal_bytes(&json);
+
+        let bot = self.bots.get_upload_bot()?;
+        let previous = match self
+            .telegram
+            .pinned_document(&bot.token, &self.chat_id)
+            .await
+        {
+            Ok(previous) => previous.filter(|p| p.file_name.starts_with(MANIFEST_PREFIX)),
+            Err(e) => {
+                log::warn!("Cannot look up the previous metadata manifest: {}", e);
+                None
+            }
+        };
+
+        let file_name = format!(
+            "{}{}.bin",
+            MANIFEST_PREFIX,
+            manifest.written_at.format("%Y%m%dT%H%M%SZ")
+        );
+        let (_, message_id) = self
+            .telegram
+            .upload_part(
+                &bot.token,
+                &self.chat_id,
+                file_name,
+                std::io::Cursor::new(sealed),
+            )
+            .await?;
+        if let Err(e) = self
+            .telegram
+            .pin_message(&bot.token, &self.chat_id, message_id)
+            .await
+        {
+            // An unpinned manifest cannot be found again; don't leave it behind.
+            self.delete(&bot, message_id).await;
+            return Err(TgCloudError::UploadFailed(format!(
+                "Cannot pin the metadata manifest (bot {} needs permission to pin messages): {}",
+                bot.bot_id, e
+            )));
+        }
+        log::debug!(
+            "Wrote metadata manifest of {} files as message {}",
+            manifest.files.len(),
+            message_id
+        );
+
+        if let Some(previous) = previous.filter(|p| p.message_id != message_id) {
+            self.delete(&bot, previous.message_id).await;
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, bot: &BotCredentials, message_id: i64) {
+        if let Err(e) = self
+            .telegram
+            .delete_message(&bot.token, &self.chat_id, message_id)
+            .await
+        {
+            log::warn!(
+                "Failed to delete metadata manifest message {}: {}",
+                message_id,
+                e
+            );
+        }
+    }
+
+    /// Reads the manifest pinned in the storage chat and adds every file it
+    /// lists that the store does not know (by `file_id`).
+    pub async fn recover(&self) -> Result<RecoveryReport> {
+        let bot = self.bots.get_upload_bot()?;
+        let pinned = self
+            .telegram
+            .pinned_document(&bot.token, &self.chat_id)
+            .await?
+            .filter(|p| p.file_name.starts_with(MANIFEST_PREFIX))
+            .ok_or_else(|| {
+                TgCloudError::FileNotFound(format!(
+                    "no metadata manifest is pinned in chat {}",
+                    self.chat_id
+                ))
+            })?;
+
+        let path = self
+            .telegram
+            .get_local_file_path(&bot.token, &pinned.file_id)
+            .await?;
+        let sealed = if std::path::Path::new(&path).is_absolute() {
+            // Local Bot API server: the file sits in its cache.
+            tokio::fs::read(&path).await?
+        } else {
+            let url = self
+                .telegram
+                .get_download_url(&bot.token, &pinned.file_id)
+                .await?;
+            let res = self.telegram.download_file(&url).await?;
+            self.telegram.read_body(res).await?
+        };
+        let manifest: MetadataManifest = serde_json::from_slice(&self.cipher.open_bytes(&sealed)?)
+            .map_err(|e| {
+                TgCloudError::IntegrityFailed(format!("Unreadable metadata manifest: {}", e))
+            })?;
+        if manifest.version > MANIFEST_VERSION {
+            return Err(TgCloudError::IntegrityFailed(format!(
+                "The metadata manifest has version {}; this tgcloud reads up to {}",
+                manifest.version, MANIFEST_VERSION
+            )));
+        }
+
+        let mut report = RecoveryReport {
+            written_at: manifest.written_at,
+            restored: 0,
+            skipped: 0,
+        };
+        for file in manifest.files {
+            if self.store.get_file_by_id(&file.file_id).await?.is_some() {
+                report.skipped += 1;
+                continue;
+            }
+            self.store
+                .save_file(FileMetadata { id: None, ..file })
+                .await?;
+            report.restored += 1;
+        }
+        Ok(report)
+    }
+}
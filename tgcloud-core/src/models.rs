@@ -28,6 +28,330 @@ pub struct FileMetadata {
     pub created_at: DateTime<Utc>,
     #[serde(default)]
     pub bot_id: Option<String>,
+    /// Chat holding the chunks while the file is archived; `None` while they
+    /// are in the storage chat.
+    #[serde(default)]
+    pub archive_chat_id: Option<String>,
+}
+
+impl FileMetadata {
+    pub fn is_archived(&self) -> bool {
+        self.archive_chat_id.is_some()
+    }
+}
+
+/// A folder (path prefix) with the files stored underneath it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FolderSummary {
+    pub name: String,
+    pub file_count: u64,
+    pub total_size: u64,
+}
+
+/// Files (or chunks) and bytes attributed to one key, e.g. a bot id or a
+/// `YYYY-MM` month.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UsageRow {
+    pub key: String,
+    pub count: u64,
+    pub bytes: u64,
+}
+
+/// Totals over a set of stored files, computed by the database.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct StorageStats {
+    pub file_count: u64,
+    pub total_bytes: u64,
+    pub chunk_count: u64,
+    /// Chunks and bytes stored through each bot, largest first.
+    pub by_bot: Vec<UsageRow>,
+    /// Files and bytes by upload month, oldest first.
+    pub by_month: Vec<UsageRow>,
+    /// Bytes stored through each configured bot against its quota, for all
+    /// files including the trash.
+    #[serde(default)]
+    pub quotas: Vec<BotQuota>,
+}
+
+/// How much of its storage quota a bot uses.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BotQuota {
+    pub bot_id: String,
+    pub stored_bytes: u64,
+    /// `None` if the bot has no quota.
+    pub quota_bytes: Option<u64>,
+}
+
+/// How `search` matches a query against file names (always case-insensitive).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Some path component starts with the query.
+    Prefix,
+    /// The query appears anywhere in the path.
+    #[default]
+    Substring,
+    /// The query's characters appear in order, ranked by how close together.
+    Fuzzy,
+}
+
+/// Which files a paginated listing covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileFilter {
+    /// Every file whose name starts with the prefix (`root` or empty for all).
+    Prefix(String),
+    /// Files below a folder, at most `depth` levels deep (`None` = all).
+    Folder { folder: String, depth: Option<u32> },
+    /// Files whose full name matches a shell-style glob.
+    Glob(String),
+}
+
+/// One page of a file listing, ordered by name. Pass `next_cursor` back to
+/// fetch the following page; it is `None` on the last page.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FilePage {
+    pub files: Vec<FileMetadata>,
+    pub next_cursor: Option<String>,
+}
+
+/// A bot as tracked in the metadata store.
+///
+/// Tokens are never persisted; they come from the configuration. The store only
+/// remembers whether a bot is still usable.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Bot {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub bot_id: String,
+    pub active: bool,
+    #[serde(default)]
+    pub deactivated_reason: Option<String>,
+    #[serde(default)]
+    pub deactivated_at: Option<DateTime<Utc>>,
+    /// Number of HTTP 429 responses Telegram has sent this bot.
+    #[serde(default)]
+    pub rate_limit_count: u64,
+    #[serde(default)]
+    pub last_rate_limited_at: Option<DateTime<Utc>>,
+    /// End of the cool-down that followed the last 429.
+    #[serde(default)]
+    pub rate_limited_until: Option<DateTime<Utc>>,
+    /// Bot API server the bot was last configured to use.
+    #[serde(default)]
+    pub telegram_api_url: Option<String>,
+}
+
+/// Current 429 back-off state of a bot, as shown by `tgcloud bot status`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RateLimitStatus {
+    pub bot_id: String,
+    pub active: bool,
+    /// Seconds until the bot takes new chunks again (0 = not throttled).
+    pub cooldown_remaining_secs: u64,
+    /// Consecutive 429s without a success in between, as seen by this process.
+    pub streak: u32,
+    pub rate_limit_count: u64,
+    pub last_rate_limited_at: Option<DateTime<Utc>>,
+    /// Smoothed upload throughput in bytes/s (0 = not measured yet).
+    pub throughput_bytes_per_sec: u64,
+    /// Chunks the bot currently runs at once, lowered after 429s.
+    #[serde(default)]
+    pub concurrency: usize,
+    /// Configured ceiling for `concurrency`.
+    #[serde(default)]
+    pub max_concurrency: usize,
+}
+
+/// A deleted file waiting in the trash. Its Telegram messages are kept until
+/// the trash retention period has passed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrashedFile {
+    #[serde(flatten)]
+    pub file: FileMetadata,
+    pub deleted_at: DateTime<Utc>,
+}
+
+/// A chunk uploaded for a file that has not been saved yet. Interrupted
+/// uploads leave these behind so running the upload again can reuse them.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LedgerChunk {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// SHA-256 of the whole file the chunk belongs to.
+    pub sha256: String,
+    pub chat_id: String,
+    pub chunk_size: u64,
+    pub chunk: FileChunk,
+    pub created_at: DateTime<Utc>,
+}
+
+// ---------------------------------------------------------------------------
+// History
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryAction {
+    Upload,
+    Download,
+    Send,
+    Rename,
+    Delete,
+    Undo,
+    Archive,
+    Unarchive,
+}
+
+impl std::fmt::Display for HistoryAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            HistoryAction::Upload => "upload",
+            HistoryAction::Download => "download",
+            HistoryAction::Send => "send",
+            HistoryAction::Rename => "rename",
+            HistoryAction::Delete => "delete",
+            HistoryAction::Undo => "undo",
+            HistoryAction::Archive => "archive",
+            HistoryAction::Unarchive => "unarchive",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One entry of the audit log: an operation on a stored file and its outcome.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub action: HistoryAction,
+    pub path: String,
+    /// New name of a renamed file.
+    #[serde(default)]
+    pub target: Option<String>,
+    pub success: bool,
+    /// Error message of a failed operation, or extra context.
+    #[serde(default)]
+    pub detail: Option<String>,
+    pub at: DateTime<Utc>,
+    /// Set once `tgcloud undo` reverted this operation.
+    #[serde(default)]
+    pub undone: bool,
+    /// Shared by the entries of one bulk operation, such as a glob delete,
+    /// which `tgcloud undo` reverts together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<String>,
+}
+
+/// Filter for [`HistoryEntry`] queries.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryQuery {
+    /// Only entries for this path (as source or rename target).
+    pub path: Option<String>,
+    /// Only entries at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Maximum number of entries, newest first.
+    pub limit: Option<i64>,
+}
+
+/// Result of `tgcloud bot bench` for one bot.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BotBenchmark {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub bot_id: String,
+    pub payload_size: u64,
+    /// Round trip of a `getMe` request.
+    pub latency_ms: u64,
+    pub upload_bytes_per_sec: f64,
+    pub download_bytes_per_sec: f64,
+    /// Set if the benchmark failed; the measurements are then zero.
+    #[serde(default)]
+    pub error: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+// ---------------------------------------------------------------------------
+// API keys
+// ---------------------------------------------------------------------------
+
+/// What a REST API key may do; each scope includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyScope {
+    /// List, search, look at stats and download files.
+    ReadOnly,
+    /// Also upload files.
+    Upload,
+    /// Everything, including renames and deletes.
+    Admin,
+}
+
+impl std::fmt::Display for ApiKeyScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ApiKeyScope::ReadOnly => "read-only",
+            ApiKeyScope::Upload => "upload",
+            ApiKeyScope::Admin => "admin",
+        })
+    }
+}
+
+impl std::str::FromStr for ApiKeyScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "read-only" | "read_only" => Ok(ApiKeyScope::ReadOnly),
+            "upload" => Ok(ApiKeyScope::Upload),
+            "admin" => Ok(ApiKeyScope::Admin),
+            _ => Err(format!(
+                "unknown scope '{}' (expected read-only, upload or admin)",
+                s
+            )),
+        }
+    }
+}
+
+/// A key scripts use for the REST API instead of the server token. Only a
+/// hash of the secret is stored.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKey {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// Public part of the key, used to list and revoke it.
+    pub key_id: String,
+    pub name: String,
+    pub scope: ApiKeyScope,
+    /// Hex SHA-256 of the whole key.
+    pub key_hash: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+// ---------------------------------------------------------------------------
+// Transfer options
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Default)]
+pub struct UploadOptions {
+    /// Replace an existing remote file with the same name instead of failing.
+    pub force: bool,
+    /// Name to store the file under. Defaults to the local path as given.
+    pub remote_name: Option<String>,
+    /// Id to run the transfer under. A new one is generated when unset.
+    pub transfer_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DownloadOptions {
+    /// Overwrite an existing local file instead of failing.
+    pub force: bool,
+    /// Copy the verified file to this path instead of leaving it only in the
+    /// Bot API server's cache.
+    pub destination: Option<std::path::PathBuf>,
+    /// Id to run the transfer under. A new one is generated when unset.
+    pub transfer_id: Option<String>,
 }
 
 // ---------------------------------------------------------------------------
@@ -36,6 +360,7 @@ pub struct FileMetadata {
 
 #[derive(Debug, Clone)]
 pub struct UploadEvent {
+    pub transfer_id: String,
     pub status: UploadStatus,
 }
 
@@ -50,6 +375,10 @@ pub enum UploadStatus {
     HashComplete {
         sha256: String,
     },
+    /// Chunks left by an interrupted upload of the same content are reused.
+    Resuming {
+        reused_chunks: u32,
+    },
     Completed {
         file_id: String,
     },
@@ -64,6 +393,7 @@ pub enum UploadStatus {
 
 #[derive(Debug, Clone)]
 pub struct DownloadEvent {
+    pub transfer_id: String,
     pub status: DownloadStatus,
 }
 
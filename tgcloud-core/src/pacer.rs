@@ -0,0 +1,62 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Window over which Telegram counts messages per chat.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Spaces out messages sent to the same chat so that all bots together stay
+/// below Telegram's per-chat flood limit, which applies regardless of which
+/// bot sends. Shared by every chunk upload, forward and delete.
+pub struct ChatPacer {
+    per_minute: usize,
+    chats: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl ChatPacer {
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute: per_minute.max(1) as usize,
+            chats: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Books the next free slot for a message to `chat_id` and returns how
+    /// long the caller has to wait for it.
+    pub fn reserve(&self, chat_id: &str) -> Duration {
+        let now = Instant::now();
+        let mut chats = self.chats.lock().expect("chat pacer poisoned");
+        let slots = chats.entry(chat_id.to_string()).or_default();
+        while slots
+            .front()
+            .is_some_and(|t| now.saturating_duration_since(*t) >= WINDOW)
+        {
+            slots.pop_front();
+        }
+
+        // Slots are booked in order, so the one `per_minute` places back
+        // decides when the next message fits into a full window.
+        let mut at = now;
+        if slots.len() >= self.per_minute {
+            at = at.max(slots[slots.len() - self.per_minute] + WINDOW);
+        }
+        if let Some(&last) = slots.back() {
+            at = at.max(last);
+        }
+        slots.push_back(at);
+        at - now
+    }
+
+    /// Waits until a message may be sent to `chat_id`.
+    pub async fn wait(&self, chat_id: &str) {
+        let delay = self.reserve(chat_id);
+        if !delay.is_zero() {
+            log::debug!(
+                "Pacing message to chat {} by {:.1}s",
+                chat_id,
+                delay.as_secs_f64()
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use crate::bot_manager::{BotCredentials, BotManager};
+use crate::errors::{Result, TgCloudError};
+use crate::models::{FileChunk, LedgerChunk};
+use crate::storage::MongoStore;
+use crate::telegram_client::TelegramClient;
+use crate::transfer::{spawn_in_transfer, TransferTracker};
+
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+/// How often a worker parked by a lowered concurrency limit checks it again.
+const CONCURRENCY_RECHECK: Duration = Duration::from_secs(1);
+
+/// A chunk waiting to be uploaded.
+#[derive(Debug, Clone)]
+pub(crate) struct ChunkJob {
+    pub index: u32,
+    pub offset: u64,
+    pub size: u64,
+    pub file_name: String,
+}
+
+/// Everything a worker needs to upload chunks of one file.
+pub(crate) struct UploadContext {
+    pub telegram: TelegramClient,
+    pub bots: Arc<BotManager>,
+    /// Every uploaded chunk is recorded in its chunk ledger.
+    pub store: MongoStore,
+    pub chat_id: String,
+    pub path: String,
+    pub sha256: String,
+    pub chunk_size: u64,
+    pub progress: Arc<AtomicU64>,
+    pub max_concurrency: usize,
+    pub cancel: CancellationToken,
+    pub tracker: Arc<TransferTracker>,
+}
+
+struct Queue {
+    pending: VecDeque<ChunkJob>,
+    in_flight: usize,
+}
+
+enum Next {
+    Job(ChunkJob),
+    /// Nothing pending, but a chunk in flight may still be handed back.
+    Wait,
+    Done,
+}
+
+/// Shared state between the workers of one upload.
+struct SchedulerState {
+    queue: Mutex<Queue>,
+    changed: Notify,
+    done: Mutex<Vec<FileChunk>>,
+    error: Mutex<Option<TgCloudError>>,
+    failed: AtomicBool,
+    global: Semaphore,
+}
+
+impl SchedulerState {
+    fn next(&self) -> Next {
+        if self.failed.load(Ordering::Relaxed) {
+            return Next::Done;
+        }
+        let mut queue = self.queue.lock().expect("chunk queue poisoned");
+        match queue.pending.pop_front() {
+            Some(job) => {
+                queue.in_flight += 1;
+                Next::Job(job)
+            }
+            None if queue.in_flight > 0 => Next::Wait,
+            None => Next::Done,
+        }
+    }
+
+    /// Marks an in-flight chunk as finished, optionally handing it back to the
+    /// queue for another bot.
+    fn finish(&self, requeue: Option<ChunkJob>) {
+        {
+            let mut queue = self.queue.lock().expect("chunk queue poisoned");
+            queue.in_flight -= 1;
+            if let Some(job) = requeue {
+                queue.pending.push_front(job);
+            }
+        }
+        self.changed.notify_waiters();
+    }
+
+    /// Whether no chunk is pending or in flight, or the upload failed.
+    fn is_finished(&self) -> bool {
+        if self.failed.load(Ordering::Relaxed) {
+            return true;
+        }
+        let queue = self.queue.lock().expect("chunk queue poisoned");
+        queue.pending.is_empty() && queue.in_flight == 0
+    }
+
+    fn fail(&self, err: TgCloudError) {
+        self.failed.store(true, Ordering::Relaxed);
+        {
+            let mut slot = self.error.lock().expect("error slot poisoned");
+            if slot.is_none() {
+                *slot = Some(err);
+            }
+        }
+        self.changed.notify_waiters();
+    }
+}
+
+/// Uploads `jobs` using a work-stealing pool: every active bot runs as many
+/// workers as its configured concurrency (scaled by its weight), which pull
+/// the next pending chunk from a shared queue whenever they are free, so a
+/// slow or throttled bot only delays the chunk it is currently working on.
+///
+/// Returns the chunks that were uploaded, plus the first error if the upload
+/// did not complete. On error the returned chunks must be rolled back.
+pub(crate) async fn upload_chunks(
+    ctx: UploadContext,
+    jobs: Vec<ChunkJob>,
+) -> (Vec<FileChunk>, Option<TgCloudError>) {
+    let total = jobs.len();
+    let ctx = Arc::new(ctx);
+    let state = Arc::new(SchedulerState {
+        queue: Mutex::new(Queue {
+            pending: jobs.into(),
+            in_flight: 0,
+        }),
+        changed: Notify::new(),
+        done: Mutex::new(Vec::with_capacity(total)),
+        error: Mutex::new(None),
+        failed: AtomicBool::new(false),
+        global: Semaphore::new(ctx.max_concurrency.max(1)),
+    });
+
+    let mut workers = FuturesUnordered::new();
+    for bot in ctx.bots.active_bots() {
+        for slot in 0..bot.concurrency {
+            let ctx = Arc::clone(&ctx);
+            let state = Arc::clone(&state);
+            let bot = bot.clone();
+            workers.push(spawn_in_transfer(async move {
+                worker(&ctx, &state, bot, slot).await
+            }));
+        }
+    }
+
+    while let Some(join_result) = workers.next().await {
+        if let Err(join_err) = join_result {
+            state.fail(TgCloudError::UploadFailed(format!(
+                "Task panicked: {}",
+                join_err
+            )));
+        }
+    }
+
+    let chunks = std::mem::take(&mut *state.done.lock().expect("chunk list poisoned"));
+    let mut error = state.error.lock().expect("error slot poisoned").take();
+    if error.is_none() && chunks.len() < total {
+        // Workers only stop early when their bot is deactivated or full.
+        let reason = if ctx.bots.active_bots().is_empty() {
+            "every bot is deactivated (Telegram rejected its token); fix the tokens and run `tgcloud bot enable`"
+        } else {
+            "every active bot is at its storage quota; raise a quota or add a bot"
+        };
+        error = Some(TgCloudError::BotManagerError(format!(
+            "Cannot upload the remaining {} chunk(s): {}",
+            total - chunks.len(),
+            reason
+        )));
+    }
+    (chunks, error)
+}
+
+/// Runs the `slot`-th worker of `bot`. Slots at or above the bot's current
+/// concurrency limit wait until it is raised again.
+async fn worker(ctx: &UploadContext, state: &SchedulerState, bot: BotCredentials, slot: usize) {
+    loop {
+        if !ctx.bots.is_active(&bot.bot_id) {
+            return;
+        }
+        // A bot in a 429 storm sits out and leaves the queue to the others.
+        if let Some(wait) = ctx.bots.cooldown_remaining(&bot.bot_id) {
+            tokio::select! {
+                _ = tokio::time::sleep(wait) => continue,
+                _ = ctx.cancel.cancelled() => {
+                    state.fail(TgCloudError::Cancelled);
+                    return;
+                }
+            }
+        }
+        if slot >= ctx.bots.concurrency_limit(&bot.bot_id) {
+            let changed = state.changed.notified();
+            if state.is_finished() {
+                return;
+            }
+            tokio::select! {
+                _ = changed => {}
+                _ = tokio::time::sleep(CONCURRENCY_RECHECK) => {}
+                _ = ctx.cancel.cancelled() => {
+                    state.fail(TgCloudError::Cancelled);
+                    return;
+                }
+            }
+            continue;
+        }
+
+        let permit = tokio::select! {
+            permit = state.global.acquire() => match permit {
+                Ok(permit) => permit,
+                Err(_) => return,
+            },
+            _ = ctx.cancel.cancelled() => {
+                state.fail(TgCloudError::Cancelled);
+                return;
+            }
+        };
+        // Registered before checking the queue so a hand-back in between is not missed.
+        let changed = state.changed.notified();
+        let job = match state.next() {
+            Next::Job(job) => job,
+            Next::Wait => {
+                drop(permit);
+                changed.await;
+                continue;
+            }
+            Next::Done => return,
+        };
+        if !ctx.bots.try_reserve(&bot.bot_id, job.size) {
+            log::info!(
+                "Bot {} is at its storage quota; leaving chunk {} to the others",
+                bot.bot_id,
+                job.index
+            );
+            state.finish(Some(job));
+            return;
+        }
+
+        let started = Instant::now();
+        ctx.tracker.chunk_started(job.index, &bot.bot_id);
+        let result = tokio::select! {
+            result = upload_one(ctx, &bot, &job) => result,
+            _ = ctx.cancel.cancelled() => Err(TgCloudError::Cancelled),
+        };
+
+        match result {
+            Ok(chunk) => {
+                ctx.tracker.chunk_done(job.index);
+                ctx.bots.report_success(&bot.bot_id);
+                ctx.bots
+                    .report_throughput(&bot.bot_id, job.size, started.elapsed());
+                let entry = LedgerChunk {
+                    id: None,
+                    sha256: ctx.sha256.clone(),
+                    chat_id: ctx.chat_id.clone(),
+                    chunk_size: ctx.chunk_size,
+                    chunk: chunk.clone(),
+                    created_at: chrono::Utc::now(),
+                };
+                if let Err(e) = ctx.store.record_ledger_chunk(&entry).await {
+                    log::warn!("Failed to record chunk {} in the ledger: {}", job.index, e);
+                }
+                state.done.lock().expect("chunk list poisoned").push(chunk);
+                state.finish(None);
+            }
+            Err(TgCloudError::BotUnauthorized { status, .. }) => {
+                log::warn!(
+                    "Bot {} rejected chunk {} (HTTP {}), handing it to another bot",
+                    bot.bot_id,
+                    job.index,
+                    status
+                );
+                ctx.bots.report_auth_failure(&bot.bot_id, status).await;
+                ctx.bots.release(&bot.bot_id, job.size);
+                ctx.tracker.chunk_pending(job.index);
+                state.finish(Some(job));
+            }
+            Err(e) => {
+                ctx.bots.release(&bot.bot_id, job.size);
+                ctx.tracker.chunk_failed(job.index);
+                let e = e.in_chunk(job.index, &bot.bot_id);
+                if !matches!(e, TgCloudError::Cancelled) {
+                    log::error!("{}", e);
+                }
+                state.finish(None);
+                state.fail(e);
+                return;
+            }
+        }
+    }
+}
+
+async fn upload_one(
+    ctx: &UploadContext,
+    bot: &BotCredentials,
+    job: &ChunkJob,
+) -> Result<FileChunk> {
+    let bots = Arc::clone(&ctx.bots);
+    let bot_id = bot.bot_id.clone();
+    let (telegram_file_id, message_id) = ctx
+        .telegram
+        .upload_part_with_retry(
+            &bot.token,
+            &ctx.chat_id,
+            job.file_name.clone(),
+            &ctx.path,
+            job.offset,
+            job.size,
+            Arc::clone(&ctx.progress),
+            Arc::new(move || bots.report_rate_limited(&bot_id)),
+        )
+        .await?;
+
+    Ok(FileChunk {
+        index: job.index,
+        bot_id: Some(bot.bot_id.clone()),
+        telegram_file_id,
+        message_id,
+        size: job.size,
+    })
+}
@@ -1,52 +1,509 @@
-use crate::errors::{Result, TgCloudError};
+use crate::bandwidth::RateLimiter;
+use crate::bot_api_server::BotApiSupervisor;
+use crate::bot_manager::{BotCredentials, BotManager};
+use crate::diff::{self, DirDiff};
+use crate::errors::{ConfigError, Result, TgCloudError};
+use crate::filter::PathFilter;
+use crate::hooks::{HookContext, HookEvent, ShellHook, TransferHook};
+use crate::mirror::{MetadataMirror, RecoveryReport};
 use crate::models::{
-    DownloadEvent, DownloadStatus, FileChunk, FileMetadata, UploadEvent, UploadStatus,
+    ApiKey, ApiKeyScope, Bot, BotBenchmark, DownloadEvent, DownloadOptions, DownloadStatus,
+    FileChunk, FileFilter, FileMetadata, FilePage, FolderSummary, HistoryAction, HistoryEntry,
+    HistoryQuery, LedgerChunk, RateLimitStatus, SearchMode, StorageStats, TrashedFile, UploadEvent,
+    UploadOptions, UploadStatus,
 };
+use crate::pacer::ChatPacer;
+use crate::scheduler::{self, ChunkJob, UploadContext};
 use crate::storage::MongoStore;
-use crate::telegram_client::TelegramClient;
+use crate::sync::{SyncAction, SyncDirection, SyncPlan};
+use crate::telegram_client::{build_http_client, TelegramClient};
+use crate::transfer::{
+    new_transfer_id, with_transfer_id, TransferGuard, TransferInfo, TransferKind, TransferManager,
+    TransferOverview,
+};
 
 use chrono::Utc;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use sha2::{Digest, Sha256};
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
 use tokio::sync::{mpsc, Semaphore};
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 /// Fixed chunk size: 2 GiB (optimized for local Telegram Bot API).
 const CHUNK_SIZE: u64 = 2_147_483_648;
+/// Buffers in flight between the reading and the hashing thread.
+const HASH_BUFFERS: usize = 4;
+/// Start of every API key, so they are recognisable in configs and logs.
+pub const API_KEY_PREFIX: &str = "tgc_";
 
 pub struct TgCloudService {
     store: MongoStore,
     telegram: TelegramClient,
-    bot_id: String,
-    bot_token: String,
+    bots: Arc<BotManager>,
+    transfers: Arc<TransferManager>,
     chat_id: String,
+    archive_chat_id: Option<String>,
+    chunk_size: u64,
+    /// Buffer size for hashing, merging and verifying files.
+    io_buffer_size: usize,
     max_concurrency: usize,
+    shutdown_grace: std::time::Duration,
+    trash_retention: chrono::Duration,
+    ledger_retention: chrono::Duration,
+    hooks: Vec<Arc<dyn TransferHook>>,
+    /// Reads and writes the metadata manifest; set whenever `METADATA_KEY` is.
+    mirror: Option<Arc<MetadataMirror>>,
+    /// Whether the manifest is written after uploads (`METADATA_MIRROR`).
+    mirror_uploads: bool,
+    /// Local Bot API server started for this service, stopped with it.
+    _bot_api: Option<BotApiSupervisor>,
 }
 
 impl TgCloudService {
     pub async fn new(config: crate::config::Config) -> Result<Self> {
-        let store = MongoStore::new(&config.mongo_uri).await?;
-        let telegram = TelegramClient::new(config.telegram_api_url.clone());
+        let mut store = MongoStore::new(&config.mongo_uri).await?.with_cache_ttl(
+            std::time::Duration::from_secs(config.metadata_cache_ttl_secs),
+        );
+        if let Some(key) = &config.metadata_key {
+            store = store.with_metadata_key(key);
+        }
+        if let Err(e) = store.ensure_indexes().await {
+            log::warn!("Failed to create metadata indexes: {}", e);
+        }
+        let http = build_http_client(&config.http, &config.tls)?;
+        let bot_api = match config.local_bot_api.clone() {
+            Some(local) => BotApiSupervisor::start(local, http.clone()).await?,
+            None => None,
+        };
+        let mut telegram = TelegramClient::with_client(http, config.telegram_api_url.clone())
+            .with_bot_api_urls(config.bot_api_urls());
+        if config.chat_messages_per_minute > 0 {
+            telegram =
+                telegram.with_chat_pacer(Arc::new(ChatPacer::new(config.chat_messages_per_minute)));
+        }
+        if config.http.stall_timeout_secs > 0 {
+            telegram = telegram.with_stall_timeout(std::time::Duration::from_secs(
+                config.http.stall_timeout_secs,
+            ));
+        }
+        if !config.bandwidth_schedule.is_unlimited() {
+            telegram = telegram.with_rate_limiter(Arc::new(RateLimiter::new(
+                config.bandwidth_schedule.clone(),
+            )));
+        }
+        let bots = Arc::new(
+            BotManager::new(
+                store.clone(),
+                &config.bots,
+                config.max_per_bot_concurrency,
+                &config.telegram_api_url,
+            )
+            .await?,
+        );
+        match store.latest_benchmarks().await {
+            Ok(benchmarks) => bots.apply_benchmarks(&benchmarks),
+            Err(e) => log::warn!("Failed to load bot benchmarks: {}", e),
+        }
+
+        let mut hooks: Vec<Arc<dyn TransferHook>> = Vec::new();
+        if !config.hooks.is_empty() {
+            hooks.push(Arc::new(ShellHook::new(config.hooks.clone())));
+        }
+
+        let mirror = config.metadata_key.as_ref().map(|key| {
+            Arc::new(MetadataMirror::new(
+                store.clone(),
+                telegram.clone(),
+                Arc::clone(&bots),
+                config.telegram_chat_id.clone(),
+                key,
+            ))
+        });
+        if let Some(mirror) = &mirror {
+            if config.metadata_mirror && config.metadata_mirror_interval_secs > 0 {
+                mirror.spawn_refresh(std::time::Duration::from_secs(
+                    config.metadata_mirror_interval_secs,
+                ));
+            }
+        }
 
         Ok(Self {
             store,
             telegram,
-            bot_id: config.bot_id,
-            bot_token: config.bot_token,
+            bots,
+            transfers: Arc::new(TransferManager::new()),
             chat_id: config.telegram_chat_id,
+            archive_chat_id: config.archive_chat_id,
+            chunk_size: CHUNK_SIZE,
+            io_buffer_size: config.io_buffer_size,
             max_concurrency: config.max_concurrency,
+            shutdown_grace: std::time::Duration::from_secs(config.shutdown_grace_secs),
+            trash_retention: i64::try_from(config.trash_retention_secs)
+                .ok()
+                .and_then(chrono::Duration::try_seconds)
+                .unwrap_or(chrono::Duration::MAX),
+            ledger_retention: i64::try_from(config.ledger_retention_secs)
+                .ok()
+                .and_then(chrono::Duration::try_seconds)
+                .unwrap_or(chrono::Duration::MAX),
+            hooks,
+            mirror,
+            mirror_uploads: config.metadata_mirror,
+            _bot_api: bot_api,
         })
     }
 
+    /// Adds a hook called before and after every upload and download, after
+    /// the shell commands from the configuration.
+    pub fn with_hook(mut self, hook: Arc<dyn TransferHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Splits uploads into chunks of `size` bytes instead of 2 GiB, so tests
+    /// can produce multi-chunk files quickly.
+    #[cfg(feature = "testing")]
+    pub fn with_chunk_size(mut self, size: u64) -> Self {
+        self.chunk_size = size.max(1);
+        self
+    }
+
+    /// The bot manager used for chunk transfers.
+    pub fn bots(&self) -> &BotManager {
+        &self.bots
+    }
+
+    /// Cancels every running transfer. Cancelled uploads remove the chunks they
+    /// already sent; cancelled downloads remove partially merged files.
+    pub fn cancel_transfers(&self) {
+        self.transfers.cancel_all();
+    }
+
+    /// Transfers currently running through this service.
+    pub fn active_transfers(&self) -> Vec<TransferInfo> {
+        self.transfers.active()
+    }
+
+    /// Progress of the running transfers, chunk by chunk, and the last
+    /// finished ones.
+    pub fn transfer_overview(&self) -> TransferOverview {
+        self.transfers.overview()
+    }
+
+    /// Stops accepting new uploads, downloads and sends. Running transfers
+    /// are left to finish; see [`TgCloudService::drain`].
+    pub fn begin_shutdown(&self) {
+        self.transfers.shutdown();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.transfers.is_shutting_down()
+    }
+
+    /// How long running transfers get to finish after a shutdown signal.
+    pub fn shutdown_grace(&self) -> std::time::Duration {
+        self.shutdown_grace
+    }
+
+    /// Waits up to `timeout` for running transfers to finish and returns the
+    /// ones that are still running afterwards.
+    pub async fn drain(&self, timeout: std::time::Duration) -> Vec<TransferInfo> {
+        self.transfers.drain(timeout).await
+    }
+
+    /// Lists the bots known to the metadata store.
+    pub async fn list_bots(&self) -> Result<Vec<Bot>> {
+        self.store.list_bots().await
+    }
+
+    /// Reports which configured bots are backing off after HTTP 429s.
+    pub async fn rate_limit_status(&self) -> Result<Vec<RateLimitStatus>> {
+        let records = self.store.list_bots().await?;
+        Ok(self.bots.rate_limit_status(&records))
+    }
+
+    /// Re-enables a bot that was deactivated after repeated auth failures.
+    pub async fn enable_bot(&self, bot_id: &str) -> Result<()> {
+        self.bots.enable_bot(bot_id).await
+    }
+
+    /// Switches a bot to a new token after the old one leaked or was
+    /// regenerated. The token must be accepted by Telegram and belong to the
+    /// same bot, since chunks can only be fetched by the bot that uploaded
+    /// them; they stay addressed by `bot_id` and need no changes. Only the
+    /// running service is updated: persisting the token is up to the caller.
+    pub async fn rotate_bot_token(&self, bot_id: &str, new_token: &str) -> Result<()> {
+        let new_token = new_token.trim();
+        let current = self.bots.get_bot(bot_id).ok_or_else(|| {
+            TgCloudError::BotManagerError(format!("Bot {} is not configured", bot_id))
+        })?;
+        let telegram_id = |token: &str| token.split(':').next().unwrap_or_default().to_string();
+        if new_token.is_empty() || !new_token.contains(':') {
+            return Err(TgCloudError::BotManagerError(
+                "The new token must look like 123456789:ABC... as issued by @BotFather".into(),
+            ));
+        }
+        if telegram_id(new_token) != telegram_id(&current.token) {
+            return Err(TgCloudError::BotManagerError(format!(
+                "The new token belongs to Telegram bot {}, not {}; chunks stored by bot {} can only be downloaded by the same Telegram bot",
+                telegram_id(new_token),
+                telegram_id(&current.token),
+                bot_id
+            )));
+        }
+        self.telegram.get_me(new_token).await?;
+        self.bots.set_token(bot_id, new_token).await?;
+        log::info!("Rotated the token of bot {}", bot_id);
+        Ok(())
+    }
+
+    /// Creates an API key and returns it together with the full key, which
+    /// is not stored and cannot be shown again.
+    pub async fn create_api_key(&self, name: &str, scope: ApiKeyScope) -> Result<(ApiKey, String)> {
+        use rand::RngCore;
+        let mut id = [0u8; 4];
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut id);
+        rand::thread_rng().fill_bytes(&mut secret);
+        let key_id = hex::encode(id);
+        let full_key = format!("{}{}_{}", API_KEY_PREFIX, key_id, hex::encode(secret));
+
+        let key = ApiKey {
+            id: None,
+            key_id,
+            name: name.to_string(),
+            scope,
+            key_hash: hex::encode(Sha256::digest(full_key.as_bytes())),
+            created_at: Utc::now(),
+            last_used_at: None,
+        };
+        self.store.insert_api_key(&key).await?;
+        Ok((key, full_key))
+    }
+
+    pub async fn list_api_keys(&self) -> Result<Vec<ApiKey>> {
+        self.store.list_api_keys().await
+    }
+
+    /// Deletes an API key; returns whether it existed.
+    pub async fn revoke_api_key(&self, key_id: &str) -> Result<bool> {
+        self.store.delete_api_key(key_id).await
+    }
+
+    /// Looks up the key a client presented. Returns `None` for anything
+    /// that is not a current key.
+    pub async fn authenticate_api_key(&self, presented: &str) -> Result<Option<ApiKey>> {
+        let Some((key_id, _)) = presented
+            .strip_prefix(API_KEY_PREFIX)
+            .and_then(|rest| rest.split_once('_'))
+        else {
+            return Ok(None);
+        };
+        let Some(key) = self.store.find_api_key(key_id).await? else {
+            return Ok(None);
+        };
+        let hash = hex::encode(Sha256::digest(presented.as_bytes()));
+        if !constant_time_eq(hash.as_bytes(), key.key_hash.as_bytes()) {
+            return Ok(None);
+        }
+        let store = self.store.clone();
+        let key_id = key.key_id.clone();
+        tokio::spawn(async move {
+            if let Err(e) = store.touch_api_key(&key_id).await {
+                log::debug!("Could not record use of API key {}: {}", key_id, e);
+            }
+        });
+        Ok(Some(key))
+    }
+
+    /// Uploads and downloads a random payload of `payload_size` bytes through
+    /// every active bot, one bot at a time, and stores the measurements.
+    pub async fn bench_bots(&self, payload_size: u64) -> Result<Vec<BotBenchmark>> {
+        let payload_path = std::env::temp_dir().join(format!("tgcloud-bench-{}", Uuid::new_v4()));
+        {
+            use rand::RngCore;
+            let mut data = vec![0u8; payload_size as usize];
+            rand::thread_rng().fill_bytes(&mut data);
+            tokio::fs::write(&payload_path, &data).await?;
+        }
+
+        let mut results = Vec::new();
+        for bot in self.bots.active_bots() {
+            let measured = self.bench_bot(&bot, &payload_path, payload_size).await;
+            if let Err(TgCloudError::BotUnauthorized { status, .. }) = &measured {
+                self.bots.report_auth_failure(&bot.bot_id, *status).await;
+            }
+            let (latency_ms, upload, download) = measured.as_ref().copied().unwrap_or_default();
+            let benchmark = BotBenchmark {
+                id: None,
+                bot_id: bot.bot_id.clone(),
+                payload_size,
+                latency_ms,
+                upload_bytes_per_sec: upload,
+                download_bytes_per_sec: download,
+                error: measured.err().map(|e| e.to_string()),
+                at: Utc::now(),
+            };
+            if let Err(e) = self.store.save_benchmark(benchmark.clone()).await {
+                log::warn!("Failed to store benchmark of bot {}: {}", bot.bot_id, e);
+            }
+            results.push(benchmark);
+        }
+
+        let _ = tokio::fs::remove_file(&payload_path).await;
+        self.bots.apply_benchmarks(&results);
+        Ok(results)
+    }
+
+    /// Returns `(latency_ms, upload B/s, download B/s)` for one bot.
+    async fn bench_bot(
+        &self,
+        bot: &BotCredentials,
+        payload_path: &std::path::Path,
+        payload_size: u64,
+    ) -> Result<(u64, f64, f64)> {
+        let started = std::time::Instant::now();
+        self.telegram.get_me(&bot.token).await?;
+        let latency_ms = started.elapsed().as_millis() as u64;
+
+        let started = std::time::Instant::now();
+        let (file_id, message_id) = self
+            .telegram
+            .upload_file(
+                &bot.token,
+                &self.chat_id,
+                &payload_path.to_string_lossy(),
+                |_| {},
+            )
+            .await?;
+        let upload_secs = started.elapsed().as_secs_f64();
+
+        let started = std::time::Instant::now();
+        let downloaded = async {
+            let path = self
+                .telegram
+                .get_local_file_path(&bot.token, &file_id)
+                .await?;
+            if std::path::Path::new(&path).is_absolute() {
+                // Local Bot API server: the file now sits in its cache.
+                tokio::fs::read(&path).await?;
+            } else {
+                let url = self.telegram.get_download_url(&bot.token, &file_id).await?;
+                let res = self.telegram.download_file(&url).await?;
+                self.telegram.read_body(res).await?;
+            }
+            Ok::<(), TgCloudError>(())
+        }
+        .await;
+        let download_secs = started.elapsed().as_secs_f64();
+
+        if let Err(e) = self
+            .telegram
+            .delete_message(&bot.token, &self.chat_id, message_id)
+            .await
+        {
+            log::warn!(
+                "Failed to delete benchmark message of bot {}: {}",
+                bot.bot_id,
+                e
+            );
+        }
+        downloaded?;
+
+        let rate = |secs: f64| payload_size as f64 / secs.max(1e-3);
+        Ok((latency_ms, rate(upload_secs), rate(download_secs)))
+    }
+
+    /// Latest stored benchmark of every bot.
+    pub async fn latest_benchmarks(&self) -> Result<Vec<BotBenchmark>> {
+        self.store.latest_benchmarks().await
+    }
+
     // =======================================================================
     // Upload
     // =======================================================================
 
-    pub async fn upload_file(&self, path: &str, sender: mpsc::Sender<UploadEvent>) -> Result<()> {
+    /// Uploads the local file at `path`, stored under the same name or
+    /// `options.remote_name`.
+    ///
+    /// Fails with `AlreadyExists` if a remote file with that name exists, unless
+    /// `options.force` is set, in which case the old file is deleted once the
+    /// new one has been stored.
+    pub async fn upload_file(
+        &self,
+        path: &str,
+        options: UploadOptions,
+        sender: mpsc::Sender<UploadEvent>,
+    ) -> Result<()> {
+        let remote_name = options
+            .remote_name
+            .clone()
+            .unwrap_or_else(|| path.to_string());
+        let transfer_id = options.transfer_id.clone().unwrap_or_else(new_transfer_id);
+        let local_path = std::path::Path::new(path);
+        let result = with_transfer_id(transfer_id.clone(), async {
+            // Registered before the hooks so that cancelling it also stops a
+            // hook that is still running.
+            let transfer =
+                self.transfers
+                    .begin(&transfer_id, TransferKind::Upload, &remote_name)?;
+            cancellable(
+                &transfer.cancel_token(),
+                self.before_hooks(
+                    HookEvent::BeforeUpload,
+                    &transfer_id,
+                    &remote_name,
+                    Some(local_path),
+                ),
+            )
+            .await?;
+            self.upload_file_inner(path, &remote_name, transfer, options.force, sender)
+                .await
+        })
+        .await
+        .map_err(|e| e.in_transfer(&transfer_id, &remote_name));
+        self.transfers
+            .set_outcome(&transfer_id, result.as_ref().err());
+        self.record(HistoryAction::Upload, &remote_name, None, &result)
+            .await;
+        if result.is_ok() {
+            self.refresh_mirror().await;
+        }
+        if let Err(e) = self.purge_ledger().await {
+            log::warn!("Failed to purge expired ledger chunks: {}", e);
+        }
+        self.after_hooks(
+            HookEvent::AfterUpload,
+            &transfer_id,
+            &remote_name,
+            Some(local_path),
+            &result,
+        )
+        .await;
+        result
+    }
+
+    async fn upload_file_inner(
+        &self,
+        path: &str,
+        remote_name: &str,
+        transfer: TransferGuard,
+        force: bool,
+        sender: mpsc::Sender<UploadEvent>,
+    ) -> Result<()> {
+        let transfer_id = transfer.id();
+        let cancel = transfer.cancel_token();
+        log::info!("Uploading {} as {}", path, remote_name);
+
+        let existing = self.store.get_file_by_path(remote_name).await?;
+        if existing.is_some() && !force {
+            return Err(TgCloudError::AlreadyExists(remote_name.to_string()));
+        }
+
         let metadata = tokio::fs::metadata(path).await?;
         let total_size = metadata.len();
 
@@ -55,13 +512,15 @@ impl TgCloudService {
             1
         } else {
             // Use manual division or handle clippy warnings if necessary
-            total_size.div_ceil(CHUNK_SIZE) as u32
+            total_size.div_ceil(self.chunk_size) as u32
         };
 
         let progress = Arc::new(AtomicU64::new(0));
+        let tracker = transfer.tracker();
 
         let _ = sender
             .send(UploadEvent {
+                transfer_id: transfer_id.to_string(),
                 status: UploadStatus::Started {
                     total_size,
                     total_chunks,
@@ -73,124 +532,112 @@ impl TgCloudService {
         // Hash the full file once for verification
         let _ = sender
             .send(UploadEvent {
+                transfer_id: transfer_id.to_string(),
                 status: UploadStatus::Hashing,
             })
             .await;
 
-        let sha256 = {
-            let mut hasher = Sha256::new();
-            let mut file_for_hash = tokio::fs::File::open(path).await?;
-            let mut buf = [0u8; 65_536];
-            loop {
-                let n = file_for_hash.read(&mut buf).await?;
-                if n == 0 {
-                    break;
-                }
-                hasher.update(&buf[..n]);
-            }
-            hex::encode(hasher.finalize())
-        };
+        let sha256 = cancellable(&cancel, sha256_file(path, self.io_buffer_size)).await?;
 
         let _ = sender
             .send(UploadEvent {
+                transfer_id: transfer_id.to_string(),
                 status: UploadStatus::HashComplete {
                     sha256: sha256.clone(),
                 },
             })
             .await;
 
-        // Parallelism allowed for large files (> 256MB total)
-        // Note: For chunked uploads (> 2GB), we definitely use it.
-        let semaphore = Arc::new(Semaphore::new(self.max_concurrency));
-        let mut futures = FuturesUnordered::new();
-
-        for chunk_index in 0..total_chunks {
-            let offset = chunk_index as u64 * CHUNK_SIZE;
-            let current_chunk_size = std::cmp::min(CHUNK_SIZE, total_size.saturating_sub(offset));
-
-            let chunk_file_name = if total_chunks == 1 {
-                std::path::Path::new(path)
-                    .file_name()
-                    .map(|n| n.to_string_lossy().to_string())
-                    .unwrap_or_else(|| "file".to_string())
-            } else {
-                format!(
-                    "{}.chunk{}",
-                    std::path::Path::new(path)
-                        .file_name()
-                        .map(|n| n.to_string_lossy().to_string())
-                        .unwrap_or_else(|| "file".to_string()),
-                    chunk_index
-                )
-            };
-
-            let sem = Arc::clone(&semaphore);
-            let telegram = self.telegram.clone();
-            let bot_token = self.bot_token.clone();
-            let bot_id = self.bot_id.clone();
-            let chat_id = self.chat_id.clone();
-            let path_owned = path.to_string();
-            let progress_clone = Arc::clone(&progress);
-
-            futures.push(tokio::spawn(async move {
-                let _permit = sem
-                    .acquire()
-                    .await
-                    .map_err(|_| TgCloudError::UploadFailed("Semaphore closed".to_string()))?;
-
-                let (tg_id, msg_id) = telegram
-                    .upload_part_with_retry(
-                        &bot_token,
-                        &chat_id,
-                        chunk_file_name,
-                        &path_owned,
-                        offset,
-                        current_chunk_size,
-                        progress_clone,
-                    )
-                    .await?;
-
-                Ok::<FileChunk, TgCloudError>(FileChunk {
-                    index: chunk_index,
-                    bot_id: Some(bot_id),
-                    telegram_file_id: tg_id,
-                    message_id: msg_id,
-                    size: current_chunk_size,
-                })
-            }));
-        }
-
-        let mut chunks: Vec<FileChunk> = Vec::with_capacity(total_chunks as usize);
-        let mut first_error: Option<TgCloudError> = None;
+        let file_name = std::path::Path::new(path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "file".to_string());
 
-        while let Some(join_result) = futures.next().await {
-            match join_result {
-                Ok(Ok(chunk)) => chunks.push(chunk),
-                Ok(Err(e)) => {
-                    if first_error.is_none() {
-                        first_error = Some(e);
-                    }
+        let jobs: Vec<ChunkJob> = (0..total_chunks)
+            .map(|index| {
+                let offset = index as u64 * self.chunk_size;
+                ChunkJob {
+                    index,
+                    offset,
+                    size: std::cmp::min(self.chunk_size, total_size.saturating_sub(offset)),
+                    file_name: if total_chunks == 1 {
+                        file_name.clone()
+                    } else {
+                        format!("{}.chunk{}", file_name, index)
+                    },
                 }
-                Err(join_err) => {
-                    if first_error.is_none() {
-                        first_error = Some(TgCloudError::UploadFailed(format!(
-                            "Task panicked: {}",
-                            join_err
-                        )));
-                    }
+            })
+            .collect();
+        tracker.start(
+            total_size,
+            Arc::clone(&progress),
+            jobs.iter().map(|j| (j.index, j.size)),
+        );
+
+        // Chunks left by an interrupted upload of the same content are reused.
+        let mut reused = Vec::new();
+        let mut pending = Vec::with_capacity(jobs.len());
+        for job in jobs {
+            match self.claim_ledger_chunk(&sha256, &job).await {
+                Some(chunk) => {
+                    progress.fetch_add(chunk.size, std::sync::atomic::Ordering::Relaxed);
+                    tracker.chunk_reused(chunk.index, chunk.bot_id.as_deref());
+                    reused.push(chunk);
                 }
+                None => pending.push(job),
             }
         }
+        if !reused.is_empty() {
+            log::info!(
+                "Reusing {} chunk(s) of {} from an interrupted upload",
+                reused.len(),
+                remote_name
+            );
+            let _ = sender
+                .send(UploadEvent {
+                    transfer_id: transfer_id.to_string(),
+                    status: UploadStatus::Resuming {
+                        reused_chunks: reused.len() as u32,
+                    },
+                })
+                .await;
+        }
+
+        let ctx = UploadContext {
+            telegram: self.telegram.clone(),
+            bots: Arc::clone(&self.bots),
+            store: self.store.clone(),
+            chat_id: self.chat_id.clone(),
+            path: path.to_string(),
+            sha256: sha256.clone(),
+            chunk_size: self.chunk_size,
+            progress: Arc::clone(&progress),
+            max_concurrency: self.max_concurrency,
+            cancel: cancel.clone(),
+            tracker,
+        };
+        let (mut chunks, first_error) = scheduler::upload_chunks(ctx, pending).await;
 
         if let Some(err) = first_error {
-            for chunk in &chunks {
-                let _ = self
-                    .telegram
-                    .delete_message(&self.bot_token, &self.chat_id, chunk.message_id)
-                    .await;
+            if matches!(err.root(), TgCloudError::Cancelled) {
+                chunks.extend(reused);
+                self.rollback_chunks(&self.chat_id, &chunks).await;
+                self.release_quota(&chunks);
+                self.forget_ledger_chunks(&chunks).await;
+            } else {
+                // Everything sent so far stays in the ledger, so running the
+                // same upload again only sends the missing chunks; see
+                // `purge_ledger` for when they are given up on.
+                self.return_ledger_chunks(&sha256, &reused).await;
+                log::info!(
+                    "Kept {} uploaded chunk(s) of {}; run the upload again to resume",
+                    chunks.len() + reused.len(),
+                    remote_name
+                );
             }
             let _ = sender
                 .send(UploadEvent {
+                    transfer_id: transfer_id.to_string(),
                     status: UploadStatus::Failed {
                         error: err.to_string(),
                     },
@@ -199,42 +646,49 @@ impl TgCloudService {
             return Err(err);
         }
 
+        chunks.extend(reused);
         chunks.sort_by_key(|c| c.index);
 
         let file_id = Uuid::new_v4().to_string();
-        let original_name = path.to_string();
+        let original_name = remote_name.to_string();
 
         let file_meta = FileMetadata {
             id: None,
             file_id: file_id.clone(),
             original_name,
             size: total_size,
-            chunk_size: CHUNK_SIZE,
+            chunk_size: self.chunk_size,
             total_chunks,
             sha256,
             chunks: chunks.clone(),
             created_at: Utc::now(),
-            bot_id: Some(self.bot_id.clone()),
+            bot_id: chunks.first().and_then(|c| c.bot_id.clone()),
+            archive_chat_id: None,
         };
 
         match self.store.save_file(file_meta).await {
             Ok(_) => {
+                self.forget_ledger_chunks(&chunks).await;
+                if let Some(old) = existing {
+                    if let Err(e) = self.delete_file_internal(old, None).await {
+                        log::warn!("Failed to remove replaced file {}: {}", remote_name, e);
+                    }
+                }
                 let _ = sender
                     .send(UploadEvent {
+                        transfer_id: transfer_id.to_string(),
                         status: UploadStatus::Completed { file_id },
                     })
                     .await;
                 Ok(())
             }
             Err(e) => {
-                for chunk in &chunks {
-                    let _ = self
-                        .telegram
-                        .delete_message(&self.bot_token, &self.chat_id, chunk.message_id)
-                        .await;
-                }
+                self.rollback_chunks(&self.chat_id, &chunks).await;
+                self.release_quota(&chunks);
+                self.forget_ledger_chunks(&chunks).await;
                 let _ = sender
                     .send(UploadEvent {
+                        transfer_id: transfer_id.to_string(),
                         status: UploadStatus::Failed {
                             error: e.to_string(),
                         },
@@ -245,22 +699,248 @@ impl TgCloudService {
         }
     }
 
+    /// Takes a chunk matching `job` out of the ledger, if an earlier attempt
+    /// uploaded one through a bot that is still configured.
+    async fn claim_ledger_chunk(&self, sha256: &str, job: &ChunkJob) -> Option<FileChunk> {
+        let claimed = self
+            .store
+            .claim_ledger_chunk(sha256, &self.chat_id, self.chunk_size, job.index, job.size)
+            .await;
+        match claimed {
+            Ok(Some(entry))
+                if self
+                    .bots
+                    .bot_for_chunk(entry.chunk.bot_id.as_deref())
+                    .is_ok() =>
+            {
+                Some(entry.chunk)
+            }
+            Ok(Some(entry)) => {
+                log::warn!(
+                    "Not reusing chunk {}: bot {:?} is no longer configured",
+                    job.index,
+                    entry.chunk.bot_id
+                );
+                None
+            }
+            Ok(None) => None,
+            Err(e) => {
+                log::warn!("Failed to read the chunk ledger: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Puts chunks claimed from the ledger back after a failed attempt.
+    async fn return_ledger_chunks(&self, sha256: &str, chunks: &[FileChunk]) {
+        for chunk in chunks {
+            let entry = LedgerChunk {
+                id: None,
+                sha256: sha256.to_string(),
+                chat_id: self.chat_id.clone(),
+                chunk_size: self.chunk_size,
+                chunk: chunk.clone(),
+                created_at: Utc::now(),
+            };
+            if let Err(e) = self.store.record_ledger_chunk(&entry).await {
+                log::warn!(
+                    "Failed to return chunk {} to the ledger: {}",
+                    chunk.index,
+                    e
+                );
+            }
+        }
+    }
+
+    /// Drops ledger entries for chunks that were saved or deleted.
+    async fn forget_ledger_chunks(&self, chunks: &[FileChunk]) {
+        let ids: Vec<i64> = chunks.iter().map(|c| c.message_id).collect();
+        if let Err(e) = self.store.remove_ledger_chunks(&self.chat_id, &ids).await {
+            log::warn!("Failed to clean up the chunk ledger: {}", e);
+        }
+    }
+
+    /// Deletes the messages of ledger entries older than the ledger retention
+    /// period: chunks of failed uploads that were never retried. A message
+    /// that cannot be deleted is logged, since nothing refers to it any more.
+    /// Returns the number of messages deleted.
+    pub async fn purge_ledger(&self) -> Result<usize> {
+        let Some(cutoff) = Utc::now().checked_sub_signed(self.ledger_retention) else {
+            return Ok(0);
+        };
+        let mut purged = 0;
+        while let Some(entry) = self.store.take_expired_ledger_chunk(cutoff).await? {
+            let chunk = &entry.chunk;
+            let result = match self.bots.bot_for_chunk(chunk.bot_id.as_deref()) {
+                Ok(bot) => {
+                    self.telegram
+                        .delete_message(&bot.token, &entry.chat_id, chunk.message_id)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => purged += 1,
+                Err(e) => log::warn!(
+                    "Failed to delete expired chunk message {} in chat {}: {}",
+                    chunk.message_id,
+                    entry.chat_id,
+                    e
+                ),
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Returns the bytes of deleted chunks to their bots' quotas.
+    fn release_quota(&self, chunks: &[FileChunk]) {
+        for chunk in chunks {
+            if let Some(bot_id) = &chunk.bot_id {
+                self.bots.release(bot_id, chunk.size);
+            }
+        }
+    }
+
+    /// Best-effort removal of chunks sent to `chat_id` by a failed upload or
+    /// move.
+    async fn rollback_chunks(&self, chat_id: &str, chunks: &[FileChunk]) {
+        for chunk in chunks {
+            let Ok(bot) = self.bots.bot_for_chunk(chunk.bot_id.as_deref()) else {
+                continue;
+            };
+            let _ = self
+                .telegram
+                .delete_message(&bot.token, chat_id, chunk.message_id)
+                .await;
+        }
+    }
+
     // =======================================================================
     // Download (Local Fetch Only)
     // =======================================================================
 
+    /// Fetches `path` into the Bot API server's local cache and, if
+    /// `options.destination` is set, copies it there and removes the cached
+    /// copy. Fails with `AlreadyExists` if the destination exists, unless
+    /// `options.force` is set.
     pub async fn download_file(
         &self,
         path: &str,
+        options: DownloadOptions,
         sender: mpsc::Sender<DownloadEvent>,
     ) -> Result<()> {
+        let transfer_id = options.transfer_id.clone().unwrap_or_else(new_transfer_id);
+        let result = with_transfer_id(transfer_id.clone(), async {
+            // Registered before the hooks, as in `upload_file`.
+            let transfer = self
+                .transfers
+                .begin(&transfer_id, TransferKind::Download, path)?;
+            cancellable(
+                &transfer.cancel_token(),
+                self.before_hooks(HookEvent::BeforeDownload, &transfer_id, path, None),
+            )
+            .await?;
+            self.download_file_inner(path, transfer, options, sender)
+                .await
+        })
+        .await
+        .map_err(|e| e.in_transfer(&transfer_id, path));
+        self.transfers
+            .set_outcome(&transfer_id, result.as_ref().err());
+        self.record(HistoryAction::Download, path, None, &result)
+            .await;
+        let written = result.as_ref().ok().map(std::path::Path::new);
+        self.after_hooks(
+            HookEvent::AfterDownload,
+            &transfer_id,
+            path,
+            written,
+            &result,
+        )
+        .await;
+        result.map(|_| ())
+    }
+
+    async fn download_file_inner(
+        &self,
+        path: &str,
+        transfer: TransferGuard,
+        options: DownloadOptions,
+        sender: mpsc::Sender<DownloadEvent>,
+    ) -> Result<String> {
+        log::info!("Downloading {}", path);
         let file_opt: Option<FileMetadata> = self.store.get_file_by_path(path).await?;
         let file = file_opt.ok_or_else(|| TgCloudError::FileNotFound(path.to_string()))?;
 
+        if let Some(dest) = &options.destination {
+            if !options.force && tokio::fs::try_exists(dest).await? {
+                return Err(TgCloudError::AlreadyExists(dest.display().to_string()));
+            }
+        }
+
+        let mut final_path = self.fetch_local(&file, &sender, &transfer).await?;
+
+        if let Some(dest) = &options.destination {
+            let partial = partial_path_for(dest);
+            let copied = cancellable(&transfer.cancel_token(), async {
+                if let Some(parent) = dest.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                let cached = tokio::fs::File::open(&final_path).await?;
+                let mut cached = BufReader::with_capacity(self.io_buffer_size, cached);
+                let out_file = tokio::fs::File::create(&partial).await?;
+                let mut out_file = BufWriter::with_capacity(self.io_buffer_size, out_file);
+                tokio::io::copy_buf(&mut cached, &mut out_file).await?;
+                out_file.flush().await?;
+                tokio::fs::rename(&partial, dest).await?;
+                Ok(())
+            })
+            .await;
+            if copied.is_err() {
+                let _ = tokio::fs::remove_file(&partial).await;
+            }
+            // The cached copy was only a staging area for the destination.
+            let _ = tokio::fs::remove_file(&final_path).await;
+            if let Some(work_dir) = std::path::Path::new(&final_path).parent() {
+                let _ = tokio::fs::remove_dir(work_dir).await;
+            }
+            copied?;
+            final_path = dest.display().to_string();
+        }
+
+        let _ = sender
+            .send(DownloadEvent {
+                transfer_id: transfer.id().to_string(),
+                status: DownloadStatus::Completed {
+                    path: final_path.clone(),
+                },
+            })
+            .await;
+
+        Ok(final_path)
+    }
+
+    /// Fetches every chunk of `file` into the Bot API server's local cache,
+    /// merges them if needed and verifies the SHA-256. Returns the path of the
+    /// reconstructed file. A partially merged file is removed on cancellation.
+    async fn fetch_local(
+        &self,
+        file: &FileMetadata,
+        sender: &mpsc::Sender<DownloadEvent>,
+        transfer: &TransferGuard,
+    ) -> Result<String> {
+        let cancel = &transfer.cancel_token();
         let progress = Arc::new(AtomicU64::new(0));
+        let tracker = transfer.tracker();
+        tracker.start(
+            file.size,
+            Arc::clone(&progress),
+            file.chunks.iter().map(|c| (c.index, c.size)),
+        );
 
         let _ = sender
             .send(DownloadEvent {
+                transfer_id: transfer.id().to_string(),
                 status: DownloadStatus::Started {
                     total_size: file.size,
                     total_chunks: file.total_chunks,
@@ -273,10 +953,31 @@ impl TgCloudService {
 
         // Sequential download for local fetch (files stay on server)
         for chunk in &file.chunks {
-            let file_path = self
-                .telegram
-                .get_local_file_path(&self.bot_token, &chunk.telegram_file_id)
-                .await?;
+            let bot = self.bots.bot_for_chunk(chunk.bot_id.as_deref())?;
+            tracker.chunk_started(chunk.index, &bot.bot_id);
+            let file_path = match cancellable(
+                cancel,
+                self.telegram
+                    .get_local_file_path(&bot.token, &chunk.telegram_file_id),
+            )
+            .await
+            {
+                Ok(p) => {
+                    self.bots.report_success(&bot.bot_id);
+                    tracker.chunk_done(chunk.index);
+                    p
+                }
+                Err(TgCloudError::BotUnauthorized { status, message }) => {
+                    tracker.chunk_failed(chunk.index);
+                    self.bots.report_auth_failure(&bot.bot_id, status).await;
+                    return Err(TgCloudError::BotUnauthorized { status, message }
+                        .in_chunk(chunk.index, &bot.bot_id));
+                }
+                Err(e) => {
+                    tracker.chunk_failed(chunk.index);
+                    return Err(e.in_chunk(chunk.index, &bot.bot_id));
+                }
+            };
 
             // In local mode, getFile returns the absolute path on disk.
             chunk_paths.push(file_path);
@@ -287,6 +988,7 @@ impl TgCloudService {
 
         let _ = sender
             .send(DownloadEvent {
+                transfer_id: transfer.id().to_string(),
                 status: DownloadStatus::Merging,
             })
             .await;
@@ -296,123 +998,357 @@ impl TgCloudService {
             .ok_or_else(|| TgCloudError::DownloadFailed("Invalid original name".to_string()))?
             .to_string_lossy();
 
-        // Merge chunks in the server's documents directory if multiple chunks exist
-        let final_path = if chunk_paths.len() > 1 {
-            let first_path = &chunk_paths[0];
-            let parent = std::path::Path::new(first_path)
-                .parent()
-                .ok_or_else(|| TgCloudError::DownloadFailed("Invalid chunk path".to_string()))?;
-            let merged_path = parent.join(original_filename.as_ref());
-
-            let mut out_file = tokio::fs::File::create(&merged_path).await?;
-            for tmp_path in &chunk_paths {
-                let mut tmp = tokio::fs::File::open(tmp_path).await?;
-                let mut buf = [0u8; 65_536];
-                loop {
-                    let n = tmp.read(&mut buf).await?;
-                    if n == 0 {
-                        break;
-                    }
-                    out_file.write_all(&buf[..n]).await?;
+        // Merge chunks in the server's documents directory if multiple chunks exist.
+        // The merged output is written to `<target>.partial` and only renamed into
+        // place once its SHA-256 matches, so an interrupted or corrupt download never
+        // leaves a plausible-looking file at the target path. The target is scratch
+        // space of the Bot API cache in a directory of its own, so concurrent
+        // downloads of files with the same base name never share a path;
+        // `download_file_inner` protects the user's destination.
+        let first_path = std::path::Path::new(&chunk_paths[0]);
+        let parent = first_path
+            .parent()
+            .ok_or_else(|| TgCloudError::DownloadFailed("Invalid chunk path".to_string()))?;
+        let work_dir = parent.join(format!("tgcloud-{}", Uuid::new_v4()));
+        tokio::fs::create_dir_all(&work_dir).await?;
+        let target_path = work_dir.join(original_filename.as_ref());
+
+        let (work_path, is_partial) = if chunk_paths.len() > 1 {
+            let partial_path = partial_path_for(&target_path);
+
+            let merged = cancellable(cancel, async {
+                let out_file = tokio::fs::File::create(&partial_path).await?;
+                let mut out_file = BufWriter::with_capacity(self.io_buffer_size, out_file);
+                for tmp_path in &chunk_paths {
+                    let tmp = tokio::fs::File::open(tmp_path).await?;
+                    let mut tmp = BufReader::with_capacity(self.io_buffer_size, tmp);
+                    tokio::io::copy_buf(&mut tmp, &mut out_file).await?;
                 }
+                out_file.flush().await?;
+                Ok(())
+            })
+            .await;
+            if let Err(e) = merged {
+                let _ = tokio::fs::remove_file(&partial_path).await;
+                let _ = tokio::fs::remove_dir(&work_dir).await;
+                return Err(e);
             }
-            out_file.flush().await?;
-            merged_path.to_string_lossy().to_string()
+            (partial_path, true)
         } else {
-            // Rename to original filename
-            let current_path = &chunk_paths[0];
-            let parent = std::path::Path::new(current_path)
-                .parent()
-                .ok_or_else(|| TgCloudError::DownloadFailed("Invalid chunk path".to_string()))?;
-            let target_path = parent.join(original_filename.as_ref());
-            let target_path_str = target_path.to_string_lossy().to_string();
-
-            if current_path != &target_path_str {
-                tokio::fs::rename(current_path, &target_path).await?;
-            }
-            target_path_str
+            // A single chunk is verified where the Bot API stored it, then renamed.
+            (first_path.to_path_buf(), false)
         };
 
         let _ = sender
             .send(DownloadEvent {
+                transfer_id: transfer.id().to_string(),
                 status: DownloadStatus::Verifying,
             })
             .await;
 
         // Verify SHA-256 of the FULL file (single chunk or merged)
-        let actual_hash = {
-            let mut hasher = Sha256::new();
-            let mut f = tokio::fs::File::open(&final_path).await?;
-            let mut buf = [0u8; 65_536];
-            loop {
-                let n = f.read(&mut buf).await?;
-                if n == 0 {
-                    break;
-                }
-                hasher.update(&buf[..n]);
-            }
-            hex::encode(hasher.finalize())
-        };
+        let actual_hash = cancellable(cancel, sha256_file(&work_path, self.io_buffer_size)).await;
 
-        if actual_hash != file.sha256 {
-            let err = TgCloudError::IntegrityFailed(format!(
+        let verified = match actual_hash {
+            Ok(hash) if hash == file.sha256 => Ok(()),
+            Ok(hash) => Err(TgCloudError::IntegrityFailed(format!(
                 "SHA256 mismatch: expected {}, got {}",
-                file.sha256, actual_hash
-            ));
-            let _ = sender
-                .send(DownloadEvent {
-                    status: DownloadStatus::Failed {
-                        error: err.to_string(),
-                    },
-                })
-                .await;
+                file.sha256, hash
+            ))),
+            Err(e) => Err(e),
+        };
+
+        if let Err(err) = verified {
+            if is_partial {
+                let _ = tokio::fs::remove_file(&work_path).await;
+            }
+            let _ = tokio::fs::remove_dir(&work_dir).await;
+            if let TgCloudError::IntegrityFailed(_) = err {
+                let _ = sender
+                    .send(DownloadEvent {
+                        transfer_id: transfer.id().to_string(),
+                        status: DownloadStatus::Failed {
+                            error: err.to_string(),
+                        },
+                    })
+                    .await;
+            }
             return Err(err);
         }
 
-        let _ = sender
-            .send(DownloadEvent {
-                status: DownloadStatus::Completed { path: final_path },
-            })
+        if let Err(e) = tokio::fs::rename(&work_path, &target_path).await {
+            if is_partial {
+                let _ = tokio::fs::remove_file(&work_path).await;
+            }
+            let _ = tokio::fs::remove_dir(&work_dir).await;
+            return Err(e.into());
+        }
+        let final_path = target_path.to_string_lossy().to_string();
+
+        Ok(final_path)
+    }
+
+    // =======================================================================
+    // Send to another chat
+    // =======================================================================
+
+    /// Delivers a stored file to `to_chat` (a numeric chat id or `@username`)
+    /// by forwarding its chunk documents from the storage chat, in order.
+    ///
+    /// Chunks already fill the Bot API's 2 GiB document limit, so a chunked
+    /// file arrives as its `<name>.chunkN` parts for the recipient to join.
+    /// Returns the message ids in the target chat, one per chunk.
+    pub async fn send_file(&self, path: &str, to_chat: &str) -> Result<Vec<i64>> {
+        let transfer_id = new_transfer_id();
+        let result = with_transfer_id(
+            transfer_id.clone(),
+            self.send_file_inner(path, to_chat, &transfer_id),
+        )
+        .await
+        .map_err(|e| e.in_transfer(&transfer_id, path));
+        self.transfers
+            .set_outcome(&transfer_id, result.as_ref().err());
+        self.record(HistoryAction::Send, path, Some(to_chat), &result)
             .await;
+        result
+    }
 
-        Ok(())
+    async fn send_file_inner(
+        &self,
+        path: &str,
+        to_chat: &str,
+        transfer_id: &str,
+    ) -> Result<Vec<i64>> {
+        let transfer = self
+            .transfers
+            .begin(transfer_id, TransferKind::Send, path)?;
+        log::info!("Sending {} to {}", path, to_chat);
+        let file_opt: Option<FileMetadata> = self.store.get_file_by_path(path).await?;
+        let file = file_opt.ok_or_else(|| TgCloudError::FileNotFound(path.to_string()))?;
+
+        let cancel = transfer.cancel_token();
+        let progress = Arc::new(AtomicU64::new(0));
+        let tracker = transfer.tracker();
+        tracker.start(
+            file.size,
+            Arc::clone(&progress),
+            file.chunks.iter().map(|c| (c.index, c.size)),
+        );
+
+        let mut chunks: Vec<&FileChunk> = file.chunks.iter().collect();
+        chunks.sort_by_key(|c| c.index);
+        let mut message_ids = Vec::with_capacity(chunks.len());
+        for chunk in chunks {
+            let bot = self.bots.bot_for_chunk(chunk.bot_id.as_deref())?;
+            tracker.chunk_started(chunk.index, &bot.bot_id);
+            let forwarded = cancellable(
+                &cancel,
+                self.telegram.forward_message(
+                    &bot.token,
+                    self.chat_of(&file),
+                    to_chat,
+                    chunk.message_id,
+                ),
+            )
+            .await;
+            match forwarded {
+                Ok(message_id) => {
+                    tracker.chunk_done(chunk.index);
+                    progress.fetch_add(chunk.size, std::sync::atomic::Ordering::Relaxed);
+                    message_ids.push(message_id);
+                }
+                Err(e) => {
+                    tracker.chunk_failed(chunk.index);
+                    return Err(e.in_chunk(chunk.index, &bot.bot_id));
+                }
+            }
+        }
+        Ok(message_ids)
     }
 
     // =======================================================================
     // Rename / Delete / List
     // =======================================================================
 
+    /// Looks up the metadata of a stored file.
+    pub async fn get_file(&self, path: &str) -> Result<FileMetadata> {
+        self.store
+            .get_file_by_path(path)
+            .await?
+            .ok_or_else(|| TgCloudError::FileNotFound(path.to_string()))
+    }
+
     pub async fn rename_file(&self, old_path: &str, new_path: &str) -> Result<()> {
-        self.store.rename_file(old_path, new_path).await
+        let result = self.store.rename_file(old_path, new_path).await;
+        self.record(HistoryAction::Rename, old_path, Some(new_path), &result)
+            .await;
+        result
     }
 
     pub async fn rename_file_by_id(&self, file_id: &str, new_name: &str) -> Result<()> {
-        self.store.rename_file_by_id(file_id, new_name).await
+        let old_name = match self.store.get_file_by_id(file_id).await? {
+            Some(file) => file.original_name,
+            None => return Err(TgCloudError::FileNotFound(file_id.to_string())),
+        };
+        let result = self.store.rename_file_by_id(file_id, new_name).await;
+        self.record(HistoryAction::Rename, &old_name, Some(new_name), &result)
+            .await;
+        result
+    }
+
+    /// Moves every file under the folder `old_prefix` to `new_prefix`, e.g.
+    /// `photos/2023/` to `archive/photos-2023/`. Either all files are moved or,
+    /// on failure, none are. Returns the number of files moved.
+    pub async fn rename_prefix(&self, old_prefix: &str, new_prefix: &str) -> Result<usize> {
+        let result = self.store.rename_prefix(old_prefix, new_prefix).await;
+        self.record(
+            HistoryAction::Rename,
+            &folder_name(old_prefix),
+            Some(&folder_name(new_prefix)),
+            &result,
+        )
+        .await;
+        result
+    }
+
+    /// Compares the local directory `local_dir` with the remote folder `prefix`.
+    pub async fn diff_dir(&self, local_dir: &std::path::Path, prefix: &str) -> Result<DirDiff> {
+        let remote = self.store.list_files_with_depth(prefix, None, true).await?;
+        diff::diff_dir(
+            local_dir,
+            &remote,
+            &diff::remote_root(prefix),
+            &PathFilter::default(),
+            self.io_buffer_size,
+        )
+        .await
+    }
+
+    /// Plans a sync between `local_dir` and the remote folder `prefix`,
+    /// considering only paths that pass `filter`.
+    pub async fn plan_sync(
+        &self,
+        local_dir: &std::path::Path,
+        prefix: &str,
+        direction: SyncDirection,
+        delete: bool,
+        filter: &PathFilter,
+    ) -> Result<SyncPlan> {
+        let root = diff::remote_root(prefix);
+        let remote = self.store.list_files_with_depth(prefix, None, true).await?;
+        // Filtered-out files are neither transferred nor deleted.
+        let diff = diff::diff_dir(local_dir, &remote, &root, filter, self.io_buffer_size).await?;
+        Ok(SyncPlan::build(&diff, direction, delete, local_dir, &root))
+    }
+
+    /// Performs one step of a sync plan. Remote deletes go to the trash.
+    pub async fn apply_sync_action(&self, action: &SyncAction) -> Result<()> {
+        match action {
+            SyncAction::Upload {
+                local,
+                remote,
+                replace,
+                ..
+            } => {
+                // Nobody listens for progress; sends on the closed channel are ignored.
+                let (tx, _) = mpsc::channel(1);
+                let options = UploadOptions {
+                    force: *replace,
+                    remote_name: Some(remote.clone()),
+                    ..Default::default()
+                };
+                self.upload_file(&local.to_string_lossy(), options, tx)
+                    .await
+            }
+            SyncAction::Download {
+                remote,
+                local,
+                replace,
+                ..
+            } => {
+                // Nobody listens for progress; sends on the closed channel are ignored.
+                let (tx, _) = mpsc::channel(1);
+                let options = DownloadOptions {
+                    force: *replace,
+                    destination: Some(local.clone()),
+                    ..Default::default()
+                };
+                self.download_file(remote, options, tx).await
+            }
+            SyncAction::DeleteRemote { remote, .. } => self.delete_file(remote).await,
+            SyncAction::DeleteLocal { local, .. } => Ok(tokio::fs::remove_file(local).await?),
+        }
     }
 
     pub async fn delete_file_by_id(&self, file_id: &str) -> Result<()> {
         let file_opt: Option<FileMetadata> = self.store.get_file_by_id(file_id).await?;
         let file = file_opt.ok_or_else(|| TgCloudError::FileNotFound(file_id.to_string()))?;
 
-        self.delete_file_internal(file).await
+        self.delete_file_internal(file, None).await
     }
 
     pub async fn delete_file(&self, path: &str) -> Result<()> {
         let file_opt: Option<FileMetadata> = self.store.get_file_by_path(path).await?;
         let file = file_opt.ok_or_else(|| TgCloudError::FileNotFound(path.to_string()))?;
 
-        self.delete_file_internal(file).await
+        self.delete_file_internal(file, None).await
+    }
+
+    /// Moves `files` to the trash as one operation, which `undo` restores as a
+    /// whole. Returns the outcome for every file, by name.
+    pub async fn delete_files(&self, files: Vec<FileMetadata>) -> Vec<(String, Result<()>)> {
+        let operation = Uuid::new_v4().to_string();
+        let mut outcomes = Vec::with_capacity(files.len());
+        for file in files {
+            let name = file.original_name.clone();
+            let result = self.trash_file(&file, Some(&operation)).await;
+            outcomes.push((name, result));
+        }
+        if let Err(e) = self.purge_trash(false).await {
+            log::warn!("Failed to purge expired trash: {}", e);
+        }
+        outcomes
+    }
+
+    /// Moves `file` to the trash. Its Telegram messages are removed once the
+    /// trash retention period has passed, so the delete can be undone until then.
+    async fn delete_file_internal(
+        &self,
+        file: FileMetadata,
+        operation: Option<&str>,
+    ) -> Result<()> {
+        self.trash_file(&file, operation).await?;
+
+        if let Err(e) = self.purge_trash(false).await {
+            log::warn!("Failed to purge expired trash: {}", e);
+        }
+        Ok(())
+    }
+
+    async fn trash_file(&self, file: &FileMetadata, operation: Option<&str>) -> Result<()> {
+        let result = self.store.trash_file(file).await;
+        self.record_in(
+            operation,
+            HistoryAction::Delete,
+            &file.original_name,
+            None,
+            &result,
+        )
+        .await;
+        result
     }
 
-    async fn delete_file_internal(&self, file: FileMetadata) -> Result<()> {
+    /// Deletes the Telegram messages holding a file's chunks.
+    async fn delete_chunks(&self, file: &FileMetadata) -> Result<()> {
         let semaphore = Arc::new(Semaphore::new(self.max_concurrency));
         let mut futures = FuturesUnordered::new();
 
         for chunk in &file.chunks {
             let sem = Arc::clone(&semaphore);
             let telegram = self.telegram.clone();
-            let bot_token = self.bot_token.clone();
-            let chat_id = self.chat_id.clone();
+            let bots = Arc::clone(&self.bots);
+            let bot_id = chunk.bot_id.clone();
+            let chat_id = self.chat_of(file).to_string();
             let message_id = chunk.message_id;
             let chunk_index = chunk.index;
 
@@ -422,15 +1358,19 @@ impl TgCloudService {
                     .await
                     .map_err(|_| TgCloudError::DeleteFailed("Semaphore closed".to_string()))?;
 
-                telegram
-                    .delete_message(&bot_token, &chat_id, message_id)
-                    .await
-                    .map_err(|e| {
-                        TgCloudError::DeleteFailed(format!(
-                            "Failed to delete chunk {}: {}",
-                            chunk_index, e
-                        ))
-                    })?;
+                let bot = bots.bot_for_chunk(bot_id.as_deref())?;
+                let result = telegram
+                    .delete_message(&bot.token, &chat_id, message_id)
+                    .await;
+                if let Err(TgCloudError::BotUnauthorized { status, .. }) = &result {
+                    bots.report_auth_failure(&bot.bot_id, *status).await;
+                }
+                result.map_err(|e| {
+                    TgCloudError::DeleteFailed(format!(
+                        "Failed to delete chunk {}: {}",
+                        chunk_index, e
+                    ))
+                })?;
 
                 Ok::<(), TgCloudError>(())
             }));
@@ -455,12 +1395,558 @@ impl TgCloudService {
             )));
         }
 
-        self.store.delete_file_by_id(&file.file_id).await?;
+        Ok(())
+    }
+
+    // =======================================================================
+    // Archive
+    // =======================================================================
+
+    /// Moves the chunks of `target`, a file or every file under a folder, to
+    /// `ARCHIVE_CHAT_ID`. Archived files can still be downloaded but are left
+    /// out of listings unless asked for. Returns the number of files moved.
+    pub async fn archive(&self, target: &str) -> Result<usize> {
+        let archive_chat = self.archive_chat_id.as_deref().ok_or_else(|| {
+            TgCloudError::ConfigError(ConfigError::MissingEnvVar("ARCHIVE_CHAT_ID".into()))
+        })?;
+        let result = self.relocate(target, Some(archive_chat)).await;
+        self.record(HistoryAction::Archive, target, Some(archive_chat), &result)
+            .await;
+        result
+    }
+
+    /// Moves the chunks of archived files under `target` back to the storage
+    /// chat. Returns the number of files moved.
+    pub async fn unarchive(&self, target: &str) -> Result<usize> {
+        let result = self.relocate(target, None).await;
+        self.record(HistoryAction::Unarchive, target, None, &result)
+            .await;
+        result
+    }
 
+    /// Moves every file named `target`, or under the folder `target`, whose
+    /// chunks are not in `to` yet. Files that fail are reported together
+    /// after the others were moved.
+    async fn relocate(&self, target: &str, to: Option<&str>) -> Result<usize> {
+        let files = match self.store.get_file_by_path(target).await? {
+            Some(file) => vec![file],
+            None => self.store.list_files_with_depth(target, None, true).await?,
+        };
+        if files.is_empty() {
+            return Err(TgCloudError::FileNotFound(target.to_string()));
+        }
+
+        let mut moved = 0;
+        let mut errors = Vec::new();
+        for file in files {
+            if file.archive_chat_id.as_deref() == to {
+                continue;
+            }
+            match self.move_chunks(&file, to).await {
+                Ok(()) => moved += 1,
+                Err(e) => errors.push(format!("{}: {}", file.original_name, e)),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(TgCloudError::UploadFailed(format!(
+                "Moved {} file(s), {} failed: {}",
+                moved,
+                errors.len(),
+                errors.join("; ")
+            )));
+        }
+        Ok(moved)
+    }
+
+    /// Forwards the chunks of `file` to `to` (the storage chat with `None`),
+    /// points its metadata at the copies and then deletes the originals. If a
+    /// chunk cannot be forwarded, the copies made so far are deleted and the
+    /// file stays where it was.
+    async fn move_chunks(&self, file: &FileMetadata, to: Option<&str>) -> Result<()> {
+        let from = self.chat_of(file);
+        let to_chat = to.unwrap_or(&self.chat_id);
+        let mut copies = Vec::with_capacity(file.chunks.len());
+        for chunk in &file.chunks {
+            let forwarded = match self.bots.bot_for_chunk(chunk.bot_id.as_deref()) {
+                Ok(bot) => self
+                    .telegram
+                    .forward_message(&bot.token, from, to_chat, chunk.message_id)
+                    .await
+                    .map_err(|e| TgCloudError::InChunk {
+                        index: chunk.index,
+                        bot_id: bot.bot_id.clone(),
+                        source: Box::new(e),
+                    }),
+                Err(e) => Err(e),
+            };
+            match forwarded {
+                Ok(message_id) => copies.push(FileChunk {
+                    message_id,
+                    ..chunk.clone()
+                }),
+                Err(e) => {
+                    self.rollback_chunks(to_chat, &copies).await;
+                    return Err(e);
+                }
+            }
+        }
+
+        if let Err(e) = self
+            .store
+            .set_file_location(&file.file_id, &copies, to)
+            .await
+        {
+            self.rollback_chunks(to_chat, &copies).await;
+            return Err(e);
+        }
+        if let Err(e) = self.delete_chunks(file).await {
+            log::warn!(
+                "Moved {} but could not delete its old messages: {}",
+                file.original_name,
+                e
+            );
+        }
         Ok(())
     }
 
-    pub async fn list_files(&self, prefix: &str) -> Result<Vec<FileMetadata>> {
-        self.store.list_files(prefix).await
+    /// Chat holding the chunks of `file`.
+    fn chat_of<'a>(&'a self, file: &'a FileMetadata) -> &'a str {
+        file.archive_chat_id.as_deref().unwrap_or(&self.chat_id)
     }
+
+    // =======================================================================
+    // Trash / Undo
+    // =======================================================================
+
+    /// Files in the trash, most recently deleted first.
+    pub async fn list_trash(&self) -> Result<Vec<TrashedFile>> {
+        self.store.list_trash(None).await
+    }
+
+    /// Trashed files whose retention period has passed.
+    pub async fn expired_trash(&self) -> Result<Vec<TrashedFile>> {
+        match Utc::now().checked_sub_signed(self.trash_retention) {
+            Some(cutoff) => self.store.list_trash(Some(cutoff)).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Permanently removes trashed files and their Telegram messages: all of
+    /// them with `all`, otherwise only those past the retention period.
+    /// Returns the number of files removed.
+    pub async fn purge_trash(&self, all: bool) -> Result<usize> {
+        let trashed = if all {
+            self.list_trash().await?
+        } else {
+            self.expired_trash().await?
+        };
+
+        let mut purged = 0;
+        let mut errors = Vec::new();
+        for entry in trashed {
+            match self.delete_chunks(&entry.file).await {
+                Ok(()) => {
+                    self.release_quota(&entry.file.chunks);
+                    self.store.remove_from_trash(&entry.file.file_id).await?;
+                    purged += 1;
+                }
+                Err(e) => errors.push(format!("{}: {}", entry.file.original_name, e)),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(TgCloudError::DeleteFailed(errors.join("; ")));
+        }
+        Ok(purged)
+    }
+
+    /// Encrypts the file names and history entries stored before
+    /// `METADATA_KEY` was set. Returns the number of documents rewritten.
+    pub async fn encrypt_metadata(&self) -> Result<u64> {
+        self.store.seal_existing().await
+    }
+
+    /// Rewrites the metadata manifest in the storage chat if
+    /// `METADATA_MIRROR` is on. Failures are only logged: the file itself is
+    /// stored, and the next refresh includes it.
+    async fn refresh_mirror(&self) {
+        let Some(mirror) = self.mirror.as_ref().filter(|_| self.mirror_uploads) else {
+            return;
+        };
+        if let Err(e) = mirror.refresh().await {
+            log::warn!("Failed to refresh the metadata manifest: {}", e);
+        }
+    }
+
+    /// Rebuilds the file metadata from the manifest pinned in the storage
+    /// chat, e.g. into an empty database after the old one was lost. Files
+    /// the store already has are kept as they are.
+    pub async fn recover_from_chat(&self) -> Result<RecoveryReport> {
+        let mirror = self.mirror.as_ref().ok_or_else(|| {
+            TgCloudError::ConfigError(ConfigError::MissingEnvVar(
+                "METADATA_KEY (the key the manifest was written with)".into(),
+            ))
+        })?;
+        mirror.recover().await
+    }
+
+    /// Reverts the most recent rename or delete that has not been undone yet,
+    /// or every part of it if it was a bulk operation. Deletes can only be
+    /// undone while the file is still in the trash. Returns the history
+    /// entries that were reverted; if some of them could not be, the first
+    /// error is returned instead and the next `undo` retries the rest.
+    pub async fn undo(&self) -> Result<Vec<HistoryEntry>> {
+        let entry = self
+            .store
+            .last_undoable()
+            .await?
+            .ok_or(TgCloudError::NothingToUndo)?;
+        let entries = match &entry.operation {
+            Some(operation) => self.store.undoable_in_operation(operation).await?,
+            None => vec![entry],
+        };
+
+        let mut reverted = Vec::with_capacity(entries.len());
+        let mut failure = None;
+        for entry in entries {
+            match self.revert(&entry).await {
+                Ok(()) => reverted.push(entry),
+                Err(e) => {
+                    failure.get_or_insert(e);
+                }
+            }
+        }
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(reverted),
+        }
+    }
+
+    /// Reverts one history entry and marks it undone.
+    async fn revert(&self, entry: &HistoryEntry) -> Result<()> {
+        let result = match (entry.action, &entry.target) {
+            (HistoryAction::Rename, Some(target)) if target.ends_with('/') => self
+                .store
+                .rename_prefix(target, &entry.path)
+                .await
+                .map(|_| ()),
+            (HistoryAction::Rename, Some(target)) => {
+                self.store.rename_file(target, &entry.path).await
+            }
+            (HistoryAction::Delete, _) => {
+                self.store.restore_from_trash(&entry.path).await.map(|_| ())
+            }
+            _ => Err(TgCloudError::Unknown(format!(
+                "Cannot undo {} of {}",
+                entry.action, entry.path
+            ))),
+        };
+        self.record_in(
+            entry.operation.as_deref(),
+            HistoryAction::Undo,
+            &entry.path,
+            Some(&entry.action.to_string()),
+            &result,
+        )
+        .await;
+        result?;
+
+        if let Some(id) = entry.id {
+            self.store.mark_undone(id).await?;
+        }
+        Ok(())
+    }
+
+    /// Lists files below `prefix`, archived ones only if `include_archived`.
+    pub async fn list_files(
+        &self,
+        prefix: &str,
+        include_archived: bool,
+    ) -> Result<Vec<FileMetadata>> {
+        self.store.list_files(prefix, include_archived).await
+    }
+
+    /// Lists files inside the folder `prefix` up to `depth` levels deep
+    /// (1 = immediate children, `None` = everything underneath), archived
+    /// ones only if `include_archived`.
+    pub async fn list_files_with_depth(
+        &self,
+        prefix: &str,
+        depth: Option<u32>,
+        include_archived: bool,
+    ) -> Result<Vec<FileMetadata>> {
+        self.store
+            .list_files_with_depth(prefix, depth, include_archived)
+            .await
+    }
+
+    /// One page of the files matching `filter`; see [`MongoStore::list_files_page`].
+    pub async fn list_files_page(
+        &self,
+        filter: &FileFilter,
+        cursor: Option<&str>,
+        limit: i64,
+        include_archived: bool,
+    ) -> Result<FilePage> {
+        self.store
+            .list_files_page(filter, cursor, limit, include_archived)
+            .await
+    }
+
+    /// Totals for the files matching `filter`. If any bot has a quota, the
+    /// stats also show how much of it each bot uses.
+    pub async fn storage_stats(&self, filter: &FileFilter) -> Result<StorageStats> {
+        let mut stats = self.store.storage_stats(filter).await?;
+        if self.bots.has_quotas() {
+            stats.quotas = self.bots.quota_usage();
+        }
+        Ok(stats)
+    }
+
+    pub async fn search(
+        &self,
+        query: &str,
+        mode: SearchMode,
+        limit: i64,
+        include_archived: bool,
+    ) -> Result<Vec<FileMetadata>> {
+        self.store
+            .search(query, mode, limit, include_archived)
+            .await
+    }
+
+    /// Finds files whose full name matches the shell-style glob `pattern`,
+    /// e.g. `backups/2023-*/*.sql.gz`.
+    pub async fn find_files(
+        &self,
+        pattern: &str,
+        include_archived: bool,
+    ) -> Result<Vec<FileMetadata>> {
+        self.store.find_by_glob(pattern, include_archived).await
+    }
+
+    /// Lists the immediate subfolders of `prefix`.
+    pub async fn list_subfolders(&self, prefix: &str) -> Result<Vec<FolderSummary>> {
+        self.store.list_subfolders(prefix).await
+    }
+
+    // =======================================================================
+    // History
+    // =======================================================================
+
+    /// Returns audit log entries matching `query`, newest first.
+    pub async fn history(&self, query: &HistoryQuery) -> Result<Vec<HistoryEntry>> {
+        self.store.list_history(query).await
+    }
+
+    /// Calls the hooks that run before a transfer; an error means the
+    /// transfer must not start.
+    async fn before_hooks(
+        &self,
+        event: HookEvent,
+        transfer_id: &str,
+        path: &str,
+        local_path: Option<&std::path::Path>,
+    ) -> Result<()> {
+        if self.hooks.is_empty() {
+            return Ok(());
+        }
+        let context = self
+            .hook_context::<()>(event, transfer_id, path, local_path, None)
+            .await;
+        for hook in &self.hooks {
+            hook.on_transfer(&context).await?;
+        }
+        Ok(())
+    }
+
+    /// Calls the hooks that run after a transfer, logging their errors.
+    /// `local_path` is the file that was read or written.
+    async fn after_hooks<T>(
+        &self,
+        event: HookEvent,
+        transfer_id: &str,
+        path: &str,
+        local_path: Option<&std::path::Path>,
+        result: &Result<T>,
+    ) {
+        if self.hooks.is_empty() {
+            return;
+        }
+        let context = self
+            .hook_context(event, transfer_id, path, local_path, Some(result))
+            .await;
+        for hook in &self.hooks {
+            if let Err(e) = hook.on_transfer(&context).await {
+                log::warn!("{} hook for {} failed: {}", event, path, e);
+            }
+        }
+    }
+
+    async fn hook_context<T>(
+        &self,
+        event: HookEvent,
+        transfer_id: &str,
+        path: &str,
+        local_path: Option<&std::path::Path>,
+        result: Option<&Result<T>>,
+    ) -> HookContext {
+        let error = result.and_then(|r| r.as_ref().err()).map(|e| e.to_string());
+        let file = if error.is_none() {
+            self.store.get_file_by_path(path).await.ok().flatten()
+        } else {
+            None
+        };
+        HookContext {
+            event,
+            transfer_id: transfer_id.to_string(),
+            path: path.to_string(),
+            local_path: local_path.map(|p| p.to_path_buf()),
+            file,
+            success: result.map(|r| r.is_ok()),
+            error,
+        }
+    }
+
+    /// Appends an operation to the audit log. Failing to write the log never
+    /// fails the operation itself.
+    async fn record<T>(
+        &self,
+        action: HistoryAction,
+        path: &str,
+        target: Option<&str>,
+        result: &Result<T>,
+    ) {
+        self.record_in(None, action, path, target, result).await
+    }
+
+    /// [`record`](Self::record) for a part of the bulk `operation`.
+    async fn record_in<T>(
+        &self,
+        operation: Option<&str>,
+        action: HistoryAction,
+        path: &str,
+        target: Option<&str>,
+        result: &Result<T>,
+    ) {
+        let entry = HistoryEntry {
+            id: None,
+            action,
+            path: path.to_string(),
+            target: target.map(str::to_string),
+            success: result.is_ok(),
+            detail: result.as_ref().err().map(|e| e.to_string()),
+            at: Utc::now(),
+            undone: false,
+            operation: operation.map(str::to_string),
+        };
+        if let Err(e) = self.store.record_history(entry).await {
+            log::warn!("Failed to record {} of {} in history: {}", action, path, e);
+        }
+    }
+}
+
+/// Folder name with a trailing slash, as recorded in the history so folder
+/// moves can be told apart from file renames.
+fn folder_name(prefix: &str) -> String {
+    format!("{}/", prefix.trim_end_matches('/'))
+}
+
+/// Hex-encoded SHA-256 of the file at `path`, read `buffer_size` bytes at a
+/// time. Runs on blocking threads and stops early if the future is dropped.
+pub(crate) async fn sha256_file(
+    path: impl AsRef<std::path::Path>,
+    buffer_size: usize,
+) -> Result<String> {
+    /// Tells the hashing threads to give up once the caller is gone.
+    struct StopOnDrop(Arc<AtomicBool>);
+    impl Drop for StopOnDrop {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    let path = path.as_ref().to_path_buf();
+    let stop = StopOnDrop(Arc::new(AtomicBool::new(false)));
+    let stopped = Arc::clone(&stop.0);
+    tokio::task::spawn_blocking(move || sha256_file_blocking(&path, buffer_size, &stopped))
+        .await
+        .map_err(|e| TgCloudError::Unknown(format!("Hashing task failed: {}", e)))?
+}
+
+/// Reads the file on the calling thread while a second thread hashes the
+/// buffers read before, so hashing runs at the speed of the slower of the two
+/// instead of their sum. This is read-ahead, not parallel hashing: the digest
+/// is computed on one core, because stored files, `checksums` and `sync`
+/// compare against the plain SHA-256 of the whole file, which cannot be split
+/// across threads.
+fn sha256_file_blocking(
+    path: &std::path::Path,
+    buffer_size: usize,
+    stop: &AtomicBool,
+) -> Result<String> {
+    use std::io::Read;
+    use std::sync::mpsc as std_mpsc;
+
+    let mut file = std::fs::File::open(path)?;
+    let (full_tx, full_rx) = std_mpsc::sync_channel::<(Vec<u8>, usize)>(HASH_BUFFERS);
+    let (empty_tx, empty_rx) = std_mpsc::channel::<Vec<u8>>();
+    for _ in 0..HASH_BUFFERS {
+        let _ = empty_tx.send(vec![0u8; buffer_size]);
+    }
+
+    std::thread::scope(|scope| {
+        let hasher = scope.spawn(move || {
+            let mut hasher = Sha256::new();
+            for (buf, n) in full_rx {
+                hasher.update(&buf[..n]);
+                let _ = empty_tx.send(buf);
+            }
+            hex::encode(hasher.finalize())
+        });
+
+        let read = (|| loop {
+            if stop.load(Ordering::Relaxed) {
+                return Err(TgCloudError::Cancelled);
+            }
+            let Ok(mut buf) = empty_rx.recv() else {
+                return Err(TgCloudError::Unknown("Hashing thread stopped".to_string()));
+            };
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                return Ok(());
+            }
+            if full_tx.send((buf, n)).is_err() {
+                return Err(TgCloudError::Unknown("Hashing thread stopped".to_string()));
+            }
+        })();
+        drop(full_tx);
+
+        let digest = hasher
+            .join()
+            .map_err(|_| TgCloudError::Unknown("Hashing thread panicked".to_string()))?;
+        read.map(|()| digest)
+    })
+}
+
+/// Runs `fut` unless `cancel` fires first, in which case it is dropped.
+async fn cancellable<T>(
+    cancel: &CancellationToken,
+    fut: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::select! {
+        result = fut => result,
+        _ = cancel.cancelled() => Err(TgCloudError::Cancelled),
+    }
+}
+
+/// Path of the temporary file a download is written to before verification.
+fn partial_path_for(target: &std::path::Path) -> std::path::PathBuf {
+    let mut name = target.as_os_str().to_owned();
+    name.push(".partial");
+    std::path::PathBuf::from(name)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
 }
@@ -1,13 +1,25 @@
+use crate::cache::MetadataCache;
+use crate::crypto::{is_sealed, MetadataCipher};
 use crate::errors::{Result, TgCloudError};
-use crate::models::FileMetadata;
+use crate::models::{
+    ApiKey, Bot, BotBenchmark, FileChunk, FileFilter, FileMetadata, FilePage, FolderSummary,
+    HistoryAction, HistoryEntry, HistoryQuery, LedgerChunk, SearchMode, StorageStats, TrashedFile,
+    UsageRow,
+};
 use futures::stream::TryStreamExt;
-use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
 use mongodb::{options::ClientOptions, Client, Collection};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct MongoStore {
     client: Client,
     db_name: String,
+    cache: Arc<MetadataCache>,
+    /// Encrypts file names and history entries when a metadata key is set.
+    names: Option<Arc<MetadataCipher>>,
 }
 
 impl MongoStore {
@@ -19,23 +31,384 @@ impl MongoStore {
         Ok(Self {
             client,
             db_name: "tgcloud".to_string(),
+            cache: Arc::new(MetadataCache::new(Duration::ZERO)),
+            names: None,
         })
     }
 
+    /// Stores file names and history entries encrypted with `key`. Values
+    /// written without a key stay readable; see [`MongoStore::seal_existing`].
+    pub fn with_metadata_key(mut self, key: &[u8; 32]) -> Self {
+        self.names = Some(Arc::new(MetadataCipher::new(key)));
+        self
+    }
+
+    /// Caches file lookups, listings and the bot list for `ttl`; writes made
+    /// through this store invalidate the cache immediately, changes made by
+    /// other processes show up once entries expire. Zero disables caching.
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache = Arc::new(MetadataCache::new(ttl));
+        self
+    }
+
+    /// Creates the indexes lookups and searches rely on. Safe to call on every
+    /// start; existing indexes are left alone.
+    pub async fn ensure_indexes(&self) -> Result<()> {
+        let by_name = mongodb::IndexModel::builder()
+            .keys(doc! { "original_name": 1 })
+            .options(
+                mongodb::options::IndexOptions::builder()
+                    .name("original_name".to_string())
+                    .build(),
+            )
+            .build();
+        self.files_collection()
+            .create_index(by_name, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        // `search` matches case-sensitive regexes against this lowercased
+        // copy of the name, which MongoDB can answer from the index keys; a
+        // case-insensitive regex on `original_name` would scan every file.
+        let by_folded_name = mongodb::IndexModel::builder()
+            .keys(doc! { "name_lower": 1 })
+            .options(
+                mongodb::options::IndexOptions::builder()
+                    .name("name_lower".to_string())
+                    .build(),
+            )
+            .build();
+        self.files_collection()
+            .create_index(by_folded_name, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        self.fold_existing_names().await?;
+        let by_content = mongodb::IndexModel::builder()
+            .keys(doc! { "sha256": 1, "chunk.index": 1 })
+            .build();
+        self.ledger_collection()
+            .create_index(by_content, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        let by_key_id = mongodb::IndexModel::builder()
+            .keys(doc! { "key_id": 1 })
+            .options(
+                mongodb::options::IndexOptions::builder()
+                    .name("key_id".to_string())
+                    .unique(true)
+                    .build(),
+            )
+            .build();
+        self.api_keys_collection()
+            .create_index(by_key_id, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        Ok(())
+    }
+
+    /// Adds `name_lower` to files written before it existed. Stores with a
+    /// metadata key keep no plain-text copy of the name; they search in memory.
+    async fn fold_existing_names(&self) -> Result<()> {
+        if self.names.is_some() {
+            return Ok(());
+        }
+        let mut cursor = self
+            .files_collection()
+            .find(doc! { "name_lower": { "$exists": false } }, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        while let Some(file) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
+            if is_sealed(&file.original_name) {
+                continue;
+            }
+            self.files_collection()
+                .update_one(
+                    doc! { "file_id": &file.file_id },
+                    doc! { "$set": self.name_fields(&file.original_name) },
+                    None,
+                )
+                .await
+                .map_err(TgCloudError::MongoError)?;
+        }
+        Ok(())
+    }
+
+    /// Round-trips a `ping` command to check that MongoDB is reachable.
+    pub async fn ping(&self) -> Result<()> {
+        self.client
+            .database(&self.db_name)
+            .run_command(doc! { "ping": 1 }, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        Ok(())
+    }
+
+    /// Names of the indexes from `ensure_indexes` that do not exist.
+    pub async fn missing_indexes(&self) -> Result<Vec<&'static str>> {
+        let files = self
+            .files_collection()
+            .list_index_names()
+            .await
+            .unwrap_or_default();
+        let ledger = self
+            .ledger_collection()
+            .list_index_names()
+            .await
+            .unwrap_or_default();
+        let api_keys = self
+            .api_keys_collection()
+            .list_index_names()
+            .await
+            .unwrap_or_default();
+        let mut missing = Vec::new();
+        if !files.iter().any(|n| n == "original_name") {
+            missing.push("files.original_name");
+        }
+        if !files.iter().any(|n| n == "name_lower") {
+            missing.push("files.name_lower");
+        }
+        if !ledger.iter().any(|n| n == "sha256_1_chunk.index_1") {
+            missing.push("chunk_ledger.sha256_chunk_index");
+        }
+        if !api_keys.iter().any(|n| n == "key_id") {
+            missing.push("api_keys.key_id");
+        }
+        Ok(missing)
+    }
+
     fn files_collection(&self) -> Collection<FileMetadata> {
         self.client.database(&self.db_name).collection("files")
     }
 
+    fn bots_collection(&self) -> Collection<Bot> {
+        self.client.database(&self.db_name).collection("bots")
+    }
+
+    fn benchmarks_collection(&self) -> Collection<BotBenchmark> {
+        self.client
+            .database(&self.db_name)
+            .collection("bot_benchmarks")
+    }
+
+    fn trash_collection(&self) -> Collection<TrashedFile> {
+        self.client.database(&self.db_name).collection("trash")
+    }
+
+    fn history_collection(&self) -> Collection<HistoryEntry> {
+        self.client.database(&self.db_name).collection("history")
+    }
+
+    fn api_keys_collection(&self) -> Collection<ApiKey> {
+        self.client.database(&self.db_name).collection("api_keys")
+    }
+
+    fn ledger_collection(&self) -> Collection<LedgerChunk> {
+        self.client
+            .database(&self.db_name)
+            .collection("chunk_ledger")
+    }
+
+    // -----------------------------------------------------------------------
+    // Metadata encryption
+    // -----------------------------------------------------------------------
+
+    fn seal(&self, value: &str) -> String {
+        match &self.names {
+            Some(cipher) => cipher.seal(value),
+            None => value.to_string(),
+        }
+    }
+
+    fn open(&self, value: &str) -> Result<String> {
+        match &self.names {
+            Some(cipher) => cipher.open(value),
+            None => Ok(value.to_string()),
+        }
+    }
+
+    /// Query value matching a stored name, encrypted or, for documents
+    /// written before the key was set, in plain text.
+    fn name_query(&self, name: &str) -> Bson {
+        match &self.names {
+            Some(cipher) => Bson::Document(doc! { "$in": [cipher.seal(name), name] }),
+            None => Bson::String(name.to_string()),
+        }
+    }
+
+    /// Fields to `$set` when a file is (re)named: the stored name and, without
+    /// a metadata key, the lowercased copy `search` queries.
+    fn name_fields(&self, name: &str) -> Document {
+        match &self.names {
+            Some(cipher) => doc! { "original_name": cipher.seal(name) },
+            None => doc! { "original_name": name, "name_lower": name.to_lowercase() },
+        }
+    }
+
+    fn seal_file(&self, mut file: FileMetadata) -> FileMetadata {
+        file.original_name = self.seal(&file.original_name);
+        file
+    }
+
+    fn open_file(&self, mut file: FileMetadata) -> Result<FileMetadata> {
+        file.original_name = self.open(&file.original_name)?;
+        Ok(file)
+    }
+
+    fn seal_history(&self, mut entry: HistoryEntry) -> HistoryEntry {
+        entry.path = self.seal(&entry.path);
+        entry.target = entry.target.map(|t| self.seal(&t));
+        entry.detail = entry.detail.map(|d| self.seal(&d));
+        entry
+    }
+
+    fn open_history(&self, mut entry: HistoryEntry) -> Result<HistoryEntry> {
+        entry.path = self.open(&entry.path)?;
+        entry.target = entry.target.map(|t| self.open(&t)).transpose()?;
+        entry.detail = entry.detail.map(|d| self.open(&d)).transpose()?;
+        Ok(entry)
+    }
+
+    /// Files whose name matches the regex `pattern`, or all files. MongoDB
+    /// cannot match encrypted names, so with a metadata key every file is
+    /// loaded and matched here instead.
+    async fn find_named(&self, pattern: Option<&str>) -> Result<Vec<FileMetadata>> {
+        if self.names.is_none() {
+            let filter = match pattern {
+                Some(p) => doc! { "original_name": { "$regex": p } },
+                None => doc! {},
+            };
+            let mut cursor = self
+                .files_collection()
+                .find(filter, None)
+                .await
+                .map_err(TgCloudError::MongoError)?;
+            let mut files = Vec::new();
+            while let Some(file) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
+                files.push(file);
+            }
+            return Ok(files);
+        }
+
+        let pattern = pattern
+            .map(regex::Regex::new)
+            .transpose()
+            .map_err(|e| TgCloudError::Unknown(format!("Invalid name pattern: {}", e)))?;
+        let mut cursor = self
+            .files_collection()
+            .find(doc! {}, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        let mut files = Vec::new();
+        while let Some(file) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
+            let file = self.open_file(file)?;
+            if pattern
+                .as_ref()
+                .is_none_or(|p| p.is_match(&file.original_name))
+            {
+                files.push(file);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Encrypts the file names and history entries that were written before
+    /// a metadata key was set. Returns the number of documents rewritten.
+    pub async fn seal_existing(&self) -> Result<u64> {
+        if self.names.is_none() {
+            return Err(TgCloudError::ConfigError(
+                crate::errors::ConfigError::MissingEnvVar("METADATA_KEY".into()),
+            ));
+        }
+        let mut sealed = 0;
+
+        let mut cursor = self
+            .files_collection()
+            .find(doc! {}, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        while let Some(file) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
+            if is_sealed(&file.original_name) {
+                continue;
+            }
+            self.files_collection()
+                .update_one(
+                    doc! { "file_id": &file.file_id },
+                    doc! {
+                        "$set": { "original_name": self.seal(&file.original_name) },
+                        "$unset": { "name_lower": "" },
+                    },
+                    None,
+                )
+                .await
+                .map_err(TgCloudError::MongoError)?;
+            sealed += 1;
+        }
+
+        let mut cursor = self
+            .trash_collection()
+            .find(doc! {}, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        while let Some(trashed) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
+            if is_sealed(&trashed.file.original_name) {
+                continue;
+            }
+            self.trash_collection()
+                .update_one(
+                    doc! { "file_id": &trashed.file.file_id },
+                    doc! { "$set": { "original_name": self.seal(&trashed.file.original_name) } },
+                    None,
+                )
+                .await
+                .map_err(TgCloudError::MongoError)?;
+            sealed += 1;
+        }
+
+        let mut cursor = self
+            .history_collection()
+            .find(doc! {}, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        while let Some(entry) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
+            let Some(id) = entry.id else { continue };
+            if is_sealed(&entry.path) {
+                continue;
+            }
+            let entry = self.seal_history(entry);
+            self.history_collection()
+                .update_one(
+                    doc! { "_id": id },
+                    doc! { "$set": {
+                        "path": &entry.path,
+                        "target": entry.target.as_deref(),
+                        "detail": entry.detail.as_deref(),
+                    } },
+                    None,
+                )
+                .await
+                .map_err(TgCloudError::MongoError)?;
+            sealed += 1;
+        }
+
+        self.cache.invalidate_files();
+        Ok(sealed)
+    }
+
     // -----------------------------------------------------------------------
     // File CRUD
     // -----------------------------------------------------------------------
 
     pub async fn save_file(&self, file: FileMetadata) -> Result<ObjectId> {
+        let name = self.name_fields(&file.original_name);
+        let mut document = mongodb::bson::to_document(&file)
+            .map_err(|e| TgCloudError::Unknown(format!("Cannot encode file metadata: {}", e)))?;
+        document.extend(name);
         let result = self
             .files_collection()
-            .insert_one(file, None)
+            .clone_with_type::<Document>()
+            .insert_one(document, None)
             .await
             .map_err(TgCloudError::MongoError)?;
+        self.cache.invalidate_files();
         result
             .inserted_id
             .as_object_id()
@@ -43,61 +416,437 @@ impl MongoStore {
     }
 
     pub async fn get_file_by_path(&self, path: &str) -> Result<Option<FileMetadata>> {
-        self.files_collection()
-            .find_one(doc! { "original_name": path }, None)
+        let generation = self.cache.by_path.generation();
+        if let Some(hit) = self.cache.by_path.get(&path.to_string()) {
+            return Ok(hit);
+        }
+        let file = self
+            .files_collection()
+            .find_one(doc! { "original_name": self.name_query(path) }, None)
             .await
-            .map_err(TgCloudError::MongoError)
+            .map_err(TgCloudError::MongoError)?
+            .map(|f| self.open_file(f))
+            .transpose()?;
+        self.cache
+            .by_path
+            .insert(generation, path.to_string(), file.clone());
+        Ok(file)
     }
 
     pub async fn get_file_by_id(&self, file_id: &str) -> Result<Option<FileMetadata>> {
-        self.files_collection()
+        let generation = self.cache.by_id.generation();
+        if let Some(hit) = self.cache.by_id.get(&file_id.to_string()) {
+            return Ok(hit);
+        }
+        let file = self
+            .files_collection()
             .find_one(doc! { "file_id": file_id }, None)
             .await
-            .map_err(TgCloudError::MongoError)
+            .map_err(TgCloudError::MongoError)?
+            .map(|f| self.open_file(f))
+            .transpose()?;
+        self.cache
+            .by_id
+            .insert(generation, file_id.to_string(), file.clone());
+        Ok(file)
     }
 
-    pub async fn list_files(&self, folder_prefix: &str) -> Result<Vec<FileMetadata>> {
-        let filter = if folder_prefix == "root" || folder_prefix.is_empty() {
-            doc! {}
+    /// Lists files below the folder `folder_prefix`. Archived files are
+    /// skipped unless `include_archived`.
+    pub async fn list_files(
+        &self,
+        folder_prefix: &str,
+        include_archived: bool,
+    ) -> Result<Vec<FileMetadata>> {
+        let key = folder_prefix.to_string();
+        let generation = self.cache.listings.generation();
+        if let Some(hit) = self.cache.listings.get(&key) {
+            return Ok(without_archived(hit, include_archived));
+        }
+
+        let pattern = if folder_prefix == "root" || folder_prefix.is_empty() {
+            None
         } else {
-            doc! { "original_name": { "$regex": format!("^{}", regex::escape(folder_prefix)) } }
+            Some(format!("^{}", regex::escape(folder_prefix)))
         };
+        let files = self.find_named(pattern.as_deref()).await?;
+        self.cache.listings.insert(generation, key, files.clone());
+        Ok(without_archived(files, include_archived))
+    }
+
+    /// Lists files below the folder `folder_prefix`, at most `depth` levels
+    /// deep (1 = immediate children). `None` lists everything underneath.
+    /// Archived files are skipped unless `include_archived`.
+    pub async fn list_files_with_depth(
+        &self,
+        folder_prefix: &str,
+        depth: Option<u32>,
+        include_archived: bool,
+    ) -> Result<Vec<FileMetadata>> {
+        let key = (folder_prefix.to_string(), depth);
+        let generation = self.cache.folder_listings.generation();
+        if let Some(hit) = self.cache.folder_listings.get(&key) {
+            return Ok(without_archived(hit, include_archived));
+        }
+
+        let files = self
+            .find_named(Some(&depth_regex(folder_prefix, depth)))
+            .await?;
+        self.cache
+            .folder_listings
+            .insert(generation, key, files.clone());
+        Ok(without_archived(files, include_archived))
+    }
 
+    /// Returns up to `limit` files matching `filter` in name order, starting
+    /// after the position encoded in `cursor` (from a previous page's
+    /// `next_cursor`). Archived files are skipped unless `include_archived`.
+    /// Unlike the other listings this never loads more than one page into
+    /// memory.
+    pub async fn list_files_page(
+        &self,
+        filter: &FileFilter,
+        cursor: Option<&str>,
+        limit: i64,
+        include_archived: bool,
+    ) -> Result<FilePage> {
+        let limit = limit.max(1);
+        let after = cursor
+            .map(|cursor| {
+                hex::decode(cursor)
+                    .ok()
+                    .and_then(|b| String::from_utf8(b).ok())
+                    .ok_or_else(|| TgCloudError::Unknown(format!("Invalid cursor: {}", cursor)))
+            })
+            .transpose()?;
+
+        if self.names.is_some() {
+            let mut files = self
+                .find_named(file_filter_regex(filter).as_deref())
+                .await?;
+            files.retain(|f| {
+                (include_archived || !f.is_archived())
+                    && after.as_ref().is_none_or(|a| f.original_name > *a)
+            });
+            files.sort_by(|a, b| a.original_name.cmp(&b.original_name));
+            let next_cursor = if files.len() as i64 > limit {
+                files.truncate(limit as usize);
+                files.last().map(|f| hex::encode(&f.original_name))
+            } else {
+                None
+            };
+            return Ok(FilePage { files, next_cursor });
+        }
+
+        let mut query = file_filter_document(filter);
+        if let Some(after) = after {
+            query = doc! { "$and": [query, { "original_name": { "$gt": after } }] };
+        }
+        if !include_archived {
+            query = doc! { "$and": [query, { "archive_chat_id": null }] };
+        }
+
+        // One extra document tells whether another page follows.
+        let options = mongodb::options::FindOptions::builder()
+            .sort(doc! { "original_name": 1 })
+            .limit(limit + 1)
+            .build();
         let mut cursor = self
             .files_collection()
-            .find(filter, None)
+            .find(query, options)
             .await
             .map_err(TgCloudError::MongoError)?;
         let mut files = Vec::new();
         while let Some(file) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
             files.push(file);
         }
+
+        let next_cursor = if files.len() as i64 > limit {
+            files.truncate(limit as usize);
+            files.last().map(|f| hex::encode(&f.original_name))
+        } else {
+            None
+        };
+        Ok(FilePage { files, next_cursor })
+    }
+
+    /// Returns every stored file, archived ones included.
+    pub async fn all_files(&self) -> Result<Vec<FileMetadata>> {
+        self.find_named(None).await
+    }
+
+    /// Lists files whose full name matches the shell-style glob `pattern`
+    /// (see [`glob_to_regex`]).
+    /// Archived files are skipped unless `include_archived`.
+    pub async fn find_by_glob(
+        &self,
+        pattern: &str,
+        include_archived: bool,
+    ) -> Result<Vec<FileMetadata>> {
+        let files = self.find_named(Some(&glob_to_regex(pattern))).await?;
+        let mut files = without_archived(files, include_archived);
+        files.sort_by(|a, b| a.original_name.cmp(&b.original_name));
         Ok(files)
     }
 
+    /// Finds files whose name matches `query` case-insensitively, at most
+    /// `limit` of them. Prefix and substring results are ordered by name,
+    /// fuzzy results by how closely the name matches; every file the fuzzy
+    /// pattern matches is ranked, not just the first `limit`.
+    pub async fn search(
+        &self,
+        query: &str,
+        mode: SearchMode,
+        limit: i64,
+        include_archived: bool,
+    ) -> Result<Vec<FileMetadata>> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let pattern = match mode {
+            SearchMode::Prefix => format!("(^|/){}", regex::escape(&query)),
+            SearchMode::Substring => regex::escape(&query),
+            SearchMode::Fuzzy => query
+                .chars()
+                .map(|c| regex::escape(&c.to_string()))
+                .collect::<Vec<_>>()
+                .join(".*"),
+        };
+        // Fuzzy matches are ranked below, so every candidate is fetched.
+        let fetch = match mode {
+            SearchMode::Fuzzy => None,
+            _ => Some(limit.max(1)),
+        };
+        let mut files = if self.names.is_some() {
+            let files = self.find_named(Some(&format!("(?i){}", pattern))).await?;
+            let mut files = without_archived(files, include_archived);
+            files.sort_by(|a, b| a.original_name.cmp(&b.original_name));
+            if let Some(fetch) = fetch {
+                files.truncate(fetch as usize);
+            }
+            files
+        } else {
+            let options = mongodb::options::FindOptions::builder()
+                .sort(doc! { "original_name": 1 })
+                .limit(fetch)
+                .build();
+            let mut query = doc! { "name_lower": { "$regex": pattern } };
+            if !include_archived {
+                query.insert("archive_chat_id", Bson::Null);
+            }
+            let mut cursor = self
+                .files_collection()
+                .find(query, options)
+                .await
+                .map_err(TgCloudError::MongoError)?;
+            let mut files = Vec::new();
+            while let Some(file) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
+                files.push(file);
+            }
+            files
+        };
+
+        if mode == SearchMode::Fuzzy {
+            files.sort_by_cached_key(|f| {
+                (
+                    fuzzy_span(&f.original_name.to_lowercase(), &query).unwrap_or(usize::MAX),
+                    f.original_name.len(),
+                )
+            });
+            files.truncate(limit.max(0) as usize);
+        }
+        Ok(files)
+    }
+
+    /// Lists the immediate subfolders of `folder_prefix` with the number of
+    /// files and bytes stored underneath each.
+    pub async fn list_subfolders(&self, folder_prefix: &str) -> Result<Vec<FolderSummary>> {
+        let base = folder_regex(folder_prefix);
+        if self.names.is_some() {
+            let dir = regex::Regex::new(&format!("{}([^/]+)/", base))
+                .map_err(|e| TgCloudError::Unknown(format!("Invalid folder: {}", e)))?;
+            let mut folders: std::collections::BTreeMap<String, FolderSummary> =
+                std::collections::BTreeMap::new();
+            for file in self.find_named(Some(dir.as_str())).await? {
+                let Some(name) = dir.captures(&file.original_name).map(|c| c[1].to_string()) else {
+                    continue;
+                };
+                let folder = folders.entry(name.clone()).or_insert(FolderSummary {
+                    name,
+                    file_count: 0,
+                    total_size: 0,
+                });
+                folder.file_count += 1;
+                folder.total_size += file.size;
+            }
+            return Ok(folders.into_values().collect());
+        }
+        let pipeline = vec![
+            doc! { "$match": { "original_name": { "$regex": format!("{}[^/]+/", base) } } },
+            doc! { "$project": {
+                "size": 1,
+                "dir": { "$regexFind": {
+                    "input": "$original_name",
+                    "regex": format!("{}([^/]+)/", base),
+                } },
+            } },
+            doc! { "$group": {
+                "_id": { "$arrayElemAt": ["$dir.captures", 0] },
+                "file_count": { "$sum": 1 },
+                "total_size": { "$sum": "$size" },
+            } },
+            doc! { "$sort": { "_id": 1 } },
+        ];
+
+        let mut cursor = self
+            .files_collection()
+            .aggregate(pipeline, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        let mut folders = Vec::new();
+        while let Some(row) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
+            folders.push(FolderSummary {
+                name: row.get_str("_id").unwrap_or_default().to_string(),
+                file_count: bson_to_u64(row.get("file_count")),
+                total_size: bson_to_u64(row.get("total_size")),
+            });
+        }
+        Ok(folders)
+    }
+
+    /// Counts files, chunks and bytes matching `filter`, broken down by bot
+    /// and upload month, in a single aggregation.
+    pub async fn storage_stats(&self, filter: &FileFilter) -> Result<StorageStats> {
+        let matching = match (&self.names, file_filter_regex(filter)) {
+            (Some(_), Some(pattern)) => {
+                let ids: Vec<String> = self
+                    .find_named(Some(&pattern))
+                    .await?
+                    .into_iter()
+                    .map(|f| f.file_id)
+                    .collect();
+                doc! { "file_id": { "$in": ids } }
+            }
+            _ => file_filter_document(filter),
+        };
+        let pipeline = vec![
+            doc! { "$match": matching },
+            doc! { "$facet": {
+                "totals": [
+                    { "$group": {
+                        "_id": null,
+                        "count": { "$sum": 1 },
+                        "bytes": { "$sum": "$size" },
+                        "chunks": { "$sum": "$total_chunks" },
+                    } },
+                ],
+                "by_bot": [
+                    { "$unwind": "$chunks" },
+                    { "$group": {
+                        "_id": { "$ifNull": ["$chunks.bot_id", { "$ifNull": ["$bot_id", "unknown"] }] },
+                        "count": { "$sum": 1 },
+                        "bytes": { "$sum": "$chunks.size" },
+                    } },
+                    { "$sort": { "bytes": -1 } },
+                ],
+                "by_month": [
+                    // `created_at` is stored as an RFC 3339 string.
+                    { "$group": {
+                        "_id": { "$substrCP": ["$created_at", 0, 7] },
+                        "count": { "$sum": 1 },
+                        "bytes": { "$sum": "$size" },
+                    } },
+                    { "$sort": { "_id": 1 } },
+                ],
+            } },
+        ];
+
+        let mut cursor = self
+            .files_collection()
+            .aggregate(pipeline, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        let Some(facets) = cursor.try_next().await.map_err(TgCloudError::MongoError)? else {
+            return Ok(StorageStats::default());
+        };
+
+        let rows = |name: &str| -> Vec<mongodb::bson::Document> {
+            facets
+                .get_array(name)
+                .map(|a| a.iter().filter_map(|v| v.as_document().cloned()).collect())
+                .unwrap_or_default()
+        };
+        let usage = |name: &str| -> Vec<UsageRow> {
+            rows(name)
+                .into_iter()
+                .map(|row| UsageRow {
+                    key: row.get_str("_id").unwrap_or_default().to_string(),
+                    count: bson_to_u64(row.get("count")),
+                    bytes: bson_to_u64(row.get("bytes")),
+                })
+                .collect()
+        };
+
+        let totals = rows("totals").into_iter().next().unwrap_or_default();
+        Ok(StorageStats {
+            file_count: bson_to_u64(totals.get("count")),
+            total_bytes: bson_to_u64(totals.get("bytes")),
+            chunk_count: bson_to_u64(totals.get("chunks")),
+            by_bot: usage("by_bot"),
+            by_month: usage("by_month"),
+            quotas: Vec::new(),
+        })
+    }
+
+    /// Bytes of chunks stored through each bot, counting files in the trash
+    /// since their messages still exist.
+    pub async fn stored_bytes_by_bot(&self) -> Result<HashMap<String, u64>> {
+        let pipeline = || {
+            vec![
+                doc! { "$unwind": "$chunks" },
+                doc! { "$group": {
+                    "_id": { "$ifNull": ["$chunks.bot_id", { "$ifNull": ["$bot_id", "unknown"] }] },
+                    "bytes": { "$sum": "$chunks.size" },
+                } },
+            ]
+        };
+        let mut totals = HashMap::new();
+        let cursors = [
+            self.files_collection().aggregate(pipeline(), None).await,
+            self.trash_collection().aggregate(pipeline(), None).await,
+        ];
+        for cursor in cursors {
+            let mut cursor = cursor.map_err(TgCloudError::MongoError)?;
+            while let Some(row) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
+                let bot_id = row.get_str("_id").unwrap_or_default().to_string();
+                *totals.entry(bot_id).or_insert(0) += bson_to_u64(row.get("bytes"));
+            }
+        }
+        Ok(totals)
+    }
+
     pub async fn rename_file(&self, old_path: &str, new_path: &str) -> Result<()> {
         let count = self
             .files_collection()
-            .count_documents(doc! { "original_name": new_path }, None)
+            .count_documents(doc! { "original_name": self.name_query(new_path) }, None)
             .await
             .map_err(TgCloudError::MongoError)?;
         if count > 0 {
-            return Err(TgCloudError::Unknown(format!(
-                "File already exists at {}",
-                new_path
-            )));
+            return Err(TgCloudError::AlreadyExists(new_path.to_string()));
         }
 
         let result = self
             .files_collection()
             .update_one(
-                doc! { "original_name": old_path },
-                doc! { "$set": { "original_name": new_path } },
+                doc! { "original_name": self.name_query(old_path) },
+                doc! { "$set": self.name_fields(new_path) },
                 None,
             )
             .await
             .map_err(TgCloudError::MongoError)?;
 
+        self.cache.invalidate_files();
         if result.modified_count == 0 {
             return Err(TgCloudError::FileNotFound(old_path.to_string()));
         }
@@ -109,12 +858,86 @@ impl MongoStore {
             .files_collection()
             .update_one(
                 doc! { "file_id": file_id },
-                doc! { "$set": { "original_name": new_name } },
+                doc! { "$set": self.name_fields(new_name) },
                 None,
             )
             .await
             .map_err(TgCloudError::MongoError)?;
 
+        self.cache.invalidate_files();
+        if result.matched_count == 0 {
+            return Err(TgCloudError::FileNotFound(file_id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Moves every file under the folder `old_prefix` to `new_prefix`. Fails
+    /// without changes if any target name is taken; if an update fails midway
+    /// the files renamed so far are moved back. Returns the number of files moved.
+    pub async fn rename_prefix(&self, old_prefix: &str, new_prefix: &str) -> Result<usize> {
+        let old_dir = folder_path(old_prefix)?;
+        let new_dir = folder_path(new_prefix)?;
+
+        let files = self.list_files_with_depth(&old_dir, None, true).await?;
+        if files.is_empty() {
+            return Err(TgCloudError::FileNotFound(old_dir));
+        }
+        let moves: Vec<(String, String, String)> = files
+            .into_iter()
+            .map(|f| {
+                let new_name = format!("{}{}", new_dir, &f.original_name[old_dir.len()..]);
+                (f.file_id, f.original_name, new_name)
+            })
+            .collect();
+
+        let mut new_names: Vec<String> = moves.iter().map(|(_, _, new)| new.clone()).collect();
+        if self.names.is_some() {
+            let sealed: Vec<String> = new_names.iter().map(|n| self.seal(n)).collect();
+            new_names.extend(sealed);
+        }
+        if let Some(taken) = self
+            .files_collection()
+            .find_one(doc! { "original_name": { "$in": &new_names } }, None)
+            .await
+            .map_err(TgCloudError::MongoError)?
+        {
+            let taken = self.open_file(taken)?;
+            return Err(TgCloudError::AlreadyExists(taken.original_name));
+        }
+
+        for (done, (file_id, _, new_name)) in moves.iter().enumerate() {
+            if let Err(e) = self.rename_file_by_id(file_id, new_name).await {
+                for (file_id, old_name, _) in &moves[..done] {
+                    if let Err(undo_err) = self.rename_file_by_id(file_id, old_name).await {
+                        log::error!("Failed to roll back rename of {}: {}", old_name, undo_err);
+                    }
+                }
+                return Err(e);
+            }
+        }
+        Ok(moves.len())
+    }
+
+    /// Records that the chunks of a file now live in `archive_chat_id` (or
+    /// in the storage chat again, with `None`) under new message ids.
+    pub async fn set_file_location(
+        &self,
+        file_id: &str,
+        chunks: &[FileChunk],
+        archive_chat_id: Option<&str>,
+    ) -> Result<()> {
+        let chunks = mongodb::bson::to_bson(chunks)
+            .map_err(|e| TgCloudError::Unknown(format!("Cannot encode chunks: {}", e)))?;
+        let result = self
+            .files_collection()
+            .update_one(
+                doc! { "file_id": file_id },
+                doc! { "$set": { "chunks": chunks, "archive_chat_id": archive_chat_id } },
+                None,
+            )
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        self.cache.invalidate_files();
         if result.matched_count == 0 {
             return Err(TgCloudError::FileNotFound(file_id.to_string()));
         }
@@ -124,9 +947,10 @@ impl MongoStore {
     pub async fn delete_file(&self, path: &str) -> Result<()> {
         let result = self
             .files_collection()
-            .delete_one(doc! { "original_name": path }, None)
+            .delete_one(doc! { "original_name": self.name_query(path) }, None)
             .await
             .map_err(TgCloudError::MongoError)?;
+        self.cache.invalidate_files();
         if result.deleted_count == 0 {
             return Err(TgCloudError::FileNotFound(path.to_string()));
         }
@@ -139,14 +963,603 @@ impl MongoStore {
             .delete_one(doc! { "file_id": file_id }, None)
             .await
             .map_err(TgCloudError::MongoError)?;
+        self.cache.invalidate_files();
         if result.deleted_count == 0 {
             return Err(TgCloudError::FileNotFound(file_id.to_string()));
         }
         Ok(())
     }
 
+    // -----------------------------------------------------------------------
+    // Trash
+    // -----------------------------------------------------------------------
+
+    /// Moves a file's metadata from `files` into `trash`.
+    pub async fn trash_file(&self, file: &FileMetadata) -> Result<()> {
+        self.trash_collection()
+            .insert_one(
+                TrashedFile {
+                    file: self.seal_file(file.clone()),
+                    deleted_at: chrono::Utc::now(),
+                },
+                None,
+            )
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        if let Err(e) = self.delete_file_by_id(&file.file_id).await {
+            let _ = self.remove_from_trash(&file.file_id).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Trashed files, most recently deleted first. With `before`, only files
+    /// deleted before that time.
+    pub async fn list_trash(
+        &self,
+        before: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<Vec<TrashedFile>> {
+        let filter = match before {
+            Some(t) => doc! { "deleted_at": {
+                "$lt": t.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+            } },
+            None => doc! {},
+        };
+        let options = mongodb::options::FindOptions::builder()
+            .sort(doc! { "deleted_at": -1 })
+            .build();
+        let mut cursor = self
+            .trash_collection()
+            .find(filter, options)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        let mut files = Vec::new();
+        while let Some(mut trashed) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
+            trashed.file = self.open_file(trashed.file)?;
+            files.push(trashed);
+        }
+        Ok(files)
+    }
+
+    /// Moves the most recently trashed file named `path` back into `files`.
+    pub async fn restore_from_trash(&self, path: &str) -> Result<FileMetadata> {
+        let options = mongodb::options::FindOneOptions::builder()
+            .sort(doc! { "deleted_at": -1 })
+            .build();
+        let mut trashed = self
+            .trash_collection()
+            .find_one(doc! { "original_name": self.name_query(path) }, options)
+            .await
+            .map_err(TgCloudError::MongoError)?
+            .ok_or_else(|| TgCloudError::FileNotFound(format!("{} (not in trash)", path)))?;
+        trashed.file = self.open_file(trashed.file)?;
+
+        if self.get_file_by_path(path).await?.is_some() {
+            return Err(TgCloudError::AlreadyExists(path.to_string()));
+        }
+        self.save_file(trashed.file.clone()).await?;
+        self.remove_from_trash(&trashed.file.file_id).await?;
+        Ok(trashed.file)
+    }
+
+    pub async fn remove_from_trash(&self, file_id: &str) -> Result<()> {
+        self.trash_collection()
+            .delete_one(doc! { "file_id": file_id }, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        Ok(())
+    }
+
+    // -----------------------------------------------------------------------
+    // Chunk ledger
+    // -----------------------------------------------------------------------
+
+    pub async fn record_ledger_chunk(&self, entry: &LedgerChunk) -> Result<()> {
+        self.ledger_collection()
+            .insert_one(entry, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        Ok(())
+    }
+
+    /// Takes a ledger entry for chunk `index` of the content `sha256` out of
+    /// the ledger, so two uploads of the same content never share a message.
+    pub async fn claim_ledger_chunk(
+        &self,
+        sha256: &str,
+        chat_id: &str,
+        chunk_size: u64,
+        index: u32,
+        size: u64,
+    ) -> Result<Option<LedgerChunk>> {
+        self.ledger_collection()
+            .find_one_and_delete(
+                doc! {
+                    "sha256": sha256,
+                    "chat_id": chat_id,
+                    "chunk_size": chunk_size as i64,
+                    "chunk.index": index as i64,
+                    "chunk.size": size as i64,
+                },
+                None,
+            )
+            .await
+            .map_err(TgCloudError::MongoError)
+    }
+
+    /// Takes one ledger entry recorded before `before` out of the ledger. An
+    /// upload cannot claim it afterwards, so its message can be deleted.
+    pub async fn take_expired_ledger_chunk(
+        &self,
+        before: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Option<LedgerChunk>> {
+        self.ledger_collection()
+            .find_one_and_delete(
+                doc! { "created_at": {
+                    "$lt": before.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+                } },
+                None,
+            )
+            .await
+            .map_err(TgCloudError::MongoError)
+    }
+
+    /// Drops the ledger entries of messages that now belong to a saved file
+    /// or were deleted.
+    pub async fn remove_ledger_chunks(&self, chat_id: &str, message_ids: &[i64]) -> Result<()> {
+        self.ledger_collection()
+            .delete_many(
+                doc! { "chat_id": chat_id, "chunk.message_id": { "$in": message_ids } },
+                None,
+            )
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        Ok(())
+    }
+
+    // -----------------------------------------------------------------------
+    // API keys
+    // -----------------------------------------------------------------------
+
+    pub async fn insert_api_key(&self, key: &ApiKey) -> Result<()> {
+        self.api_keys_collection()
+            .insert_one(key, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        Ok(())
+    }
+
+    pub async fn list_api_keys(&self) -> Result<Vec<ApiKey>> {
+        let options = mongodb::options::FindOptions::builder()
+            .sort(doc! { "created_at": 1 })
+            .build();
+        self.api_keys_collection()
+            .find(None, options)
+            .await
+            .map_err(TgCloudError::MongoError)?
+            .try_collect()
+            .await
+            .map_err(TgCloudError::MongoError)
+    }
+
+    pub async fn find_api_key(&self, key_id: &str) -> Result<Option<ApiKey>> {
+        self.api_keys_collection()
+            .find_one(doc! { "key_id": key_id }, None)
+            .await
+            .map_err(TgCloudError::MongoError)
+    }
+
+    /// Deletes the key and returns whether it existed.
+    pub async fn delete_api_key(&self, key_id: &str) -> Result<bool> {
+        let result = self
+            .api_keys_collection()
+            .delete_one(doc! { "key_id": key_id }, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        Ok(result.deleted_count > 0)
+    }
+
+    pub async fn touch_api_key(&self, key_id: &str) -> Result<()> {
+        self.api_keys_collection()
+            .update_one(
+                doc! { "key_id": key_id },
+                doc! { "$set": { "last_used_at": chrono::Utc::now().to_rfc3339() } },
+                None,
+            )
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        Ok(())
+    }
+
+    // -----------------------------------------------------------------------
+    // Bots
+    // -----------------------------------------------------------------------
+
+    /// Ensures a record exists for `bot_id` and returns it. New bots start active.
+    pub async fn register_bot(&self, bot_id: &str, telegram_api_url: &str) -> Result<Bot> {
+        let options = mongodb::options::FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(mongodb::options::ReturnDocument::After)
+            .build();
+        let bot = self
+            .bots_collection()
+            .find_one_and_update(
+                doc! { "bot_id": bot_id },
+                doc! {
+                    "$setOnInsert": { "bot_id": bot_id, "active": true },
+                    "$set": { "telegram_api_url": telegram_api_url },
+                },
+                options,
+            )
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        self.cache.bots.clear();
+        bot.ok_or_else(|| TgCloudError::Unknown(format!("Failed to register bot {}", bot_id)))
+    }
+
+    pub async fn list_bots(&self) -> Result<Vec<Bot>> {
+        let generation = self.cache.bots.generation();
+        if let Some(hit) = self.cache.bots.get(&()) {
+            return Ok(hit);
+        }
+        let mut cursor = self
+            .bots_collection()
+            .find(doc! {}, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        let mut bots = Vec::new();
+        while let Some(bot) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
+            bots.push(bot);
+        }
+        self.cache.bots.insert(generation, (), bots.clone());
+        Ok(bots)
+    }
+
+    /// Marks a bot active or inactive. `reason` is recorded when deactivating.
+    pub async fn set_bot_active(
+        &self,
+        bot_id: &str,
+        active: bool,
+        reason: Option<&str>,
+    ) -> Result<()> {
+        let update = if active {
+            doc! { "$set": { "active": true, "deactivated_reason": null, "deactivated_at": null } }
+        } else {
+            doc! { "$set": {
+                "active": false,
+                "deactivated_reason": reason,
+                "deactivated_at": chrono::Utc::now().to_rfc3339(),
+            } }
+        };
+        let result = self
+            .bots_collection()
+            .update_one(doc! { "bot_id": bot_id }, update, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        self.cache.bots.clear();
+        if result.matched_count == 0 {
+            return Err(TgCloudError::BotManagerError(format!(
+                "Unknown bot: {}",
+                bot_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Records a 429 for a bot and the end of the cool-down it triggered.
+    pub async fn record_rate_limit(
+        &self,
+        bot_id: &str,
+        until: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        self.bots_collection()
+            .update_one(
+                doc! { "bot_id": bot_id },
+                doc! {
+                    "$inc": { "rate_limit_count": 1_i64 },
+                    "$set": {
+                        "last_rate_limited_at": chrono::Utc::now().to_rfc3339(),
+                        "rate_limited_until": until.to_rfc3339(),
+                    },
+                },
+                None,
+            )
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        self.cache.bots.clear();
+        Ok(())
+    }
+
+    // -----------------------------------------------------------------------
+    // History
+    // -----------------------------------------------------------------------
+
+    pub async fn record_history(&self, entry: HistoryEntry) -> Result<()> {
+        self.history_collection()
+            .insert_one(self.seal_history(entry), None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        Ok(())
+    }
+
+    /// Returns matching history entries, newest first.
+    pub async fn list_history(&self, query: &HistoryQuery) -> Result<Vec<HistoryEntry>> {
+        let mut filter = doc! {};
+        if let Some(path) = &query.path {
+            let path = self.name_query(path);
+            filter.insert(
+                "$or",
+                vec![doc! { "path": path.clone() }, doc! { "target": path }],
+            );
+        }
+        if let Some(since) = query.since {
+            // Timestamps are stored as RFC 3339 strings, which sort chronologically.
+            filter.insert(
+                "at",
+                doc! { "$gte": since.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true) },
+            );
+        }
+        let options = mongodb::options::FindOptions::builder()
+            .sort(doc! { "at": -1 })
+            .limit(query.limit)
+            .build();
+
+        let mut cursor = self
+            .history_collection()
+            .find(filter, options)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        let mut entries = Vec::new();
+        while let Some(entry) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
+            entries.push(self.open_history(entry)?);
+        }
+        Ok(entries)
+    }
+
+    /// The most recent successful rename or delete that was not undone yet.
+    pub async fn last_undoable(&self) -> Result<Option<HistoryEntry>> {
+        let actions: Vec<mongodb::bson::Bson> = [HistoryAction::Rename, HistoryAction::Delete]
+            .iter()
+            .map(|a| mongodb::bson::Bson::String(a.to_string()))
+            .collect();
+        let options = mongodb::options::FindOneOptions::builder()
+            .sort(doc! { "at": -1 })
+            .build();
+        self.history_collection()
+            .find_one(
+                doc! {
+                    "action": { "$in": actions },
+                    "success": true,
+                    "undone": { "$ne": true },
+                },
+                options,
+            )
+            .await
+            .map_err(TgCloudError::MongoError)?
+            .map(|entry| self.open_history(entry))
+            .transpose()
+    }
+
+    /// Entries of the bulk `operation` that succeeded and were not undone yet.
+    pub async fn undoable_in_operation(&self, operation: &str) -> Result<Vec<HistoryEntry>> {
+        let mut cursor = self
+            .history_collection()
+            .find(
+                doc! {
+                    "operation": operation,
+                    "success": true,
+                    "undone": { "$ne": true },
+                },
+                None,
+            )
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        let mut entries = Vec::new();
+        while let Some(entry) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
+            entries.push(self.open_history(entry)?);
+        }
+        Ok(entries)
+    }
+
+    pub async fn mark_undone(&self, id: ObjectId) -> Result<()> {
+        self.history_collection()
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "undone": true } },
+                None,
+            )
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        Ok(())
+    }
+
+    pub async fn save_benchmark(&self, benchmark: BotBenchmark) -> Result<()> {
+        self.benchmarks_collection()
+            .insert_one(benchmark, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        Ok(())
+    }
+
+    /// The most recent benchmark of every bot.
+    pub async fn latest_benchmarks(&self) -> Result<Vec<BotBenchmark>> {
+        let pipeline = vec![
+            doc! { "$sort": { "at": -1 } },
+            doc! { "$group": { "_id": "$bot_id", "latest": { "$first": "$$ROOT" } } },
+            doc! { "$replaceRoot": { "newRoot": "$latest" } },
+        ];
+        let mut cursor = self
+            .benchmarks_collection()
+            .aggregate(pipeline, None)
+            .await
+            .map_err(TgCloudError::MongoError)?;
+        let mut benchmarks = Vec::new();
+        while let Some(row) = cursor.try_next().await.map_err(TgCloudError::MongoError)? {
+            let benchmark = mongodb::bson::from_document(row)
+                .map_err(|e| TgCloudError::Unknown(format!("Invalid benchmark: {}", e)))?;
+            benchmarks.push(benchmark);
+        }
+        Ok(benchmarks)
+    }
+
     pub async fn increment_bot_usage(&self, _bot_id: &str) -> Result<()> {
         // No-op in single-bot mode
         Ok(())
     }
 }
+
+/// Whether `s` contains shell glob metacharacters.
+pub fn is_glob(s: &str) -> bool {
+    s.contains(['*', '?', '[', '{'])
+}
+
+/// Compiles a shell-style glob into an anchored regex:
+/// `*` matches within one path segment, `**` across segments, `?` one
+/// character, `[abc]`/`[!abc]` a character class and `{a,b}` alternatives.
+pub fn glob_to_regex(glob: &str) -> String {
+    let mut out = String::from("^");
+    let chars: Vec<char> = glob.chars().collect();
+    let mut i = 0;
+    let mut in_braces = 0usize;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                // `**/` also matches zero directories.
+                if chars.get(i + 2) == Some(&'/') {
+                    out.push_str("(?:.*/)?");
+                    i += 2;
+                } else {
+                    out.push_str(".*");
+                    i += 1;
+                }
+            }
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            '[' => match chars[i + 1..].iter().position(|&c| c == ']') {
+                Some(len) if len > 0 => {
+                    let class: String = chars[i + 1..i + 1 + len].iter().collect();
+                    out.push('[');
+                    match class.strip_prefix('!') {
+                        Some(rest) => {
+                            out.push('^');
+                            out.push_str(&rest.replace('\\', "\\\\"));
+                        }
+                        None => out.push_str(&class.replace('\\', "\\\\")),
+                    }
+                    out.push(']');
+                    i += len + 1;
+                }
+                _ => out.push_str("\\["),
+            },
+            '{' => {
+                in_braces += 1;
+                out.push_str("(?:");
+            }
+            '}' if in_braces > 0 => {
+                in_braces -= 1;
+                out.push(')');
+            }
+            ',' if in_braces > 0 => out.push('|'),
+            _ => out.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+
+    out.push('$');
+    out
+}
+
+/// Anchored regex matching everything inside the folder `prefix`
+/// (`root` or empty for the top level, where a leading `/` is optional).
+fn folder_regex(prefix: &str) -> String {
+    let trimmed = prefix.trim_end_matches('/');
+    if prefix == "root" || trimmed.is_empty() {
+        "^/?".to_string()
+    } else {
+        format!("^{}/", regex::escape(trimmed))
+    }
+}
+
+/// Length in characters of the shortest stretch of `name` containing the
+/// characters of `query` in order, or `None` if it does not contain them.
+fn fuzzy_span(name: &str, query: &str) -> Option<usize> {
+    let name: Vec<char> = name.chars().collect();
+    let query: Vec<char> = query.chars().collect();
+    let first = *query.first()?;
+    let mut best: Option<usize> = None;
+    for start in (0..name.len()).filter(|&i| name[i] == first) {
+        let mut matched = 1;
+        let mut end = start;
+        for (i, c) in name.iter().enumerate().skip(start + 1) {
+            if matched == query.len() {
+                break;
+            }
+            if *c == query[matched] {
+                matched += 1;
+                end = i;
+            }
+        }
+        if matched == query.len() {
+            let span = end - start + 1;
+            best = Some(best.map_or(span, |b| b.min(span)));
+        }
+    }
+    best
+}
+
+/// Regex for files below `prefix`, at most `depth` levels deep.
+fn depth_regex(prefix: &str, depth: Option<u32>) -> String {
+    let base = folder_regex(prefix);
+    match depth {
+        Some(d) => format!("{}([^/]+/){{0,{}}}[^/]+$", base, d.saturating_sub(1)),
+        None => base,
+    }
+}
+
+/// Drops archived files from `files` unless `include_archived`.
+fn without_archived(mut files: Vec<FileMetadata>, include_archived: bool) -> Vec<FileMetadata> {
+    if !include_archived {
+        files.retain(|f| !f.is_archived());
+    }
+    files
+}
+
+/// Regex for the names matching `filter`, or `None` if it matches all files.
+fn file_filter_regex(filter: &FileFilter) -> Option<String> {
+    match filter {
+        FileFilter::Prefix(p) if p == "root" || p.is_empty() => None,
+        FileFilter::Prefix(p) => Some(format!("^{}", regex::escape(p))),
+        FileFilter::Folder { folder, depth } => Some(depth_regex(folder, *depth)),
+        FileFilter::Glob(pattern) => Some(glob_to_regex(pattern)),
+    }
+}
+
+fn file_filter_document(filter: &FileFilter) -> mongodb::bson::Document {
+    match file_filter_regex(filter) {
+        Some(pattern) => doc! { "original_name": { "$regex": pattern } },
+        None => doc! {},
+    }
+}
+
+/// Normalizes a folder name to `name/`, rejecting the root folder.
+fn folder_path(prefix: &str) -> Result<String> {
+    let trimmed = prefix.trim_end_matches('/');
+    if prefix == "root" || trimmed.is_empty() {
+        return Err(TgCloudError::Unknown(
+            "The root folder cannot be moved".to_string(),
+        ));
+    }
+    Ok(format!("{}/", trimmed))
+}
+
+/// Reads a numeric aggregation result regardless of the BSON integer width.
+fn bson_to_u64(value: Option<&mongodb::bson::Bson>) -> u64 {
+    match value {
+        Some(mongodb::bson::Bson::Int32(v)) => *v as u64,
+        Some(mongodb::bson::Bson::Int64(v)) => *v as u64,
+        Some(mongodb::bson::Bson::Double(v)) => *v as u64,
+        _ => 0,
+    }
+}
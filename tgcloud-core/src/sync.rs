@@ -0,0 +1,138 @@
+use crate::diff::{DiffKind, DirDiff};
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Which side of a sync is the source of truth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncDirection {
+    /// Make the remote folder match the local directory.
+    Push,
+    /// Make the local directory match the remote folder.
+    Pull,
+}
+
+/// One step of a sync.
+#[derive(Debug, Clone)]
+pub enum SyncAction {
+    Upload {
+        local: PathBuf,
+        remote: String,
+        size: u64,
+        /// An older remote version is replaced.
+        replace: bool,
+    },
+    Download {
+        remote: String,
+        local: PathBuf,
+        size: u64,
+        /// An older local version is overwritten.
+        replace: bool,
+    },
+    DeleteRemote {
+        remote: String,
+        size: u64,
+    },
+    DeleteLocal {
+        local: PathBuf,
+        size: u64,
+    },
+}
+
+/// Everything a sync would do, in execution order.
+#[derive(Debug, Clone, Default)]
+pub struct SyncPlan {
+    pub actions: Vec<SyncAction>,
+}
+
+impl SyncPlan {
+    /// Derives the actions that bring the target side of `diff` in line with
+    /// its source. Extra files on the target side are only deleted with `delete`.
+    pub fn build(
+        diff: &DirDiff,
+        direction: SyncDirection,
+        delete: bool,
+        local_dir: &Path,
+        remote_root: &str,
+    ) -> Self {
+        let mut actions = Vec::new();
+        for entry in &diff.entries {
+            let remote = format!("{}{}", remote_root, entry.name);
+            let local = local_dir.join(&entry.name);
+            let local_size = entry.local_size.unwrap_or(0);
+            let remote_size = entry.remote.as_ref().map_or(0, |f| f.size);
+
+            let action = match (direction, entry.kind) {
+                (_, DiffKind::Unchanged) => None,
+                (SyncDirection::Push, DiffKind::OnlyLocal | DiffKind::Modified) => {
+                    Some(SyncAction::Upload {
+                        local,
+                        remote,
+                        size: local_size,
+                        replace: entry.kind == DiffKind::Modified,
+                    })
+                }
+                (SyncDirection::Push, DiffKind::OnlyRemote) => {
+                    delete.then_some(SyncAction::DeleteRemote {
+                        remote,
+                        size: remote_size,
+                    })
+                }
+                (SyncDirection::Pull, DiffKind::OnlyRemote | DiffKind::Modified) => {
+                    Some(SyncAction::Download {
+                        remote,
+                        local,
+                        size: remote_size,
+                        replace: entry.kind == DiffKind::Modified,
+                    })
+                }
+                (SyncDirection::Pull, DiffKind::OnlyLocal) => {
+                    delete.then_some(SyncAction::DeleteLocal {
+                        local,
+                        size: local_size,
+                    })
+                }
+            };
+            actions.extend(action);
+        }
+        Self { actions }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    pub fn upload_bytes(&self) -> u64 {
+        self.actions
+            .iter()
+            .map(|a| match a {
+                SyncAction::Upload { size, .. } => *size,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    pub fn download_bytes(&self) -> u64 {
+        self.actions
+            .iter()
+            .map(|a| match a {
+                SyncAction::Download { size, .. } => *size,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Number of deletions and the bytes they free.
+    pub fn deletions(&self) -> (usize, u64) {
+        self.actions
+            .iter()
+            .filter_map(|a| match a {
+                SyncAction::DeleteRemote { size, .. } | SyncAction::DeleteLocal { size, .. } => {
+                    Some(*size)
+                }
+                _ => None,
+            })
+            .fold((0, 0), |(n, bytes), size| (n + 1, bytes + size))
+    }
+}
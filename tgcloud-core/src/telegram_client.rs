@@ -1,11 +1,15 @@
-use crate::errors::{Result, TgCloudError};
-use reqwest::{multipart, Body, Client, StatusCode};
+use crate::bandwidth::{RateLimiter, ThrottledReader};
+use crate::config::{HttpConfig, TlsConfig};
+use crate::errors::{ConfigError, Result, TgCloudError, TransientKind};
+use crate::pacer::ChatPacer;
+use reqwest::{multipart, Body, Certificate, Client, Identity, StatusCode};
 use serde_json::Value;
+use std::collections::HashMap;
 use std::pin::Pin;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::io::AsyncRead;
 use tokio_util::codec::{BytesCodec, FramedRead};
 
@@ -14,25 +18,152 @@ const MAX_RETRIES: u32 = 5;
 /// Base delay for exponential backoff.
 const BASE_DELAY_MS: u64 = 1_000;
 
+/// Builds the HTTP client used for the Bot API with the given pool, timeout
+/// and TLS settings. Build it once and share it (see
+/// [`TelegramClient::with_client`]) so chunks reuse pooled connections.
+pub fn build_http_client(http: &HttpConfig, tls: &TlsConfig) -> Result<Client> {
+    let mut builder = Client::builder()
+        .pool_max_idle_per_host(http.pool_max_idle_per_host)
+        .pool_idle_timeout(
+            (http.pool_idle_timeout_secs > 0)
+                .then(|| Duration::from_secs(http.pool_idle_timeout_secs)),
+        )
+        .tcp_keepalive(
+            (http.tcp_keepalive_secs > 0).then(|| Duration::from_secs(http.tcp_keepalive_secs)),
+        )
+        .tcp_nodelay(true);
+    if http.http2 {
+        builder = builder
+            .http2_prior_knowledge()
+            .http2_adaptive_window(true)
+            .http2_keep_alive_interval(Duration::from_secs(30))
+            .http2_keep_alive_while_idle(true);
+    }
+    if http.connect_timeout_secs > 0 {
+        builder = builder.connect_timeout(Duration::from_secs(http.connect_timeout_secs));
+    }
+    if http.request_timeout_secs > 0 {
+        builder = builder.timeout(Duration::from_secs(http.request_timeout_secs));
+    }
+
+    if let Some(path) = &tls.ca_bundle {
+        let pem = read_tls_file(path, "CA bundle")?;
+        let certs = Certificate::from_pem_bundle(&pem).map_err(|e| {
+            ConfigError::General(format!("Invalid CA bundle {}: {}", path.display(), e))
+        })?;
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    if let Some(cert_path) = &tls.client_cert {
+        let cert = read_tls_file(cert_path, "client certificate")?;
+        let identity = match &tls.client_key {
+            Some(key_path) => {
+                let key = read_tls_file(key_path, "client key")?;
+                Identity::from_pkcs8_pem(&cert, &key)
+            }
+            None => {
+                Identity::from_pkcs12_der(&cert, tls.client_cert_password.as_deref().unwrap_or(""))
+            }
+        }
+        .map_err(|e| {
+            ConfigError::General(format!(
+                "Invalid client certificate {}: {}",
+                cert_path.display(),
+                e
+            ))
+        })?;
+        builder = builder.identity(identity);
+    }
+
+    if tls.accept_invalid_certs {
+        log::warn!("TLS certificate verification is disabled for the Bot API server");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    Ok(builder.build()?)
+}
+
+fn read_tls_file(path: &std::path::Path, what: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| {
+        ConfigError::General(format!("Cannot read {} {}: {}", what, path.display(), e)).into()
+    })
+}
+
+/// A document in a pinned message, see [`TelegramClient::pinned_document`].
+#[derive(Debug, Clone)]
+pub struct PinnedDocument {
+    pub message_id: i64,
+    pub file_id: String,
+    pub file_name: String,
+}
+
 #[derive(Clone)]
 pub struct TelegramClient {
     client: Client,
     api_url: String,
+    /// Bot API servers of bots that don't use `api_url`, by the Telegram bot
+    /// id at the start of their token.
+    bot_api_urls: Arc<HashMap<String, String>>,
+    limiter: Option<Arc<RateLimiter>>,
+    pacer: Option<Arc<ChatPacer>>,
+    stall_timeout: Option<Duration>,
 }
 
 impl TelegramClient {
-    /// Create a new client sharing the given `reqwest::Client`.
+    /// Create a new client sharing the given `reqwest::Client`, usually one
+    /// from [`build_http_client`]. Clones share the same connection pool.
     pub fn with_client(client: Client, api_url: String) -> Self {
-        Self { client, api_url }
-    }
-
-    pub fn new(api_url: String) -> Self {
         Self {
-            client: Client::new(),
+            client,
             api_url,
+            bot_api_urls: Arc::default(),
+            limiter: None,
+            pacer: None,
+            stall_timeout: None,
         }
     }
 
+    /// Sends the requests of some bots to other Bot API servers: `urls` maps
+    /// the Telegram bot id at the start of a token to its server, see
+    /// [`crate::Config::bot_api_urls`].
+    pub fn with_bot_api_urls(mut self, urls: HashMap<String, String>) -> Self {
+        self.bot_api_urls = Arc::new(urls);
+        self
+    }
+
+    /// Bot API server that requests made with `token` go to.
+    fn api_url_for(&self, token: &str) -> &str {
+        let telegram_id = token.split(':').next().unwrap_or_default();
+        self.bot_api_urls
+            .get(telegram_id)
+            .map_or(self.api_url.as_str(), String::as_str)
+    }
+
+    /// Throttles every upload made through this client with `limiter`.
+    pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Paces every message sent or deleted through this client with `pacer`.
+    pub fn with_chat_pacer(mut self, pacer: Arc<ChatPacer>) -> Self {
+        self.pacer = Some(pacer);
+        self
+    }
+
+    /// Waits for the chat pacer, if any, before a message to `chat_id`.
+    async fn pace(&self, chat_id: &str) {
+        pace(&self.pacer, chat_id).await;
+    }
+
+    /// Aborts (and retries) transfers that move no bytes for `timeout`.
+    pub fn with_stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = Some(timeout);
+        self
+    }
+
     /// Return a reference to the inner `reqwest::Client` so callers can share it.
     pub fn http_client(&self) -> &Client {
         &self.client
@@ -58,8 +189,11 @@ impl TelegramClient {
 
         let token = token.to_string();
         let chat_id = chat_id.to_string();
-        let api_url = self.api_url.clone();
+        let api_url = self.api_url_for(&token).to_string();
         let client = self.client.clone();
+        let limiter = self.limiter.clone();
+        let pacer = self.pacer.clone();
+        let stall_timeout = self.stall_timeout;
         let path = path.to_string();
 
         self.with_retry(move || {
@@ -69,11 +203,31 @@ impl TelegramClient {
             let client = client.clone();
             let file_name = file_name.clone();
             let path = path.clone();
+            let limiter = limiter.clone();
+            let pacer = pacer.clone();
             async move {
+                pace(&pacer, &chat_id).await;
                 let file = tokio::fs::File::open(&path).await?;
-                let stream = FramedRead::new(file, BytesCodec::new());
+                let size = file.metadata().await?.len();
+                let reader = ThrottledReader::new(file, limiter);
+                let (reader, activity) = StallReader::new(reader);
+                let stream = FramedRead::new(reader, BytesCodec::new());
                 let file_body = Body::wrap_stream(stream);
-                upload_stream_inner(&client, &api_url, &token, &chat_id, file_name, file_body).await
+                watch_stall(
+                    stall_timeout,
+                    &activity,
+                    upload_stream_inner(
+                        &client,
+                        &pacer,
+                        &api_url,
+                        &token,
+                        &chat_id,
+                        file_name,
+                        file_body,
+                        Some(size),
+                    ),
+                )
+                .await
             }
         })
         .await
@@ -90,15 +244,24 @@ impl TelegramClient {
         file_name: String,
         reader: impl tokio::io::AsyncRead + Send + Sync + 'static,
     ) -> Result<(String, i64)> {
+        self.pace(chat_id).await;
+        let reader = ThrottledReader::new(Box::pin(reader), self.limiter.clone());
+        let (reader, activity) = StallReader::new(reader);
         let stream = FramedRead::new(reader, BytesCodec::new());
         let file_body = Body::wrap_stream(stream);
-        upload_stream_inner(
-            &self.client,
-            &self.api_url,
-            token,
-            chat_id,
-            file_name,
-            file_body,
+        watch_stall(
+            self.stall_timeout,
+            &activity,
+            upload_stream_inner(
+                &self.client,
+                &self.pacer,
+                self.api_url_for(token),
+                token,
+                chat_id,
+                file_name,
+                file_body,
+                None,
+            ),
         )
         .await
     }
@@ -107,6 +270,9 @@ impl TelegramClient {
     // Upload part with retry — re-opens the file for each attempt
     // -----------------------------------------------------------------------
 
+    /// `on_rate_limited` is called for every attempt Telegram answers with 429.
+    /// Bytes of failed attempts are taken back out of `progress`, so it never
+    /// counts a byte twice.
     #[allow(clippy::too_many_arguments)]
     pub async fn upload_part_with_retry(
         &self,
@@ -117,13 +283,17 @@ impl TelegramClient {
         offset: u64,
         length: u64,
         progress: Arc<AtomicU64>,
+        on_rate_limited: Arc<dyn Fn() + Send + Sync>,
     ) -> Result<(String, i64)> {
         use tokio::io::AsyncSeekExt;
 
         let token = token.to_string();
         let chat_id = chat_id.to_string();
-        let api_url = self.api_url.clone();
+        let api_url = self.api_url_for(&token).to_string();
         let client = self.client.clone();
+        let limiter = self.limiter.clone();
+        let pacer = self.pacer.clone();
+        let stall_timeout = self.stall_timeout;
         let file_name_owned = file_name;
         let file_path_owned = file_path.to_string();
 
@@ -135,14 +305,43 @@ impl TelegramClient {
             let file_name = file_name_owned.clone();
             let file_path = file_path_owned.clone();
             let progress = Arc::clone(&progress);
+            let limiter = limiter.clone();
+            let on_rate_limited = Arc::clone(&on_rate_limited);
+            let pacer = pacer.clone();
             async move {
+                pace(&pacer, &chat_id).await;
                 let mut file = tokio::fs::File::open(&file_path).await?;
                 file.seek(std::io::SeekFrom::Start(offset)).await?;
                 let reader = tokio::io::AsyncReadExt::take(file, length);
-                let reader_with_progress = ProgressWrapper::new(reader, progress);
-                let stream = FramedRead::new(reader_with_progress, BytesCodec::new());
+                let reader = ThrottledReader::new(reader, limiter);
+                let reader = ProgressWrapper::new(reader, Arc::clone(&progress));
+                let attempt = Arc::new(AtomicU64::new(0));
+                let reader_with_progress = ProgressWrapper::new(reader, Arc::clone(&attempt));
+                let (reader, activity) = StallReader::new(reader_with_progress);
+                let stream = FramedRead::new(reader, BytesCodec::new());
                 let file_body = Body::wrap_stream(stream);
-                upload_stream_inner(&client, &api_url, &token, &chat_id, file_name, file_body).await
+                let result = watch_stall(
+                    stall_timeout,
+                    &activity,
+                    upload_stream_inner(
+                        &client,
+                        &pacer,
+                        &api_url,
+                        &token,
+                        &chat_id,
+                        file_name,
+                        file_body,
+                        Some(length),
+                    ),
+                )
+                .await;
+                if result.as_ref().is_err_and(is_rate_limit) {
+                    on_rate_limited();
+                }
+                if result.is_err() {
+                    progress.fetch_sub(attempt.load(Ordering::Relaxed), Ordering::Relaxed);
+                }
+                result
             }
         })
         .await
@@ -153,13 +352,15 @@ impl TelegramClient {
     // -----------------------------------------------------------------------
 
     pub async fn delete_message(&self, token: &str, chat_id: &str, message_id: i64) -> Result<()> {
-        let url = format!("{}/bot{}/deleteMessage", self.api_url, token);
+        let url = format!("{}/bot{}/deleteMessage", self.api_url_for(token), token);
         let params = [
             ("chat_id", chat_id.to_string()),
             ("message_id", message_id.to_string()),
         ];
 
+        self.pace(chat_id).await;
         let res = self.client.post(&url).form(&params).send().await?;
+        check_auth_status(&res)?;
 
         if !res.status().is_success() {
             return Err(TgCloudError::UploadFailed(format!(
@@ -170,13 +371,166 @@ impl TelegramClient {
         Ok(())
     }
 
+    // -----------------------------------------------------------------------
+    // getMe
+    // -----------------------------------------------------------------------
+
+    /// Calls `getMe`, the cheapest authenticated request, e.g. to measure latency.
+    pub async fn get_me(&self, token: &str) -> Result<()> {
+        let url = format!("{}/bot{}/getMe", self.api_url_for(token), token);
+        let res = self.client.get(&url).send().await?;
+        check_auth_status(&res)?;
+        let json: Value = res.json().await?;
+        if !json["ok"].as_bool().unwrap_or(false) {
+            return Err(TgCloudError::Unknown(format!("getMe failed: {}", json)));
+        }
+        Ok(())
+    }
+
+    /// Returns the membership status (`creator`, `administrator`, `member`,
+    /// `left`, ...) of the bot behind `token` in `chat_id`.
+    pub async fn chat_member_status(&self, token: &str, chat_id: &str) -> Result<String> {
+        let user_id = token.split(':').next().unwrap_or_default();
+        let url = format!("{}/bot{}/getChatMember", self.api_url_for(token), token);
+        let params = [("chat_id", chat_id), ("user_id", user_id)];
+        let res = self.client.get(&url).query(&params).send().await?;
+        check_auth_status(&res)?;
+        let json: Value = res.json().await?;
+        if !json["ok"].as_bool().unwrap_or(false) {
+            return Err(TgCloudError::Unknown(format!(
+                "getChatMember failed: {}",
+                json["description"].as_str().unwrap_or("no description")
+            )));
+        }
+        Ok(json["result"]["status"]
+            .as_str()
+            .unwrap_or("unknown")
+            .to_string())
+    }
+
+    /// Sends a plain request to the Bot API server at `api_url` and returns
+    /// the HTTP status, to tell an unreachable server from a rejecting one.
+    pub async fn probe(&self, api_url: &str) -> Result<u16> {
+        let res = self.client.get(api_url).send().await?;
+        Ok(res.status().as_u16())
+    }
+
+    // -----------------------------------------------------------------------
+    // Forward message
+    // -----------------------------------------------------------------------
+
+    /// Forwards `message_id` from `from_chat_id` to `to_chat_id` and returns the
+    /// id of the new message.
+    pub async fn forward_message(
+        &self,
+        token: &str,
+        from_chat_id: &str,
+        to_chat_id: &str,
+        message_id: i64,
+    ) -> Result<i64> {
+        let url = format!("{}/bot{}/forwardMessage", self.api_url_for(token), token);
+        let params = [
+            ("chat_id", to_chat_id.to_string()),
+            ("from_chat_id", from_chat_id.to_string()),
+            ("message_id", message_id.to_string()),
+        ];
+
+        self.pace(to_chat_id).await;
+        let res = self.client.post(&url).form(&params).send().await?;
+        check_auth_status(&res)?;
+        let json: Value = res.json().await?;
+
+        if !json["ok"].as_bool().unwrap_or(false) {
+            return Err(TgCloudError::UploadFailed(format!(
+                "Forward failed: {}",
+                json
+            )));
+        }
+
+        json["result"]["message_id"]
+            .as_i64()
+            .ok_or_else(|| TgCloudError::UploadFailed("No message_id in response".to_string()))
+    }
+
+    // -----------------------------------------------------------------------
+    // Pinned messages
+    // -----------------------------------------------------------------------
+
+    /// Pins `message_id` in `chat_id` without notifying the chat's members.
+    pub async fn pin_message(&self, token: &str, chat_id: &str, message_id: i64) -> Result<()> {
+        let url = format!("{}/bot{}/pinChatMessage", self.api_url_for(token), token);
+        let params = [
+            ("chat_id", chat_id.to_string()),
+            ("message_id", message_id.to_string()),
+            ("disable_notification", "true".to_string()),
+        ];
+
+        self.pace(chat_id).await;
+        let res = self.client.post(&url).form(&params).send().await?;
+        check_auth_status(&res)?;
+        let json: Value = res.json().await?;
+        if !json["ok"].as_bool().unwrap_or(false) {
+            return Err(TgCloudError::UploadFailed(format!(
+                "pinChatMessage failed: {}",
+                json["description"].as_str().unwrap_or("no description")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the document of the most recently pinned message in
+    /// `chat_id`, if that message holds one.
+    pub async fn pinned_document(
+        &self,
+        token: &str,
+        chat_id: &str,
+    ) -> Result<Option<PinnedDocument>> {
+        let url = format!("{}/bot{}/getChat", self.api_url_for(token), token);
+        self.pace(chat_id).await;
+        let res = self
+            .client
+            .get(&url)
+            .query(&[("chat_id", chat_id)])
+            .send()
+            .await?;
+        check_auth_status(&res)?;
+        let json: Value = res.json().await?;
+        if !json["ok"].as_bool().unwrap_or(false) {
+            return Err(TgCloudError::Unknown(format!(
+                "getChat failed: {}",
+                json["description"].as_str().unwrap_or("no description")
+            )));
+        }
+        let pinned = &json["result"]["pinned_message"];
+        let (Some(message_id), Some(file_id)) = (
+            pinned["message_id"].as_i64(),
+            pinned["document"]["file_id"].as_str(),
+        ) else {
+            return Ok(None);
+        };
+        Ok(Some(PinnedDocument {
+            message_id,
+            file_id: file_id.to_string(),
+            file_name: pinned["document"]["file_name"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+        }))
+    }
+
     // -----------------------------------------------------------------------
     // Download helpers
     // -----------------------------------------------------------------------
 
     pub async fn get_download_url(&self, token: &str, file_id: &str) -> Result<String> {
-        let url = format!("{}/bot{}/getFile?file_id={}", self.api_url, token, file_id);
+        let url = format!(
+            "{}/bot{}/getFile?file_id={}",
+            self.api_url_for(token),
+            token,
+            file_id
+        );
         let res = self.client.get(&url).send().await?;
+        check_auth_status(&res)?;
         let json: Value = res.json().await?;
 
         let file_path = json["result"]["file_path"].as_str().ok_or_else(|| {
@@ -189,14 +543,22 @@ impl TelegramClient {
 
         Ok(format!(
             "{}/file/bot{}/{}",
-            self.api_url, token, sanitized_path
+            self.api_url_for(token),
+            token,
+            sanitized_path
         ))
     }
 
     /// Triggers getFile and returns the local file path (absolute in local mode).
     pub async fn get_local_file_path(&self, token: &str, file_id: &str) -> Result<String> {
-        let url = format!("{}/bot{}/getFile?file_id={}", self.api_url, token, file_id);
+        let url = format!(
+            "{}/bot{}/getFile?file_id={}",
+            self.api_url_for(token),
+            token,
+            file_id
+        );
         let res = self.client.get(&url).send().await?;
+        check_auth_status(&res)?;
         let json: Value = res.json().await?;
 
         let file_path = json["result"]["file_path"].as_str().ok_or_else(|| {
@@ -217,6 +579,34 @@ impl TelegramClient {
         Ok(res)
     }
 
+    /// Reads a download response to the end, failing if no bytes arrive for
+    /// the stall timeout.
+    pub async fn read_body(&self, mut res: reqwest::Response) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        loop {
+            let next = match self.stall_timeout {
+                Some(timeout) => {
+                    tokio::time::timeout(timeout, res.chunk())
+                        .await
+                        .map_err(|_| TgCloudError::Transient {
+                            kind: TransientKind::Stalled,
+                            http_status: None,
+                            retry_after: None,
+                            message: format!(
+                                "Transfer stalled: no bytes received for {}s",
+                                timeout.as_secs()
+                            ),
+                        })?
+                }
+                None => res.chunk().await,
+            };
+            match next? {
+                Some(bytes) => body.extend_from_slice(&bytes),
+                None => return Ok(body),
+            }
+        }
+    }
+
     /// Download with automatic retry on 429 / 5xx.
     pub async fn download_file_with_retry(
         &self,
@@ -231,7 +621,7 @@ impl TelegramClient {
             let url = url_owned.clone();
             async move {
                 let res = client.get(&url).send().await?;
-                check_transient_status(&res)?;
+                let res = check_transient_status(res).await?;
                 if !res.status().is_success() {
                     return Err(TgCloudError::DownloadFailed(format!(
                         "Download failed: {}",
@@ -266,7 +656,13 @@ impl TelegramClient {
                         return Err(e);
                     }
                     last_error = e.to_string();
-                    let delay = backoff_delay(attempt);
+                    let delay = match &e {
+                        TgCloudError::Transient {
+                            retry_after: Some(wait),
+                            ..
+                        } => backoff_delay(attempt).max(*wait),
+                        _ => backoff_delay(attempt),
+                    };
                     log::warn!(
                         "Retryable error (attempt {}/{}): {}. Retrying in {:?}",
                         attempt + 1,
@@ -290,26 +686,40 @@ impl TelegramClient {
 // Free functions (not methods — avoids borrow issues with closures)
 // ===========================================================================
 
+async fn pace(pacer: &Option<Arc<ChatPacer>>, chat_id: &str) {
+    if let Some(pacer) = pacer {
+        pacer.wait(chat_id).await;
+    }
+}
+
+/// Sends `body` as a document. With `expected_size`, the size Telegram
+/// reports for the stored document is checked and a short copy is deleted and
+/// reported as a transient error, so the caller's retry uploads it again.
+/// The caller paces the upload; `pacer` paces that delete.
+#[allow(clippy::too_many_arguments)]
 async fn upload_stream_inner(
     client: &Client,
+    pacer: &Option<Arc<ChatPacer>>,
     api_url: &str,
     token: &str,
     chat_id: &str,
     file_name: String,
     body: Body,
+    expected_size: Option<u64>,
 ) -> Result<(String, i64)> {
     let form = multipart::Form::new()
         .text("chat_id", chat_id.to_string())
         .part(
             "document",
-            multipart::Part::stream(body).file_name(file_name),
+            multipart::Part::stream(body).file_name(file_name.clone()),
         );
 
     let url = format!("{}/bot{}/sendDocument", api_url, token);
     let res = client.post(&url).multipart(form).send().await?;
 
     // Check for transient HTTP errors that should trigger retry.
-    check_transient_status(&res)?;
+    let res = check_transient_status(res).await?;
+    check_auth_status(&res)?;
 
     if !res.status().is_success() {
         let status = res.status();
@@ -338,45 +748,129 @@ async fn upload_stream_inner(
         .as_i64()
         .ok_or_else(|| TgCloudError::UploadFailed("No message_id in response".to_string()))?;
 
+    if let Some(expected) = expected_size {
+        let stored = match json["result"]["document"]["file_size"].as_u64() {
+            Some(size) => Some(size),
+            None => stored_file_size(client, api_url, token, &file_id).await?,
+        };
+        match stored {
+            Some(stored) if stored != expected => {
+                let url = format!("{}/bot{}/deleteMessage", api_url, token);
+                let params = [
+                    ("chat_id", chat_id.to_string()),
+                    ("message_id", message_id.to_string()),
+                ];
+                pace(pacer, chat_id).await;
+                let failure = match client.post(&url).form(&params).send().await {
+                    Ok(res) if res.status().is_success() => None,
+                    Ok(res) => Some(format!("HTTP {}", res.status())),
+                    Err(e) => Some(e.to_string()),
+                };
+                if let Some(failure) = failure {
+                    log::warn!(
+                        "Failed to delete truncated message {} in chat {} ({}); delete it by hand",
+                        message_id,
+                        chat_id,
+                        failure
+                    );
+                }
+                return Err(TgCloudError::Transient {
+                    kind: TransientKind::Truncated,
+                    http_status: None,
+                    retry_after: None,
+                    message: format!(
+                        "Telegram stored {} of {} bytes of {}",
+                        stored, expected, file_name
+                    ),
+                });
+            }
+            Some(_) => {}
+            None => log::debug!("Telegram did not report a size for {}", file_id),
+        }
+    }
+
     Ok((file_id, message_id))
 }
 
-/// Returns a retryable error if the response status is 429 or 5xx.
-/// This must be called *before* consuming the response body.
-fn check_transient_status(res: &reqwest::Response) -> Result<()> {
+/// Asks `getFile` for the size Telegram stored for `file_id`.
+async fn stored_file_size(
+    client: &Client,
+    api_url: &str,
+    token: &str,
+    file_id: &str,
+) -> Result<Option<u64>> {
+    let url = format!("{}/bot{}/getFile?file_id={}", api_url, token, file_id);
+    let res = client.get(&url).send().await?;
+    let json: Value = res.json().await?;
+    Ok(json["result"]["file_size"].as_u64())
+}
+
+/// Fails with a `Transient` error if the response status is 429 or 5xx,
+/// taking the wait Telegram asks for from `parameters.retry_after` or the
+/// `Retry-After` header. Any other response is handed back untouched.
+async fn check_transient_status(res: reqwest::Response) -> Result<reqwest::Response> {
     let status = res.status();
     if status == StatusCode::TOO_MANY_REQUESTS {
-        return Err(TgCloudError::UploadFailed(
-            "Rate limited (HTTP 429)".to_string(),
-        ));
+        let header = res
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok());
+        let body: Value = res.json().await.unwrap_or(Value::Null);
+        let retry_after = body["parameters"]["retry_after"].as_u64().or(header);
+        return Err(TgCloudError::Transient {
+            kind: TransientKind::RateLimited,
+            http_status: Some(status.as_u16()),
+            retry_after: retry_after.map(Duration::from_secs),
+            message: match retry_after {
+                Some(secs) => format!("Rate limited (HTTP 429), retry after {}s", secs),
+                None => "Rate limited (HTTP 429)".to_string(),
+            },
+        });
     }
     if status.is_server_error() {
-        return Err(TgCloudError::UploadFailed(format!(
-            "Server error (HTTP {})",
-            status.as_u16()
-        )));
+        return Err(TgCloudError::Transient {
+            kind: TransientKind::ServerError,
+            http_status: Some(status.as_u16()),
+            retry_after: None,
+            message: format!("Server error (HTTP {})", status.as_u16()),
+        });
+    }
+    Ok(res)
+}
+
+/// Returns `BotUnauthorized` if Telegram rejected the bot itself (401/403),
+/// e.g. a revoked token or a bot that was removed from the chat.
+fn check_auth_status(res: &reqwest::Response) -> Result<()> {
+    let status = res.status();
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        return Err(TgCloudError::BotUnauthorized {
+            status: status.as_u16(),
+            message: status.canonical_reason().unwrap_or("").to_string(),
+        });
     }
     Ok(())
 }
 
-/// Determine whether an error is retryable (429 or 5xx related).
+/// Whether Telegram answered with 429 Too Many Requests.
+pub(crate) fn is_rate_limit(err: &TgCloudError) -> bool {
+    matches!(
+        err,
+        TgCloudError::Transient {
+            kind: TransientKind::RateLimited,
+            ..
+        }
+    )
+}
+
+/// Whether repeating the request may succeed: transient Telegram responses
+/// and connection failures or timeouts, but not malformed responses or
+/// permanent API errors.
 fn is_retryable(err: &TgCloudError) -> bool {
     match err {
-        TgCloudError::UploadFailed(msg)
-        | TgCloudError::DownloadFailed(msg)
-        | TgCloudError::DeleteFailed(msg) => {
-            msg.contains("429")
-                || msg.contains("Rate limited")
-                || msg.contains("Server error")
-                || msg.contains("500")
-                || msg.contains("502")
-                || msg.contains("503")
-                || msg.contains("504")
-        }
-        TgCloudError::RateLimited(_) => true,
-        TgCloudError::TelegramError(_) => {
-            // reqwest connection/timeout errors are retryable
-            true
+        TgCloudError::Transient { .. } => true,
+        TgCloudError::TelegramError(e) => {
+            e.is_connect() || e.is_timeout() || e.is_request() || e.is_body()
         }
         _ => false,
     }
@@ -395,6 +889,103 @@ fn backoff_delay(attempt: u32) -> Duration {
     let ms = (base as i64 + jitter).max(100) as u64;
     Duration::from_millis(ms)
 }
+/// When a request body last handed out bytes, shared between a `StallReader`
+/// and `watch_stall`.
+struct Activity {
+    started: Instant,
+    last_ms: AtomicU64,
+    finished: AtomicBool,
+}
+
+impl Activity {
+    fn touch(&self) {
+        self.last_ms
+            .store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Time since the body last made progress; zero once it was fully read,
+    /// because waiting for the response is covered by the request timeout.
+    fn idle(&self) -> Duration {
+        if self.finished.load(Ordering::Relaxed) {
+            return Duration::ZERO;
+        }
+        let last = Duration::from_millis(self.last_ms.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last)
+    }
+}
+
+/// Records read activity of a request body for stall detection.
+struct StallReader<R> {
+    inner: R,
+    activity: Arc<Activity>,
+}
+
+impl<R> StallReader<R> {
+    fn new(inner: R) -> (Self, Arc<Activity>) {
+        let activity = Arc::new(Activity {
+            started: Instant::now(),
+            last_ms: AtomicU64::new(0),
+            finished: AtomicBool::new(false),
+        });
+        let reader = Self {
+            inner,
+            activity: Arc::clone(&activity),
+        };
+        (reader, activity)
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for StallReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            if buf.filled().len() == before && buf.remaining() > 0 {
+                self.activity.finished.store(true, Ordering::Relaxed);
+            } else {
+                self.activity.touch();
+            }
+        }
+        poll
+    }
+}
+
+/// Runs `request`, failing with a retryable error if its body reports no
+/// activity for `timeout`.
+async fn watch_stall<T>(
+    timeout: Option<Duration>,
+    activity: &Activity,
+    request: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    let Some(timeout) = timeout else {
+        return request.await;
+    };
+    tokio::pin!(request);
+    let mut tick = tokio::time::interval(timeout.min(Duration::from_secs(1)));
+    loop {
+        tokio::select! {
+            result = &mut request => return result,
+            _ = tick.tick() => {
+                if activity.idle() >= timeout {
+                    return Err(TgCloudError::Transient {
+                        kind: TransientKind::Stalled,
+                        http_status: None,
+                        retry_after: None,
+                        message: format!(
+                            "Transfer stalled: no bytes sent for {}s",
+                            timeout.as_secs()
+                        ),
+                    });
+                }
+            }
+        }
+    }
+}
+
 /// A wrapper that tracks bytes read from an underlying AsyncRead.
 struct ProgressWrapper<R> {
     inner: R,
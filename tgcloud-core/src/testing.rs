@@ -0,0 +1,485 @@
+//! An in-process stand-in for a local Bot API server, for tests. It speaks
+//! enough of the Bot API for tgcloud's transfers (`sendDocument`, `getFile`,
+//! `deleteMessage`, `forwardMessage`, `getMe` and `getChatMember`), stores
+//! documents in a temporary directory like `telegram-bot-api --local` does,
+//! and can be told to answer with 429s, 5xx errors or delays.
+//!
+//! Only built with the `testing` feature.
+
+use crate::bandwidth::BandwidthSchedule;
+use crate::config::{
+    BotConfig, Config, HookConfig, HttpConfig, TlsConfig, DEFAULT_MAX_GLOBAL_CONCURRENCY,
+    DEFAULT_MAX_PER_BOT_CONCURRENCY,
+};
+
+use axum::{
+    extract::{DefaultBodyLimit, FromRequest, Multipart, Path, Request, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    routing::any,
+    Form, Router,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// How a request matched by a [`FaultRule`] is answered.
+#[derive(Debug, Clone)]
+pub enum Fault {
+    /// HTTP 429 asking the client to wait `retry_after` seconds.
+    RateLimited { retry_after: u64 },
+    /// An HTTP 5xx error such as 502.
+    ServerError(u16),
+    /// HTTP 400 with this description, as Telegram answers invalid requests.
+    BadRequest(String),
+    /// HTTP 401, as for a revoked token.
+    Unauthorized,
+    /// Waits this long, then handles the request normally.
+    Delay(Duration),
+}
+
+impl Fault {
+    /// Applies the fault to requests for `method`, e.g. `"sendDocument"`.
+    pub fn on(self, method: &str) -> FaultRule {
+        FaultRule {
+            fault: self,
+            method: method.to_string(),
+            token: None,
+            remaining: None,
+        }
+    }
+}
+
+/// A fault plus the requests it applies to. Matches every request for its
+/// method unless narrowed with [`FaultRule::for_bot`] or [`FaultRule::times`].
+#[derive(Debug, Clone)]
+pub struct FaultRule {
+    fault: Fault,
+    method: String,
+    token: Option<String>,
+    remaining: Option<u32>,
+}
+
+impl FaultRule {
+    /// Only applies to requests made with `token`.
+    pub fn for_bot(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Only applies to the next `n` matching requests.
+    pub fn times(mut self, n: u32) -> Self {
+        self.remaining = Some(n);
+        self
+    }
+
+    fn matches(&self, token: &str, method: &str) -> bool {
+        self.method == method
+            && self.token.as_deref().is_none_or(|t| t == token)
+            && self.remaining != Some(0)
+    }
+}
+
+/// A document posted to the chat and not deleted since.
+#[derive(Debug, Clone)]
+pub struct FakeMessage {
+    pub message_id: i64,
+    /// Token of the bot that sent it.
+    pub token: String,
+    pub chat_id: String,
+    pub file_id: String,
+    pub file_name: String,
+    pub size: u64,
+    /// Where the document is stored, as `getFile` reports it.
+    pub path: PathBuf,
+}
+
+/// A request the server received.
+#[derive(Debug, Clone)]
+pub struct FakeRequest {
+    pub token: String,
+    pub method: String,
+    /// Status the server answered with.
+    pub status: u16,
+}
+
+#[derive(Default)]
+struct FakeState {
+    dir: PathBuf,
+    chat_id: String,
+    next_message_id: i64,
+    messages: Vec<FakeMessage>,
+    deleted: Vec<FakeMessage>,
+    requests: Vec<FakeRequest>,
+    rules: Vec<FaultRule>,
+}
+
+type Shared = Arc<Mutex<FakeState>>;
+
+/// A fake Bot API server listening on a random local port until dropped.
+pub struct FakeTelegramServer {
+    url: String,
+    dir: PathBuf,
+    state: Shared,
+    stop: CancellationToken,
+}
+
+impl FakeTelegramServer {
+    /// Starts the server with a fresh chat and document directory.
+    pub async fn start() -> Self {
+        let dir = std::env::temp_dir().join(format!("tgcloud-fake-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("cannot create the fake server directory");
+        let state = Arc::new(Mutex::new(FakeState {
+            dir: dir.clone(),
+            chat_id: format!("-100{}", rand::random::<u32>()),
+            next_message_id: 1,
+            ..Default::default()
+        }));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("cannot bind the fake server");
+        let url = format!(
+            "http://{}",
+            listener.local_addr().expect("fake server has no address")
+        );
+        let app = Router::new()
+            .route("/:bot/:method", any(handle))
+            .layer(DefaultBodyLimit::disable())
+            .with_state(Arc::clone(&state));
+        let stop = CancellationToken::new();
+        let shutdown = stop.clone().cancelled_owned();
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown)
+                .await;
+        });
+
+        Self {
+            url,
+            dir,
+            state,
+            stop,
+        }
+    }
+
+    /// Base URL to use as `TELEGRAM_API_URL`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The only chat the server accepts documents for.
+    pub fn chat_id(&self) -> String {
+        self.lock().chat_id.clone()
+    }
+
+    /// A configuration for `bots` that talks to this server, with pacing
+    /// and bandwidth limits off so tests run at full speed.
+    pub fn config(&self, mongo_uri: &str, bots: Vec<BotConfig>) -> Config {
+        Config {
+            mongo_uri: mongo_uri.to_string(),
+            telegram_api_url: self.url.clone(),
+            telegram_chat_id: self.chat_id(),
+            archive_chat_id: None,
+            bots,
+            max_concurrency: DEFAULT_MAX_GLOBAL_CONCURRENCY,
+            max_per_bot_concurrency: DEFAULT_MAX_PER_BOT_CONCURRENCY,
+            shutdown_grace_secs: 0,
+            trash_retention_secs: 0,
+            ledger_retention_secs: crate::config::DEFAULT_LEDGER_RETENTION_SECS,
+            bandwidth_schedule: BandwidthSchedule::default(),
+            metadata_cache_ttl_secs: 0,
+            chat_messages_per_minute: 0,
+            io_buffer_size: crate::config::DEFAULT_IO_BUFFER_SIZE,
+            http: HttpConfig::default(),
+            tls: TlsConfig::default(),
+            local_bot_api: None,
+            hooks: HookConfig::default(),
+            metadata_key: None,
+            metadata_mirror: false,
+            metadata_mirror_interval_secs: 0,
+        }
+    }
+
+    /// Adds a fault. Rules are checked in the order they were added and the
+    /// first matching one applies.
+    pub fn inject(&self, rule: FaultRule) {
+        self.lock().rules.push(rule);
+    }
+
+    /// Removes every fault.
+    pub fn clear_faults(&self) {
+        self.lock().rules.clear();
+    }
+
+    /// Documents currently in the chat, oldest first.
+    pub fn messages(&self) -> Vec<FakeMessage> {
+        self.lock().messages.clone()
+    }
+
+    /// Documents removed with `deleteMessage`, in the order they were deleted.
+    pub fn deleted(&self) -> Vec<FakeMessage> {
+        self.lock().deleted.clone()
+    }
+
+    /// Every request received so far.
+    pub fn requests(&self) -> Vec<FakeRequest> {
+        self.lock().requests.clone()
+    }
+
+    /// Number of requests received for `method`.
+    pub fn count(&self, method: &str) -> usize {
+        self.lock()
+            .requests
+            .iter()
+            .filter(|r| r.method == method)
+            .count()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FakeState> {
+        self.state.lock().expect("fake server state poisoned")
+    }
+}
+
+impl Drop for FakeTelegramServer {
+    fn drop(&mut self) {
+        self.stop.cancel();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Request handling
+// ---------------------------------------------------------------------------
+
+async fn handle(
+    State(state): State<Shared>,
+    Path((bot, method)): Path<(String, String)>,
+    request: Request,
+) -> Response {
+    let Some(token) = bot.strip_prefix("bot").map(str::to_string) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let fault = {
+        let mut state = state.lock().expect("fake server state poisoned");
+        let rule = state
+            .rules
+            .iter_mut()
+            .find(|rule| rule.matches(&token, &method));
+        rule.map(|rule| {
+            if let Some(n) = rule.remaining.as_mut() {
+                *n -= 1;
+            }
+            rule.fault.clone()
+        })
+    };
+    let response = match fault {
+        Some(Fault::Delay(wait)) => {
+            tokio::time::sleep(wait).await;
+            dispatch(&state, &token, &method, request).await
+        }
+        Some(fault) => {
+            // Read the body first, so the client sees the answer rather than
+            // a connection reset in the middle of sending it.
+            let _ = axum::body::to_bytes(request.into_body(), usize::MAX).await;
+            fault_response(&fault)
+        }
+        None => dispatch(&state, &token, &method, request).await,
+    };
+
+    state
+        .lock()
+        .expect("fake server state poisoned")
+        .requests
+        .push(FakeRequest {
+            token,
+            method,
+            status: response.status().as_u16(),
+        });
+    response
+}
+
+async fn dispatch(state: &Shared, token: &str, method: &str, request: Request) -> Response {
+    if method == "sendDocument" {
+        return match Multipart::from_request(request, &()).await {
+            Ok(form) => send_document(state, token, form).await,
+            Err(e) => error(StatusCode::BAD_REQUEST, &e.body_text()),
+        };
+    }
+    let params = match Form::<HashMap<String, String>>::from_request(request, &()).await {
+        Ok(Form(params)) => params,
+        Err(e) => return error(StatusCode::BAD_REQUEST, &e.body_text()),
+    };
+    let param = |name: &str| params.get(name).cloned().unwrap_or_default();
+    let mut state = state.lock().expect("fake server state poisoned");
+
+    match method {
+        "getMe" => ok(json!({
+            "id": token.split(':').next().unwrap_or_default(),
+            "is_bot": true,
+            "first_name": "tgcloud test bot",
+        })),
+        "getChatMember" => {
+            if param("chat_id") != state.chat_id {
+                return error(StatusCode::BAD_REQUEST, "Bad Request: chat not found");
+            }
+            ok(json!({ "status": "administrator" }))
+        }
+        "getFile" => {
+            let file_id = param("file_id");
+            let stored = state
+                .messages
+                .iter()
+                .chain(&state.deleted)
+                .find(|m| m.file_id == file_id);
+            match stored {
+                Some(m) => ok(json!({
+                    "file_id": m.file_id,
+                    "file_size": m.size,
+                    "file_path": m.path.display().to_string(),
+                })),
+                None => error(StatusCode::BAD_REQUEST, "Bad Request: invalid file_id"),
+            }
+        }
+        "deleteMessage" => {
+            let message_id = param("message_id").parse::<i64>().unwrap_or_default();
+            let chat_id = param("chat_id");
+            match state
+                .messages
+                .iter()
+                .position(|m| m.message_id == message_id && m.chat_id == chat_id)
+            {
+                Some(i) => {
+                    let message = state.messages.remove(i);
+                    state.deleted.push(message);
+                    ok(json!(true))
+                }
+                None => error(
+                    StatusCode::BAD_REQUEST,
+                    "Bad Request: message to delete not found",
+                ),
+            }
+        }
+        "forwardMessage" => {
+            let message_id = param("message_id").parse::<i64>().unwrap_or_default();
+            let original = state
+                .messages
+                .iter()
+                .find(|m| m.message_id == message_id && m.chat_id == param("from_chat_id"))
+                .cloned();
+            let Some(original) = original else {
+                return error(
+                    StatusCode::BAD_REQUEST,
+                    "Bad Request: message to forward not found",
+                );
+            };
+            let message_id = state.next_message_id;
+            state.next_message_id += 1;
+            state.messages.push(FakeMessage {
+                message_id,
+                token: token.to_string(),
+                chat_id: param("chat_id"),
+                ..original.clone()
+            });
+            ok(json!({
+                "message_id": message_id,
+                "document": { "file_id": original.file_id, "file_size": original.size },
+            }))
+        }
+        _ => error(StatusCode::NOT_FOUND, "Not Found: method not found"),
+    }
+}
+
+async fn send_document(state: &Shared, token: &str, mut form: Multipart) -> Response {
+    let mut chat_id = String::new();
+    let mut document = None;
+    loop {
+        match form.next_field().await {
+            Ok(Some(field)) => match field.name() {
+                Some("chat_id") => chat_id = field.text().await.unwrap_or_default(),
+                Some("document") => {
+                    let name = field.file_name().unwrap_or("document").to_string();
+                    match field.bytes().await {
+                        Ok(bytes) => document = Some((name, bytes)),
+                        // The client went away mid-upload.
+                        Err(e) => return error(StatusCode::BAD_REQUEST, &e.body_text()),
+                    }
+                }
+                _ => {}
+            },
+            Ok(None) => break,
+            Err(e) => return error(StatusCode::BAD_REQUEST, &e.body_text()),
+        }
+    }
+
+    let mut state = state.lock().expect("fake server state poisoned");
+    if chat_id != state.chat_id {
+        return error(StatusCode::BAD_REQUEST, "Bad Request: chat not found");
+    }
+    let Some((file_name, bytes)) = document else {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "Bad Request: there is no document in the request",
+        );
+    };
+
+    let message_id = state.next_message_id;
+    state.next_message_id += 1;
+    let file_id = format!("fake-file-{}", message_id);
+    let path = state.dir.join(&file_id);
+    if let Err(e) = std::fs::write(&path, &bytes) {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string());
+    }
+    let size = bytes.len() as u64;
+    state.messages.push(FakeMessage {
+        message_id,
+        token: token.to_string(),
+        chat_id,
+        file_id: file_id.clone(),
+        file_name: file_name.clone(),
+        size,
+        path,
+    });
+    ok(json!({
+        "message_id": message_id,
+        "document": { "file_id": file_id, "file_name": file_name, "file_size": size },
+    }))
+}
+
+fn ok(result: Value) -> Response {
+    Json(json!({ "ok": true, "result": result })).into_response()
+}
+
+fn error(status: StatusCode, description: &str) -> Response {
+    let body = json!({
+        "ok": false,
+        "error_code": status.as_u16(),
+        "description": description,
+    });
+    (status, Json(body)).into_response()
+}
+
+fn fault_response(fault: &Fault) -> Response {
+    match fault {
+        Fault::RateLimited { retry_after } => {
+            let body = json!({
+                "ok": false,
+                "error_code": 429,
+                "description": format!("Too Many Requests: retry after {}", retry_after),
+                "parameters": { "retry_after": retry_after },
+            });
+            (StatusCode::TOO_MANY_REQUESTS, Json(body)).into_response()
+        }
+        Fault::ServerError(code) => error(
+            StatusCode::from_u16(*code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+            "Internal Server Error",
+        ),
+        Fault::BadRequest(description) => error(StatusCode::BAD_REQUEST, description),
+        Fault::Unauthorized => error(StatusCode::UNAUTHORIZED, "Unauthorized"),
+        Fault::Delay(_) => unreachable!("delays are handled before dispatching"),
+    }
+}
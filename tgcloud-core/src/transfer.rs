@@ -0,0 +1,488 @@
+use crate::errors::{Result, TgCloudError};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+tokio::task_local! {
+    static TRANSFER_ID: String;
+}
+
+/// Generates the id that identifies a transfer in logs, events, errors and
+/// API responses.
+pub fn new_transfer_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// Id of the transfer the current task works on, if any.
+pub fn current_transfer_id() -> Option<String> {
+    TRANSFER_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Runs `fut` with `id` as the current transfer id, so log lines written while
+/// it runs carry the id.
+pub async fn with_transfer_id<F: Future>(id: String, fut: F) -> F::Output {
+    TRANSFER_ID.scope(id, fut).await
+}
+
+/// Like `tokio::spawn`, but the spawned task keeps the current transfer id.
+pub fn spawn_in_transfer<F>(fut: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match current_transfer_id() {
+        Some(id) => tokio::spawn(TRANSFER_ID.scope(id, fut)),
+        None => tokio::spawn(fut),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferKind {
+    Upload,
+    Download,
+    Send,
+}
+
+/// A transfer currently running through the service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferInfo {
+    pub id: String,
+    pub kind: TransferKind,
+    pub path: String,
+    pub started_at: DateTime<Utc>,
+}
+
+/// Finished transfers kept for the dashboard.
+const RECENT_TRANSFERS: usize = 50;
+/// The current speed of a transfer is measured over at least this long.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferState {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkState {
+    Pending,
+    Running,
+    Done,
+    /// Left by an interrupted upload of the same content and not sent again.
+    Reused,
+    Failed,
+}
+
+/// Where one chunk of a transfer stands.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkStatus {
+    pub index: u32,
+    pub size: u64,
+    pub state: ChunkState,
+    /// Bot sending or fetching the chunk, once one picked it up.
+    pub bot_id: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Average speed of the finished chunk, in bytes per second.
+    pub bytes_per_sec: Option<u64>,
+}
+
+/// A running or recently finished transfer with its progress.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferStatus {
+    #[serde(flatten)]
+    pub info: TransferInfo,
+    pub state: TransferState,
+    pub total_size: u64,
+    pub transferred: u64,
+    /// Current speed while running, average speed once finished.
+    pub bytes_per_sec: u64,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+    pub chunks: Vec<ChunkStatus>,
+}
+
+/// Running transfers, oldest first, and the last finished ones, newest first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferOverview {
+    pub active: Vec<TransferStatus>,
+    pub recent: Vec<TransferStatus>,
+}
+
+#[derive(Default)]
+struct Rate {
+    sampled: Option<(Instant, u64)>,
+    bytes_per_sec: u64,
+}
+
+/// Progress of one transfer, updated by the code moving its chunks.
+#[derive(Default)]
+pub(crate) struct TransferTracker {
+    total_size: AtomicU64,
+    progress: Mutex<Option<Arc<AtomicU64>>>,
+    chunks: Mutex<Vec<ChunkStatus>>,
+    rate: Mutex<Rate>,
+}
+
+impl TransferTracker {
+    /// Sets the size of the transfer, the counter its bytes are reported to
+    /// and its chunks as `(index, size)`, all pending.
+    pub(crate) fn start(
+        &self,
+        total_size: u64,
+        progress: Arc<AtomicU64>,
+        chunks: impl IntoIterator<Item = (u32, u64)>,
+    ) {
+        self.total_size.store(total_size, Ordering::Relaxed);
+        *self.progress.lock().expect("transfer progress poisoned") = Some(progress);
+        *self.chunks.lock().expect("chunk status poisoned") = chunks
+            .into_iter()
+            .map(|(index, size)| ChunkStatus {
+                index,
+                size,
+                state: ChunkState::Pending,
+                bot_id: None,
+                started_at: None,
+                finished_at: None,
+                bytes_per_sec: None,
+            })
+            .collect();
+    }
+
+    pub(crate) fn chunk_started(&self, index: u32, bot_id: &str) {
+        self.update(index, |c| {
+            c.state = ChunkState::Running;
+            c.bot_id = Some(bot_id.to_string());
+            c.started_at = Some(Utc::now());
+            c.finished_at = None;
+        });
+    }
+
+    pub(crate) fn chunk_done(&self, index: u32) {
+        self.update(index, |c| {
+            let now = Utc::now();
+            c.state = ChunkState::Done;
+            c.finished_at = Some(now);
+            c.bytes_per_sec = c.started_at.map(|started| per_sec(c.size, now - started));
+        });
+    }
+
+    pub(crate) fn chunk_reused(&self, index: u32, bot_id: Option<&str>) {
+        self.update(index, |c| {
+            c.state = ChunkState::Reused;
+            c.bot_id = bot_id.map(str::to_string);
+        });
+    }
+
+    /// Puts a chunk back in the queue, e.g. after its bot was rejected.
+    pub(crate) fn chunk_pending(&self, index: u32) {
+        self.update(index, |c| {
+            c.state = ChunkState::Pending;
+            c.started_at = None;
+        });
+    }
+
+    pub(crate) fn chunk_failed(&self, index: u32) {
+        self.update(index, |c| {
+            c.state = ChunkState::Failed;
+            c.finished_at = Some(Utc::now());
+        });
+    }
+
+    fn update(&self, index: u32, f: impl FnOnce(&mut ChunkStatus)) {
+        let mut chunks = self.chunks.lock().expect("chunk status poisoned");
+        if let Some(chunk) = chunks.iter_mut().find(|c| c.index == index) {
+            f(chunk);
+        }
+    }
+
+    fn transferred(&self) -> u64 {
+        self.progress
+            .lock()
+            .expect("transfer progress poisoned")
+            .as_ref()
+            .map_or(0, |p| p.load(Ordering::Relaxed))
+    }
+
+    /// Speed over the last [`RATE_WINDOW`] or more, so frequent polling does
+    /// not turn it into noise.
+    fn current_rate(&self, transferred: u64) -> u64 {
+        let mut rate = self.rate.lock().expect("transfer rate poisoned");
+        let now = Instant::now();
+        match rate.sampled {
+            Some((at, _)) if now - at < RATE_WINDOW => {}
+            Some((at, bytes)) => {
+                rate.bytes_per_sec = per_sec_std(transferred.saturating_sub(bytes), now - at);
+                rate.sampled = Some((now, transferred));
+            }
+            None => rate.sampled = Some((now, transferred)),
+        }
+        rate.bytes_per_sec
+    }
+
+    fn status(&self, info: &TransferInfo, state: TransferState) -> TransferStatus {
+        let transferred = self.transferred();
+        let (bytes_per_sec, finished_at) = match state {
+            TransferState::Running => (self.current_rate(transferred), None),
+            _ => {
+                let now = Utc::now();
+                (per_sec(transferred, now - info.started_at), Some(now))
+            }
+        };
+        TransferStatus {
+            info: info.clone(),
+            state,
+            total_size: self.total_size.load(Ordering::Relaxed),
+            transferred,
+            bytes_per_sec,
+            finished_at,
+            error: None,
+            chunks: self.chunks.lock().expect("chunk status poisoned").clone(),
+        }
+    }
+}
+
+fn per_sec(bytes: u64, elapsed: chrono::Duration) -> u64 {
+    per_sec_std(bytes, elapsed.to_std().unwrap_or_default())
+}
+
+fn per_sec_std(bytes: u64, elapsed: Duration) -> u64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        (bytes as f64 / secs) as u64
+    } else {
+        0
+    }
+}
+
+struct ActiveTransfer {
+    info: TransferInfo,
+    cancel: CancellationToken,
+    tracker: Arc<TransferTracker>,
+}
+
+/// Keeps track of running transfers so the service can cancel them, stop
+/// accepting new work on shutdown and wait for the running ones to finish.
+#[derive(Default)]
+pub struct TransferManager {
+    shutting_down: AtomicBool,
+    active: Mutex<HashMap<String, ActiveTransfer>>,
+    recent: Mutex<VecDeque<TransferStatus>>,
+    idle: Notify,
+}
+
+impl TransferManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a new transfer under `id`. Fails with `ShuttingDown` once
+    /// shutdown began. The transfer is unregistered when the returned guard is
+    /// dropped.
+    pub fn begin(
+        self: &Arc<Self>,
+        id: &str,
+        kind: TransferKind,
+        path: &str,
+    ) -> Result<TransferGuard> {
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return Err(TgCloudError::ShuttingDown);
+        }
+        let info = TransferInfo {
+            id: id.to_string(),
+            kind,
+            path: path.to_string(),
+            started_at: Utc::now(),
+        };
+        let id = info.id.clone();
+        let cancel = CancellationToken::new();
+        let tracker = Arc::new(TransferTracker::default());
+        self.active.lock().expect("transfer list poisoned").insert(
+            id.clone(),
+            ActiveTransfer {
+                info,
+                cancel: cancel.clone(),
+                tracker: Arc::clone(&tracker),
+            },
+        );
+        Ok(TransferGuard {
+            manager: Arc::clone(self),
+            id,
+            cancel,
+            tracker,
+        })
+    }
+
+    /// Snapshot of the running transfers.
+    pub fn active(&self) -> Vec<TransferInfo> {
+        let mut list: Vec<TransferInfo> = self
+            .active
+            .lock()
+            .expect("transfer list poisoned")
+            .values()
+            .map(|t| t.info.clone())
+            .collect();
+        list.sort_by_key(|t| t.started_at);
+        list
+    }
+
+    /// Progress of the running transfers and the last finished ones.
+    pub fn overview(&self) -> TransferOverview {
+        let mut active: Vec<TransferStatus> = self
+            .active
+            .lock()
+            .expect("transfer list poisoned")
+            .values()
+            .map(|t| t.tracker.status(&t.info, TransferState::Running))
+            .collect();
+        active.sort_by_key(|t| t.info.started_at);
+        let recent = self
+            .recent
+            .lock()
+            .expect("recent transfers poisoned")
+            .iter()
+            .cloned()
+            .collect();
+        TransferOverview { active, recent }
+    }
+
+    /// Records how a finished transfer ended. Transfers count as completed
+    /// until this is called.
+    pub fn set_outcome(&self, id: &str, error: Option<&TgCloudError>) {
+        let Some(error) = error else { return };
+        let mut recent = self.recent.lock().expect("recent transfers poisoned");
+        if let Some(t) = recent.iter_mut().find(|t| t.info.id == id) {
+            t.state = match error.root() {
+                TgCloudError::Cancelled => TransferState::Cancelled,
+                _ => TransferState::Failed,
+            };
+            t.error = Some(error.to_string());
+        }
+    }
+
+    /// Cancels a running transfer. Returns `false` if it is not running.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.active.lock().expect("transfer list poisoned").get(id) {
+            Some(t) => {
+                t.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Cancels every running transfer.
+    pub fn cancel_all(&self) {
+        for t in self.active.lock().expect("transfer list poisoned").values() {
+            t.cancel.cancel();
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Stops accepting new transfers.
+    pub fn shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    /// Waits until every running transfer finished or `timeout` elapsed.
+    /// Returns the transfers that are still running.
+    pub async fn drain(&self, timeout: Duration) -> Vec<TransferInfo> {
+        let wait = async {
+            loop {
+                let idle = self.idle.notified();
+                if self
+                    .active
+                    .lock()
+                    .expect("transfer list poisoned")
+                    .is_empty()
+                {
+                    return;
+                }
+                idle.await;
+            }
+        };
+        let _ = tokio::time::timeout(timeout, wait).await;
+        self.active()
+    }
+
+    fn finish(&self, id: &str) {
+        let mut active = self.active.lock().expect("transfer list poisoned");
+        if let Some(t) = active.remove(id) {
+            let status = t.tracker.status(&t.info, TransferState::Completed);
+            let mut recent = self.recent.lock().expect("recent transfers poisoned");
+            recent.push_front(status);
+            recent.truncate(RECENT_TRANSFERS);
+        }
+        if active.is_empty() {
+            self.idle.notify_waiters();
+        }
+    }
+}
+
+/// Unregisters its transfer on drop.
+pub struct TransferGuard {
+    manager: Arc<TransferManager>,
+    id: String,
+    cancel: CancellationToken,
+    tracker: Arc<TransferTracker>,
+}
+
+impl TransferGuard {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Token that is cancelled when the transfer is cancelled.
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Where the transfer reports its progress for [`TransferManager::overview`].
+    pub(crate) fn tracker(&self) -> Arc<TransferTracker> {
+        Arc::clone(&self.tracker)
+    }
+}
+
+impl Drop for TransferGuard {
+    fn drop(&mut self) {
+        self.manager.finish(&self.id);
+    }
+}
+
+/// Writes transfers that did not finish before shutdown to `path` so they can
+/// be inspected and re-run after a restart.
+pub fn save_pending(path: &Path, transfers: &[TransferInfo]) -> Result<()> {
+    let json = serde_json::to_vec_pretty(transfers)
+        .map_err(|e| TgCloudError::Unknown(format!("Failed to encode transfers: {}", e)))?;
+    std::fs::write(path, json)?;
+    Ok(())
+}
+
+/// Reads and removes the transfers saved by a previous shutdown.
+pub fn take_pending(path: &Path) -> Result<Vec<TransferInfo>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let data = std::fs::read(path)?;
+    std::fs::remove_file(path)?;
+    serde_json::from_slice(&data)
+        .map_err(|e| TgCloudError::Unknown(format!("Failed to decode transfers: {}", e)))
+}
@@ -0,0 +1,152 @@
+//! `TelegramClient` against the fake Bot API server: retries, rate limits and
+//! the requests transfers depend on.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use tgcloud_core::testing::{FakeTelegramServer, Fault};
+use tgcloud_core::{TelegramClient, TgCloudError};
+
+const TOKEN: &str = "1001:TEST";
+
+fn client(server: &FakeTelegramServer) -> TelegramClient {
+    TelegramClient::with_client(reqwest::Client::new(), server.url().to_string())
+}
+
+/// Writes `contents` to a fresh file in the temp directory.
+fn temp_file(contents: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("tgcloud-test-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+async fn upload(
+    client: &TelegramClient,
+    chat_id: &str,
+    path: &std::path::Path,
+    on_rate_limited: Arc<dyn Fn() + Send + Sync>,
+) -> tgcloud_core::Result<(String, i64)> {
+    let size = std::fs::metadata(path).unwrap().len();
+    client
+        .upload_part_with_retry(
+            TOKEN,
+            chat_id,
+            "part.bin".to_string(),
+            path.to_str().unwrap(),
+            0,
+            size,
+            Arc::new(AtomicU64::new(0)),
+            on_rate_limited,
+        )
+        .await
+}
+
+#[tokio::test]
+async fn upload_retries_server_errors() {
+    let server = FakeTelegramServer::start().await;
+    server.inject(Fault::ServerError(502).on("sendDocument").times(2));
+    let path = temp_file(b"flaky upstream");
+
+    let (file_id, message_id) = upload(&client(&server), &server.chat_id(), &path, Arc::new(|| {}))
+        .await
+        .expect("upload should succeed on the third attempt");
+
+    assert_eq!(server.count("sendDocument"), 3);
+    let messages = server.messages();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].file_id, file_id);
+    assert_eq!(messages[0].message_id, message_id);
+    assert_eq!(std::fs::read(&messages[0].path).unwrap(), b"flaky upstream");
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn upload_waits_out_rate_limits() {
+    let server = FakeTelegramServer::start().await;
+    server.inject(
+        Fault::RateLimited { retry_after: 1 }
+            .on("sendDocument")
+            .times(1),
+    );
+    let path = temp_file(b"throttled");
+    let limited = Arc::new(AtomicU32::new(0));
+    let counter = Arc::clone(&limited);
+
+    let started = std::time::Instant::now();
+    upload(
+        &client(&server),
+        &server.chat_id(),
+        &path,
+        Arc::new(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }),
+    )
+    .await
+    .expect("upload should succeed after the 429");
+
+    assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+    assert_eq!(limited.load(Ordering::Relaxed), 1);
+    assert_eq!(server.messages().len(), 1);
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn upload_does_not_retry_rejections() {
+    let server = FakeTelegramServer::start().await;
+    server.inject(Fault::BadRequest("Bad Request: file is too big".into()).on("sendDocument"));
+    let path = temp_file(b"too big");
+
+    let err = upload(&client(&server), &server.chat_id(), &path, Arc::new(|| {}))
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, TgCloudError::UploadFailed(_)), "{:?}", err);
+    assert_eq!(server.count("sendDocument"), 1);
+    assert!(server.messages().is_empty());
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn upload_reports_revoked_tokens() {
+    let server = FakeTelegramServer::start().await;
+    server.inject(Fault::Unauthorized.on("sendDocument").for_bot(TOKEN));
+    let path = temp_file(b"revoked");
+
+    let err = upload(&client(&server), &server.chat_id(), &path, Arc::new(|| {}))
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(err, TgCloudError::BotUnauthorized { status: 401, .. }),
+        "{:?}",
+        err
+    );
+    assert_eq!(server.count("sendDocument"), 1);
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn get_file_and_delete_message() {
+    let server = FakeTelegramServer::start().await;
+    let client = client(&server);
+    let chat_id = server.chat_id();
+    let path = temp_file(b"round trip");
+
+    let (file_id, message_id) = upload(&client, &chat_id, &path, Arc::new(|| {}))
+        .await
+        .unwrap();
+    let stored = client.get_local_file_path(TOKEN, &file_id).await.unwrap();
+    assert_eq!(std::fs::read(stored).unwrap(), b"round trip");
+
+    client
+        .delete_message(TOKEN, &chat_id, message_id)
+        .await
+        .unwrap();
+    assert!(server.messages().is_empty());
+    assert_eq!(server.deleted().len(), 1);
+    assert!(client
+        .delete_message(TOKEN, &chat_id, message_id)
+        .await
+        .is_err());
+    let _ = std::fs::remove_file(path);
+}
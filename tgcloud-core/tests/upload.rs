@@ -0,0 +1,341 @@
+//! Whole uploads and downloads through `TgCloudService` against the fake Bot
+//! API server.
+//!
+//! The service keeps its metadata in MongoDB, so these tests only run when
+//! `TGCLOUD_TEST_MONGO_URI` points at a disposable server; locally they pass
+//! without doing anything otherwise, but under CI (`CI` set) a missing URI
+//! fails them. Every test uses its own chat and bot ids, so runs do not see
+//! each other's data.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+use tgcloud_core::testing::{FakeTelegramServer, Fault};
+use tgcloud_core::{BotConfig, DownloadOptions, TgCloudError, TgCloudService, UploadOptions};
+use tokio::sync::mpsc;
+
+const CHUNK_SIZE: u64 = 1024;
+
+fn mongo_uri() -> Option<String> {
+    match std::env::var("TGCLOUD_TEST_MONGO_URI") {
+        Ok(uri) if !uri.trim().is_empty() => Some(uri),
+        _ if std::env::var_os("CI").is_some() => {
+            panic!("TGCLOUD_TEST_MONGO_URI must point at a MongoDB server under CI")
+        }
+        _ => {
+            eprintln!("TGCLOUD_TEST_MONGO_URI is not set, skipping");
+            None
+        }
+    }
+}
+
+/// A bot with a unique id, running one chunk at a time.
+fn bot() -> BotConfig {
+    let bot_id = rand::random::<u32>().to_string();
+    BotConfig {
+        token: format!("{}:TEST", bot_id),
+        bot_id,
+        weight: 1,
+        max_concurrency: Some(1),
+        quota: None,
+        telegram_api_url: None,
+    }
+}
+
+async fn service(server: &FakeTelegramServer, uri: &str, bots: &[BotConfig]) -> TgCloudService {
+    TgCloudService::new(server.config(uri, bots.to_vec()))
+        .await
+        .expect("cannot start the service")
+        .with_chunk_size(CHUNK_SIZE)
+}
+
+/// A file of `chunks` full chunks with distinct contents.
+fn temp_file(chunks: u64) -> (PathBuf, Vec<u8>) {
+    let contents: Vec<u8> = (0..chunks * CHUNK_SIZE).map(|i| (i % 251) as u8).collect();
+    let path = std::env::temp_dir().join(format!("tgcloud-test-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, &contents).unwrap();
+    (path, contents)
+}
+
+fn remote_name() -> String {
+    format!("tests/{}.bin", uuid::Uuid::new_v4())
+}
+
+async fn upload(
+    service: &TgCloudService,
+    path: &std::path::Path,
+    remote: &str,
+) -> tgcloud_core::Result<()> {
+    let (tx, _rx) = mpsc::channel(100);
+    let options = UploadOptions {
+        remote_name: Some(remote.to_string()),
+        ..Default::default()
+    };
+    service
+        .upload_file(path.to_str().unwrap(), options, tx)
+        .await
+}
+
+async fn download(
+    service: &TgCloudService,
+    remote: &str,
+    destination: &std::path::Path,
+) -> tgcloud_core::Result<()> {
+    let (tx, _rx) = mpsc::channel(100);
+    let options = DownloadOptions {
+        destination: Some(destination.to_path_buf()),
+        ..Default::default()
+    };
+    service.download_file(remote, options, tx).await
+}
+
+#[tokio::test]
+async fn chunks_are_spread_over_all_bots() {
+    let Some(uri) = mongo_uri() else { return };
+    let server = FakeTelegramServer::start().await;
+    // Slow enough that one bot cannot take every chunk before the other starts.
+    server.inject(Fault::Delay(Duration::from_millis(50)).on("sendDocument"));
+    let bots = [bot(), bot()];
+    let service = service(&server, &uri, &bots).await;
+    let (path, _) = temp_file(8);
+    let remote = remote_name();
+
+    upload(&service, &path, &remote).await.unwrap();
+
+    let file = service.get_file(&remote).await.unwrap();
+    assert_eq!(file.total_chunks, 8);
+    let used: HashSet<_> = file
+        .chunks
+        .iter()
+        .filter_map(|c| c.bot_id.clone())
+        .collect();
+    assert_eq!(used.len(), 2, "chunks went to {:?}", used);
+    for bot in &bots {
+        assert!(server.messages().iter().any(|m| m.token == bot.token));
+    }
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn rejected_bot_hands_its_chunks_to_the_others() {
+    let Some(uri) = mongo_uri() else { return };
+    let server = FakeTelegramServer::start().await;
+    let bots = [bot(), bot()];
+    server.inject(
+        Fault::Unauthorized
+            .on("sendDocument")
+            .for_bot(&bots[0].token),
+    );
+    let service = service(&server, &uri, &bots).await;
+    let (path, _) = temp_file(4);
+    let remote = remote_name();
+
+    upload(&service, &path, &remote).await.unwrap();
+
+    let file = service.get_file(&remote).await.unwrap();
+    assert!(file
+        .chunks
+        .iter()
+        .all(|c| c.bot_id.as_deref() == Some(bots[1].bot_id.as_str())));
+    assert_eq!(server.messages().len(), 4);
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn flaky_server_still_round_trips() {
+    let Some(uri) = mongo_uri() else { return };
+    let server = FakeTelegramServer::start().await;
+    server.inject(Fault::ServerError(502).on("sendDocument").times(2));
+    server.inject(
+        Fault::RateLimited { retry_after: 1 }
+            .on("sendDocument")
+            .times(1),
+    );
+    let service = service(&server, &uri, &[bot()]).await;
+    let (path, contents) = temp_file(3);
+    let remote = remote_name();
+
+    upload(&service, &path, &remote).await.unwrap();
+    assert_eq!(server.messages().len(), 3);
+
+    let destination = std::env::temp_dir().join(format!("tgcloud-test-{}", uuid::Uuid::new_v4()));
+    let (tx, _rx) = mpsc::channel(100);
+    let options = DownloadOptions {
+        destination: Some(destination.clone()),
+        ..Default::default()
+    };
+    service.download_file(&remote, options, tx).await.unwrap();
+    assert_eq!(std::fs::read(&destination).unwrap(), contents);
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(destination);
+}
+
+#[tokio::test]
+async fn failed_upload_resumes_without_resending_chunks() {
+    let Some(uri) = mongo_uri() else { return };
+    let server = FakeTelegramServer::start().await;
+    server.inject(
+        Fault::BadRequest("Bad Request: wrong file identifier".into())
+            .on("sendDocument")
+            .times(1),
+    );
+    let service = service(&server, &uri, &[bot()]).await;
+    let (path, _) = temp_file(4);
+    let remote = remote_name();
+
+    assert!(upload(&service, &path, &remote).await.is_err());
+    let kept = server.messages().len();
+    let sent = server.count("sendDocument");
+
+    upload(&service, &path, &remote).await.unwrap();
+    assert_eq!(server.count("sendDocument") - sent, 4 - kept);
+    assert_eq!(server.messages().len(), 4);
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn cancelled_upload_is_rolled_back() {
+    let Some(uri) = mongo_uri() else { return };
+    let server = FakeTelegramServer::start().await;
+    let delay = Duration::from_millis(200);
+    server.inject(Fault::Delay(delay).on("sendDocument"));
+    let service = service(&server, &uri, &[bot()]).await;
+    let (path, _) = temp_file(6);
+    let remote = remote_name();
+
+    let (result, _) = tokio::join!(upload(&service, &path, &remote), async {
+        tokio::time::sleep(delay * 3).await;
+        service.cancel_transfers();
+    });
+
+    let err = result.unwrap_err();
+    assert!(matches!(err.root(), TgCloudError::Cancelled), "{:?}", err);
+    assert!(matches!(
+        service.get_file(&remote).await.unwrap_err().root(),
+        TgCloudError::FileNotFound(_)
+    ));
+    // The chunk in flight when the upload was cancelled may still land, as
+    // it would with the real API; everything confirmed before was deleted.
+    tokio::time::sleep(delay).await;
+    assert!(!server.deleted().is_empty());
+    assert!(server.messages().len() <= 1);
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn concurrent_downloads_of_same_named_files_stay_apart() {
+    let Some(uri) = mongo_uri() else { return };
+    let server = FakeTelegramServer::start().await;
+    let service = service(&server, &uri, &[bot()]).await;
+    let (path_a, contents_a) = temp_file(3);
+    let (path_b, _) = temp_file(3);
+    let contents_b: Vec<u8> = contents_a.iter().map(|b| b ^ 0xff).collect();
+    std::fs::write(&path_b, &contents_b).unwrap();
+    let folder = format!("tests/{}", uuid::Uuid::new_v4());
+    let remote_a = format!("{}/a/report.bin", folder);
+    let remote_b = format!("{}/b/report.bin", folder);
+    upload(&service, &path_a, &remote_a).await.unwrap();
+    upload(&service, &path_b, &remote_b).await.unwrap();
+
+    // Both downloads reach the merge at about the same time.
+    server.inject(Fault::Delay(Duration::from_millis(50)).on("getFile"));
+    let dest_a = std::env::temp_dir().join(format!("tgcloud-test-{}", uuid::Uuid::new_v4()));
+    let dest_b = std::env::temp_dir().join(format!("tgcloud-test-{}", uuid::Uuid::new_v4()));
+    let (a, b) = tokio::join!(
+        download(&service, &remote_a, &dest_a),
+        download(&service, &remote_b, &dest_b)
+    );
+    a.unwrap();
+    b.unwrap();
+
+    assert_eq!(std::fs::read(&dest_a).unwrap(), contents_a);
+    assert_eq!(std::fs::read(&dest_b).unwrap(), contents_b);
+    for path in [path_a, path_b, dest_a, dest_b] {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[tokio::test]
+async fn chunked_send_forwards_every_chunk_in_order() {
+    let Some(uri) = mongo_uri() else { return };
+    let server = FakeTelegramServer::start().await;
+    let service = service(&server, &uri, &[bot()]).await;
+    let (path, _) = temp_file(3);
+    let remote = remote_name();
+    upload(&service, &path, &remote).await.unwrap();
+
+    let message_ids = service.send_file(&remote, "@recipient").await.unwrap();
+
+    assert_eq!(message_ids.len(), 3);
+    assert_eq!(server.count("forwardMessage"), 3);
+    let forwarded: Vec<_> = server
+        .messages()
+        .into_iter()
+        .filter(|m| m.chat_id == "@recipient")
+        .collect();
+    let ids: Vec<i64> = forwarded.iter().map(|m| m.message_id).collect();
+    assert_eq!(ids, message_ids);
+    // Chunks are named after the uploaded file.
+    let base = path.file_name().unwrap().to_string_lossy();
+    let names: Vec<String> = forwarded.iter().map(|m| m.file_name.clone()).collect();
+    let expected: Vec<String> = (0..3).map(|i| format!("{}.chunk{}", base, i)).collect();
+    assert_eq!(names, expected);
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn undo_restores_every_file_of_a_bulk_delete() {
+    let Some(uri) = mongo_uri() else { return };
+    let server = FakeTelegramServer::start().await;
+    let service = service(&server, &uri, &[bot()]).await;
+    let (path, _) = temp_file(1);
+    let remotes = [remote_name(), remote_name(), remote_name()];
+    let mut files = Vec::new();
+    for remote in &remotes {
+        upload(&service, &path, remote).await.unwrap();
+        files.push(service.get_file(remote).await.unwrap());
+    }
+
+    for (_, result) in service.delete_files(files).await {
+        result.unwrap();
+    }
+    for remote in &remotes {
+        assert!(service.get_file(remote).await.is_err());
+    }
+
+    let undone = service.undo().await.unwrap();
+    assert_eq!(undone.len(), remotes.len());
+    for remote in &remotes {
+        service.get_file(remote).await.unwrap();
+    }
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn failed_copy_to_the_destination_leaves_no_partial_file() {
+    let Some(uri) = mongo_uri() else { return };
+    let server = FakeTelegramServer::start().await;
+    let service = service(&server, &uri, &[bot()]).await;
+    let (path, _) = temp_file(2);
+    let remote = remote_name();
+    upload(&service, &path, &remote).await.unwrap();
+
+    // A non-empty directory in the way: `force` gets past the existence check,
+    // but the final rename fails.
+    let dest = std::env::temp_dir().join(format!("tgcloud-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir(&dest).unwrap();
+    std::fs::write(dest.join("keep"), b"keep").unwrap();
+    let (tx, _rx) = mpsc::channel(100);
+    let options = DownloadOptions {
+        destination: Some(dest.clone()),
+        force: true,
+        ..Default::default()
+    };
+    assert!(service.download_file(&remote, options, tx).await.is_err());
+
+    let mut partial = dest.clone().into_os_string();
+    partial.push(".partial");
+    assert!(!PathBuf::from(partial).exists());
+    let _ = std::fs::remove_dir_all(dest);
+    let _ = std::fs::remove_file(path);
+}
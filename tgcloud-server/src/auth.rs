@@ -0,0 +1,102 @@
+use crate::web::error_response;
+use axum::extract::{Request, State};
+use axum::http::{header, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Json, Response};
+use base64::Engine;
+use std::sync::Arc;
+use tgcloud_core::{ApiKeyScope, ErrorReport, TgCloudService};
+
+/// What the auth middleware checks credentials against.
+#[derive(Clone)]
+pub struct AuthState {
+    /// `SERVER_AUTH_TOKEN`, which grants everything.
+    pub token: Arc<String>,
+    pub service: Arc<TgCloudService>,
+}
+
+/// Rejects requests that carry neither the configured token nor an API key
+/// whose scope covers the request. Either is accepted as a bearer token
+/// (scripts, CLIs) or as the Basic auth password (browsers, which then show
+/// a login prompt; the user name is ignored).
+pub async fn require_auth(State(auth): State<AuthState>, request: Request, next: Next) -> Response {
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(credential);
+    let Some(presented) = presented else {
+        return unauthorized();
+    };
+    if constant_time_eq(presented.as_bytes(), auth.token.as_bytes()) {
+        return next.run(request).await;
+    }
+
+    match auth.service.authenticate_api_key(&presented).await {
+        Ok(Some(key)) => {
+            let needed = required_scope(request.method(), request.uri().path());
+            if key.scope >= needed {
+                next.run(request).await
+            } else {
+                (
+                    StatusCode::FORBIDDEN,
+                    Json(ErrorReport::new(
+                        "forbidden",
+                        format!(
+                            "API key '{}' is {}, this request needs {}",
+                            key.name, key.scope, needed
+                        ),
+                    )),
+                )
+                    .into_response()
+            }
+        }
+        Ok(None) => unauthorized(),
+        Err(e) => error_response(&e),
+    }
+}
+
+/// Scope an API key needs for a request.
+fn required_scope(method: &Method, path: &str) -> ApiKeyScope {
+    if path == "/api/tus" || path.starts_with("/api/tus/") || path.starts_with("/api/v1/upload/") {
+        ApiKeyScope::Upload
+    } else if method == Method::GET || method == Method::HEAD || path == "/api/download" {
+        ApiKeyScope::ReadOnly
+    } else {
+        ApiKeyScope::Admin
+    }
+}
+
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Basic realm=\"tgcloud\"")],
+        Json(ErrorReport::new(
+            "unauthorized",
+            "Missing or wrong token or API key",
+        )),
+    )
+        .into_response()
+}
+
+/// Extracts the secret from a `Bearer` or `Basic` authorization header.
+fn credential(value: &str) -> Option<String> {
+    let (scheme, rest) = value.split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return Some(rest.trim().to_string());
+    }
+    if scheme.eq_ignore_ascii_case("basic") {
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(rest.trim())
+            .ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        return decoded
+            .split_once(':')
+            .map(|(_, password)| password.to_string());
+    }
+    None
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
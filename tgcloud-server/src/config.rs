@@ -0,0 +1,101 @@
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tgcloud_core::ConfigError;
+
+/// Default address the server listens on.
+pub const DEFAULT_BIND: &str = "127.0.0.1:8090";
+/// Default number of uploads and downloads the server runs at once.
+pub const DEFAULT_MAX_TRANSFERS: usize = 4;
+
+/// Settings of the HTTP server itself, on top of the core [`tgcloud_core::Config`].
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub bind: SocketAddr,
+    /// PEM certificate chain and private key; both set enables HTTPS.
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// Token clients must present as `Authorization: Bearer <token>` or as
+    /// the password of HTTP Basic auth. `None` leaves the server open.
+    pub auth_token: Option<String>,
+    /// Tokio worker threads; `None` uses one per CPU core.
+    pub worker_threads: Option<usize>,
+    /// Transfers started through the API that may run at the same time;
+    /// further ones wait for a free slot.
+    pub max_transfers: usize,
+    /// Where resumable uploads are kept until complete; `None` uses
+    /// `uploads` in the tgcloud config directory.
+    pub upload_dir: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: DEFAULT_BIND.parse().expect("valid default bind address"),
+            tls_cert: None,
+            tls_key: None,
+            auth_token: None,
+            worker_threads: None,
+            max_transfers: DEFAULT_MAX_TRANSFERS,
+            upload_dir: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Reads `SERVER_*` variables. Call after [`tgcloud_core::Config::from_env`],
+    /// which loads `~/.config/tgcloud/.env`.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let defaults = Self::default();
+
+        let bind = match env::var("SERVER_BIND") {
+            Ok(v) => v.trim().parse().map_err(|_| {
+                ConfigError::General("SERVER_BIND must be an address like 0.0.0.0:8090".into())
+            })?,
+            Err(_) => defaults.bind,
+        };
+
+        let tls_cert = env_nonempty("SERVER_TLS_CERT").map(PathBuf::from);
+        let tls_key = env_nonempty("SERVER_TLS_KEY").map(PathBuf::from);
+        if tls_cert.is_some() != tls_key.is_some() {
+            return Err(ConfigError::General(
+                "SERVER_TLS_CERT and SERVER_TLS_KEY must be set together".into(),
+            ));
+        }
+
+        let worker_threads = match env_nonempty("SERVER_WORKER_THREADS") {
+            Some(v) => Some(v.parse().ok().filter(|&n: &usize| n > 0).ok_or_else(|| {
+                ConfigError::General("SERVER_WORKER_THREADS must be a positive number".into())
+            })?),
+            None => None,
+        };
+
+        let max_transfers = match env_nonempty("SERVER_MAX_TRANSFERS") {
+            Some(v) => v.parse().ok().filter(|&n: &usize| n > 0).ok_or_else(|| {
+                ConfigError::General("SERVER_MAX_TRANSFERS must be a positive number".into())
+            })?,
+            None => defaults.max_transfers,
+        };
+
+        Ok(Self {
+            bind,
+            tls_cert,
+            tls_key,
+            auth_token: env_nonempty("SERVER_AUTH_TOKEN"),
+            worker_threads,
+            max_transfers,
+            upload_dir: env_nonempty("SERVER_UPLOAD_DIR").map(PathBuf::from),
+        })
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.tls_cert.is_some() && self.tls_key.is_some()
+    }
+}
+
+fn env_nonempty(name: &str) -> Option<String> {
+    env::var(name)
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
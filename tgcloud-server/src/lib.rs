@@ -0,0 +1,12 @@
+//! The tgcloud HTTP server: the web UI and JSON API on top of
+//! [`tgcloud_core::TgCloudService`], usable as the `tgcloud-server` daemon or
+//! embedded by `tgcloud --gui`.
+
+mod auth;
+pub mod config;
+mod tus;
+mod upload_api;
+mod web;
+
+pub use config::ServerConfig;
+pub use web::start_server;
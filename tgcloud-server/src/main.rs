@@ -0,0 +1,23 @@
+use anyhow::Context;
+use std::sync::Arc;
+use tgcloud_core::{Config, TgCloudService};
+use tgcloud_server::ServerConfig;
+
+fn main() -> anyhow::Result<()> {
+    tgcloud_core::init_logger("info");
+
+    let config = Config::from_env().map_err(|e| anyhow::anyhow!(e.to_string()))?;
+    let server_config = ServerConfig::from_env().map_err(|e| anyhow::anyhow!(e.to_string()))?;
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(threads) = server_config.worker_threads {
+        runtime.worker_threads(threads);
+    }
+    runtime.build()?.block_on(async {
+        let service = TgCloudService::new(config)
+            .await
+            .context("Failed to initialize service")?;
+        tgcloud_server::start_server(Arc::new(service), server_config).await
+    })
+}
@@ -0,0 +1,597 @@
+//! Resumable uploads over the tus 1.0.0 protocol (<https://tus.io>), with the
+//! creation, termination and expiration extensions. Bytes are appended to a
+//! file under the upload directory; once all have arrived the file is
+//! uploaded to Telegram like any other and removed.
+
+use crate::web::{error_response, WebState};
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+    routing::options,
+    Router,
+};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tgcloud_core::{ErrorReport, TgCloudError, UploadOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,termination,expiration";
+/// How long an unfinished upload is kept after its last byte arrived.
+const UPLOAD_EXPIRY: chrono::Duration = chrono::Duration::hours(24);
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+/// Response header carrying the id of the transfer started by the last PATCH.
+const TRANSFER_ID_HEADER: &str = "Tgcloud-Transfer-Id";
+
+/// What is known about an upload besides its bytes, stored next to them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct UploadInfo {
+    pub(crate) length: u64,
+    /// Remote name, from the `filename` (or `name`) metadata entry.
+    pub(crate) filename: String,
+    /// The raw `Upload-Metadata` header, returned by HEAD.
+    metadata: String,
+    created_at: DateTime<Utc>,
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+    /// Set once `/api/v1/upload/complete` started the Telegram upload.
+    #[serde(default)]
+    pub(crate) transfer_id: Option<String>,
+}
+
+/// Upload directory plus the uploads a request is currently writing to.
+pub struct TusUploads {
+    dir: PathBuf,
+    busy: Mutex<HashSet<String>>,
+}
+
+/// Marks an upload as being written to until dropped.
+pub(crate) struct BusyGuard<'a> {
+    uploads: &'a TusUploads,
+    id: String,
+}
+
+impl Drop for BusyGuard<'_> {
+    fn drop(&mut self) {
+        self.uploads
+            .busy
+            .lock()
+            .expect("tus busy set poisoned")
+            .remove(&self.id);
+    }
+}
+
+impl TusUploads {
+    pub fn new(dir: PathBuf) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            busy: Mutex::new(HashSet::new()),
+        })
+    }
+
+    fn data_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.part", id))
+    }
+
+    fn info_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    pub(crate) fn try_lock(&self, id: &str) -> Option<BusyGuard<'_>> {
+        let mut busy = self.busy.lock().expect("tus busy set poisoned");
+        busy.insert(id.to_string()).then(|| BusyGuard {
+            uploads: self,
+            id: id.to_string(),
+        })
+    }
+
+    pub(crate) async fn info(&self, id: &str) -> std::io::Result<Option<UploadInfo>> {
+        // Ids are generated by us; anything else cannot name an upload.
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Ok(None);
+        }
+        match tokio::fs::read(self.info_path(id)).await {
+            Ok(json) => Ok(serde_json::from_slice(&json).ok()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub(crate) async fn save_info(&self, id: &str, info: &UploadInfo) -> std::io::Result<()> {
+        let json = serde_json::to_vec(info).map_err(std::io::Error::other)?;
+        tokio::fs::write(self.info_path(id), json).await
+    }
+
+    /// Bytes received so far; the file on disk is the source of truth.
+    pub(crate) async fn offset(&self, id: &str) -> std::io::Result<u64> {
+        match tokio::fs::metadata(self.data_path(id)).await {
+            Ok(meta) => Ok(meta.len()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn remove(&self, id: &str) {
+        let _ = tokio::fs::remove_file(self.data_path(id)).await;
+        let _ = tokio::fs::remove_file(self.info_path(id)).await;
+    }
+
+    /// Registers a new, empty upload and returns its id.
+    pub(crate) async fn create(
+        &self,
+        filename: String,
+        length: u64,
+        metadata: String,
+    ) -> std::io::Result<(String, UploadInfo)> {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let now = Utc::now();
+        let info = UploadInfo {
+            length,
+            filename,
+            metadata,
+            created_at: now,
+            expires_at: Some(now + UPLOAD_EXPIRY),
+            transfer_id: None,
+        };
+        let created = async {
+            tokio::fs::File::create(self.data_path(&id)).await?;
+            self.save_info(&id, &info).await
+        };
+        if let Err(e) = created.await {
+            self.remove(&id).await;
+            return Err(e);
+        }
+        Ok((id, info))
+    }
+
+    /// Appends `body` to an upload that has `received` bytes, after dropping
+    /// its first `skip` bytes. Whatever arrives before the connection drops
+    /// is kept, so the client can continue from there. Stops without writing
+    /// the piece that would take the upload past its length.
+    pub(crate) async fn append(
+        &self,
+        id: &str,
+        info: &UploadInfo,
+        received: u64,
+        mut skip: u64,
+        body: Body,
+    ) -> std::io::Result<Appended> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(self.data_path(id))
+            .await?;
+        let mut received = received;
+        let mut stream = body.into_data_stream();
+        let mut too_long = false;
+        while let Some(piece) = stream.next().await {
+            let Ok(mut piece) = piece else {
+                log::debug!("Upload {} interrupted at {} bytes", id, received);
+                break;
+            };
+            if skip > 0 {
+                let skipped = skip.min(piece.len() as u64);
+                let _ = piece.split_to(skipped as usize);
+                skip -= skipped;
+            }
+            if received + piece.len() as u64 > info.length {
+                too_long = true;
+                break;
+            }
+            file.write_all(&piece).await?;
+            received += piece.len() as u64;
+        }
+        file.flush().await?;
+        Ok(Appended { received, too_long })
+    }
+
+    /// Pushes the expiry of an unfinished upload back after activity.
+    pub(crate) async fn extend_expiry(&self, id: &str, info: &mut UploadInfo) {
+        info.expires_at = Some(Utc::now() + UPLOAD_EXPIRY);
+        if let Err(e) = self.save_info(id, info).await {
+            log::warn!("Could not extend the expiry of upload {}: {}", id, e);
+        }
+    }
+
+    /// Ids of every upload in the directory.
+    fn ids(&self) -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let name = e.file_name().to_string_lossy().to_string();
+                name.strip_suffix(".json").map(str::to_string)
+            })
+            .collect()
+    }
+}
+
+/// Outcome of [`TusUploads::append`].
+pub(crate) struct Appended {
+    /// Bytes of the upload on disk afterwards.
+    pub(crate) received: u64,
+    /// The body went past the upload's length; the rest was not written.
+    pub(crate) too_long: bool,
+}
+
+pub fn routes() -> Router<WebState> {
+    Router::new()
+        .route("/api/tus", options(options_handler).post(create_handler))
+        .route(
+            "/api/tus/:id",
+            axum::routing::head(head_handler)
+                .patch(patch_handler)
+                .delete(delete_handler),
+        )
+}
+
+/// Resumes uploads whose bytes all arrived before the last shutdown, then
+/// removes expired ones every hour.
+pub fn spawn_maintenance(state: WebState) {
+    tokio::spawn(async move {
+        for id in state.tus.ids() {
+            let Ok(Some(info)) = state.tus.info(&id).await else {
+                continue;
+            };
+            if state.tus.offset(&id).await.ok() == Some(info.length) {
+                let transfer_id = start_transfer(&state, &id, &info);
+                log::info!(
+                    "Resuming upload of {} received before the last shutdown [transfer {}]",
+                    info.filename,
+                    transfer_id
+                );
+            }
+        }
+
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            for id in state.tus.ids() {
+                let Some(_busy) = state.tus.try_lock(&id) else {
+                    continue;
+                };
+                let expired = match state.tus.info(&id).await {
+                    // Complete uploads belong to a running transfer.
+                    Ok(Some(info)) if state.tus.offset(&id).await.ok() == Some(info.length) => {
+                        false
+                    }
+                    Ok(Some(info)) => info.expires_at.is_some_and(|at| at < Utc::now()),
+                    Ok(None) => true,
+                    Err(_) => false,
+                };
+                if expired {
+                    log::info!("Removing expired upload {}", id);
+                    state.tus.remove(&id).await;
+                }
+            }
+        }
+    });
+}
+
+fn tus_response(status: StatusCode) -> Response {
+    let mut response = status.into_response();
+    response
+        .headers_mut()
+        .insert("Tus-Resumable", HeaderValue::from_static(TUS_VERSION));
+    response
+}
+
+fn tus_error(status: StatusCode, code: &'static str, message: impl Into<String>) -> Response {
+    let mut response = (status, Json(ErrorReport::new(code, message))).into_response();
+    response
+        .headers_mut()
+        .insert("Tus-Resumable", HeaderValue::from_static(TUS_VERSION));
+    response
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// The rejection of a request speaking another protocol version.
+fn version_mismatch(headers: &HeaderMap) -> Option<Response> {
+    match header_str(headers, "Tus-Resumable") {
+        Some(TUS_VERSION) => None,
+        _ => {
+            let mut response = tus_error(
+                StatusCode::PRECONDITION_FAILED,
+                "tus_version",
+                format!("Only tus {} is supported", TUS_VERSION),
+            );
+            response
+                .headers_mut()
+                .insert("Tus-Version", HeaderValue::from_static(TUS_VERSION));
+            Some(response)
+        }
+    }
+}
+
+fn set_header(response: &mut Response, name: &'static str, value: impl ToString) {
+    if let Ok(value) = HeaderValue::from_str(&value.to_string()) {
+        response.headers_mut().insert(name, value);
+    }
+}
+
+/// Formats a time as an HTTP date, as `Upload-Expires` requires.
+fn http_date(at: DateTime<Utc>) -> String {
+    at.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Parses `Upload-Metadata`: comma-separated `key base64value` pairs.
+fn metadata_value(metadata: &str, key: &str) -> Option<String> {
+    metadata.split(',').find_map(|pair| {
+        let mut parts = pair.trim().splitn(2, ' ');
+        if parts.next()? != key {
+            return None;
+        }
+        let value = base64::engine::general_purpose::STANDARD
+            .decode(parts.next().unwrap_or("").trim())
+            .ok()?;
+        String::from_utf8(value).ok()
+    })
+}
+
+async fn options_handler() -> Response {
+    let mut response = tus_response(StatusCode::NO_CONTENT);
+    set_header(&mut response, "Tus-Version", TUS_VERSION);
+    set_header(&mut response, "Tus-Extension", TUS_EXTENSIONS);
+    response
+}
+
+async fn create_handler(State(state): State<WebState>, headers: HeaderMap) -> Response {
+    if let Some(response) = version_mismatch(&headers) {
+        return response;
+    }
+    if state.service.is_shutting_down() {
+        return error_response(&TgCloudError::ShuttingDown);
+    }
+
+    let Some(length) = header_str(&headers, "Upload-Length").and_then(|v| v.parse::<u64>().ok())
+    else {
+        return tus_error(
+            StatusCode::BAD_REQUEST,
+            "bad_request",
+            "Upload-Length is required",
+        );
+    };
+    let metadata = header_str(&headers, "Upload-Metadata")
+        .unwrap_or_default()
+        .to_string();
+    let Some(filename) = metadata_value(&metadata, "filename")
+        .or_else(|| metadata_value(&metadata, "name"))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+    else {
+        return tus_error(
+            StatusCode::BAD_REQUEST,
+            "bad_request",
+            "Upload-Metadata must contain a filename",
+        );
+    };
+
+    let (id, info) = match state.tus.create(filename, length, metadata).await {
+        Ok(created) => created,
+        Err(e) => return error_response(&TgCloudError::IoError(e)),
+    };
+
+    let mut response = tus_response(StatusCode::CREATED);
+    set_header(&mut response, "Location", format!("/api/tus/{}", id));
+    if let Some(expires) = info.expires_at {
+        set_header(&mut response, "Upload-Expires", http_date(expires));
+    }
+    // Nothing to wait for with an empty file.
+    if length == 0 {
+        let transfer_id = start_transfer(&state, &id, &info);
+        set_header(&mut response, TRANSFER_ID_HEADER, transfer_id);
+    }
+    response
+}
+
+async fn head_handler(
+    State(state): State<WebState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(response) = version_mismatch(&headers) {
+        return response;
+    }
+    let info = match state.tus.info(&id).await {
+        Ok(Some(info)) => info,
+        Ok(None) => return tus_response(StatusCode::NOT_FOUND),
+        Err(e) => return error_response(&TgCloudError::IoError(e)),
+    };
+    let offset = match state.tus.offset(&id).await {
+        Ok(offset) => offset,
+        Err(e) => return error_response(&TgCloudError::IoError(e)),
+    };
+
+    let mut response = tus_response(StatusCode::OK);
+    set_header(&mut response, "Upload-Offset", offset);
+    set_header(&mut response, "Upload-Length", info.length);
+    if !info.metadata.is_empty() {
+        set_header(&mut response, "Upload-Metadata", &info.metadata);
+    }
+    if let Some(expires) = info.expires_at {
+        set_header(&mut response, "Upload-Expires", http_date(expires));
+    }
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+async fn patch_handler(
+    State(state): State<WebState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Response {
+    if let Some(response) = version_mismatch(&headers) {
+        return response;
+    }
+    if header_str(&headers, header::CONTENT_TYPE.as_str())
+        != Some("application/offset+octet-stream")
+    {
+        return tus_error(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "bad_request",
+            "Content-Type must be application/offset+octet-stream",
+        );
+    }
+    let Some(offset) = header_str(&headers, "Upload-Offset").and_then(|v| v.parse::<u64>().ok())
+    else {
+        return tus_error(
+            StatusCode::BAD_REQUEST,
+            "bad_request",
+            "Upload-Offset is required",
+        );
+    };
+
+    // A client resuming after a dropped connection may arrive before the
+    // old request noticed; it is told to try again shortly.
+    let Some(_busy) = state.tus.try_lock(&id) else {
+        return tus_error(
+            StatusCode::LOCKED,
+            "upload_locked",
+            "Another request is writing to this upload",
+        );
+    };
+    let mut info = match state.tus.info(&id).await {
+        Ok(Some(info)) => info,
+        Ok(None) => return tus_response(StatusCode::NOT_FOUND),
+        Err(e) => return error_response(&TgCloudError::IoError(e)),
+    };
+    let current = match state.tus.offset(&id).await {
+        Ok(current) => current,
+        Err(e) => return error_response(&TgCloudError::IoError(e)),
+    };
+    if offset != current {
+        let mut response = tus_error(
+            StatusCode::CONFLICT,
+            "offset_mismatch",
+            format!(
+                "Upload-Offset is {} but {} bytes were received",
+                offset, current
+            ),
+        );
+        set_header(&mut response, "Upload-Offset", current);
+        return response;
+    }
+    if current == info.length {
+        let mut response = tus_response(StatusCode::NO_CONTENT);
+        set_header(&mut response, "Upload-Offset", current);
+        return response;
+    }
+
+    let Appended { received, too_long } = match state.tus.append(&id, &info, current, 0, body).await
+    {
+        Ok(appended) => appended,
+        Err(e) => return error_response(&TgCloudError::IoError(e)),
+    };
+
+    if too_long {
+        let mut response = tus_error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "bad_request",
+            format!("Upload-Length is {} bytes", info.length),
+        );
+        set_header(&mut response, "Upload-Offset", received);
+        return response;
+    }
+
+    let mut response = tus_response(StatusCode::NO_CONTENT);
+    set_header(&mut response, "Upload-Offset", received);
+    if received == info.length {
+        if state.service.is_shutting_down() {
+            // Picked up by `spawn_maintenance` on the next start.
+            return response;
+        }
+        let transfer_id = start_transfer(&state, &id, &info);
+        set_header(&mut response, TRANSFER_ID_HEADER, transfer_id);
+    } else {
+        state.tus.extend_expiry(&id, &mut info).await;
+        if let Some(expires) = info.expires_at {
+            set_header(&mut response, "Upload-Expires", http_date(expires));
+        }
+    }
+    response
+}
+
+async fn delete_handler(
+    State(state): State<WebState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(response) = version_mismatch(&headers) {
+        return response;
+    }
+    let Some(_busy) = state.tus.try_lock(&id) else {
+        return tus_error(
+            StatusCode::LOCKED,
+            "upload_locked",
+            "Another request is writing to this upload",
+        );
+    };
+    match state.tus.info(&id).await {
+        Ok(Some(_)) => {
+            state.tus.remove(&id).await;
+            tus_response(StatusCode::NO_CONTENT)
+        }
+        Ok(None) => tus_response(StatusCode::NOT_FOUND),
+        Err(e) => error_response(&TgCloudError::IoError(e)),
+    }
+}
+
+/// Uploads a completely received file to Telegram in the background and
+/// removes it afterwards. Returns the transfer id.
+fn start_transfer(state: &WebState, id: &str, info: &UploadInfo) -> String {
+    let transfer_id = tgcloud_core::new_transfer_id();
+    start_transfer_as(state, id, info, transfer_id.clone());
+    transfer_id
+}
+
+/// [`start_transfer`] under a transfer id chosen by the caller.
+pub(crate) fn start_transfer_as(
+    state: &WebState,
+    id: &str,
+    info: &UploadInfo,
+    transfer_id: String,
+) {
+    let (tx, _rx) = mpsc::channel(100);
+    let service = Arc::clone(&state.service);
+    let slots = Arc::clone(&state.transfer_slots);
+    let uploads = Arc::clone(&state.tus);
+    let id = id.to_string();
+    let remote_name = info.filename.clone();
+    let options = UploadOptions {
+        remote_name: Some(remote_name.clone()),
+        transfer_id: Some(transfer_id.clone()),
+        ..Default::default()
+    };
+
+    tokio::spawn(tgcloud_core::with_transfer_id(transfer_id, async move {
+        let Ok(_slot) = slots.acquire_owned().await else {
+            return;
+        };
+        let path = uploads.data_path(&id).to_string_lossy().to_string();
+        if let Err(e) = service.upload_file(&path, options, tx).await {
+            // Interrupted by a shutdown: resumed on the next start.
+            if service.is_shutting_down() {
+                return;
+            }
+            log::warn!("Upload of {} failed: {}", remote_name, e);
+        }
+        uploads.remove(&id).await;
+    }));
+}
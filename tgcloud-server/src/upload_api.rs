@@ -0,0 +1,220 @@
+//! Piecewise uploads for the browser: `init` registers a file, `chunk` appends
+//! a piece at an offset and `complete` hands the file to the uploader. Pieces
+//! are staged in the same directory as tus uploads, so a failed piece can be
+//! sent again without restarting the file.
+
+use crate::tus::{start_transfer_as, Appended};
+use crate::web::{error_response, WebState};
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    routing::post,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use tgcloud_core::{ErrorReport, TgCloudError};
+
+pub fn routes() -> Router<WebState> {
+    Router::new()
+        .route("/api/v1/upload/init", post(init_handler))
+        .route("/api/v1/upload/chunk", post(chunk_handler))
+        .route("/api/v1/upload/complete", post(complete_handler))
+}
+
+#[derive(Deserialize)]
+struct InitRequest {
+    filename: String,
+    size: u64,
+}
+
+#[derive(Deserialize)]
+struct ChunkQuery {
+    upload_id: String,
+    offset: u64,
+}
+
+#[derive(Deserialize)]
+struct CompleteRequest {
+    upload_id: String,
+}
+
+/// How much of an upload has arrived; also the body of a 409, telling the
+/// client where to continue.
+#[derive(Serialize)]
+struct UploadProgress {
+    upload_id: String,
+    received: u64,
+    size: u64,
+}
+
+/// Body of the 202 returned once the Telegram upload is started.
+#[derive(Serialize)]
+struct UploadCompleted {
+    upload_id: String,
+    transfer_id: String,
+}
+
+fn bad_request(status: StatusCode, code: &'static str, message: impl Into<String>) -> Response {
+    (status, Json(ErrorReport::new(code, message))).into_response()
+}
+
+fn unknown_upload(id: &str) -> Response {
+    bad_request(
+        StatusCode::NOT_FOUND,
+        "not_found",
+        format!("No upload {}", id),
+    )
+}
+
+fn upload_locked() -> Response {
+    bad_request(
+        StatusCode::LOCKED,
+        "upload_locked",
+        "Another request is writing to this upload",
+    )
+}
+
+async fn init_handler(State(state): State<WebState>, Json(payload): Json<InitRequest>) -> Response {
+    if state.service.is_shutting_down() {
+        return error_response(&TgCloudError::ShuttingDown);
+    }
+    let filename = payload.filename.trim().to_string();
+    if filename.is_empty() {
+        return bad_request(StatusCode::BAD_REQUEST, "bad_request", "filename is empty");
+    }
+    match state
+        .tus
+        .create(filename, payload.size, String::new())
+        .await
+    {
+        Ok((upload_id, info)) => (
+            StatusCode::CREATED,
+            Json(UploadProgress {
+                upload_id,
+                received: 0,
+                size: info.length,
+            }),
+        )
+            .into_response(),
+        Err(e) => error_response(&TgCloudError::IoError(e)),
+    }
+}
+
+/// Appends the body at `offset`. A piece that was already received, fully or
+/// in part, is accepted again so the client can retry blindly after an error.
+async fn chunk_handler(
+    State(state): State<WebState>,
+    Query(query): Query<ChunkQuery>,
+    body: Body,
+) -> Response {
+    let id = query.upload_id;
+    let Some(_busy) = state.tus.try_lock(&id) else {
+        return upload_locked();
+    };
+    let mut info = match state.tus.info(&id).await {
+        Ok(Some(info)) => info,
+        Ok(None) => return unknown_upload(&id),
+        Err(e) => return error_response(&TgCloudError::IoError(e)),
+    };
+    if info.transfer_id.is_some() {
+        return bad_request(
+            StatusCode::CONFLICT,
+            "already_completed",
+            format!("Upload {} is already complete", id),
+        );
+    }
+    let current = match state.tus.offset(&id).await {
+        Ok(current) => current,
+        Err(e) => return error_response(&TgCloudError::IoError(e)),
+    };
+    let size = info.length;
+    let progress = |received| UploadProgress {
+        upload_id: id.clone(),
+        received,
+        size,
+    };
+    if query.offset > current {
+        return (StatusCode::CONFLICT, Json(progress(current))).into_response();
+    }
+
+    let skip = current - query.offset;
+    let Appended { received, too_long } =
+        match state.tus.append(&id, &info, current, skip, body).await {
+            Ok(appended) => appended,
+            Err(e) => return error_response(&TgCloudError::IoError(e)),
+        };
+    if too_long {
+        return bad_request(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "bad_request",
+            format!("The upload is {} bytes", info.length),
+        );
+    }
+    if received < info.length {
+        state.tus.extend_expiry(&id, &mut info).await;
+    }
+    Json(progress(received)).into_response()
+}
+
+/// Starts the Telegram upload of a fully received file. Calling it again
+/// returns the same transfer id.
+async fn complete_handler(
+    State(state): State<WebState>,
+    Json(payload): Json<CompleteRequest>,
+) -> Response {
+    let id = payload.upload_id;
+    let Some(_busy) = state.tus.try_lock(&id) else {
+        return upload_locked();
+    };
+    let mut info = match state.tus.info(&id).await {
+        Ok(Some(info)) => info,
+        Ok(None) => return unknown_upload(&id),
+        Err(e) => return error_response(&TgCloudError::IoError(e)),
+    };
+    if let Some(transfer_id) = info.transfer_id {
+        return (
+            StatusCode::ACCEPTED,
+            Json(UploadCompleted {
+                upload_id: id,
+                transfer_id,
+            }),
+        )
+            .into_response();
+    }
+    let received = match state.tus.offset(&id).await {
+        Ok(received) => received,
+        Err(e) => return error_response(&TgCloudError::IoError(e)),
+    };
+    if received != info.length {
+        return (
+            StatusCode::CONFLICT,
+            Json(UploadProgress {
+                upload_id: id,
+                received,
+                size: info.length,
+            }),
+        )
+            .into_response();
+    }
+    if state.service.is_shutting_down() {
+        return error_response(&TgCloudError::ShuttingDown);
+    }
+
+    // Recorded before the transfer starts, which removes the upload when done.
+    let transfer_id = tgcloud_core::new_transfer_id();
+    info.transfer_id = Some(transfer_id.clone());
+    if let Err(e) = state.tus.save_info(&id, &info).await {
+        return error_response(&TgCloudError::IoError(e));
+    }
+    start_transfer_as(&state, &id, &info, transfer_id.clone());
+    (
+        StatusCode::ACCEPTED,
+        Json(UploadCompleted {
+            upload_id: id,
+            transfer_id,
+        }),
+    )
+        .into_response()
+}
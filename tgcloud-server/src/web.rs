@@ -0,0 +1,460 @@
+use crate::auth;
+use crate::config::ServerConfig;
+use crate::tus::{self, TusUploads};
+use crate::upload_api;
+use askama::Template;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    middleware,
+    response::{Html, IntoResponse, Json, Response},
+    routing::{delete, get, post},
+    Router,
+};
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tgcloud_core::{
+    DownloadOptions, ErrorReport, FileFilter, FileMetadata, SearchMode, TgCloudError,
+    TgCloudService,
+};
+use tokio::sync::{mpsc, Semaphore};
+use tower_http::cors::CorsLayer;
+
+#[derive(Clone)]
+pub struct WebState {
+    pub service: Arc<TgCloudService>,
+    /// Limits how many transfers started through the API run at once.
+    pub transfer_slots: Arc<Semaphore>,
+    /// Resumable uploads in progress.
+    pub tus: Arc<TusUploads>,
+}
+
+#[derive(Serialize)]
+struct FileInfo {
+    file_id: String,
+    original_name: String,
+    size: String,
+    created_at: String,
+    sha256: String,
+    total_chunks: u32,
+    archived: bool,
+}
+
+#[derive(Template)]
+#[template(path = "index.html")]
+struct IndexTemplate {
+    files: Vec<FileInfo>,
+    next_cursor: Option<String>,
+}
+
+/// Live view of `/api/v1/transfers`; the page fetches everything itself.
+#[derive(Template)]
+#[template(path = "transfers.html")]
+struct TransfersTemplate;
+
+/// Files per page of the web UI and the default page size of `/api/files`.
+const PAGE_SIZE: i64 = 200;
+/// Largest page `/api/files` hands out.
+const MAX_PAGE_SIZE: i64 = 1000;
+
+#[derive(Deserialize)]
+struct ListQuery {
+    #[serde(default)]
+    prefix: Option<String>,
+    #[serde(default)]
+    cursor: Option<String>,
+    #[serde(default)]
+    limit: Option<i64>,
+    /// Also list archived files.
+    #[serde(default)]
+    archived: bool,
+}
+
+#[derive(Serialize)]
+struct FilePageInfo {
+    files: Vec<FileInfo>,
+    next_cursor: Option<String>,
+}
+
+/// File recording transfers that were still running when the server stopped.
+const PENDING_TRANSFERS_FILE: &str = "pending_transfers.json";
+
+/// Serves the web UI and API until Ctrl-C or SIGTERM, then waits for running
+/// transfers as described in the README.
+pub async fn start_server(
+    service: Arc<TgCloudService>,
+    config: ServerConfig,
+) -> anyhow::Result<()> {
+    let pending_path = tgcloud_core::config_dir()?.join(PENDING_TRANSFERS_FILE);
+    match tgcloud_core::take_pending(&pending_path) {
+        Ok(interrupted) if !interrupted.is_empty() => {
+            println!(
+                "  {} {} transfer(s) were interrupted by the last shutdown and must be re-run:",
+                "⚠".yellow(),
+                interrupted.len()
+            );
+            for t in interrupted {
+                println!("     {:?} {}", t.kind, t.path);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => log::warn!("Could not read {}: {}", pending_path.display(), e),
+    }
+
+    let upload_dir = match &config.upload_dir {
+        Some(dir) => dir.clone(),
+        None => tgcloud_core::config_dir()?.join("uploads"),
+    };
+    let state = WebState {
+        service: Arc::clone(&service),
+        transfer_slots: Arc::new(Semaphore::new(config.max_transfers)),
+        tus: Arc::new(TusUploads::new(upload_dir)?),
+    };
+    tus::spawn_maintenance(state.clone());
+
+    let mut app = Router::new()
+        .route("/", get(index_handler))
+        .route("/transfers", get(transfers_page_handler))
+        .route("/api/files", get(list_files_handler))
+        .route("/api/stats", get(stats_handler))
+        .route("/api/v1/search", get(search_handler))
+        .route("/api/v1/ratelimits", get(rate_limits_handler))
+        .route("/api/v1/transfers", get(transfers_handler))
+        .route("/api/download", post(download_handler))
+        .route("/api/rename", post(rename_handler))
+        .route("/api/file/:path", delete(delete_file_handler))
+        .merge(tus::routes())
+        .merge(upload_api::routes())
+        .layer(CorsLayer::permissive())
+        .with_state(state);
+    match &config.auth_token {
+        Some(token) => {
+            app = app.layer(middleware::from_fn_with_state(
+                auth::AuthState {
+                    token: Arc::new(token.clone()),
+                    service: Arc::clone(&service),
+                },
+                auth::require_auth,
+            ));
+        }
+        None if !config.bind.ip().is_loopback() => println!(
+            "  {} Listening on {} without SERVER_AUTH_TOKEN: anyone who can reach it has full access",
+            "⚠".yellow(),
+            config.bind
+        ),
+        None => {}
+    }
+
+    let addr = config.bind;
+    let scheme = if config.is_tls() { "https" } else { "http" };
+    println!(
+        "\n  {} TGCloud Web UI running at {}://{}",
+        "🌐".cyan(),
+        scheme,
+        addr
+    );
+
+    let shutdown_service = Arc::clone(&service);
+    match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            let tls = axum_server::tls_openssl::OpenSSLConfig::from_pem_file(cert, key)
+                .map_err(|e| anyhow::anyhow!("Failed to load TLS certificate: {}", e))?;
+            let handle = axum_server::Handle::new();
+            let shutdown_handle = handle.clone();
+            tokio::spawn(async move {
+                shutdown_signal().await;
+                shutdown_service.begin_shutdown();
+                shutdown_handle.graceful_shutdown(None);
+            });
+            axum_server::bind_openssl(addr, tls)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await?;
+        }
+        _ => {
+            let listener = tokio::net::TcpListener::bind(&addr).await?;
+            axum::serve(listener, app.into_make_service())
+                .with_graceful_shutdown(async move {
+                    shutdown_signal().await;
+                    shutdown_service.begin_shutdown();
+                })
+                .await?;
+        }
+    }
+
+    let running = service.active_transfers();
+    if !running.is_empty() {
+        println!(
+            "\n  {} Waiting up to {}s for {} running transfer(s)...",
+            "⏳".cyan(),
+            service.shutdown_grace().as_secs(),
+            running.len()
+        );
+    }
+    let unfinished = service.drain(service.shutdown_grace()).await;
+    if !unfinished.is_empty() {
+        tgcloud_core::save_pending(&pending_path, &unfinished)?;
+        println!(
+            "  {} {} transfer(s) did not finish; saved to {}",
+            "⚠".yellow(),
+            unfinished.len(),
+            pending_path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Answers with the error as JSON (see `ErrorReport`) and a status matching
+/// its kind.
+pub(crate) fn error_response(e: &TgCloudError) -> Response {
+    let status = match e.root() {
+        TgCloudError::FileNotFound(_) => StatusCode::NOT_FOUND,
+        TgCloudError::AlreadyExists(_) => StatusCode::CONFLICT,
+        TgCloudError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+        TgCloudError::TelegramError(_)
+        | TgCloudError::BotUnauthorized { .. }
+        | TgCloudError::Transient { .. }
+        | TgCloudError::RetryExhausted { .. } => StatusCode::BAD_GATEWAY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, Json(e)).into_response()
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix (how service managers stop daemons).
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install CTRL+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    println!(
+        "\n  {} Shutting down: no longer accepting new transfers",
+        "🛑".cyan()
+    );
+}
+
+fn format_file_info(f: FileMetadata) -> FileInfo {
+    FileInfo {
+        archived: f.is_archived(),
+        file_id: f.file_id,
+        original_name: std::path::Path::new(&f.original_name)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or(f.original_name),
+        size: human_bytes::human_bytes(f.size as f64),
+        created_at: f.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+        sha256: f.sha256,
+        total_chunks: f.total_chunks,
+    }
+}
+
+fn render(template: impl Template) -> Response {
+    match template.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorReport::new(
+                "template",
+                format!("Template error: {}", e),
+            )),
+        )
+            .into_response(),
+    }
+}
+
+async fn index_handler(State(state): State<WebState>) -> impl IntoResponse {
+    let filter = FileFilter::Prefix("root".to_string());
+    match state
+        .service
+        .list_files_page(&filter, None, PAGE_SIZE, false)
+        .await
+    {
+        Ok(page) => {
+            let files = page.files.into_iter().map(format_file_info).collect();
+            render(IndexTemplate {
+                files,
+                next_cursor: page.next_cursor,
+            })
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn transfers_page_handler() -> impl IntoResponse {
+    render(TransfersTemplate)
+}
+
+/// Running and recent transfers of this server, with the state, bot and
+/// speed of every chunk.
+async fn transfers_handler(State(state): State<WebState>) -> impl IntoResponse {
+    Json(state.service.transfer_overview())
+}
+
+async fn list_files_handler(
+    State(state): State<WebState>,
+    Query(query): Query<ListQuery>,
+) -> impl IntoResponse {
+    let filter = FileFilter::Prefix(query.prefix.unwrap_or_else(|| "root".to_string()));
+    let limit = query.limit.unwrap_or(PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    match state
+        .service
+        .list_files_page(&filter, query.cursor.as_deref(), limit, query.archived)
+        .await
+    {
+        Ok(page) => Json(FilePageInfo {
+            files: page.files.into_iter().map(format_file_info).collect(),
+            next_cursor: page.next_cursor,
+        })
+        .into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(Deserialize)]
+struct StatsQuery {
+    #[serde(default)]
+    prefix: Option<String>,
+}
+
+/// Totals by bot and month for a dashboard.
+async fn stats_handler(
+    State(state): State<WebState>,
+    Query(query): Query<StatsQuery>,
+) -> impl IntoResponse {
+    let filter = FileFilter::Folder {
+        folder: query.prefix.unwrap_or_else(|| "root".to_string()),
+        depth: None,
+    };
+    match state.service.storage_stats(&filter).await {
+        Ok(stats) => Json(stats).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn rate_limits_handler(State(state): State<WebState>) -> impl IntoResponse {
+    match state.service.rate_limit_status().await {
+        Ok(status) => Json(status).into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    #[serde(default)]
+    mode: SearchMode,
+    #[serde(default)]
+    limit: Option<i64>,
+    /// Also search archived files.
+    #[serde(default)]
+    archived: bool,
+}
+
+async fn search_handler(
+    State(state): State<WebState>,
+    Query(query): Query<SearchQuery>,
+) -> impl IntoResponse {
+    let limit = query.limit.unwrap_or(PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    match state
+        .service
+        .search(&query.q, query.mode, limit, query.archived)
+        .await
+    {
+        Ok(files) => {
+            let files: Vec<FileInfo> = files.into_iter().map(format_file_info).collect();
+            Json(files).into_response()
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+#[derive(Deserialize)]
+struct RenameRequest {
+    file_id: String,
+    new_path: String,
+}
+
+async fn rename_handler(
+    State(state): State<WebState>,
+    Json(payload): Json<RenameRequest>,
+) -> impl IntoResponse {
+    match state
+        .service
+        .rename_file_by_id(&payload.file_id, &payload.new_path)
+        .await
+    {
+        Ok(_) => StatusCode::OK.into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn delete_file_handler(
+    State(state): State<WebState>,
+    Path(file_id): Path<String>,
+) -> impl IntoResponse {
+    match state.service.delete_file_by_id(&file_id).await {
+        Ok(_) => StatusCode::OK.into_response(),
+        Err(e) => error_response(&e),
+    }
+}
+
+/// Body of the 202 returned when a download is queued.
+#[derive(Serialize)]
+struct TransferAccepted {
+    transfer_id: String,
+}
+
+#[derive(Deserialize)]
+struct DownloadRequest {
+    remote_path: String,
+}
+
+async fn download_handler(
+    State(state): State<WebState>,
+    Json(payload): Json<DownloadRequest>,
+) -> impl IntoResponse {
+    if state.service.is_shutting_down() {
+        return error_response(&TgCloudError::ShuttingDown);
+    }
+
+    let (tx, _rx) = mpsc::channel(100);
+    let service = state.service.clone();
+    let slots = Arc::clone(&state.transfer_slots);
+    let path = payload.remote_path.clone();
+    let transfer_id = tgcloud_core::new_transfer_id();
+    let options = DownloadOptions {
+        transfer_id: Some(transfer_id.clone()),
+        ..Default::default()
+    };
+
+    tokio::spawn(tgcloud_core::with_transfer_id(
+        transfer_id.clone(),
+        async move {
+            let Ok(_slot) = slots.acquire_owned().await else {
+                return;
+            };
+            if let Err(e) = service.download_file(&path, options, tx).await {
+                log::warn!("Download of {} failed: {}", path, e);
+            }
+        },
+    ));
+
+    (StatusCode::ACCEPTED, Json(TransferAccepted { transfer_id })).into_response()
+}